@@ -7,11 +7,17 @@
 //   function and arguments, signatures and function lifetime
 
 use crate::builtins::{get_builtin_by_name, Builtin};
-use crate::errors::{Result, SemanticError};
+use crate::errors::{collect_errors, Result, SemanticError};
+use std::error::Error;
 
 use crate::{
+    derivative::expand_derivatives,
     evaluate::evaluate_in_context,
-    parser::{ExpressionNode, ProgramNode, StatementNode},
+    parser::{
+        Comparator, CompareNode, ExpressionKind, ExpressionNode, Operator, ProgramNode,
+        StatementKind, SumRange, UnaryOperator,
+    },
+    tokens::Span,
 };
 
 pub struct Global {
@@ -27,11 +33,14 @@ pub struct Slider {
 
 pub struct Function {
     pub name: String,
-    pub arg_count: u8,
+    pub arguments: Vec<String>,
+    // The body of the function, used to evaluate it at compile time
+    pub value: ExpressionNode,
 }
 
 pub(crate) struct Context<'a> {
     pub globals: &'a Vec<Global>,
+    pub sliders: &'a Vec<Slider>,
     pub functions: &'a Vec<Function>,
     pub locals: &'a Vec<String>,
 }
@@ -61,33 +70,102 @@ fn is_name_new(name: &str, context: &Context) -> bool {
     }
     true
 }
+// True if the value of the expression depends on a slider, possibly through a function call
+fn uses_sliders(expr: &ExpressionNode, context: &Context) -> bool {
+    match &expr.kind {
+        ExpressionKind::Number(_) => false,
+        ExpressionKind::Variable(name) => {
+            !context.locals.contains(name) && context.sliders.iter().any(|s| &s.name == name)
+        }
+        ExpressionKind::BinaryOp { left, right, .. } => {
+            uses_sliders(left, context) || uses_sliders(right, context)
+        }
+        ExpressionKind::UnaryOp { right, .. } => uses_sliders(right, context),
+        ExpressionKind::FunctionCall { name, args } => {
+            if args.iter().any(|arg| uses_sliders(arg, context)) {
+                return true;
+            }
+            match context.functions.iter().find(|f| &f.name == name) {
+                Some(function) => uses_sliders(
+                    &function.value,
+                    &Context {
+                        globals: context.globals,
+                        sliders: context.sliders,
+                        functions: context.functions,
+                        locals: &function.arguments,
+                    },
+                ),
+                None => false,
+            }
+        }
+        ExpressionKind::IfExpression {
+            condition,
+            if_true,
+            if_false,
+        } => {
+            uses_sliders(&condition.left, context)
+                || uses_sliders(&condition.right, context)
+                || uses_sliders(if_true, context)
+                || uses_sliders(if_false, context)
+        }
+        ExpressionKind::SumExpression { value, range }
+        | ExpressionKind::Numeric { value, range, .. } => {
+            let mut locals = context.locals.clone();
+            locals.push(range.variable_name.clone());
+            uses_sliders(&range.lower, context)
+                || uses_sliders(&range.upper, context)
+                || uses_sliders(
+                    value,
+                    &Context {
+                        globals: context.globals,
+                        sliders: context.sliders,
+                        functions: context.functions,
+                        locals: &locals,
+                    },
+                )
+        }
+        ExpressionKind::Derivative { value, .. } => uses_sliders(value, context),
+    }
+}
 
-// Returns a list of the used imported functions
-fn analyze_expression(expr: &ExpressionNode, context: &Context) -> Result<Vec<Builtin>> {
+// Returns a list of the used imported functions.
+// Errors are added to `errors` and the analysis goes on, so that all the problems are reported.
+fn analyze_expression(
+    expr: &ExpressionNode,
+    context: &Context,
+    errors: &mut Vec<Box<dyn Error>>,
+) -> Vec<Builtin> {
     let mut builtins = Vec::new();
     // Check that an expression is valid
     // 1. there are no undefined variables
     // 2. functions are called wit the correct arguments
-    match expr {
-        ExpressionNode::Number(_) => {}
-        ExpressionNode::Variable(name) => {
+    match &expr.kind {
+        ExpressionKind::Number(_) => {}
+        ExpressionKind::Variable(name) => {
             // check that variable has not been defined
             if is_name_new(name, context) {
-                return Err(SemanticError {
-                    message: format!("Undefined variable: '{name}'"),
-                }
-                .into());
+                errors.push(
+                    SemanticError {
+                        span: expr.span,
+                        message: format!("Undefined variable: '{name}'"),
+                    }
+                    .into(),
+                );
             }
-            // NOTE: We could substitute globals here for their value, but we will do that when emitting code instead.
+            // The globals are replaced by their value when the program is simplified
         }
-        ExpressionNode::BinaryOp { op: _, left, right } => {
-            builtins.append(&mut analyze_expression(left, context)?);
-            builtins.append(&mut analyze_expression(right, context)?);
+        ExpressionKind::BinaryOp { op, left, right } => {
+            // x^y is compiled as a call to Pow
+            if *op == Operator::Power {
+                builtins.push(Builtin::Pow);
+            }
+            builtins.append(&mut analyze_expression(left, context, errors));
+            builtins.append(&mut analyze_expression(right, context, errors));
         }
-        ExpressionNode::UnaryOp { op: _, right } => {
-            builtins.append(&mut analyze_expression(right, context)?)
+        ExpressionKind::UnaryOp { op: _, right } => {
+            builtins.append(&mut analyze_expression(right, context, errors))
         }
-        ExpressionNode::FunctionCall { name, args } => {
+        ExpressionKind::FunctionCall { name, args } => {
             // We need to check:
             // 1. function exists
             // 2. arguments are correct
@@ -99,75 +177,409 @@ fn analyze_expression(expr: &ExpressionNode, context: &Context) -> Result<Vec<Bu
             if name.chars().next().unwrap().is_uppercase() {
                 if let Some(builtin) = get_builtin_by_name(name) {
                     if arg_count != builtin.arg_count() {
-                        return Err(SemanticError {
-                            message: format!("Expected exactly one argument but got {arg_count}"),
-                        }
-                        .into());
+                        errors.push(
+                            SemanticError {
+                                span: expr.span,
+                                message: format!(
+                                    "Expected {} arguments but got {arg_count}",
+                                    builtin.arg_count()
+                                ),
+                            }
+                            .into(),
+                        );
                     }
                     builtins.push(builtin);
-                    for arg in args {
-                        builtins.append(&mut analyze_expression(arg, context)?);
-                    }
-                    return Ok(builtins);
-                }
-                return Err(SemanticError {
-                    message: format!("Unrecognized function: '{name}'"),
-                }
-                .into());
-            }
-
-            if is_name_new(name, context) {
-                return Err(SemanticError {
-                    message: format!("Undefined function: '{name}'"),
+                } else {
+                    errors.push(
+                        SemanticError {
+                            span: expr.span,
+                            message: format!("Unrecognized function: '{name}'"),
+                        }
+                        .into(),
+                    );
                 }
-                .into());
-            }
-            // It's a user defined function
-            for function in context.functions.iter() {
-                if name == &function.name {
-                    if arg_count != function.arg_count {
-                        return Err(SemanticError {
+            } else if let Some(function) = context.functions.iter().find(|f| &f.name == name) {
+                // It's a user defined function
+                if arg_count as usize != function.arguments.len() {
+                    errors.push(
+                        SemanticError {
+                            span: expr.span,
                             message: format!(
                                 "Expected {} arguments but got {}",
-                                function.arg_count, arg_count
+                                function.arguments.len(),
+                                arg_count
                             ),
                         }
-                        .into());
-                    }
-                    for arg in args {
-                        builtins.append(&mut analyze_expression(arg, context)?);
-                    }
-                    return Ok(builtins);
+                        .into(),
+                    );
                 }
+            } else {
+                errors.push(
+                    SemanticError {
+                        span: expr.span,
+                        message: format!("Undefined function: '{name}'"),
+                    }
+                    .into(),
+                );
             }
-            return Err(SemanticError {
-                message: format!("Undefined function: '{name}'"),
+            for arg in args {
+                builtins.append(&mut analyze_expression(arg, context, errors));
             }
-            .into());
         }
-        ExpressionNode::IfExpression {
+        ExpressionKind::IfExpression {
             condition,
             if_true,
             if_false,
         } => {
-            analyze_expression(&condition.left, context)?;
-            analyze_expression(&condition.right, context)?;
-            analyze_expression(if_true, context)?;
-            analyze_expression(if_false, context)?;
+            builtins.append(&mut analyze_expression(&condition.left, context, errors));
+            builtins.append(&mut analyze_expression(&condition.right, context, errors));
+            builtins.append(&mut analyze_expression(if_true, context, errors));
+            builtins.append(&mut analyze_expression(if_false, context, errors));
         }
-        ExpressionNode::SumExpression { value, range } => {
+        ExpressionKind::SumExpression { value, range }
+        | ExpressionKind::Numeric { value, range, .. } => {
+            // The bounds are outside of the scope of the variable
+            builtins.append(&mut analyze_expression(&range.lower, context, errors));
+            builtins.append(&mut analyze_expression(&range.upper, context, errors));
             let name = range.variable_name.clone();
             let mut locals = context.locals.clone();
             locals.push(name);
             let new_context = Context {
                 globals: context.globals,
+                sliders: context.sliders,
                 functions: context.functions,
                 locals: &locals,
             };
-            analyze_expression(value, &new_context)?;
+            builtins.append(&mut analyze_expression(value, &new_context, errors));
+        }
+        // The derivatives have been computed before the analysis, see `expand_derivatives`
+        ExpressionKind::Derivative { value, .. } => {
+            builtins.append(&mut analyze_expression(value, context, errors))
+        }
+    };
+    builtins
+}
+
+// Evaluates a constant expression, on error it is recorded and the value is NaN
+fn evaluate_constant(
+    expr: &ExpressionNode,
+    context: &Context,
+    errors: &mut Vec<Box<dyn Error>>,
+) -> f64 {
+    match evaluate_in_context(expr, context) {
+        Ok(f) => f,
+        Err(error) => {
+            errors.push(error);
+            f64::NAN
+        }
+    }
+}
+
+// Replaces the bounds of the range of a plot by their value
+fn evaluate_range(range: &mut SumRange, context: &Context, errors: &mut Vec<Box<dyn Error>>) {
+    let minimum = evaluate_constant(&range.lower, context, errors);
+    let maximum = evaluate_constant(&range.upper, context, errors);
+    *range.lower = number_node(minimum, range.lower.span);
+    *range.upper = number_node(maximum, range.upper.span);
+}
+
+// A ContourPlot or a DensityPlot needs a different variable for each of its ranges
+fn check_plane_ranges(x_range: &SumRange, y_range: &SumRange, span: Span) -> Result<()> {
+    if x_range.variable_name == y_range.variable_name {
+        Err(SemanticError {
+            span,
+            message: format!(
+                "Both ranges have the same variable '{}'",
+                x_range.variable_name
+            ),
+        }
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+fn number_node(f: f64, span: Span) -> ExpressionNode {
+    ExpressionNode::new(ExpressionKind::Number(f), span)
+}
+
+fn number_value(expr: &ExpressionNode) -> Option<f64> {
+    match expr.kind {
+        ExpressionKind::Number(f) => Some(f),
+        _ => None,
+    }
+}
+
+// Folds the constant subtrees of an expression and removes the operations that do not change
+// the value: x*1, 1*x, x/1, x+0, 0+x, x-0, x^1, +x and -(-x). Divisions by 0 are left for the
+// runtime and calls to user defined functions are never folded.
+// `locals` are the arguments and Sum variables, they shadow the globals.
+fn simplify_expression(
+    expr: &ExpressionNode,
+    globals: &[Global],
+    locals: &[String],
+) -> ExpressionNode {
+    let span = expr.span;
+    match &expr.kind {
+        ExpressionKind::Number(_) => expr.clone(),
+        ExpressionKind::Variable(name) => {
+            if !locals.contains(name) {
+                if let Some(global) = globals.iter().find(|g| &g.name == name) {
+                    return number_node(global.value, span);
+                }
+            }
+            expr.clone()
+        }
+        ExpressionKind::BinaryOp { op, left, right } => {
+            let left = simplify_expression(left, globals, locals);
+            let right = simplify_expression(right, globals, locals);
+            match (op, number_value(&left), number_value(&right)) {
+                (Operator::Divide, Some(_), Some(0.0)) => {}
+                (_, Some(l), Some(r)) => {
+                    let f = match op {
+                        Operator::Plus => l + r,
+                        Operator::Minus => l - r,
+                        Operator::Times => l * r,
+                        Operator::Divide => l / r,
+                        Operator::Power => l.powf(r),
+                    };
+                    return number_node(f, span);
+                }
+                (Operator::Plus | Operator::Minus, _, Some(0.0))
+                | (Operator::Times | Operator::Divide | Operator::Power, _, Some(1.0)) => {
+                    return ExpressionNode::new(left.kind, span);
+                }
+                (Operator::Plus, Some(0.0), _) | (Operator::Times, Some(1.0), _) => {
+                    return ExpressionNode::new(right.kind, span);
+                }
+                _ => {}
+            }
+            ExpressionNode::new(
+                ExpressionKind::BinaryOp {
+                    op: op.clone(),
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+                span,
+            )
+        }
+        ExpressionKind::UnaryOp { op, right } => {
+            let right = simplify_expression(right, globals, locals);
+            if *op == UnaryOperator::Plus {
+                return ExpressionNode::new(right.kind, span);
+            }
+            match right.kind {
+                ExpressionKind::Number(f) => number_node(-f, span),
+                ExpressionKind::UnaryOp {
+                    op: UnaryOperator::Minus,
+                    right,
+                } => ExpressionNode::new(right.kind, span),
+                kind => ExpressionNode::new(
+                    ExpressionKind::UnaryOp {
+                        op: UnaryOperator::Minus,
+                        right: Box::new(ExpressionNode::new(kind, right.span)),
+                    },
+                    span,
+                ),
+            }
+        }
+        ExpressionKind::FunctionCall { name, args } => {
+            let args: Vec<ExpressionNode> = args
+                .iter()
+                .map(|arg| simplify_expression(arg, globals, locals))
+                .collect();
+            if let Some(builtin) = get_builtin_by_name(name) {
+                let values: Option<Vec<f64>> = args.iter().map(number_value).collect();
+                if let Some(values) = values {
+                    return number_node(builtin.evaluate(&values), span);
+                }
+            }
+            ExpressionNode::new(
+                ExpressionKind::FunctionCall {
+                    name: name.clone(),
+                    args,
+                },
+                span,
+            )
+        }
+        ExpressionKind::IfExpression {
+            condition,
+            if_true,
+            if_false,
+        } => {
+            let left = simplify_expression(&condition.left, globals, locals);
+            let right = simplify_expression(&condition.right, globals, locals);
+            let if_true = simplify_expression(if_true, globals, locals);
+            let if_false = simplify_expression(if_false, globals, locals);
+            if let (Some(l), Some(r)) = (number_value(&left), number_value(&right)) {
+                let holds = match condition.op {
+                    Comparator::Equal => l == r,
+                    Comparator::NotEqual => l != r,
+                    Comparator::LessThan => l < r,
+                    Comparator::GreaterThan => l > r,
+                    Comparator::LessThanOrEqual => l <= r,
+                    Comparator::GreaterThanOrEqual => l >= r,
+                };
+                let branch = if holds { if_true } else { if_false };
+                return ExpressionNode::new(branch.kind, span);
+            }
+            ExpressionNode::new(
+                ExpressionKind::IfExpression {
+                    condition: CompareNode {
+                        op: condition.op.clone(),
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    },
+                    if_true: Box::new(if_true),
+                    if_false: Box::new(if_false),
+                },
+                span,
+            )
+        }
+        ExpressionKind::SumExpression { value, range }
+        | ExpressionKind::Numeric { value, range, .. } => {
+            let mut sum_locals = locals.to_vec();
+            sum_locals.push(range.variable_name.clone());
+            let value = Box::new(simplify_expression(value, globals, &sum_locals));
+            let range = SumRange {
+                variable_name: range.variable_name.clone(),
+                lower: Box::new(simplify_expression(&range.lower, globals, locals)),
+                upper: Box::new(simplify_expression(&range.upper, globals, locals)),
+            };
+            let kind = match &expr.kind {
+                ExpressionKind::Numeric { method, .. } => ExpressionKind::Numeric {
+                    method: *method,
+                    value,
+                    range,
+                },
+                _ => ExpressionKind::SumExpression { value, range },
+            };
+            ExpressionNode::new(kind, span)
+        }
+        ExpressionKind::Derivative { .. } => expr.clone(),
+    }
+}
+
+// The builtins called by an expression, `^` is a call to Pow
+fn collect_builtins(expr: &ExpressionNode, builtins: &mut Vec<Builtin>) {
+    let mut add = |builtin| {
+        if !builtins.contains(&builtin) {
+            builtins.push(builtin);
         }
     };
-    Ok(builtins)
+    match &expr.kind {
+        ExpressionKind::Number(_) | ExpressionKind::Variable(_) => {}
+        ExpressionKind::BinaryOp { op, left, right } => {
+            if *op == Operator::Power {
+                add(Builtin::Pow);
+            }
+            collect_builtins(left, builtins);
+            collect_builtins(right, builtins);
+        }
+        ExpressionKind::UnaryOp { right, .. } => collect_builtins(right, builtins),
+        ExpressionKind::FunctionCall { name, args } => {
+            if let Some(builtin) = get_builtin_by_name(name) {
+                add(builtin);
+            }
+            for arg in args {
+                collect_builtins(arg, builtins);
+            }
+        }
+        ExpressionKind::IfExpression {
+            condition,
+            if_true,
+            if_false,
+        } => {
+            collect_builtins(&condition.left, builtins);
+            collect_builtins(&condition.right, builtins);
+            collect_builtins(if_true, builtins);
+            collect_builtins(if_false, builtins);
+        }
+        ExpressionKind::SumExpression { value, range }
+        | ExpressionKind::Numeric { value, range, .. } => {
+            collect_builtins(&range.lower, builtins);
+            collect_builtins(&range.upper, builtins);
+            collect_builtins(value, builtins);
+        }
+        ExpressionKind::Derivative { value, .. } => collect_builtins(value, builtins),
+    }
+}
+
+/// Simplifies the functions, plots and prints of an analyzed program, see `simplify_expression`.
+/// The function bodies of the symbol table are replaced too and the builtins that are no longer
+/// called are not imported.
+pub(crate) fn simplify_program(program: &mut ProgramNode, symbol_table: &mut SymbolTable) {
+    let globals = &symbol_table.globals;
+    let mut builtins = Vec::new();
+    for statement in program.statements.iter_mut() {
+        match &mut statement.kind {
+            StatementKind::FunctionDeclaration {
+                name,
+                arguments,
+                value,
+            } => {
+                *value = simplify_expression(value, globals, arguments);
+                collect_builtins(value, &mut builtins);
+                if let Some(function) = symbol_table.functions.iter_mut().find(|f| &f.name == name)
+                {
+                    function.value = value.clone();
+                }
+            }
+            StatementKind::PlotStatement {
+                functions, x_range, ..
+            } => {
+                let locals = [x_range.variable_name.clone()];
+                for function in functions {
+                    function.value = simplify_expression(&function.value, globals, &locals);
+                    collect_builtins(&function.value, &mut builtins);
+                }
+            }
+            StatementKind::PolarPlotStatement { functions, range } => {
+                let locals = [range.variable_name.clone()];
+                for function in functions {
+                    function.value = simplify_expression(&function.value, globals, &locals);
+                    collect_builtins(&function.value, &mut builtins);
+                }
+                // redraw computes the points (r*Cos(θ), r*Sin(θ))
+                for builtin in [Builtin::Cos, Builtin::Sin] {
+                    if !builtins.contains(&builtin) {
+                        builtins.push(builtin);
+                    }
+                }
+            }
+            StatementKind::ContourPlotStatement {
+                value,
+                x_range,
+                y_range,
+                ..
+            }
+            | StatementKind::DensityPlotStatement {
+                value,
+                x_range,
+                y_range,
+                ..
+            } => {
+                let locals = [x_range.variable_name.clone(), y_range.variable_name.clone()];
+                *value = simplify_expression(value, globals, &locals);
+                collect_builtins(value, &mut builtins);
+            }
+            StatementKind::ParametricPlotStatement { functions, range } => {
+                let locals = [range.variable_name.clone()];
+                for function in functions {
+                    for value in [&mut function.x, &mut function.y] {
+                        *value = simplify_expression(value, globals, &locals);
+                        collect_builtins(value, &mut builtins);
+                    }
+                }
+            }
+            StatementKind::PrintStatement { argument } => {
+                *argument = simplify_expression(argument, globals, &[]);
+                collect_builtins(argument, &mut builtins);
+            }
+            StatementKind::ConstantAssignment { .. } | StatementKind::Slider { .. } => {}
+        }
+    }
+    symbol_table.builtins = builtins;
 }
 
 pub(crate) fn analyze_program(program: &mut ProgramNode) -> Result<SymbolTable> {
@@ -175,72 +587,72 @@ pub(crate) fn analyze_program(program: &mut ProgramNode) -> Result<SymbolTable>
     let mut sliders = Vec::new();
     let mut functions = Vec::new();
     let mut builtins = Vec::new();
+    let mut errors = Vec::new();
+
+    expand_derivatives(program, &mut errors);
 
     let mut seen_names: Vec<String> = Vec::new();
 
     for statement in program.statements.iter_mut() {
-        match statement {
-            StatementNode::ConstantAssignment { name, value } => {
+        let span = statement.span;
+        match &mut statement.kind {
+            StatementKind::ConstantAssignment { name, value } => {
                 // We need to check
                 // 1. It has not been used before
                 // 2. It can be evaluated using all constants defined before
                 if seen_names.contains(name) {
-                    return Err(SemanticError {
-                        message: format!("Variable has already been defined '{name}'"),
-                    }
-                    .into());
+                    errors.push(
+                        SemanticError {
+                            span,
+                            message: format!("Variable has already been defined '{name}'"),
+                        }
+                        .into(),
+                    );
+                    continue;
                 }
-                let f = evaluate_in_context(
+                let f = evaluate_constant(
                     value,
                     &Context {
                         globals: &globals,
+                        sliders: &sliders,
                         functions: &functions,
                         locals: &vec![],
                     },
-                )?;
-                *value = ExpressionNode::Number(f);
+                    &mut errors,
+                );
+                *value = ExpressionNode::new(ExpressionKind::Number(f), value.span);
+                // Even if we failed to evaluate it the name is defined from now on
                 globals.push(Global {
                     name: name.clone(),
                     value: f,
                 });
                 seen_names.push(name.clone());
             }
-            StatementNode::Slider {
+            StatementKind::Slider {
                 name,
                 default_value,
                 minimum_value,
                 maximum_value,
             } => {
                 if seen_names.contains(name) {
-                    return Err(SemanticError {
-                        message: format!("Variable has already been defined '{name}'"),
-                    }
-                    .into());
+                    errors.push(
+                        SemanticError {
+                            span,
+                            message: format!("Variable has already been defined '{name}'"),
+                        }
+                        .into(),
+                    );
+                    continue;
                 }
-                let default = evaluate_in_context(
-                    default_value,
-                    &Context {
-                        globals: &globals,
-                        functions: &functions,
-                        locals: &vec![],
-                    },
-                )?;
-                let minimum = evaluate_in_context(
-                    minimum_value,
-                    &Context {
-                        globals: &globals,
-                        functions: &functions,
-                        locals: &vec![],
-                    },
-                )?;
-                let maximum = evaluate_in_context(
-                    maximum_value,
-                    &Context {
-                        globals: &globals,
-                        functions: &functions,
-                        locals: &vec![],
-                    },
-                )?;
+                let context = &Context {
+                    globals: &globals,
+                    sliders: &sliders,
+                    functions: &functions,
+                    locals: &vec![],
+                };
+                let default = evaluate_constant(default_value, context, &mut errors);
+                let minimum = evaluate_constant(minimum_value, context, &mut errors);
+                let maximum = evaluate_constant(maximum_value, context, &mut errors);
                 sliders.push(Slider {
                     name: name.clone(),
                     minimum,
@@ -249,16 +661,20 @@ pub(crate) fn analyze_program(program: &mut ProgramNode) -> Result<SymbolTable>
                 });
                 seen_names.push(name.clone());
             }
-            StatementNode::FunctionDeclaration {
+            StatementKind::FunctionDeclaration {
                 name,
                 arguments,
                 value,
             } => {
                 if seen_names.contains(name) {
-                    return Err(SemanticError {
-                        message: format!("Variable has already been defined '{name}'"),
-                    }
-                    .into());
+                    errors.push(
+                        SemanticError {
+                            span,
+                            message: format!("Variable has already been defined '{name}'"),
+                        }
+                        .into(),
+                    );
+                    continue;
                 }
                 seen_names.push(name.clone());
                 let mut locals = arguments.clone();
@@ -269,16 +685,19 @@ pub(crate) fn analyze_program(program: &mut ProgramNode) -> Result<SymbolTable>
                     value,
                     &Context {
                         globals: &globals,
+                        sliders: &sliders,
                         functions: &functions,
                         locals: &locals,
                     },
-                )?);
+                    &mut errors,
+                ));
                 functions.push(Function {
                     name: name.clone(),
-                    arg_count: arguments.len() as u8,
+                    arguments: arguments.clone(),
+                    value: value.clone(),
                 })
             }
-            StatementNode::PlotStatement {
+            StatementKind::PlotStatement {
                 functions: function_list,
                 x_range,
                 y_range,
@@ -286,36 +705,158 @@ pub(crate) fn analyze_program(program: &mut ProgramNode) -> Result<SymbolTable>
                 let name = x_range.variable_name.clone();
                 let context = &Context {
                     globals: &globals,
+                    sliders: &sliders,
                     functions: &functions,
                     locals: &vec![name],
                 };
                 for function in function_list {
-                    builtins.append(&mut analyze_expression(&function.value, context)?);
+                    builtins.append(&mut analyze_expression(
+                        &function.value,
+                        context,
+                        &mut errors,
+                    ));
                 }
-                let minimum = evaluate_in_context(&x_range.lower, context)?;
-                let maximum = evaluate_in_context(&x_range.upper, context)?;
-                *x_range.lower = ExpressionNode::Number(minimum);
-                *x_range.upper = ExpressionNode::Number(maximum);
-                
+                evaluate_range(x_range, context, &mut errors);
+
                 if let Some(range) = y_range {
                     let context = &Context {
                         globals: &globals,
+                        sliders: &sliders,
                         functions: &functions,
                         locals: &vec![],
                     };
-                    let minimum = evaluate_in_context(&range.minimum, context)?;
-                    let maximum = evaluate_in_context(&range.maximum, context)?;
-                    range.minimum = ExpressionNode::Number(minimum);
-                    range.maximum = ExpressionNode::Number(maximum);
+                    let minimum = evaluate_constant(&range.minimum, context, &mut errors);
+                    let maximum = evaluate_constant(&range.maximum, context, &mut errors);
+                    range.minimum =
+                        ExpressionNode::new(ExpressionKind::Number(minimum), range.minimum.span);
+                    range.maximum =
+                        ExpressionNode::new(ExpressionKind::Number(maximum), range.maximum.span);
+                }
+            }
+            StatementKind::ParametricPlotStatement {
+                functions: function_list,
+                range,
+            } => {
+                let context = &Context {
+                    globals: &globals,
+                    sliders: &sliders,
+                    functions: &functions,
+                    locals: &vec![range.variable_name.clone()],
+                };
+                for function in function_list {
+                    for value in [&function.x, &function.y] {
+                        builtins.append(&mut analyze_expression(value, context, &mut errors));
+                    }
+                }
+                evaluate_range(range, context, &mut errors);
+            }
+            StatementKind::PolarPlotStatement {
+                functions: function_list,
+                range,
+            } => {
+                let context = &Context {
+                    globals: &globals,
+                    sliders: &sliders,
+                    functions: &functions,
+                    locals: &vec![range.variable_name.clone()],
+                };
+                for function in function_list {
+                    builtins.append(&mut analyze_expression(
+                        &function.value,
+                        context,
+                        &mut errors,
+                    ));
                 }
+                evaluate_range(range, context, &mut errors);
+                // redraw computes the points (r*Cos(θ), r*Sin(θ))
+                builtins.extend([Builtin::Cos, Builtin::Sin]);
             }
-            StatementNode::PrintStatement { argument } => todo!(),
+            StatementKind::ContourPlotStatement {
+                value,
+                x_range,
+                y_range,
+                levels,
+                ..
+            } => {
+                if let Err(error) = check_plane_ranges(x_range, y_range, span) {
+                    errors.push(error);
+                    continue;
+                }
+                let context = &Context {
+                    globals: &globals,
+                    sliders: &sliders,
+                    functions: &functions,
+                    locals: &vec![x_range.variable_name.clone(), y_range.variable_name.clone()],
+                };
+                builtins.append(&mut analyze_expression(value, context, &mut errors));
+                evaluate_range(x_range, context, &mut errors);
+                evaluate_range(y_range, context, &mut errors);
+                for level in levels {
+                    let f = evaluate_constant(level, context, &mut errors);
+                    *level = number_node(f, level.span);
+                }
+            }
+            StatementKind::DensityPlotStatement {
+                value,
+                x_range,
+                y_range,
+                ..
+            } => {
+                if let Err(error) = check_plane_ranges(x_range, y_range, span) {
+                    errors.push(error);
+                    continue;
+                }
+                let context = &Context {
+                    globals: &globals,
+                    sliders: &sliders,
+                    functions: &functions,
+                    locals: &vec![x_range.variable_name.clone(), y_range.variable_name.clone()],
+                };
+                builtins.append(&mut analyze_expression(value, context, &mut errors));
+                evaluate_range(x_range, context, &mut errors);
+                evaluate_range(y_range, context, &mut errors);
+            }
+            StatementKind::PrintStatement { argument } => {
+                let context = &Context {
+                    globals: &globals,
+                    sliders: &sliders,
+                    functions: &functions,
+                    locals: &vec![],
+                };
+                if uses_sliders(argument, context) {
+                    // It will be printed by the module every time the plots are redrawn
+                    let locals = sliders.iter().map(|s| s.name.clone()).collect();
+                    builtins.append(&mut analyze_expression(
+                        argument,
+                        &Context {
+                            globals: &globals,
+                            sliders: &sliders,
+                            functions: &functions,
+                            locals: &locals,
+                        },
+                        &mut errors,
+                    ));
+                } else {
+                    // It is printed by the compiler
+                    let f = evaluate_constant(argument, context, &mut errors);
+                    *argument = ExpressionNode::new(ExpressionKind::Number(f), argument.span);
+                }
+            }
+        }
+    }
+    collect_errors(errors)?;
+
+    // Each builtin is imported only once
+    let mut unique_builtins = Vec::new();
+    for builtin in builtins {
+        if !unique_builtins.contains(&builtin) {
+            unique_builtins.push(builtin);
         }
     }
     Ok(SymbolTable {
         globals,
         sliders,
         functions,
-        builtins,
+        builtins: unique_builtins,
     })
 }
@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Builtin {
     Sin,
     Cos,
@@ -54,6 +54,29 @@ impl Builtin {
             Builtin::Pow => "Pow",
         }
     }
+    /// Computes the value of the builtin like the JavaScript Math functions imported by the driver
+    pub fn evaluate(&self, args: &[f64]) -> f64 {
+        match &self {
+            Builtin::Sin => args[0].sin(),
+            Builtin::Cos => args[0].cos(),
+            Builtin::Tan => args[0].tan(),
+            Builtin::Asin => args[0].asin(),
+            Builtin::Acos => args[0].acos(),
+            Builtin::Atan => args[0].atan(),
+            Builtin::Sinh => args[0].sinh(),
+            Builtin::Cosh => args[0].cosh(),
+            Builtin::Tanh => args[0].tanh(),
+            Builtin::Asinh => args[0].asinh(),
+            Builtin::Acosh => args[0].acosh(),
+            Builtin::Atanh => args[0].atanh(),
+            Builtin::Log => args[0].ln(),
+            Builtin::Log10 => args[0].log10(),
+            Builtin::Exp => args[0].exp(),
+            Builtin::Sqrt => args[0].sqrt(),
+            Builtin::Atan2 => args[0].atan2(args[1]),
+            Builtin::Pow => args[0].powf(args[1]),
+        }
+    }
 }
 
 pub(crate) fn get_builtin_by_name(name: &str) -> Option<Builtin> {
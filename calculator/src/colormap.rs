@@ -0,0 +1,104 @@
+// The color maps of DensityPlot. A color map is a list of colors at evenly spaced positions from 0
+// to 1 and the color at a position in between is interpolated linearly between its two neighbours.
+//
+// The wasm backend has no tables, so each channel is written as a sum of ramps instead:
+// c(t) = c(0) + Σ change_k * max(0, t - start_k), where the ramp k starts at the position of the
+// color k and its change is the slope of the piece after it minus the slope of the piece before.
+// Both backends compute the channels this way, with the operations in the same order.
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorMap {
+    Viridis,
+    Grayscale,
+    // blue for the negative values and red for the positive ones, 0 is in the middle
+    Diverging,
+}
+
+impl ColorMap {
+    pub const ALL: [ColorMap; 3] = [ColorMap::Viridis, ColorMap::Grayscale, ColorMap::Diverging];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorMap::Viridis => "viridis",
+            ColorMap::Grayscale => "grayscale",
+            ColorMap::Diverging => "diverging",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ColorMap> {
+        ColorMap::ALL.into_iter().find(|map| map.name() == name)
+    }
+
+    fn colors(&self) -> &'static [[f64; 3]] {
+        match self {
+            ColorMap::Viridis => &[
+                [68.0, 1.0, 84.0],
+                [71.0, 44.0, 122.0],
+                [59.0, 82.0, 139.0],
+                [44.0, 114.0, 142.0],
+                [33.0, 145.0, 140.0],
+                [39.0, 173.0, 129.0],
+                [92.0, 200.0, 99.0],
+                [170.0, 220.0, 50.0],
+                [253.0, 231.0, 37.0],
+            ],
+            ColorMap::Grayscale => &[[0.0, 0.0, 0.0], [255.0, 255.0, 255.0]],
+            ColorMap::Diverging => &[
+                [59.0, 76.0, 192.0],
+                [141.0, 176.0, 254.0],
+                [221.0, 221.0, 221.0],
+                [244.0, 154.0, 123.0],
+                [180.0, 4.0, 38.0],
+            ],
+        }
+    }
+
+    // The range of values going from the position 0 to the position 1, given the minimum and the
+    // maximum of the values. It is widened when it is a single point.
+    pub fn range(&self, minimum: f64, maximum: f64) -> (f64, f64) {
+        let (lower, upper) = match self {
+            ColorMap::Diverging => {
+                let bound = f64::max(-minimum, maximum);
+                (-bound, bound)
+            }
+            _ => (minimum, maximum),
+        };
+        if lower < upper {
+            (lower, upper)
+        } else {
+            (lower - 1.0, upper + 1.0)
+        }
+    }
+
+    // The value of the channel at position 0 and the start and the change of each ramp
+    pub fn ramps(&self, channel: usize) -> (f64, Vec<(f64, f64)>) {
+        let colors = self.colors();
+        let pieces = (colors.len() - 1) as f64;
+        let mut ramps = Vec::new();
+        let mut previous_slope = 0.0;
+        for (k, pair) in colors.windows(2).enumerate() {
+            let slope = (pair[1][channel] - pair[0][channel]) * pieces;
+            ramps.push((k as f64 / pieces, slope - previous_slope));
+            previous_slope = slope;
+        }
+        (colors[0][channel], ramps)
+    }
+
+    // The red, green and blue of the color at `position`, which is between 0 and 1
+    pub fn color(&self, position: f64) -> [u8; 3] {
+        [0, 1, 2].map(|channel| {
+            let (mut value, ramps) = self.ramps(channel);
+            for (start, change) in ramps {
+                value += change * f64::max(0.0, position - start);
+            }
+            (value + 0.5) as u8
+        })
+    }
+}
+
+// The position of a value in the range of a color map, between 0 and 1. The halves do not
+// overflow when the range is wider than the largest f64. It is NaN when the range is too narrow
+// to divide by.
+pub fn position(value: f64, (lower, upper): (f64, f64)) -> f64 {
+    ((value * 0.5 - lower * 0.5) / (upper * 0.5 - lower * 0.5)).clamp(0.0, 1.0)
+}
@@ -0,0 +1,175 @@
+// Common subexpression elimination on the IR. Everything in Keith is pure, so an expression that
+// appears twice, like `h(x)` in `If(x > 0, h(x), -h(x))` or `f(x)` in `f(x)*f(x)`, has the same
+// value both times and can be computed once into a new local.
+//
+// The body of a function and the body of each Sum or Numeric, which runs many times, are handled
+// separately. Inside them an expression can be repeated in places that are not always evaluated,
+// the branches of an If. The value is computed in the innermost branch (or body) that contains
+// every copy: with `local.tee` at the first copy if that one is always evaluated, otherwise with
+// `local.set` before the branch starts. Then the other copies read the local.
+
+use crate::ir::{Expression, Program};
+
+struct Occurrence {
+    // the expression printed, equal expressions have the same key
+    key: String,
+    size: usize,
+    // the position of the operand taken at each step from the root
+    path: Vec<usize>,
+    // the lengths of the prefixes of `path` that start a branch, the first one is the root
+    blocks: Vec<usize>,
+}
+
+fn is_leaf(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::Number(_) | Expression::Local(_) | Expression::Global(_)
+    )
+}
+
+// The expressions that can be replaced by a local, in the order they are evaluated.
+// The bodies of the Sums and Numerics are not visited, they are evaluated many times.
+fn collect(
+    expression: &Expression,
+    path: &mut Vec<usize>,
+    blocks: &mut Vec<usize>,
+    occurrences: &mut Vec<Occurrence>,
+) {
+    if is_leaf(expression) {
+        return;
+    }
+    occurrences.push(Occurrence {
+        key: format!("{expression:?}"),
+        size: expression.size(),
+        path: path.clone(),
+        blocks: blocks.clone(),
+    });
+    for (position, child) in expression.children().into_iter().enumerate() {
+        let is_branch = matches!(expression, Expression::If { .. }) && position >= 2;
+        if matches!(
+            expression,
+            Expression::Sum { .. } | Expression::Numeric { .. }
+        ) && position == 2
+        {
+            continue;
+        }
+        path.push(position);
+        if is_branch {
+            blocks.push(path.len());
+        }
+        collect(child, path, blocks, occurrences);
+        if is_branch {
+            blocks.pop();
+        }
+        path.pop();
+    }
+}
+
+fn node_at<'a>(expression: &'a mut Expression, path: &[usize]) -> &'a mut Expression {
+    match path.split_first() {
+        Some((position, rest)) => node_at(expression.children_mut().swap_remove(*position), rest),
+        None => expression,
+    }
+}
+
+// Replaces the largest repeated expression by a local, returns false if there is none
+fn eliminate_one(root: &mut Expression, locals: &mut Vec<String>) -> bool {
+    let mut occurrences = Vec::new();
+    collect(root, &mut Vec::new(), &mut vec![0], &mut occurrences);
+    // the first of the largest ones, so the result does not depend on hashing
+    let mut best: Option<&Occurrence> = None;
+    for occurrence in &occurrences {
+        let repeated = occurrences
+            .iter()
+            .filter(|other| other.key == occurrence.key)
+            .count()
+            > 1;
+        if repeated && best.is_none_or(|best| occurrence.size > best.size) {
+            best = Some(occurrence);
+        }
+    }
+    let Some(best) = best else {
+        return false;
+    };
+    let copies: Vec<&Occurrence> = occurrences
+        .iter()
+        .filter(|occurrence| occurrence.key == best.key)
+        .collect();
+
+    // The innermost branch that contains every copy
+    let first = copies[0];
+    let common = copies
+        .iter()
+        .map(|copy| {
+            copy.path
+                .iter()
+                .zip(&first.path)
+                .take_while(|(a, b)| a == b)
+                .count()
+        })
+        .min()
+        .unwrap();
+    let block = *first.blocks.iter().rev().find(|b| **b <= common).unwrap();
+
+    let local = locals.len() as u32;
+    locals.push("cse".to_string());
+    let value = node_at(root, &first.path).clone();
+    for copy in &copies[1..] {
+        *node_at(root, &copy.path) = Expression::Local(local);
+    }
+    if first.blocks.last() == Some(&block) {
+        // the first copy is evaluated whenever the others are
+        let node = node_at(root, &first.path);
+        *node = Expression::Tee {
+            local,
+            value: Box::new(value),
+        };
+    } else {
+        *node_at(root, &first.path) = Expression::Local(local);
+        let node = node_at(root, &first.path[..block]);
+        let body = std::mem::replace(node, Expression::Number(0.0));
+        *node = Expression::Let {
+            local,
+            value: Box::new(value),
+            body: Box::new(body),
+        };
+    }
+    true
+}
+
+fn eliminate(expression: &mut Expression, locals: &mut Vec<String>) {
+    while eliminate_one(expression, locals) {}
+    // Then the bodies of the Sums and Numerics, the locals of the loop can only be used there
+    let mut pending = vec![expression];
+    while let Some(expression) = pending.pop() {
+        match expression {
+            Expression::Sum {
+                lower,
+                upper,
+                value,
+                ..
+            }
+            | Expression::Numeric {
+                lower,
+                upper,
+                value,
+                ..
+            } => {
+                eliminate(value, locals);
+                pending.push(lower);
+                pending.push(upper);
+            }
+            _ => pending.extend(expression.children_mut()),
+        }
+    }
+}
+
+/// Computes the repeated subexpressions of every function and print only once
+pub(crate) fn eliminate_common_subexpressions(program: &mut Program) {
+    for function in &mut program.functions {
+        eliminate(&mut function.body, &mut function.locals);
+    }
+    for print in &mut program.prints {
+        eliminate(print, &mut program.print_locals);
+    }
+}
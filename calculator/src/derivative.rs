@@ -0,0 +1,869 @@
+// Symbolic differentiation, `D(expression, x)` is replaced by the derivative of the expression
+// with respect to x before the program is analyzed.
+//
+// The derivative of a call to a user defined function uses the chain rule with the partial
+// derivatives of the function, which are new functions declared right after it: `fDx` is the
+// derivative of the body of f with respect to x, that can be one of its arguments or a slider.
+// A `D` is appended to the name until it is not a builtin or a name declared by the program, so
+// the program with the new functions can be printed and parsed again.
+//
+// The derivative of If is the derivative of each branch and the one of a Sum is the Sum of the
+// derivatives, as long as the bounds do not depend on x. Integrate follows the Leibniz rule, its
+// bounds can depend on x. The derivatives of Solve, FindMinimum and FindMaximum come from the
+// implicit function theorem, they do not depend on the bounds. The results are simplified later
+// with the rest of the program, here only the zeros and ones the rules introduce are removed.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use crate::{
+    builtins::{get_builtin_by_name, Builtin},
+    errors::{Result, SemanticError},
+    numeric::Method,
+    parser::{
+        CompareNode, ExpressionKind, ExpressionNode, Operator, ProgramNode, StatementKind,
+        StatementNode, SumRange, UnaryOperator,
+    },
+    tokens::Span,
+};
+
+fn number(f: f64, span: Span) -> ExpressionNode {
+    ExpressionNode::new(ExpressionKind::Number(f), span)
+}
+
+fn is_number(expr: &ExpressionNode, f: f64) -> bool {
+    matches!(expr.kind, ExpressionKind::Number(g) if g == f)
+}
+
+fn binary(op: Operator, left: ExpressionNode, right: ExpressionNode) -> ExpressionNode {
+    let span = left.span.to(right.span);
+    if let (ExpressionKind::Number(l), ExpressionKind::Number(r)) = (&left.kind, &right.kind) {
+        match op {
+            Operator::Plus => return number(l + r, span),
+            Operator::Minus => return number(l - r, span),
+            Operator::Times => return number(l * r, span),
+            _ => {}
+        }
+    }
+    ExpressionNode::new(
+        ExpressionKind::BinaryOp {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+        span,
+    )
+}
+
+fn add(left: ExpressionNode, right: ExpressionNode) -> ExpressionNode {
+    if is_number(&left, 0.0) {
+        right
+    } else if is_number(&right, 0.0) {
+        left
+    } else {
+        binary(Operator::Plus, left, right)
+    }
+}
+
+fn subtract(left: ExpressionNode, right: ExpressionNode) -> ExpressionNode {
+    if is_number(&right, 0.0) {
+        left
+    } else if is_number(&left, 0.0) {
+        negate(right)
+    } else {
+        binary(Operator::Minus, left, right)
+    }
+}
+
+fn multiply(left: ExpressionNode, right: ExpressionNode) -> ExpressionNode {
+    if is_number(&left, 0.0) || is_number(&right, 0.0) {
+        number(0.0, left.span.to(right.span))
+    } else if is_number(&left, 1.0) {
+        right
+    } else if is_number(&right, 1.0) {
+        left
+    } else {
+        binary(Operator::Times, left, right)
+    }
+}
+
+fn divide(left: ExpressionNode, right: ExpressionNode) -> ExpressionNode {
+    if is_number(&left, 0.0) || is_number(&right, 1.0) {
+        left
+    } else {
+        binary(Operator::Divide, left, right)
+    }
+}
+
+fn power(left: ExpressionNode, right: ExpressionNode) -> ExpressionNode {
+    if is_number(&right, 1.0) {
+        left
+    } else {
+        binary(Operator::Power, left, right)
+    }
+}
+
+fn negate(expr: ExpressionNode) -> ExpressionNode {
+    let span = expr.span;
+    match expr.kind {
+        ExpressionKind::Number(f) => number(-f, span),
+        ExpressionKind::UnaryOp {
+            op: UnaryOperator::Minus,
+            right,
+        } => *right,
+        kind => ExpressionNode::new(
+            ExpressionKind::UnaryOp {
+                op: UnaryOperator::Minus,
+                right: Box::new(ExpressionNode::new(kind, span)),
+            },
+            span,
+        ),
+    }
+}
+
+fn call(name: &str, args: Vec<ExpressionNode>, span: Span) -> ExpressionNode {
+    ExpressionNode::new(
+        ExpressionKind::FunctionCall {
+            name: name.to_string(),
+            args,
+        },
+        span,
+    )
+}
+
+// The derivative of a^b given the derivatives of a and b
+fn power_rule(
+    a: &ExpressionNode,
+    b: &ExpressionNode,
+    da: ExpressionNode,
+    db: ExpressionNode,
+) -> ExpressionNode {
+    let span = a.span.to(b.span);
+    if is_number(&db, 0.0) {
+        // b*a^(b-1)*a'
+        let exponent = subtract(b.clone(), number(1.0, span));
+        return multiply(multiply(b.clone(), power(a.clone(), exponent)), da);
+    }
+    // a^b*(b'*Log(a) + b*a'/a)
+    let log = multiply(db, call("Log", vec![a.clone()], span));
+    let base = divide(multiply(b.clone(), da), a.clone());
+    multiply(power(a.clone(), b.clone()), add(log, base))
+}
+
+// The derivative of a builtin with respect to its argument, evaluated at `u`
+fn builtin_derivative(builtin: Builtin, u: &ExpressionNode) -> ExpressionNode {
+    let span = u.span;
+    let one = || number(1.0, span);
+    let u_squared = || multiply(u.clone(), u.clone());
+    match builtin {
+        Builtin::Sin => call("Cos", vec![u.clone()], span),
+        Builtin::Cos => negate(call("Sin", vec![u.clone()], span)),
+        Builtin::Tan => {
+            let cos = call("Cos", vec![u.clone()], span);
+            divide(one(), multiply(cos.clone(), cos))
+        }
+        Builtin::Asin => divide(
+            one(),
+            call("Sqrt", vec![subtract(one(), u_squared())], span),
+        ),
+        Builtin::Acos => negate(divide(
+            one(),
+            call("Sqrt", vec![subtract(one(), u_squared())], span),
+        )),
+        Builtin::Atan => divide(one(), add(one(), u_squared())),
+        Builtin::Sinh => call("Cosh", vec![u.clone()], span),
+        Builtin::Cosh => call("Sinh", vec![u.clone()], span),
+        Builtin::Tanh => {
+            let cosh = call("Cosh", vec![u.clone()], span);
+            divide(one(), multiply(cosh.clone(), cosh))
+        }
+        Builtin::Asinh => divide(one(), call("Sqrt", vec![add(u_squared(), one())], span)),
+        Builtin::Acosh => divide(
+            one(),
+            call("Sqrt", vec![subtract(u_squared(), one())], span),
+        ),
+        Builtin::Atanh => divide(one(), subtract(one(), u_squared())),
+        Builtin::Log => divide(one(), u.clone()),
+        Builtin::Log10 => divide(
+            one(),
+            multiply(u.clone(), call("Log", vec![number(10.0, span)], span)),
+        ),
+        Builtin::Exp => call("Exp", vec![u.clone()], span),
+        Builtin::Sqrt => divide(
+            one(),
+            multiply(number(2.0, span), call("Sqrt", vec![u.clone()], span)),
+        ),
+        // the functions of two arguments are handled by the caller
+        Builtin::Atan2 | Builtin::Pow => unreachable!(),
+    }
+}
+
+// A user defined function, the bodies have no D left
+struct Function {
+    name: String,
+    arguments: Vec<String>,
+    value: ExpressionNode,
+    // the declaration, the partial derivatives are declared after it
+    statement: usize,
+    span: Span,
+}
+
+struct Differentiator {
+    functions: Vec<Function>,
+    // the new declarations and the statement they follow
+    declarations: Vec<(usize, StatementNode)>,
+    // the name of the derivative of a function, by its index, with respect to a variable
+    partials: HashMap<(usize, String), String>,
+    // the names declared by the program and the new functions
+    names: HashSet<String>,
+}
+
+impl Differentiator {
+    fn function(&self, name: &str) -> Option<usize> {
+        self.functions.iter().position(|f| f.name == name)
+    }
+
+    // True if the value of the expression can change with `x`
+    fn depends_on(&self, expr: &ExpressionNode, x: &str) -> bool {
+        match &expr.kind {
+            ExpressionKind::Number(_) => false,
+            ExpressionKind::Variable(name) => name == x,
+            ExpressionKind::BinaryOp { left, right, .. } => {
+                self.depends_on(left, x) || self.depends_on(right, x)
+            }
+            ExpressionKind::UnaryOp { right, .. } => self.depends_on(right, x),
+            ExpressionKind::FunctionCall { name, args } => {
+                if args.iter().any(|arg| self.depends_on(arg, x)) {
+                    return true;
+                }
+                // a function can use a slider
+                match self.function(name) {
+                    Some(index) => {
+                        let function = &self.functions[index];
+                        !function.arguments.iter().any(|a| a == x)
+                            && self.depends_on(&function.value, x)
+                    }
+                    None => false,
+                }
+            }
+            ExpressionKind::IfExpression {
+                condition,
+                if_true,
+                if_false,
+            } => {
+                self.depends_on(&condition.left, x)
+                    || self.depends_on(&condition.right, x)
+                    || self.depends_on(if_true, x)
+                    || self.depends_on(if_false, x)
+            }
+            ExpressionKind::SumExpression { value, range }
+            | ExpressionKind::Numeric { value, range, .. } => {
+                self.depends_on(&range.lower, x)
+                    || self.depends_on(&range.upper, x)
+                    || (range.variable_name != x && self.depends_on(value, x))
+            }
+            ExpressionKind::Derivative { value, .. } => self.depends_on(value, x),
+        }
+    }
+
+    // The name of the derivative of the function with respect to `x`, it is declared the first
+    // time it is needed
+    fn partial(&mut self, index: usize, x: &str) -> Result<String> {
+        let key = (index, x.to_string());
+        if let Some(name) = self.partials.get(&key) {
+            return Ok(name.clone());
+        }
+        let mut name = format!("{}D{x}", self.functions[index].name);
+        while self.names.contains(&name) || get_builtin_by_name(&name).is_some() {
+            name.push('D');
+        }
+        self.names.insert(name.clone());
+        self.partials.insert(key, name.clone());
+        let value = self.differentiate(&self.functions[index].value.clone(), x)?;
+        let function = &self.functions[index];
+        let declaration = StatementNode {
+            kind: StatementKind::FunctionDeclaration {
+                name: name.clone(),
+                arguments: function.arguments.clone(),
+                value: value.clone(),
+            },
+            span: function.span,
+        };
+        self.declarations.push((function.statement, declaration));
+        self.functions.push(Function {
+            name: name.clone(),
+            arguments: function.arguments.clone(),
+            value,
+            statement: function.statement,
+            span: function.span,
+        });
+        Ok(name)
+    }
+
+    fn differentiate(&mut self, expr: &ExpressionNode, x: &str) -> Result<ExpressionNode> {
+        let span = expr.span;
+        let derivative = match &expr.kind {
+            ExpressionKind::Number(_) => number(0.0, span),
+            ExpressionKind::Variable(name) => number(if name == x { 1.0 } else { 0.0 }, span),
+            ExpressionKind::BinaryOp { op, left, right } => {
+                let dl = self.differentiate(left, x)?;
+                let dr = self.differentiate(right, x)?;
+                let (l, r) = ((**left).clone(), (**right).clone());
+                match op {
+                    Operator::Plus => add(dl, dr),
+                    Operator::Minus => subtract(dl, dr),
+                    Operator::Times => add(multiply(dl, r), multiply(l, dr)),
+                    Operator::Divide if is_number(&dr, 0.0) => divide(dl, r),
+                    Operator::Divide => divide(
+                        subtract(multiply(dl, r.clone()), multiply(l, dr)),
+                        multiply(r.clone(), r),
+                    ),
+                    Operator::Power => power_rule(&l, &r, dl, dr),
+                }
+            }
+            ExpressionKind::UnaryOp { op, right } => {
+                let dr = self.differentiate(right, x)?;
+                match op {
+                    UnaryOperator::Plus => dr,
+                    UnaryOperator::Minus => negate(dr),
+                }
+            }
+            ExpressionKind::FunctionCall { name, args } => {
+                self.differentiate_call(expr, name, args, x)?
+            }
+            ExpressionKind::IfExpression {
+                condition,
+                if_true,
+                if_false,
+            } => {
+                let if_true = self.differentiate(if_true, x)?;
+                let if_false = self.differentiate(if_false, x)?;
+                if is_number(&if_true, 0.0) && is_number(&if_false, 0.0) {
+                    return Ok(if_true);
+                }
+                ExpressionNode::new(
+                    ExpressionKind::IfExpression {
+                        condition: condition.clone(),
+                        if_true: Box::new(if_true),
+                        if_false: Box::new(if_false),
+                    },
+                    span,
+                )
+            }
+            ExpressionKind::SumExpression { value, range } => {
+                if self.depends_on(&range.lower, x) || self.depends_on(&range.upper, x) {
+                    return Err(SemanticError {
+                        span,
+                        message: format!("Cannot differentiate a Sum whose bounds depend on '{x}'"),
+                    }
+                    .into());
+                }
+                // the variable of the Sum hides x
+                if range.variable_name == x {
+                    return Ok(number(0.0, span));
+                }
+                let value = self.differentiate(value, x)?;
+                if is_number(&value, 0.0) {
+                    return Ok(value);
+                }
+                ExpressionNode::new(
+                    ExpressionKind::SumExpression {
+                        value: Box::new(value),
+                        range: range.clone(),
+                    },
+                    span,
+                )
+            }
+            ExpressionKind::Numeric {
+                method: method @ Method::Integrate { .. },
+                value,
+                range,
+            } => {
+                // Leibniz rule: value(upper)*upper' - value(lower)*lower' + Integrate(value', range)
+                let t = &range.variable_name;
+                let mut bound = |bound: &ExpressionNode| -> Result<ExpressionNode> {
+                    let derivative = self.differentiate(bound, x)?;
+                    if is_number(&derivative, 0.0) {
+                        return Ok(derivative);
+                    }
+                    Ok(multiply(substitute(value, t, bound)?, derivative))
+                };
+                let upper = bound(&range.upper)?;
+                let lower = bound(&range.lower)?;
+                // the variable of the integral hides x
+                let inside = if t == x {
+                    number(0.0, span)
+                } else {
+                    self.differentiate(value, x)?
+                };
+                let inside = if is_number(&inside, 0.0) {
+                    inside
+                } else {
+                    ExpressionNode::new(
+                        ExpressionKind::Numeric {
+                            method: *method,
+                            value: Box::new(inside),
+                            range: range.clone(),
+                        },
+                        span,
+                    )
+                };
+                subtract(add(inside, upper), lower)
+            }
+            ExpressionKind::Numeric {
+                method,
+                value,
+                range,
+            } => {
+                // The implicit function theorem: the root r of g(t) is moved by -g'x/g't at r,
+                // and the minimum or the maximum of the value is a root of its derivative in t.
+                // The bounds do not move it.
+                let t = &range.variable_name;
+                if t == x {
+                    return Ok(number(0.0, span));
+                }
+                let g = match method {
+                    Method::Solve => *value.clone(),
+                    _ => self.differentiate(value, t)?,
+                };
+                let moved = self.differentiate(&g, x)?;
+                if is_number(&moved, 0.0) {
+                    return Ok(moved);
+                }
+                let slope = self.differentiate(&g, t)?;
+                negate(divide(
+                    substitute(&moved, t, expr)?,
+                    substitute(&slope, t, expr)?,
+                ))
+            }
+            ExpressionKind::Derivative { value, variable } => {
+                let value = self.differentiate(value, variable)?;
+                self.differentiate(&value, x)?
+            }
+        };
+        Ok(derivative)
+    }
+
+    // The chain rule
+    fn differentiate_call(
+        &mut self,
+        expr: &ExpressionNode,
+        name: &str,
+        args: &[ExpressionNode],
+        x: &str,
+    ) -> Result<ExpressionNode> {
+        let span = expr.span;
+        let mut derivatives = Vec::new();
+        for arg in args {
+            derivatives.push(self.differentiate(arg, x)?);
+        }
+        if let Some(builtin) = get_builtin_by_name(name) {
+            if args.len() != builtin.arg_count() as usize {
+                return Err(SemanticError {
+                    span,
+                    message: format!(
+                        "Expected {} arguments but got {}",
+                        builtin.arg_count(),
+                        args.len()
+                    ),
+                }
+                .into());
+            }
+            return Ok(match builtin {
+                Builtin::Pow => {
+                    let db = derivatives.pop().unwrap();
+                    let da = derivatives.pop().unwrap();
+                    power_rule(&args[0], &args[1], da, db)
+                }
+                Builtin::Atan2 => {
+                    // Atan2(y, x)' = (x*y' - y*x')/(x*x + y*y)
+                    let (y, x) = (&args[0], &args[1]);
+                    let dx = derivatives.pop().unwrap();
+                    let dy = derivatives.pop().unwrap();
+                    divide(
+                        subtract(multiply(x.clone(), dy), multiply(y.clone(), dx)),
+                        add(
+                            multiply(x.clone(), x.clone()),
+                            multiply(y.clone(), y.clone()),
+                        ),
+                    )
+                }
+                _ => {
+                    let du = derivatives.pop().unwrap();
+                    multiply(builtin_derivative(builtin, &args[0]), du)
+                }
+            });
+        }
+
+        let Some(index) = self.function(name) else {
+            return Err(SemanticError {
+                span,
+                message: format!("Undefined function: '{name}'"),
+            }
+            .into());
+        };
+        let arguments = self.functions[index].arguments.clone();
+        if args.len() != arguments.len() {
+            return Err(SemanticError {
+                span,
+                message: format!(
+                    "Expected {} arguments but got {}",
+                    arguments.len(),
+                    args.len()
+                ),
+            }
+            .into());
+        }
+        let mut result = number(0.0, span);
+        for (argument, derivative) in arguments.iter().zip(derivatives) {
+            if !is_number(&derivative, 0.0) {
+                let partial = self.partial(index, argument)?;
+                result = add(
+                    result,
+                    multiply(call(&partial, args.to_vec(), span), derivative),
+                );
+            }
+        }
+        // x can be a slider used in the body
+        if !arguments.iter().any(|a| a == x) && self.depends_on(&self.functions[index].value, x) {
+            let partial = self.partial(index, x)?;
+            result = add(result, call(&partial, args.to_vec(), span));
+        }
+        Ok(result)
+    }
+
+    fn expand_range(&mut self, range: &SumRange) -> Result<SumRange> {
+        Ok(SumRange {
+            variable_name: range.variable_name.clone(),
+            lower: Box::new(self.expand(&range.lower)?),
+            upper: Box::new(self.expand(&range.upper)?),
+        })
+    }
+
+    // Replaces every D in the expression by the derivative
+    fn expand(&mut self, expr: &ExpressionNode) -> Result<ExpressionNode> {
+        let span = expr.span;
+        let kind = match &expr.kind {
+            ExpressionKind::Number(_) | ExpressionKind::Variable(_) => return Ok(expr.clone()),
+            ExpressionKind::BinaryOp { op, left, right } => ExpressionKind::BinaryOp {
+                op: op.clone(),
+                left: Box::new(self.expand(left)?),
+                right: Box::new(self.expand(right)?),
+            },
+            ExpressionKind::UnaryOp { op, right } => ExpressionKind::UnaryOp {
+                op: op.clone(),
+                right: Box::new(self.expand(right)?),
+            },
+            ExpressionKind::FunctionCall { name, args } => {
+                let mut expanded = Vec::new();
+                for arg in args {
+                    expanded.push(self.expand(arg)?);
+                }
+                ExpressionKind::FunctionCall {
+                    name: name.clone(),
+                    args: expanded,
+                }
+            }
+            ExpressionKind::IfExpression {
+                condition,
+                if_true,
+                if_false,
+            } => ExpressionKind::IfExpression {
+                condition: CompareNode {
+                    op: condition.op.clone(),
+                    left: Box::new(self.expand(&condition.left)?),
+                    right: Box::new(self.expand(&condition.right)?),
+                },
+                if_true: Box::new(self.expand(if_true)?),
+                if_false: Box::new(self.expand(if_false)?),
+            },
+            ExpressionKind::SumExpression { value, range } => ExpressionKind::SumExpression {
+                value: Box::new(self.expand(value)?),
+                range: self.expand_range(range)?,
+            },
+            ExpressionKind::Numeric {
+                method,
+                value,
+                range,
+            } => ExpressionKind::Numeric {
+                method: *method,
+                value: Box::new(self.expand(value)?),
+                range: self.expand_range(range)?,
+            },
+            ExpressionKind::Derivative { value, variable } => {
+                let value = self.expand(value)?;
+                let derivative = self.differentiate(&value, variable)?;
+                // the derivative points to the D it comes from
+                return Ok(ExpressionNode::new(derivative.kind, span));
+            }
+        };
+        Ok(ExpressionNode::new(kind, span))
+    }
+}
+
+// True if `predicate` holds for a node of the expression
+fn contains(expr: &ExpressionNode, predicate: &impl Fn(&ExpressionNode) -> bool) -> bool {
+    if predicate(expr) {
+        return true;
+    }
+    match &expr.kind {
+        ExpressionKind::Number(_) | ExpressionKind::Variable(_) => false,
+        ExpressionKind::BinaryOp { left, right, .. } => {
+            contains(left, predicate) || contains(right, predicate)
+        }
+        ExpressionKind::UnaryOp { right, .. } => contains(right, predicate),
+        ExpressionKind::FunctionCall { args, .. } => {
+            args.iter().any(|arg| contains(arg, predicate))
+        }
+        ExpressionKind::IfExpression {
+            condition,
+            if_true,
+            if_false,
+        } => {
+            contains(&condition.left, predicate)
+                || contains(&condition.right, predicate)
+                || contains(if_true, predicate)
+                || contains(if_false, predicate)
+        }
+        ExpressionKind::SumExpression { value, range }
+        | ExpressionKind::Numeric { value, range, .. } => {
+            contains(value, predicate)
+                || contains(&range.lower, predicate)
+                || contains(&range.upper, predicate)
+        }
+        ExpressionKind::Derivative { value, .. } => contains(value, predicate),
+    }
+}
+
+fn calls(expr: &ExpressionNode, function: &str) -> bool {
+    contains(
+        expr,
+        &|node| matches!(&node.kind, ExpressionKind::FunctionCall { name, .. } if name == function),
+    )
+}
+
+fn mentions(expr: &ExpressionNode, variable: &str) -> bool {
+    contains(
+        expr,
+        &|node| matches!(&node.kind, ExpressionKind::Variable(name) if name == variable),
+    )
+}
+
+// The expression with `replacement` in place of the variable `t`
+fn substitute(
+    expr: &ExpressionNode,
+    t: &str,
+    replacement: &ExpressionNode,
+) -> Result<ExpressionNode> {
+    let span = expr.span;
+    let substitute = |expr: &ExpressionNode| -> Result<Box<ExpressionNode>> {
+        Ok(Box::new(substitute(expr, t, replacement)?))
+    };
+    let kind = match &expr.kind {
+        ExpressionKind::Number(_) => return Ok(expr.clone()),
+        ExpressionKind::Variable(name) if name == t => return Ok(replacement.clone()),
+        ExpressionKind::Variable(_) => return Ok(expr.clone()),
+        ExpressionKind::BinaryOp { op, left, right } => ExpressionKind::BinaryOp {
+            op: op.clone(),
+            left: substitute(left)?,
+            right: substitute(right)?,
+        },
+        ExpressionKind::UnaryOp { op, right } => ExpressionKind::UnaryOp {
+            op: op.clone(),
+            right: substitute(right)?,
+        },
+        ExpressionKind::FunctionCall { name, args } => {
+            let mut substituted = Vec::new();
+            for arg in args {
+                substituted.push(*substitute(arg)?);
+            }
+            ExpressionKind::FunctionCall {
+                name: name.clone(),
+                args: substituted,
+            }
+        }
+        ExpressionKind::IfExpression {
+            condition,
+            if_true,
+            if_false,
+        } => ExpressionKind::IfExpression {
+            condition: CompareNode {
+                op: condition.op.clone(),
+                left: substitute(&condition.left)?,
+                right: substitute(&condition.right)?,
+            },
+            if_true: substitute(if_true)?,
+            if_false: substitute(if_false)?,
+        },
+        ExpressionKind::SumExpression { value, range }
+        | ExpressionKind::Numeric { value, range, .. } => {
+            let variable = &range.variable_name;
+            // the variable of the inner Sum hides t
+            let value = if variable == t {
+                value.clone()
+            } else if mentions(value, t) && mentions(replacement, variable) {
+                return Err(SemanticError {
+                    span,
+                    message: format!(
+                        "Cannot differentiate, '{variable}' is also the variable of a range inside"
+                    ),
+                }
+                .into());
+            } else {
+                substitute(value)?
+            };
+            let range = SumRange {
+                variable_name: variable.clone(),
+                lower: substitute(&range.lower)?,
+                upper: substitute(&range.upper)?,
+            };
+            match &expr.kind {
+                ExpressionKind::Numeric { method, .. } => ExpressionKind::Numeric {
+                    method: *method,
+                    value,
+                    range,
+                },
+                _ => ExpressionKind::SumExpression { value, range },
+            }
+        }
+        ExpressionKind::Derivative { value, variable } => ExpressionKind::Derivative {
+            value: substitute(value)?,
+            variable: variable.clone(),
+        },
+    };
+    Ok(ExpressionNode::new(kind, span))
+}
+
+// Expands the expression in place, on error it is recorded and the expression becomes 0
+fn expand_in_place(
+    differentiator: &mut Differentiator,
+    expr: &mut ExpressionNode,
+    errors: &mut Vec<Box<dyn Error>>,
+) {
+    match differentiator.expand(expr) {
+        Ok(expanded) => *expr = expanded,
+        Err(error) => {
+            errors.push(error);
+            *expr = number(0.0, expr.span);
+        }
+    }
+}
+
+/// Replaces every `D(expression, x)` of the program by its derivative and declares the partial
+/// derivatives of the user defined functions it needs
+pub(crate) fn expand_derivatives(program: &mut ProgramNode, errors: &mut Vec<Box<dyn Error>>) {
+    let names = program
+        .statements
+        .iter()
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::ConstantAssignment { name, .. }
+            | StatementKind::Slider { name, .. }
+            | StatementKind::FunctionDeclaration { name, .. } => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut differentiator = Differentiator {
+        functions: Vec::new(),
+        declarations: Vec::new(),
+        partials: HashMap::new(),
+        names,
+    };
+    for (index, statement) in program.statements.iter_mut().enumerate() {
+        let span = statement.span;
+        match &mut statement.kind {
+            StatementKind::ConstantAssignment { value, .. } => {
+                expand_in_place(&mut differentiator, value, errors)
+            }
+            StatementKind::Slider {
+                default_value,
+                minimum_value,
+                maximum_value,
+                ..
+            } => {
+                expand_in_place(&mut differentiator, default_value, errors);
+                expand_in_place(&mut differentiator, minimum_value, errors);
+                expand_in_place(&mut differentiator, maximum_value, errors);
+            }
+            StatementKind::FunctionDeclaration {
+                name,
+                arguments,
+                value,
+            } => {
+                expand_in_place(&mut differentiator, value, errors);
+                // a function cannot call itself, the analyzer reports it
+                if calls(value, name) {
+                    continue;
+                }
+                differentiator.functions.push(Function {
+                    name: name.clone(),
+                    arguments: arguments.clone(),
+                    value: value.clone(),
+                    statement: index,
+                    span,
+                });
+            }
+            StatementKind::PlotStatement {
+                functions, x_range, ..
+            } => {
+                for function in functions {
+                    expand_in_place(&mut differentiator, &mut function.value, errors);
+                }
+                expand_in_place(&mut differentiator, &mut x_range.lower, errors);
+                expand_in_place(&mut differentiator, &mut x_range.upper, errors);
+            }
+            StatementKind::ParametricPlotStatement { functions, range } => {
+                for function in functions {
+                    expand_in_place(&mut differentiator, &mut function.x, errors);
+                    expand_in_place(&mut differentiator, &mut function.y, errors);
+                }
+                expand_in_place(&mut differentiator, &mut range.lower, errors);
+                expand_in_place(&mut differentiator, &mut range.upper, errors);
+            }
+            StatementKind::PolarPlotStatement { functions, range } => {
+                for function in functions {
+                    expand_in_place(&mut differentiator, &mut function.value, errors);
+                }
+                expand_in_place(&mut differentiator, &mut range.lower, errors);
+                expand_in_place(&mut differentiator, &mut range.upper, errors);
+            }
+            StatementKind::ContourPlotStatement {
+                value,
+                x_range,
+                y_range,
+                levels,
+                ..
+            } => {
+                expand_in_place(&mut differentiator, value, errors);
+                for range in [x_range, y_range] {
+                    expand_in_place(&mut differentiator, &mut range.lower, errors);
+                    expand_in_place(&mut differentiator, &mut range.upper, errors);
+                }
+                for level in levels {
+                    expand_in_place(&mut differentiator, level, errors);
+                }
+            }
+            StatementKind::DensityPlotStatement {
+                value,
+                x_range,
+                y_range,
+                ..
+            } => {
+                expand_in_place(&mut differentiator, value, errors);
+                for range in [x_range, y_range] {
+                    expand_in_place(&mut differentiator, &mut range.lower, errors);
+                    expand_in_place(&mut differentiator, &mut range.upper, errors);
+                }
+            }
+            StatementKind::PrintStatement { argument } => {
+                expand_in_place(&mut differentiator, argument, errors)
+            }
+        }
+    }
+    // The partial derivatives of a function follow it in the order they were created, a
+    // derivative only calls the derivatives of the functions declared before
+    let mut declarations = differentiator.declarations;
+    declarations.sort_by_key(|(index, _)| *index);
+    for (index, declaration) in declarations.into_iter().rev() {
+        program.statements.insert(index + 1, declaration);
+    }
+}
@@ -0,0 +1,267 @@
+// Disassembles a wasm binary: the sections with their place in the file, what the type, import,
+// function, memory, global and export sections contain and every instruction of the code section
+// with its offset and bytes. It reads any module the decoder understands, not just ours.
+// The layout follows `wasm-objdump -x -d`.
+
+use crate::{
+    opcodes::{FUNCTION_EXPORT_KIND, GLOBAL_EXPORT_KIND, MEMORY_EXPORT_KIND},
+    wasm_decoder::{decode, ImportKind, Instruction, Limits, Module},
+    wat::{instruction_text, value_type, Names},
+};
+
+// At most this many bytes are shown for an instruction, br_table can be much longer
+const MAX_INSTRUCTION_BYTES: usize = 10;
+
+fn section_name(id: u8) -> &'static str {
+    match id {
+        0 => "Custom",
+        1 => "Type",
+        2 => "Import",
+        3 => "Function",
+        4 => "Table",
+        5 => "Memory",
+        6 => "Global",
+        7 => "Export",
+        8 => "Start",
+        9 => "Elem",
+        10 => "Code",
+        11 => "Data",
+        12 => "DataCount",
+        _ => "Unknown",
+    }
+}
+
+// The number of entries of the sections the decoder reads
+fn section_count(module: &Module, id: u8) -> Option<usize> {
+    match id {
+        1 => Some(module.types.len()),
+        2 => Some(module.imports.len()),
+        3 => Some(module.functions.len()),
+        5 => Some(module.memory.iter().count()),
+        6 => Some(module.globals.len()),
+        7 => Some(module.exports.len()),
+        10 => Some(module.code.len()),
+        _ => None,
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let mut text: Vec<String> = bytes
+        .iter()
+        .take(MAX_INSTRUCTION_BYTES)
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    if bytes.len() > MAX_INSTRUCTION_BYTES {
+        text.push("...".to_string());
+    }
+    text.join(" ")
+}
+
+fn limits_text(limits: &Limits) -> String {
+    match limits.maximum {
+        Some(maximum) => format!("initial={} max={maximum}", limits.minimum),
+        None => format!("initial={}", limits.minimum),
+    }
+}
+
+// ` <name>` if the function has one
+fn function_label(names: &Names, index: u32) -> String {
+    match names.functions.get(&index) {
+        Some(name) => format!(" <{name}>"),
+        None => String::new(),
+    }
+}
+
+// Binaries have no names unless they come with a name section, the imports and exports are enough
+fn module_names(module: &Module) -> Names {
+    let mut names = Names::default();
+    let mut function_index = 0;
+    let mut global_index = 0;
+    for import in &module.imports {
+        let name = format!("{}.{}", import.module, import.name);
+        match import.kind {
+            ImportKind::Function(_) => {
+                names.functions.insert(function_index, name);
+                function_index += 1;
+            }
+            ImportKind::Global { .. } => {
+                names.globals.insert(global_index, name);
+                global_index += 1;
+            }
+            ImportKind::Table(_) | ImportKind::Memory(_) => {}
+        }
+    }
+    for export in &module.exports {
+        match export.kind {
+            FUNCTION_EXPORT_KIND => names.functions.entry(export.index),
+            GLOBAL_EXPORT_KIND => names.globals.entry(export.index),
+            _ => continue,
+        }
+        .or_insert_with(|| export.name.clone());
+    }
+    names
+}
+
+/// The listing of `keithc disasm`
+pub(crate) fn disassemble(bytes: &[u8], file_name: &str) -> Result<String, String> {
+    let module = decode(bytes)?;
+    let names = module_names(&module);
+    let mut text = format!("{file_name}:\tfile format wasm 0x1\n\n");
+
+    text.push_str("Sections:\n\n");
+    for section in &module.sections {
+        let end = section.offset + section.size as usize;
+        text.push_str(&format!(
+            "{:>9} start={:#010x} end={:#010x} (size={:#010x})",
+            section_name(section.id),
+            section.offset,
+            end,
+            section.size
+        ));
+        if let Some(name) = &section.name {
+            text.push_str(&format!(" \"{name}\""));
+        } else if let Some(count) = section_count(&module, section.id) {
+            text.push_str(&format!(" count: {count}"));
+        }
+        text.push('\n');
+    }
+
+    text.push_str("\nSection Details:\n\n");
+    text.push_str(&format!("Type[{}]:\n", module.types.len()));
+    for (index, function_type) in module.types.iter().enumerate() {
+        let params: Vec<String> = function_type
+            .params
+            .iter()
+            .map(|t| value_type(*t).to_string())
+            .collect();
+        let results: Vec<String> = function_type
+            .results
+            .iter()
+            .map(|t| value_type(*t).to_string())
+            .collect();
+        text.push_str(&format!(
+            " - type[{index}] ({}) -> {}\n",
+            params.join(", "),
+            match results.len() {
+                0 => "nil".to_string(),
+                _ => results.join(", "),
+            }
+        ));
+    }
+
+    text.push_str(&format!("Import[{}]:\n", module.imports.len()));
+    let mut function_index = 0;
+    let mut global_index = 0;
+    for import in &module.imports {
+        let source = format!("{}.{}", import.module, import.name);
+        match &import.kind {
+            ImportKind::Function(type_index) => {
+                text.push_str(&format!(
+                    " - func[{function_index}] sig={type_index} <- {source}\n"
+                ));
+                function_index += 1;
+            }
+            ImportKind::Table(limits) => {
+                text.push_str(&format!(" - table {} <- {source}\n", limits_text(limits)))
+            }
+            ImportKind::Memory(limits) => text.push_str(&format!(
+                " - memory pages: {} <- {source}\n",
+                limits_text(limits)
+            )),
+            ImportKind::Global {
+                value_type: t,
+                mutable,
+            } => {
+                text.push_str(&format!(
+                    " - global[{global_index}] {} mutable={} <- {source}\n",
+                    value_type(*t),
+                    *mutable as u8
+                ));
+                global_index += 1;
+            }
+        }
+    }
+
+    text.push_str(&format!("Function[{}]:\n", module.functions.len()));
+    for (offset, type_index) in module.functions.iter().enumerate() {
+        let index = function_index + offset as u32;
+        text.push_str(&format!(
+            " - func[{index}] sig={type_index}{}\n",
+            function_label(&names, index)
+        ));
+    }
+
+    if let Some(limits) = &module.memory {
+        text.push_str("Memory[1]:\n");
+        text.push_str(&format!(" - memory[0] pages: {}\n", limits_text(limits)));
+    }
+
+    text.push_str(&format!("Global[{}]:\n", module.globals.len()));
+    for (offset, global) in module.globals.iter().enumerate() {
+        let init: Vec<String> = global
+            .init
+            .iter()
+            .filter(|instruction| !matches!(instruction, Instruction::End))
+            .map(|instruction| instruction_text(instruction, &names, 0))
+            .collect();
+        text.push_str(&format!(
+            " - global[{}] {} mutable={} - init {}\n",
+            global_index + offset as u32,
+            value_type(global.value_type),
+            global.mutable as u8,
+            init.join(", ")
+        ));
+    }
+
+    text.push_str(&format!("Export[{}]:\n", module.exports.len()));
+    for export in &module.exports {
+        let kind = match export.kind {
+            FUNCTION_EXPORT_KIND => "func",
+            MEMORY_EXPORT_KIND => "memory",
+            GLOBAL_EXPORT_KIND => "global",
+            _ => "table",
+        };
+        text.push_str(&format!(
+            " - {kind}[{}] -> \"{}\"\n",
+            export.index, export.name
+        ));
+    }
+
+    text.push_str("\nCode Disassembly:\n");
+    for (offset, body) in module.code.iter().enumerate() {
+        let index = function_index + offset as u32;
+        text.push_str(&format!(
+            "\n{:06x} func[{index}]{}:\n",
+            body.offset,
+            function_label(&names, index)
+        ));
+        for (count, t) in &body.locals {
+            text.push_str(&format!("           | local {count} {}\n", value_type(*t)));
+        }
+        let end = body.offset + body.size as usize;
+        let mut depth = 0usize;
+        for (position, instruction) in body.code.iter().enumerate() {
+            let start = body.offsets[position];
+            let next = body.offsets.get(position + 1).copied().unwrap_or(end);
+            if matches!(instruction, Instruction::End | Instruction::Else) {
+                depth = depth.saturating_sub(1);
+            }
+            text.push_str(&format!(
+                " {start:06x}: {:<30} | {}{}\n",
+                hex_bytes(&bytes[start..next]),
+                "  ".repeat(depth),
+                instruction_text(instruction, &names, index)
+            ));
+            if matches!(
+                instruction,
+                Instruction::Block(_)
+                    | Instruction::Loop(_)
+                    | Instruction::If(_)
+                    | Instruction::Else
+            ) {
+                depth += 1;
+            }
+        }
+    }
+    Ok(text)
+}
@@ -0,0 +1,435 @@
+// The driver is the html and JavaScript code that runs the compiled wasm module in the browser.
+// It creates one slider per slider variable (imported by the module as a mutable global),
+// one canvas per plot statement and draws the curves sampled by the exported `redraw` function.
+
+use crate::{
+    analyzer::SymbolTable,
+    emitter::{runtime_prints, CONTOUR_CELLS, PLOT_DATA_ADDRESS},
+    parser::{ExpressionKind, ExpressionNode, Options, ProgramNode, StatementKind},
+    pretty_print::pretty_print_expression,
+};
+
+const CANVAS_WIDTH: u32 = 600;
+const CANVAS_HEIGHT: u32 = 400;
+
+// Everything that does not depend on the program being compiled
+const DRIVER_SCRIPT: &str = r##"
+// The range of the values sampled, [Infinity, -Infinity] when none of them was finite
+const validRange = (minimum, maximum) => {
+    if (!(minimum <= maximum)) {
+        [minimum, maximum] = [-1, 1];
+    }
+    if (minimum === maximum) {
+        minimum -= 1;
+        maximum += 1;
+    }
+    return [minimum, maximum];
+};
+
+// Widens one of the ranges so that a unit is the same length in both axes
+const equalAspect = (canvas, [xMin, xMax], [yMin, yMax]) => {
+    const scale = Math.max(
+        (xMax - xMin) / (canvas.width - 1),
+        (yMax - yMin) / (canvas.height - 1)
+    );
+    const xMiddle = (xMin + xMax) / 2;
+    const yMiddle = (yMin + yMax) / 2;
+    const xHalf = scale * (canvas.width - 1) / 2;
+    const yHalf = scale * (canvas.height - 1) / 2;
+    return [[xMiddle - xHalf, xMiddle + xHalf], [yMiddle - yHalf, yMiddle + yHalf]];
+};
+
+// Draws the axes over the canvas and returns the functions mapping x and y to the canvas
+const drawAxisLines = (canvas, [xMin, xMax], [yMin, yMax]) => {
+    const context = canvas.getContext("2d");
+    const width = canvas.width;
+    const height = canvas.height;
+    const toX = (x) => (x - xMin) / (xMax - xMin) * (width - 1);
+    const toY = (y) => (yMax - y) / (yMax - yMin) * (height - 1);
+
+    context.strokeStyle = "#aaaaaa";
+    context.lineWidth = 1;
+    context.beginPath();
+    if (xMin <= 0 && 0 <= xMax) {
+        context.moveTo(toX(0), 0);
+        context.lineTo(toX(0), height);
+    }
+    if (yMin <= 0 && 0 <= yMax) {
+        context.moveTo(0, toY(0));
+        context.lineTo(width, toY(0));
+    }
+    context.stroke();
+    return [toX, toY];
+};
+
+// Clears the canvas, draws the axes and returns the functions mapping x and y to the canvas
+const drawAxes = (canvas, xRange, yRange) => {
+    canvas.getContext("2d").clearRect(0, 0, canvas.width, canvas.height);
+    return drawAxisLines(canvas, xRange, yRange);
+};
+
+// Connects the points (x(i), y(i)) for i < count, leaving a gap where one of them is not finite
+const drawCurve = (canvas, options, count, x, y) => {
+    const context = canvas.getContext("2d");
+    context.strokeStyle = options.color;
+    context.lineWidth = options.width;
+    context.beginPath();
+    let penDown = false;
+    for (let i = 0; i < count; i++) {
+        const [px, py] = [x(i), y(i)];
+        if (!Number.isFinite(px) || !Number.isFinite(py)) {
+            penDown = false;
+            continue;
+        }
+        if (penDown) {
+            context.lineTo(px, py);
+        } else {
+            context.moveTo(px, py);
+            penDown = true;
+        }
+    }
+    context.stroke();
+};
+
+const drawPlot = (canvas, plot, data, offset, count) => {
+    const yRange = plot.yRange ?? validRange(data[offset], data[offset + 1]);
+    offset += 2;
+    const [xMin, xMax] = plot.xRange;
+    const [toX, toY] = drawAxes(canvas, plot.xRange, yRange);
+    for (const options of plot.functions) {
+        const start = offset;
+        drawCurve(
+            canvas,
+            options,
+            count,
+            (i) => toX(xMin + (xMax - xMin) * i / (count - 1)),
+            (i) => toY(data[start + i])
+        );
+        offset += count;
+    }
+    return offset;
+};
+
+const drawParametricPlot = (canvas, plot, data, offset, count) => {
+    let xRange = validRange(data[offset], data[offset + 1]);
+    let yRange = validRange(data[offset + 2], data[offset + 3]);
+    offset += 4;
+    if (plot.equalAspect) {
+        [xRange, yRange] = equalAspect(canvas, xRange, yRange);
+    }
+    const [toX, toY] = drawAxes(canvas, xRange, yRange);
+    for (const options of plot.functions) {
+        const start = offset;
+        drawCurve(
+            canvas,
+            options,
+            count,
+            (i) => toX(data[start + 2 * i]),
+            (i) => toY(data[start + 2 * i + 1])
+        );
+        offset += 2 * count;
+    }
+    return offset;
+};
+
+const drawContourPlot = (canvas, plot, data, offset) => {
+    const count = data[offset];
+    // the segments follow the values at the points of the grid
+    offset += 1 + (contourCells + 1) * (contourCells + 1);
+    const [xRange, yRange] = equalAspect(canvas, plot.xRange, plot.yRange);
+    const [toX, toY] = drawAxes(canvas, xRange, yRange);
+    const context = canvas.getContext("2d");
+    context.strokeStyle = plot.options.color;
+    context.lineWidth = plot.options.width;
+    context.beginPath();
+    for (let i = 0; i < count; i++) {
+        const [x1, y1, x2, y2] = data.subarray(offset + 4 * i, offset + 4 * i + 4);
+        context.moveTo(toX(x1), toY(y1));
+        context.lineTo(toX(x2), toY(y2));
+    }
+    context.stroke();
+    return offset + 4 * count;
+};
+
+// The pixels follow the header and the values, one per pixel. Each pixel is 4 bytes and
+// the next plot starts at a multiple of 8 bytes.
+const drawDensityPlot = (canvas, plot, data, offset) => {
+    const pixelCount = canvas.width * canvas.height;
+    offset += 2 + pixelCount;
+    const pixels = new Uint8ClampedArray(data.buffer, data.byteOffset + 8 * offset, 4 * pixelCount);
+    const image = new ImageData(pixels, canvas.width, canvas.height);
+    canvas.getContext("2d").putImageData(image, 0, 0);
+    drawAxisLines(canvas, plot.xRange, plot.yRange);
+    return offset + Math.ceil(pixelCount / 2);
+};
+
+const createSliders = (globals, redraw) => {
+    const container = document.getElementById("sliders");
+    for (const slider of sliders) {
+        const div = document.createElement("div");
+        const label = document.createElement("label");
+        const input = document.createElement("input");
+        const value = document.createElement("span");
+        label.textContent = `${slider.name} `;
+        input.type = "range";
+        input.min = slider.minimum;
+        input.max = slider.maximum;
+        input.step = (slider.maximum - slider.minimum) / 100;
+        input.value = slider.default;
+        value.textContent = ` ${slider.default}`;
+        input.addEventListener("input", () => {
+            globals[slider.name].value = Number(input.value);
+            value.textContent = ` ${input.value}`;
+            redraw();
+        });
+        div.appendChild(label);
+        div.appendChild(input);
+        div.appendChild(value);
+        container.appendChild(div);
+    }
+};
+
+const createCanvases = () => {
+    const container = document.getElementById("plots");
+    return plots.map(() => {
+        const canvas = document.createElement("canvas");
+        canvas.width = canvasWidth;
+        canvas.height = canvasHeight;
+        container.appendChild(canvas);
+        return canvas;
+    });
+};
+
+window.addEventListener("load", async () => {
+    const globals = {};
+    for (const slider of sliders) {
+        globals[slider.name] = new WebAssembly.Global({ value: "f64", mutable: true }, slider.default);
+    }
+    const print = (index, value) => {
+        console.log(`${prints[index]} = ${value}`);
+        return value;
+    };
+    const importObject = { Math: mathImports, globals, console: { print } };
+    const { instance } = await WebAssembly.instantiateStreaming(fetch(wasmFile), importObject);
+    const canvases = createCanvases();
+    const redraw = () => {
+        const count = instance.exports.redraw(canvasWidth, canvasHeight);
+        const data = new Float64Array(instance.exports.memory.buffer, plotDataAddress);
+        let offset = 0;
+        plots.forEach((plot, index) => {
+            let draw = drawPlot;
+            if (plot.contour) {
+                draw = drawContourPlot;
+            } else if (plot.density) {
+                draw = drawDensityPlot;
+            } else if (plot.parametric) {
+                draw = drawParametricPlot;
+            }
+            offset = draw(canvases[index], plot, data, offset, count);
+        });
+    };
+    createSliders(globals, redraw);
+    redraw();
+});
+"##;
+
+fn js_number(f: f64) -> String {
+    if f.is_nan() {
+        "NaN".to_string()
+    } else if f.is_infinite() {
+        if f > 0.0 {
+            "Infinity".to_string()
+        } else {
+            "-Infinity".to_string()
+        }
+    } else {
+        format!("{f}")
+    }
+}
+
+fn js_string(s: &str) -> String {
+    let mut result = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '<' => result.push_str("\\u003c"),
+            '\n' => result.push_str("\\n"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn as_number(node: &ExpressionNode) -> Result<f64, String> {
+    if let ExpressionKind::Number(f) = node.kind {
+        Ok(f)
+    } else {
+        Err("Expected number at this point".to_string())
+    }
+}
+
+fn emit_sliders(symbol_table: &SymbolTable) -> String {
+    let sliders: Vec<String> = symbol_table
+        .sliders
+        .iter()
+        .map(|slider| {
+            format!(
+                "{{ name: {}, minimum: {}, maximum: {}, default: {} }}",
+                js_string(&slider.name),
+                js_number(slider.minimum),
+                js_number(slider.maximum),
+                js_number(slider.default)
+            )
+        })
+        .collect();
+    format!("const sliders = [{}];\n", sliders.join(", "))
+}
+
+fn emit_options(options: &Options) -> String {
+    format!(
+        "{{ color: {}, width: {} }}",
+        js_string(&options.color),
+        options.width
+    )
+}
+
+fn emit_plots(node: &ProgramNode) -> Result<String, String> {
+    let mut plots = Vec::new();
+    for statement in &node.statements {
+        if let StatementKind::ParametricPlotStatement { functions, .. } = &statement.kind {
+            let functions: Vec<String> = functions
+                .iter()
+                .map(|function| emit_options(&function.options))
+                .collect();
+            plots.push(format!(
+                "{{ parametric: true, equalAspect: false, functions: [{}] }}",
+                functions.join(", ")
+            ));
+        } else if let StatementKind::PolarPlotStatement { functions, .. } = &statement.kind {
+            // the points are written like the ones of a parametric plot
+            let functions: Vec<String> = functions
+                .iter()
+                .map(|function| emit_options(&function.options))
+                .collect();
+            plots.push(format!(
+                "{{ parametric: true, equalAspect: true, functions: [{}] }}",
+                functions.join(", ")
+            ));
+        } else if let StatementKind::ContourPlotStatement {
+            x_range,
+            y_range,
+            options,
+            ..
+        } = &statement.kind
+        {
+            plots.push(format!(
+                "{{ contour: true, xRange: [{}, {}], yRange: [{}, {}], options: {} }}",
+                js_number(as_number(&x_range.lower)?),
+                js_number(as_number(&x_range.upper)?),
+                js_number(as_number(&y_range.lower)?),
+                js_number(as_number(&y_range.upper)?),
+                emit_options(options)
+            ));
+        } else if let StatementKind::DensityPlotStatement {
+            x_range, y_range, ..
+        } = &statement.kind
+        {
+            plots.push(format!(
+                "{{ density: true, xRange: [{}, {}], yRange: [{}, {}] }}",
+                js_number(as_number(&x_range.lower)?),
+                js_number(as_number(&x_range.upper)?),
+                js_number(as_number(&y_range.lower)?),
+                js_number(as_number(&y_range.upper)?)
+            ));
+        } else if let StatementKind::PlotStatement {
+            functions,
+            x_range,
+            y_range,
+        } = &statement.kind
+        {
+            let x_range = format!(
+                "[{}, {}]",
+                js_number(as_number(&x_range.lower)?),
+                js_number(as_number(&x_range.upper)?)
+            );
+            let y_range = match y_range {
+                Some(range) => format!(
+                    "[{}, {}]",
+                    js_number(as_number(&range.minimum)?),
+                    js_number(as_number(&range.maximum)?)
+                ),
+                None => "null".to_string(),
+            };
+            let functions: Vec<String> = functions
+                .iter()
+                .map(|function| emit_options(&function.options))
+                .collect();
+            plots.push(format!(
+                "{{ xRange: {x_range}, yRange: {y_range}, functions: [{}] }}",
+                functions.join(", ")
+            ));
+        }
+    }
+    Ok(format!("const plots = [{}];\n", plots.join(", ")))
+}
+
+// The text of the Print statements printed by the module
+fn emit_prints(node: &ProgramNode) -> String {
+    let prints: Vec<String> = runtime_prints(node)
+        .iter()
+        .map(|value| js_string(&pretty_print_expression(value)))
+        .collect();
+    format!("const prints = [{}];\n", prints.join(", "))
+}
+
+fn emit_math_imports(symbol_table: &SymbolTable) -> String {
+    // Keith builtins are named like the JavaScript Math functions but capitalized
+    let imports: Vec<String> = symbol_table
+        .builtins
+        .iter()
+        .map(|builtin| format!("{}: Math.{}", builtin.name(), builtin.name().to_lowercase()))
+        .collect();
+    format!("const mathImports = {{ {} }};\n", imports.join(", "))
+}
+
+/// Returns the html page that loads `wasm_file_name` and draws the plots of the program
+pub(crate) fn emit_driver(
+    node: &ProgramNode,
+    symbol_table: &SymbolTable,
+    wasm_file_name: &str,
+) -> Result<String, String> {
+    let mut script = String::new();
+    script.push_str(&format!(
+        "const wasmFile = {};\n",
+        js_string(wasm_file_name)
+    ));
+    script.push_str(&format!("const plotDataAddress = {PLOT_DATA_ADDRESS};\n"));
+    script.push_str(&format!("const contourCells = {CONTOUR_CELLS};\n"));
+    script.push_str(&format!("const canvasWidth = {CANVAS_WIDTH};\n"));
+    script.push_str(&format!("const canvasHeight = {CANVAS_HEIGHT};\n"));
+    script.push_str(&emit_math_imports(symbol_table));
+    script.push_str(&emit_sliders(symbol_table));
+    script.push_str(&emit_plots(node)?);
+    script.push_str(&emit_prints(node));
+    script.push_str(DRIVER_SCRIPT);
+
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>An advanced function plotter</title>
+    <script>
+{script}    </script>
+</head>
+<body>
+    <div id="sliders"></div>
+    <div id="plots"></div>
+</body>
+</html>
+"#
+    ))
+}
@@ -1,16 +1,38 @@
-use std::collections::HashMap;
-
 use crate::{
-    analyzer::SymbolTable,
-    parser::{Comparator, ExpressionNode, Operator, ProgramNode, StatementNode, UnaryOperator},
+    builtins::Builtin,
+    colormap::ColorMap,
+    errors::{InternalCompilerError, Result},
+    ir::{BinaryOperator, Expression, Import, Plot, PlotKind, Program},
+    numeric::{self, Condition, Statement},
+    parser::{Comparator, ExpressionKind, ExpressionNode, ProgramNode, StatementKind},
+    validator::validate,
 };
 
 use crate::opcodes::*;
 
-struct Stack {
-    pointer: u32,
-    variables: HashMap<String, u32>,
-}
+// Memory layout of the values written by `redraw(width, height)`, which returns the number `n`
+// of samples taken for each function.
+// Starting at PLOT_DATA_ADDRESS, for each Plot statement, in order:
+//   * minimum and maximum of the finite values sampled (two f64)
+//   * for each function in the plot, the n values f(x_0), ..., f(x_{n-1}) (n f64)
+// The samples x_i are evenly spaced and x_0 and x_{n-1} are the ends of the x range.
+// For a ParametricPlot statement, sampled at t_0, ..., t_{n-1} in the same way:
+//   * minimum and maximum of x and then of y, of the points where both are finite (four f64)
+//   * for each curve, the n points x(t_0), y(t_0), ..., x(t_{n-1}), y(t_{n-1}) (2n f64)
+// A PolarPlot statement is written like a ParametricPlot of the curves (r(θ)*cos θ, r(θ)*sin θ).
+// For a ContourPlot statement, with a grid of CONTOUR_CELLS by CONTOUR_CELLS cells on its ranges:
+//   * the number m of segments of all the levels (one f64)
+//   * the values F(x_i, y_j) at the points of the grid, the row j = 0 first ((CONTOUR_CELLS + 1)²
+//     f64)
+//   * the segments x1, y1, x2, y2 where F is equal to a level, found with marching squares
+//     (4m f64)
+// For a DensityPlot statement, sampled at one point per pixel of n columns and r = max(2, height)
+// rows, x_i as in a Plot and y_0 the upper end of the y range:
+//   * minimum and maximum of the finite values sampled (two f64)
+//   * the values F(x_i, y_j), the row j = 0 first (n*r f64)
+//   * the colors of the pixels in the same order, the bytes red, green, blue and alpha, all 0
+//     when the value is not finite (4*n*r bytes, followed by 4 bytes of padding if n*r is odd)
+pub(crate) const PLOT_DATA_ADDRESS: u32 = 0;
 
 // fn encode_leb128(value: u32) -> Vec<u8> {
 //     fn encode(i: u32, r: &[u8]) -> Vec<u8> {
@@ -46,6 +68,23 @@ fn encode_leb128(mut value: u32) -> Vec<u8> {
     }
 }
 
+// Signed integers (like the argument of i32.const) use the signed variant of leb128
+// https://en.wikipedia.org/wiki/LEB128#Signed_LEB128
+pub(crate) fn encode_sleb128(mut value: i32) -> Vec<u8> {
+    let mut result = vec![];
+    loop {
+        let byte = value as u8 & 0b0111_1111;
+        // arithmetic shift, keeps the sign
+        value >>= 7;
+        let sign_bit_clear = byte & 0b0100_0000 == 0;
+        if (value == 0 && sign_bit_clear) || (value == -1 && !sign_bit_clear) {
+            result.push(byte);
+            return result;
+        }
+        result.push(byte | 0b1000_0000);
+    }
+}
+
 #[inline(always)]
 fn encode_f64(f: f64) -> Vec<u8> {
     f.to_le_bytes().to_vec()
@@ -61,31 +100,30 @@ fn encode_str(str: &str) -> Vec<u8> {
     result
 }
 
-fn emit_type_section(
-    symbol_table: &SymbolTable,
-    signatures: &mut Vec<u8>,
-) -> Result<Vec<u8>, String> {
-    let functions = &symbol_table.functions;
+fn emit_type_section(program: &Program, signatures: &mut Vec<u8>) -> Result<Vec<u8>> {
     // In Keith all functions return a single f64, the only difference is how many f64 they consume.
-    // At least one function (main) has signature () => f64
-    signatures.push(0);
-    for function in functions {
-        let arg_count = function.arg_count;
+    // At least one function (redraw) has signature (f64, f64) => f64
+    signatures.push(2);
+    // the user defined and the plot functions
+    for function in &program.functions {
+        let arg_count = function.argument_count as u8;
         if !signatures.contains(&arg_count) {
             signatures.push(arg_count);
         }
     }
-    for function in &symbol_table.builtins {
-        let arg_count = function.arg_count();
+    // the builtins, and print(index, value) has the signature of redraw
+    for import in &program.imports {
+        let arg_count = import_arg_count(import);
         if !signatures.contains(&arg_count) {
             signatures.push(arg_count);
         }
     }
     signatures.sort();
-    let mut signature_vec: Vec<u8> = vec![];
+    let mut signature_vec = encode_leb128(signatures.len() as u32);
     for arg_count in signatures {
         // It's a function (0x60) and has `arg_count` arguments
-        let mut signature_vec = vec![FUNCTION_TYPE_MARKER, *arg_count];
+        signature_vec.push(FUNCTION_TYPE_MARKER);
+        signature_vec.push(*arg_count);
         // all the arguments are f64
         signature_vec.append(&mut vec![F64_TYPE; *arg_count as usize]);
         // return type
@@ -97,100 +135,85 @@ fn emit_type_section(
     Ok(result)
 }
 
-fn emit_imports_section(
-    root: &ProgramNode,
-    symbol_table: &SymbolTable,
-    signatures: &[u8],
-    constants: &mut Vec<String>,
-) -> Result<Vec<u8>, String> {
+fn import_arg_count(import: &Import) -> u8 {
+    match import {
+        Import::Builtin(builtin) => builtin.arg_count(),
+        Import::Print => 2,
+    }
+}
+
+fn emit_imports_section(program: &Program, signatures: &[u8]) -> Result<Vec<u8>> {
     let math = encode_str("Math");
     let globals = encode_str("globals");
     let mut imports = Vec::new();
     let mut length = 0;
-    // first we import the functions
-    for builtin in &symbol_table.builtins {
-        imports.append(&mut math.clone());
-        imports.append(&mut encode_str(builtin.name()));
+    // first we import the functions, the builtins from Math and
+    // console.print(index, value) if there are Print statements that depend on the sliders
+    for import in &program.imports {
+        match import {
+            Import::Builtin(builtin) => {
+                imports.append(&mut math.clone());
+                imports.append(&mut encode_str(builtin.name()));
+            }
+            Import::Print => {
+                imports.append(&mut encode_str("console"));
+                imports.append(&mut encode_str("print"));
+            }
+        }
         // the import descriptor (it's a function)
         imports.push(FUNCTION_DESCRIPTOR);
         let function_type = signatures
             .iter()
-            .position(|&s| s == builtin.arg_count())
+            .position(|&s| s == import_arg_count(import))
             .expect("");
         imports.push(function_type as u8);
         length += 1;
     }
     // then we import the mutable globals (sliders)
-    for statement in &root.statements {
-        if let StatementNode::Slider { name, .. } = statement {
-            imports.append(&mut globals.clone());
-            imports.append(&mut encode_str(name));
-            // the import descriptor (it's a constant)
-            imports.push(CONSTANT_DESCRIPTOR);
-            // It's an f64
-            imports.push(F64_TYPE);
-            // The 'constant' is mutable
-            imports.push(CONSTANT_MUTABLE);
-            constants.push(name.clone());
-            length += 1;
-        }
+    for name in &program.sliders {
+        imports.append(&mut globals.clone());
+        imports.append(&mut encode_str(name));
+        // the import descriptor (it's a constant)
+        imports.push(CONSTANT_DESCRIPTOR);
+        // It's an f64
+        imports.push(F64_TYPE);
+        // The 'constant' is mutable
+        imports.push(CONSTANT_MUTABLE);
+        length += 1;
     }
+    let mut bytes = encode_leb128(length);
+    bytes.append(&mut imports);
     let mut result = vec![SECTION_IMPORTS];
-    result.append(&mut encode_leb128(imports.len() as u32));
-    result.append(&mut encode_leb128(length));
-    result.append(&mut imports);
+    result.append(&mut encode_leb128(bytes.len() as u32));
+    result.append(&mut bytes);
     Ok(result)
 }
 
-fn emit_function_section(root: &ProgramNode, signatures: &[u8]) -> Result<Vec<u8>, String> {
+fn emit_function_section(program: &Program, signatures: &[u8]) -> Result<Vec<u8>> {
+    let type_index = |arg_count: u8| signatures.iter().position(|&s| s == arg_count).expect("");
     let mut function_count = 0;
-    let mut bytes = Vec::new();
-    for statement in &root.statements {
-        if let StatementNode::FunctionDeclaration { arguments, .. } = statement {
-            function_count += 1;
-            let arg_count = arguments.len() as u8;
-            let function_type_index = signatures.iter().position(|&s| s == arg_count).expect("");
-            bytes.push(function_type_index as u8);
-        }
+    let mut types = Vec::new();
+    // the user defined functions and then the plot functions
+    for function in &program.functions {
+        function_count += 1;
+        types.push(type_index(function.argument_count as u8) as u8);
     }
+    // redraw(width, height)
+    function_count += 1;
+    types.push(type_index(2) as u8);
+
+    let mut bytes = encode_leb128(function_count);
+    bytes.append(&mut types);
     let mut result = vec![SECTION_FUNCTION];
     let bytes_count = bytes.len() as u32;
     result.append(&mut encode_leb128(bytes_count));
-    result.append(&mut encode_leb128(function_count));
     result.append(&mut bytes);
     Ok(result)
 }
 
-fn emit_global_section(node: &ProgramNode) -> Result<Vec<u8>, String> {
-    let mut bytes = Vec::new();
-    let heap_base = 8 * 1024 * 1024;
-
-    // Two globals
-    bytes.push(0x02);
-
-    // heap base (index 0, immutable)
-    bytes.push(I32_TYPE);
-    bytes.push(CONSTANT_IMMUTABLE);
-    bytes.push(INSTR_I32_CONST);
-    bytes.append(&mut encode_leb128(heap_base));
-    bytes.push(EXPRESSION_END);
-
-    // stack pointer (index 1, mutable)
-    bytes.push(I32_TYPE);
-    bytes.push(CONSTANT_MUTABLE);
-    bytes.push(INSTR_I32_CONST);
-    bytes.append(&mut encode_leb128(heap_base));
-    bytes.push(EXPRESSION_END);
-
-    let mut result = vec![SECTION_GLOBAL];
-    result.append(&mut encode_leb128(bytes.len() as u32));
-    result.append(&mut bytes);
-
-    Ok(result)
-}
-
-fn emit_memory_section(_node: &ProgramNode) -> Result<Vec<u8>, String> {
-    let mut bytes = vec![0x01, LIMITS_FLAG_NO_MAX, 0x01]; // one memory, one initial page
+fn emit_memory_section(_program: &Program) -> Result<Vec<u8>> {
+    // one memory, one initial page, redraw grows it to fit the samples
+    let mut bytes = vec![0x01, LIMITS_FLAG_NO_MAX, 0x01];
     let mut result = vec![SECTION_MEMORY];
     let bytes_count = bytes.len() as u32;
     result.append(&mut encode_leb128(bytes_count));
@@ -198,7 +221,7 @@ fn emit_memory_section(_node: &ProgramNode) -> Result<Vec<u8>, String> {
     Ok(result)
 }
 
-fn emit_export_section(_node: &ProgramNode) -> Result<Vec<u8>, String> {
+fn emit_export_section(program: &Program) -> Result<Vec<u8>> {
     // The export section consists of a single function draw(width, height) and the memory
     let mut bytes = vec![0x02]; // two exports
     bytes.append(&mut encode_str("memory"));
@@ -207,8 +230,7 @@ fn emit_export_section(_node: &ProgramNode) -> Result<Vec<u8>, String> {
 
     bytes.append(&mut encode_str("redraw"));
     bytes.push(FUNCTION_EXPORT_KIND);
-    // TODO: redraw function index
-    bytes.push(0x00);
+    bytes.append(&mut encode_leb128(program.redraw_index()));
 
     let mut result = vec![SECTION_EXPORT];
     let bytes_count = bytes.len() as u32;
@@ -217,481 +239,1295 @@ fn emit_export_section(_node: &ProgramNode) -> Result<Vec<u8>, String> {
     Ok(result)
 }
 
-fn emit_code_for_expression(
-    node: &ExpressionNode,
-    symbol_table: &SymbolTable,
-    arguments: &[String],
-    functions: &[String],
-    locals: &Stack,
-) -> Result<Vec<u8>, String> {
-    let mut result = Vec::new();
-    match node {
-        ExpressionNode::Number(f) => {
+fn comparator_opcode(comparator: &Comparator) -> u8 {
+    match comparator {
+        Comparator::Equal => INSTR_F64_EQ,
+        Comparator::NotEqual => INSTR_F64_NE,
+        Comparator::LessThan => INSTR_F64_LT,
+        Comparator::GreaterThan => INSTR_F64_GT,
+        Comparator::LessThanOrEqual => INSTR_F64_LE,
+        Comparator::GreaterThanOrEqual => INSTR_F64_GE,
+    }
+}
+
+fn emit_routine_value(value: &numeric::Value, variable: u32, result: &mut Vec<u8>) {
+    match value {
+        numeric::Value::Number(f) => {
             result.push(INSTR_F64_CONST);
             result.append(&mut encode_f64(*f));
         }
-        ExpressionNode::Variable(name) => {
-            if let Some(index) = arguments.iter().position(|s| s == name) {
-                // It's an arguments
-                result.push(INSTR_LOCAL_GET);
-                result.push(index as u8);
-            } else if let Some(index) = symbol_table.globals.iter().position(|s| &s.name == name) {
-                // It's a global, we look up it's value
-                let f = symbol_table.globals[index].value;
-                result.push(INSTR_F64_CONST);
-                result.append(&mut encode_f64(f));
-            } else if let Some(index) = symbol_table.sliders.iter().position(|s| &s.name == name) {
-                // It's a slider
-                result.push(INSTR_GLOBAL_GET);
-                result.push(index as u8);
-            } else {
-                return Err(format!("Unrecognized variable name '{name}'"));
-            };
+        numeric::Value::Register(register) => {
+            result.push(INSTR_LOCAL_GET);
+            result.append(&mut encode_leb128(variable + register));
+        }
+        numeric::Value::Abs(value) => {
+            // max(value, 0 - value)
+            emit_routine_value(value, variable, result);
+            result.push(INSTR_F64_CONST);
+            result.append(&mut encode_f64(0.0));
+            emit_routine_value(value, variable, result);
+            result.push(INSTR_F64_SUB);
+            result.push(INSTR_F64_MAX);
+        }
+        numeric::Value::Ceil(value) => {
+            emit_routine_value(value, variable, result);
+            result.push(INSTR_F64_CEIL);
         }
-        ExpressionNode::BinaryOp { op, left, right } => {
-            let mut lhs =
-                emit_code_for_expression(left, symbol_table, arguments, functions, locals)?;
-            let mut rhs =
-                emit_code_for_expression(right, symbol_table, arguments, functions, locals)?;
-            result.append(&mut lhs);
-            result.append(&mut rhs);
+        numeric::Value::Binary(op, left, right) => {
+            emit_routine_value(left, variable, result);
+            emit_routine_value(right, variable, result);
             match op {
-                Operator::Plus => result.push(INSTR_F64_ADD),
-                Operator::Minus => result.push(INSTR_F64_SUB),
-                Operator::Times => result.push(INSTR_F64_MUL),
-                Operator::Divide => result.push(INSTR_F64_DIV),
-                Operator::Power => {
-                    result.push(INSTR_FUNCTION_CALL);
-                    if let Some(function_index) = functions.iter().position(|s| s == "Pow") {
-                        result.push(function_index as u8);
-                    } else {
-                        return Err("Error".to_string());
-                    }
-                }
+                BinaryOperator::Add => result.push(INSTR_F64_ADD),
+                BinaryOperator::Subtract => result.push(INSTR_F64_SUB),
+                BinaryOperator::Multiply => result.push(INSTR_F64_MUL),
+                BinaryOperator::Divide => result.push(INSTR_F64_DIV),
             };
         }
-        ExpressionNode::UnaryOp { op, right } => {
-            match op {
-                UnaryOperator::Plus => result.append(&mut emit_code_for_expression(
-                    right,
-                    symbol_table,
-                    arguments,
-                    functions,
-                    locals,
-                )?),
-                UnaryOperator::Minus => {
-                    result.append(&mut emit_code_for_expression(
-                        right,
-                        symbol_table,
-                        arguments,
-                        functions,
-                        locals,
-                    )?);
-                    result.push(INSTR_F64_CONST);
-                    let minus_one: f64 = -1.0;
-                    result.append(&mut minus_one.to_le_bytes().to_vec());
-                    result.push(INSTR_F64_MUL);
+    }
+}
+
+// Leaves an i32 on the stack, 0 when the condition does not hold
+fn emit_routine_condition(condition: &Condition, variable: u32, result: &mut Vec<u8>) {
+    match condition {
+        Condition::Compare(comparator, left, right) => {
+            emit_routine_value(left, variable, result);
+            emit_routine_value(right, variable, result);
+            result.push(comparator_opcode(comparator));
+        }
+        // both are 0 or 1, the product is the and and the sum is the or
+        Condition::And(left, right) => {
+            emit_routine_condition(left, variable, result);
+            emit_routine_condition(right, variable, result);
+            result.push(INSTR_I32_MUL);
+        }
+        Condition::Or(left, right) => {
+            emit_routine_condition(left, variable, result);
+            emit_routine_condition(right, variable, result);
+            result.push(INSTR_I32_ADD);
+        }
+    }
+}
+
+// The code of the statements of a routine whose register 0 is the local `variable`, `value` is the
+// code of the expression and a Break is a `br` of `break_depth`
+fn emit_routine_statements(
+    statements: &[Statement],
+    variable: u32,
+    value: &[u8],
+    break_depth: u32,
+    result: &mut Vec<u8>,
+) {
+    for statement in statements {
+        match statement {
+            Statement::Set(register, register_value) => {
+                emit_routine_value(register_value, variable, result);
+                result.push(INSTR_LOCAL_SET);
+                result.append(&mut encode_leb128(variable + register));
+            }
+            Statement::Evaluate(register) => {
+                result.extend_from_slice(value);
+                result.push(INSTR_LOCAL_SET);
+                result.append(&mut encode_leb128(variable + register));
+            }
+            Statement::If(condition, if_true, if_false) => {
+                emit_routine_condition(condition, variable, result);
+                result.push(INSTR_BLOCK_IF);
+                result.push(INSTR_VOID);
+                emit_routine_statements(if_true, variable, value, break_depth + 1, result);
+                if !if_false.is_empty() {
+                    result.push(INSTR_BLOCK_ELSE);
+                    emit_routine_statements(if_false, variable, value, break_depth + 1, result);
                 }
+                result.push(EXPRESSION_END);
+            }
+            Statement::While(condition, body) => {
+                // block loop if condition body; continue end end end
+                result.push(INSTR_BLOCK);
+                result.push(INSTR_VOID);
+                result.push(INSTR_BLOCK_LOOP);
+                result.push(INSTR_VOID);
+                emit_routine_condition(condition, variable, result);
+                result.push(INSTR_BLOCK_IF);
+                result.push(INSTR_VOID);
+                emit_routine_statements(body, variable, value, 2, result);
+                result.push(INSTR_BR);
+                result.push(0x01); // break depth
+                result.push(EXPRESSION_END);
+                result.push(EXPRESSION_END);
+                result.push(EXPRESSION_END);
+            }
+            Statement::Break => {
+                result.push(INSTR_BR);
+                result.append(&mut encode_leb128(break_depth));
+            }
+        }
+    }
+}
+
+// `local_base` is the index of the first local of the expression in the wasm function
+fn emit_code_for_expression(node: &Expression, local_base: u32) -> Result<Vec<u8>> {
+    let mut result = Vec::new();
+    match node {
+        Expression::Number(f) => {
+            result.push(INSTR_F64_CONST);
+            result.append(&mut encode_f64(*f));
+        }
+        Expression::Local(index) => {
+            // An argument or the variable of a Sum
+            result.push(INSTR_LOCAL_GET);
+            result.append(&mut encode_leb128(local_base + index));
+        }
+        Expression::Global(index) => {
+            // It's a slider
+            result.push(INSTR_GLOBAL_GET);
+            result.append(&mut encode_leb128(*index));
+        }
+        Expression::Binary { op, left, right } => {
+            result.append(&mut emit_code_for_expression(left, local_base)?);
+            result.append(&mut emit_code_for_expression(right, local_base)?);
+            match op {
+                BinaryOperator::Add => result.push(INSTR_F64_ADD),
+                BinaryOperator::Subtract => result.push(INSTR_F64_SUB),
+                BinaryOperator::Multiply => result.push(INSTR_F64_MUL),
+                BinaryOperator::Divide => result.push(INSTR_F64_DIV),
             };
         }
-        ExpressionNode::FunctionCall { name, args } => {
-            for arg in args {
-                result.append(&mut emit_code_for_expression(
-                    arg,
-                    symbol_table,
-                    arguments,
-                    functions,
-                    locals,
-                )?);
+        Expression::Negate(right) => {
+            result.append(&mut emit_code_for_expression(right, local_base)?);
+            result.push(INSTR_F64_CONST);
+            let minus_one: f64 = -1.0;
+            result.append(&mut minus_one.to_le_bytes().to_vec());
+            result.push(INSTR_F64_MUL);
+        }
+        Expression::Call {
+            function,
+            arguments,
+        } => {
+            for argument in arguments {
+                result.append(&mut emit_code_for_expression(argument, local_base)?);
             }
             result.push(INSTR_FUNCTION_CALL);
-            if let Some(function_index) = functions.iter().position(|s| s == name) {
-                result.push(function_index as u8);
-            } else {
-                return Err("Error".to_string());
-            }
+            result.append(&mut encode_leb128(*function));
         }
-        ExpressionNode::IfExpression {
-            condition,
+        Expression::If {
+            comparator,
+            left,
+            right,
             if_true,
             if_false,
         } => {
-            result.append(&mut emit_code_for_expression(
-                &condition.left,
-                symbol_table,
-                arguments,
-                functions,
-                locals,
-            )?);
-            result.append(&mut emit_code_for_expression(
-                &condition.right,
-                symbol_table,
-                arguments,
-                functions,
-                locals,
-            )?);
-            let op = match condition.op {
-                Comparator::Equal => INSTR_F64_EQ,
-                Comparator::NotEqual => INSTR_F64_NE,
-                Comparator::LessThan => INSTR_F64_LT,
-                Comparator::GreaterThan => INSTR_F64_GT,
-                Comparator::LessThanOrEqual => INSTR_F64_LE,
-                Comparator::GreaterThanOrEqual => INSTR_F64_GE,
-            };
-            result.push(op);
+            result.append(&mut emit_code_for_expression(left, local_base)?);
+            result.append(&mut emit_code_for_expression(right, local_base)?);
+            result.push(comparator_opcode(comparator));
             result.push(INSTR_BLOCK_IF);
             // In Keith If always return a double
             result.push(F64_TYPE);
-            result.append(&mut emit_code_for_expression(
-                if_true,
-                symbol_table,
-                arguments,
-                functions,
-                locals,
-            )?);
+            result.append(&mut emit_code_for_expression(if_true, local_base)?);
             result.push(INSTR_BLOCK_ELSE);
-            result.append(&mut emit_code_for_expression(
-                if_false,
-                symbol_table,
-                arguments,
-                functions,
-                locals,
-            )?);
+            result.append(&mut emit_code_for_expression(if_false, local_base)?);
             result.push(EXPRESSION_END);
         }
-        ExpressionNode::SumExpression { value, range } => {
-            let lower = if let ExpressionNode::Number(x) = *range.lower {
-                x
-            } else {
-                return Err("Expecting number at this point".to_string());
-            };
-            let upper = if let ExpressionNode::Number(x) = *range.upper {
-                x
-            } else {
-                return Err("Expecting number at this point".to_string());
-            };
+        Expression::Sum {
+            variable,
+            total,
+            limit,
+            lower,
+            upper,
+            value,
+        } => {
+            let variable = local_base + variable;
+            let total = local_base + total;
+            let limit = local_base + limit;
 
-            // We define two local variables:
-            // * 'i' a counter of type i32 (4 bytes)
-            // * 'result' the total sum, an f64 (8 bytes)
-            // So we make space in the stack for those two variables
-            let stack_pointer = locals.pointer - 12;
-            let mut variables = locals.variables.clone();
-
-            // let i = 0
-            result.push(INSTR_I32_CONST);
-            result.append(&mut encode_leb128(stack_pointer)); // address
-                                                              // value
-            result.push(INSTR_I32_CONST);
-            result.push(0x00);
-            result.push(MEMORY_I32_STORE);
-            result.push(0x02); // alignment
-            result.push(0x00); // offset
-            variables.insert(range.variable_name.clone(), stack_pointer);
-
-            // let result = lower
-            result.push(INSTR_I32_CONST);
-            result.append(&mut encode_leb128(stack_pointer));
-            // value
+            // The variable is an f64 and not an i32 counter because the bounds need not be integers
+            // variable = lower; limit = upper; total = 0
+            result.append(&mut emit_code_for_expression(lower, local_base)?);
+            result.push(INSTR_LOCAL_SET);
+            result.append(&mut encode_leb128(variable));
+            result.append(&mut emit_code_for_expression(upper, local_base)?);
+            result.push(INSTR_LOCAL_SET);
+            result.append(&mut encode_leb128(limit));
             result.push(INSTR_F64_CONST);
-            result.append(&mut lower.to_le_bytes().to_vec());
-            result.push(MEMORY_F64_STORE);
-            result.push(0x03); // alignment
-            result.push(0x04); // offset
-            variables.insert(format!("_sum_{}", range.variable_name), stack_pointer);
+            result.append(&mut encode_f64(0.0));
+            result.push(INSTR_LOCAL_SET);
+            result.append(&mut encode_leb128(total));
 
             result.push(INSTR_BLOCK_LOOP);
             result.push(INSTR_VOID);
 
-            // i < range.max
-            result.append(&mut encode_leb128(stack_pointer));
-            result.push(MEMORY_I32_LOAD);
-            result.push(0x02);
-            result.push(0x00);
-            // cast i into f64
-            result.push(INSTR_F64_CONVERT_I32_S);
-
-            result.push(INSTR_F64_CONST);
-            result.append(&mut upper.to_le_bytes().to_vec());
-            result.push(INSTR_F64_LT);
+            // variable <= limit (false if limit is NaN)
+            result.push(INSTR_LOCAL_GET);
+            result.append(&mut encode_leb128(variable));
+            result.push(INSTR_LOCAL_GET);
+            result.append(&mut encode_leb128(limit));
+            result.push(INSTR_F64_LE);
 
             result.push(INSTR_BLOCK_IF);
             result.push(INSTR_VOID);
 
-            // sum += value
-            result.append(&mut emit_code_for_expression(
-                value,
-                symbol_table,
-                arguments,
-                functions,
-                &Stack {
-                    pointer: stack_pointer,
-                    variables,
-                },
-            )?);
-
-            // Get sum from memory
-            result.append(&mut encode_leb128(stack_pointer + 4));
-            result.push(MEMORY_F64_LOAD);
-            result.push(0x02);
-            result.push(0x00);
-
-            // Add it
+            // total += value
+            result.push(INSTR_LOCAL_GET);
+            result.append(&mut encode_leb128(total));
+            result.append(&mut emit_code_for_expression(value, local_base)?);
             result.push(INSTR_F64_ADD);
+            result.push(INSTR_LOCAL_SET);
+            result.append(&mut encode_leb128(total));
 
-            // Put it back in memory
-            result.append(&mut encode_leb128(stack_pointer + 4));
-            result.push(MEMORY_F64_STORE);
-            result.push(0x02);
-            result.push(0x00);
-
+            // variable += 1
             result.push(INSTR_LOCAL_GET);
-            result.push(0x02);
+            result.append(&mut encode_leb128(variable));
+            result.push(INSTR_F64_CONST);
+            result.append(&mut encode_f64(1.0));
             result.push(INSTR_F64_ADD);
+            result.push(INSTR_LOCAL_SET);
+            result.append(&mut encode_leb128(variable));
 
-            // i++
-            // Get i
-            result.append(&mut encode_leb128(stack_pointer));
-            result.push(MEMORY_I32_LOAD);
-            result.push(0x02);
-            result.push(0x00);
-
-            result.push(INSTR_I32_CONST);
-            result.push(1);
-            result.push(INSTR_I32_ADD);
-
-            // break 1, continue loop
+            // continue the loop, the if is depth 0
             result.push(INSTR_BR);
-            // break depth
-            result.push(0x01);
+            result.push(0x01); // break depth
 
             result.push(EXPRESSION_END);
             result.push(EXPRESSION_END);
 
-            // return result
+            // the value of the Sum
+            result.push(INSTR_LOCAL_GET);
+            result.append(&mut encode_leb128(total));
+        }
+        Expression::Numeric {
+            method,
+            variable,
+            lower,
+            upper,
+            value,
+        } => {
+            // The registers are the variable and the locals that follow it
+            let variable = local_base + variable;
+            let routine = method.routine();
+            result.append(&mut emit_code_for_expression(lower, local_base)?);
+            result.push(INSTR_LOCAL_SET);
+            result.append(&mut encode_leb128(variable + 1));
+            result.append(&mut emit_code_for_expression(upper, local_base)?);
+            result.push(INSTR_LOCAL_SET);
+            result.append(&mut encode_leb128(variable + 2));
+            let value = emit_code_for_expression(value, local_base)?;
+            emit_routine_statements(&routine.code, variable, &value, 0, &mut result);
             result.push(INSTR_LOCAL_GET);
-            result.push(0x02);
+            result.append(&mut encode_leb128(variable + routine.result));
+        }
+        Expression::Tee { local, value } => {
+            result.append(&mut emit_code_for_expression(value, local_base)?);
+            result.push(INSTR_LOCAL_TEE);
+            result.append(&mut encode_leb128(local_base + local));
+        }
+        Expression::Let { local, value, body } => {
+            result.append(&mut emit_code_for_expression(value, local_base)?);
+            result.push(INSTR_LOCAL_SET);
+            result.append(&mut encode_leb128(local_base + local));
+            result.append(&mut emit_code_for_expression(body, local_base)?);
         }
     };
     Ok(result)
 }
 
-fn emit_code_section(
-    root: &ProgramNode,
-    symbol_table: &SymbolTable,
-    functions: &[String],
-) -> Result<Vec<u8>, String> {
-    // Has the code for all the functions
-    let mut bytes = Vec::new();
-    let mut function_count = 0;
-    let stack = &Stack {
-        pointer: 8 * 1024 * 1024,
-        variables: HashMap::new(),
+// The names of the arguments and the locals of redraw, in index order, for the text format.
+// The arguments are f64, then there are seven i32 locals and the other ones are f64.
+pub(crate) const REDRAW_LOCALS: [&str; 22] = [
+    "width", "height", "n", "i", "address", "header", "j", "cell", "rows", "step", "y", "y_min",
+    "y_max", "t", "x", "x_min", "x_max", "f00", "f10", "f01", "f11", "y_step",
+];
+
+// The index of a local of redraw
+fn redraw_local(name: &str) -> u8 {
+    REDRAW_LOCALS
+        .iter()
+        .position(|local| *local == name)
+        .unwrap() as u8
+}
+
+fn get(bytes: &mut Vec<u8>, local: u8) {
+    bytes.push(INSTR_LOCAL_GET);
+    bytes.push(local);
+}
+
+fn set(bytes: &mut Vec<u8>, local: u8) {
+    bytes.push(INSTR_LOCAL_SET);
+    bytes.push(local);
+}
+
+fn constant(bytes: &mut Vec<u8>, f: f64) {
+    bytes.push(INSTR_F64_CONST);
+    bytes.append(&mut encode_f64(f));
+}
+
+// local += increment, for an i32 local
+fn increment(bytes: &mut Vec<u8>, local: u8, increment: i32) {
+    get(bytes, local);
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(increment));
+    bytes.push(INSTR_I32_ADD);
+    set(bytes, local);
+}
+
+// The number of iterations of a loop, a constant or the value of an i32 local
+#[derive(Clone, Copy)]
+enum Count {
+    Constant(i32),
+    Local(u8),
+}
+
+// for counter in 0..count { body }, count must be positive
+fn emit_counted_loop(
+    bytes: &mut Vec<u8>,
+    counter: u8,
+    count: Count,
+    body: impl FnOnce(&mut Vec<u8>),
+) {
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(0));
+    set(bytes, counter);
+    bytes.push(INSTR_BLOCK_LOOP);
+    bytes.push(INSTR_VOID);
+    body(bytes);
+    increment(bytes, counter, 1);
+    get(bytes, counter);
+    match count {
+        Count::Constant(count) => {
+            bytes.push(INSTR_I32_CONST);
+            bytes.append(&mut encode_sleb128(count));
+        }
+        Count::Local(local) => get(bytes, local),
+    }
+    bytes.push(INSTR_I32_LT_S);
+    bytes.push(INSTR_BR_IF);
+    bytes.push(0x00); // break depth
+    bytes.push(EXPRESSION_END);
+}
+
+// The loops of redraw that sample the curves of a ParametricPlot or a PolarPlot and then write
+// its header, see PLOT_DATA_ADDRESS. The locals are the ones of redraw, y_min, y_max and step are
+// already set.
+fn emit_parametric_samples(bytes: &mut Vec<u8>, plot: &Plot, imports: &[Import]) -> Result<()> {
+    let [n, i, address, header, step, t] =
+        ["n", "i", "address", "header", "step", "t"].map(redraw_local);
+    let [x, y, x_min, x_max, y_min, y_max] =
+        ["x", "y", "x_min", "x_max", "y_min", "y_max"].map(redraw_local);
+
+    let call = |bytes: &mut Vec<u8>, index: u32| {
+        bytes.push(INSTR_FUNCTION_CALL);
+        bytes.append(&mut encode_leb128(index));
     };
-    for statement in &root.statements {
-        if let StatementNode::FunctionDeclaration {
-            arguments,
-            name,
-            value,
-        } = statement
-        {
-            // function size
-            let mut function_bytes = Vec::new();
-            function_bytes.append(&mut emit_code_for_expression(
-                value,
-                symbol_table,
-                arguments,
-                functions,
-                stack,
-            )?);
-            // end
-            function_bytes.push(EXPRESSION_END);
-            bytes.append(&mut encode_leb128(function_bytes.len() as u32));
-            bytes.append(&mut function_bytes);
-            function_count += 1;
+    let builtin = |builtin: Builtin| {
+        let index = imports
+            .iter()
+            .position(|import| *import == Import::Builtin(builtin));
+        index
+            .map(|index| index as u32)
+            .ok_or_else(|| InternalCompilerError {
+                message: format!("{} is not imported", builtin.name()),
+            })
+    };
+
+    // x_min = +inf; x_max = -inf
+    constant(bytes, f64::INFINITY);
+    set(bytes, x_min);
+    constant(bytes, f64::NEG_INFINITY);
+    set(bytes, x_max);
+
+    let functions_per_curve = match plot.kind {
+        PlotKind::Polar => 1,
+        _ => 2,
+    };
+    for curve in plot.functions.chunks(functions_per_curve) {
+        // i = 0
+        bytes.push(INSTR_I32_CONST);
+        bytes.append(&mut encode_sleb128(0));
+        set(bytes, i);
+
+        bytes.push(INSTR_BLOCK_LOOP);
+        bytes.push(INSTR_VOID);
+
+        // t = t0 + i*step
+        constant(bytes, plot.x_min);
+        get(bytes, i);
+        bytes.push(INSTR_F64_CONVERT_I32_S);
+        get(bytes, step);
+        bytes.push(INSTR_F64_MUL);
+        bytes.push(INSTR_F64_ADD);
+        set(bytes, t);
+
+        if plot.kind == PlotKind::Polar {
+            // y = r(t); x = y*cos(t); y = y*sin(t)
+            get(bytes, t);
+            call(bytes, curve[0]);
+            set(bytes, y);
+            for (local, trigonometric) in [(x, Builtin::Cos), (y, Builtin::Sin)] {
+                get(bytes, y);
+                get(bytes, t);
+                call(bytes, builtin(trigonometric)?);
+                bytes.push(INSTR_F64_MUL);
+                set(bytes, local);
+            }
+        } else {
+            // x = x(t); y = y(t)
+            for (function_index, local) in [(curve[0], x), (curve[1], y)] {
+                get(bytes, t);
+                call(bytes, function_index);
+                set(bytes, local);
+            }
         }
+
+        // memory[address] = x; memory[address + 8] = y
+        for (local, offset) in [(x, 0), (y, 8)] {
+            get(bytes, address);
+            get(bytes, local);
+            bytes.push(MEMORY_F64_STORE);
+            bytes.push(0x03); // alignment
+            bytes.push(offset);
+        }
+
+        // Only the points where both are finite count, (x - x) + (y - y) is NaN otherwise
+        get(bytes, x);
+        get(bytes, x);
+        bytes.push(INSTR_F64_SUB);
+        get(bytes, y);
+        get(bytes, y);
+        bytes.push(INSTR_F64_SUB);
+        bytes.push(INSTR_F64_ADD);
+        constant(bytes, 0.0);
+        bytes.push(INSTR_F64_EQ);
+        bytes.push(INSTR_BLOCK_IF);
+        bytes.push(INSTR_VOID);
+        for (value, minimum, maximum) in [(x, x_min, x_max), (y, y_min, y_max)] {
+            // minimum = min(minimum, value); maximum = max(maximum, value)
+            get(bytes, minimum);
+            get(bytes, value);
+            bytes.push(INSTR_F64_MIN);
+            set(bytes, minimum);
+            get(bytes, maximum);
+            get(bytes, value);
+            bytes.push(INSTR_F64_MAX);
+            set(bytes, maximum);
+        }
+        bytes.push(EXPRESSION_END);
+
+        increment(bytes, address, 16);
+
+        // i++, continue while i < n
+        get(bytes, i);
+        bytes.push(INSTR_I32_CONST);
+        bytes.append(&mut encode_sleb128(1));
+        bytes.push(INSTR_I32_ADD);
+        bytes.push(INSTR_LOCAL_TEE);
+        bytes.push(i);
+        get(bytes, n);
+        bytes.push(INSTR_I32_LT_S);
+        bytes.push(INSTR_BR_IF);
+        bytes.push(0x00); // break depth
+        bytes.push(EXPRESSION_END);
     }
-    let mut plot_function_count = 0;
-    // Now we define the function redraw(width, height)
-    for statement in &root.statements {
-        if let StatementNode::PlotStatement {
-            functions: plot_functions,
-            x_range,
-            y_range,
-        } = statement
-        {
-            let arguments = &vec![x_range.variable_name.to_string()];
-            for function in plot_functions {
-                let mut function_bytes = Vec::new();
-                function_bytes.append(&mut emit_code_for_expression(
-                    &function.value,
-                    symbol_table,
-                    arguments,
-                    functions,
-                    stack,
-                )?);
-                // end
-                function_bytes.push(EXPRESSION_END);
-                bytes.append(&mut encode_leb128(function_bytes.len() as u32));
-                bytes.append(&mut function_bytes);
-                plot_function_count += 1;
+
+    // the header is x_min, x_max, y_min, y_max
+    for (local, offset) in [(x_min, 0), (x_max, 8), (y_min, 16), (y_max, 24)] {
+        get(bytes, header);
+        get(bytes, local);
+        bytes.push(MEMORY_F64_STORE);
+        bytes.push(0x03);
+        bytes.push(offset);
+    }
+    Ok(())
+}
+
+// The number of cells in each direction of the grid where the function of a ContourPlot is sampled
+pub(crate) const CONTOUR_CELLS: i32 = 100;
+
+// The most bytes a ContourPlot with `level_count` levels writes after its header: the values at
+// the points of the grid and two segments per cell and level
+fn contour_size(level_count: usize) -> i32 {
+    let grid_size = 8 * (CONTOUR_CELLS + 1) * (CONTOUR_CELLS + 1);
+    grid_size + 64 * CONTOUR_CELLS * CONTOUR_CELLS * level_count as i32
+}
+
+// The loops of redraw that sample the function of a ContourPlot on the grid, then find the
+// segments of the curve of each level with marching squares and finally write the number of
+// segments in the header, see PLOT_DATA_ADDRESS. The function must be the first plot function.
+fn emit_contour_samples(bytes: &mut Vec<u8>, plot: &Plot, y_min: f64, y_max: f64, levels: &[f64]) {
+    let [i, j, address, header, cell] = ["i", "j", "address", "header", "cell"].map(redraw_local);
+    let [x, y, f00, f10, f01, f11] = ["x", "y", "f00", "f10", "f01", "f11"].map(redraw_local);
+    let x_min = plot.x_min;
+    let dx = (plot.x_max - plot.x_min) / CONTOUR_CELLS as f64;
+    let dy = (y_max - y_min) / CONTOUR_CELLS as f64;
+    // the bytes of a row of the grid
+    let row = 8 * (CONTOUR_CELLS + 1);
+    let corner = |bytes: &mut Vec<u8>| {
+        // x = x_min + i*dx; y = y_min + j*dy
+        for (local, minimum, step, counter) in [(x, x_min, dx, i), (y, y_min, dy, j)] {
+            constant(bytes, minimum);
+            get(bytes, counter);
+            bytes.push(INSTR_F64_CONVERT_I32_S);
+            constant(bytes, step);
+            bytes.push(INSTR_F64_MUL);
+            bytes.push(INSTR_F64_ADD);
+            set(bytes, local);
+        }
+    };
+
+    // the values at the points (x_min + i*dx, y_min + j*dy), row by row
+    let points = Count::Constant(CONTOUR_CELLS + 1);
+    emit_counted_loop(bytes, j, points, |bytes| {
+        emit_counted_loop(bytes, i, points, |bytes| {
+            corner(bytes);
+            get(bytes, address);
+            get(bytes, x);
+            get(bytes, y);
+            bytes.push(INSTR_FUNCTION_CALL);
+            bytes.append(&mut encode_leb128(plot.functions[0]));
+            bytes.push(MEMORY_F64_STORE);
+            bytes.push(0x03); // alignment
+            bytes.push(0x00); // offset
+            increment(bytes, address, 8);
+        });
+    });
+
+    // The edges of the cell: the locals of the values at its ends, the offset of its first end
+    // from the corner (x, y) and whether it is along x
+    let bottom = (f00, f10, 0.0, 0.0, true);
+    let right = (f10, f11, dx, 0.0, false);
+    let top = (f01, f11, 0.0, dy, true);
+    let left = (f00, f01, 0.0, 0.0, false);
+    // The crossing of an edge, if one of the values is negative and the other one is not. It is
+    // the fraction v0/(v0 - v1) of the edge from its first end.
+    let crossing = |bytes: &mut Vec<u8>, (v0, v1, x_offset, y_offset, along_x)| {
+        get(bytes, v0);
+        constant(bytes, 0.0);
+        bytes.push(INSTR_F64_LT);
+        get(bytes, v1);
+        constant(bytes, 0.0);
+        bytes.push(INSTR_F64_LT);
+        bytes.push(INSTR_I32_SUB);
+        bytes.push(INSTR_BLOCK_IF);
+        bytes.push(INSTR_VOID);
+        for (local, offset, step, memory_offset) in [(x, x_offset, dx, 0), (y, y_offset, dy, 8)] {
+            get(bytes, address);
+            get(bytes, local);
+            if offset != 0.0 {
+                constant(bytes, offset);
+                bytes.push(INSTR_F64_ADD);
+            }
+            if along_x == (local == x) {
+                constant(bytes, step);
+                get(bytes, v0);
+                bytes.push(INSTR_F64_MUL);
+                get(bytes, v0);
+                get(bytes, v1);
+                bytes.push(INSTR_F64_SUB);
+                bytes.push(INSTR_F64_DIV);
+                bytes.push(INSTR_F64_ADD);
+            }
+            bytes.push(MEMORY_F64_STORE);
+            bytes.push(0x03);
+            bytes.push(memory_offset);
+        }
+        increment(bytes, address, 16);
+        bytes.push(EXPRESSION_END);
+    };
+
+    for level in levels {
+        // cell = header + 8, the address of the value at the corner (i, j) of the cell
+        get(bytes, header);
+        bytes.push(INSTR_I32_CONST);
+        bytes.append(&mut encode_sleb128(8));
+        bytes.push(INSTR_I32_ADD);
+        set(bytes, cell);
+        let cells = Count::Constant(CONTOUR_CELLS);
+        emit_counted_loop(bytes, j, cells, |bytes| {
+            emit_counted_loop(bytes, i, cells, |bytes| {
+                // the values at the corners minus the level
+                for (local, offset) in [(f00, 0), (f10, 8), (f01, row), (f11, row + 8)] {
+                    get(bytes, cell);
+                    bytes.push(MEMORY_F64_LOAD);
+                    bytes.push(0x03);
+                    bytes.append(&mut encode_leb128(offset as u32));
+                    constant(bytes, *level);
+                    bytes.push(INSTR_F64_SUB);
+                    set(bytes, local);
+                }
+
+                // Only the cells where the four values are finite
+                for local in [f00, f10, f01, f11] {
+                    get(bytes, local);
+                    get(bytes, local);
+                    bytes.push(INSTR_F64_SUB);
+                }
+                bytes.push(INSTR_F64_ADD);
+                bytes.push(INSTR_F64_ADD);
+                bytes.push(INSTR_F64_ADD);
+                constant(bytes, 0.0);
+                bytes.push(INSTR_F64_EQ);
+                bytes.push(INSTR_BLOCK_IF);
+                bytes.push(INSTR_VOID);
+                corner(bytes);
+                // Two consecutive crossings are a segment. If the four edges are crossed, the
+                // segments cut off the corners with a sign different from the center of the cell,
+                // the mean of the corners.
+                for local in [f00, f10, f01, f11] {
+                    get(bytes, local);
+                }
+                bytes.push(INSTR_F64_ADD);
+                bytes.push(INSTR_F64_ADD);
+                bytes.push(INSTR_F64_ADD);
+                constant(bytes, 0.0);
+                bytes.push(INSTR_F64_LT);
+                get(bytes, f00);
+                constant(bytes, 0.0);
+                bytes.push(INSTR_F64_LT);
+                bytes.push(INSTR_I32_SUB);
+                bytes.push(INSTR_BLOCK_IF);
+                bytes.push(INSTR_VOID);
+                // cut off the corners (i, j) and (i + 1, j + 1)
+                for edge in [left, bottom, right, top] {
+                    crossing(bytes, edge);
+                }
+                bytes.push(INSTR_BLOCK_ELSE);
+                // cut off the corners (i + 1, j) and (i, j + 1)
+                for edge in [bottom, right, top, left] {
+                    crossing(bytes, edge);
+                }
+                bytes.push(EXPRESSION_END);
+                bytes.push(EXPRESSION_END);
+
+                increment(bytes, cell, 8);
+            });
+            // the last point of the row is not the corner (i, j) of a cell
+            increment(bytes, cell, 8);
+        });
+    }
+
+    // memory[header] = (address - header - grid_size)/32, the number of segments
+    get(bytes, header);
+    get(bytes, address);
+    get(bytes, header);
+    bytes.push(INSTR_I32_SUB);
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(8 + row * (CONTOUR_CELLS + 1)));
+    bytes.push(INSTR_I32_SUB);
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(5));
+    bytes.push(INSTR_I32_SHR_U);
+    bytes.push(INSTR_F64_CONVERT_I32_S);
+    bytes.push(MEMORY_F64_STORE);
+    bytes.push(0x03);
+    bytes.push(0x00);
+}
+
+// The loops of redraw that sample the function of a DensityPlot at the pixels and write the
+// header and then the color of each pixel, see PLOT_DATA_ADDRESS. The function must be the first
+// plot function, step and rows are already set.
+fn emit_density_samples(
+    bytes: &mut Vec<u8>,
+    plot: &Plot,
+    y_min: f64,
+    y_max: f64,
+    color_map: ColorMap,
+) {
+    let [n, rows, i, j, address, header, cell] =
+        ["n", "rows", "i", "j", "address", "header", "cell"].map(redraw_local);
+    let [step, y_step, x, y, t] = ["step", "y_step", "x", "y", "t"].map(redraw_local);
+    let [lower, upper] = ["y_min", "y_max"].map(redraw_local);
+
+    // y_step = (y_max - y_min)/(rows - 1)
+    constant(bytes, y_max - y_min);
+    get(bytes, rows);
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(1));
+    bytes.push(INSTR_I32_SUB);
+    bytes.push(INSTR_F64_CONVERT_I32_S);
+    bytes.push(INSTR_F64_DIV);
+    set(bytes, y_step);
+
+    // lower = +inf; upper = -inf
+    constant(bytes, f64::INFINITY);
+    set(bytes, lower);
+    constant(bytes, f64::NEG_INFINITY);
+    set(bytes, upper);
+
+    // the values at the points (x_min + i*step, y_max - j*y_step), row by row
+    emit_counted_loop(bytes, j, Count::Local(rows), |bytes| {
+        constant(bytes, y_max);
+        get(bytes, j);
+        bytes.push(INSTR_F64_CONVERT_I32_S);
+        get(bytes, y_step);
+        bytes.push(INSTR_F64_MUL);
+        bytes.push(INSTR_F64_SUB);
+        set(bytes, y);
+        emit_counted_loop(bytes, i, Count::Local(n), |bytes| {
+            get(bytes, address);
+            constant(bytes, plot.x_min);
+            get(bytes, i);
+            bytes.push(INSTR_F64_CONVERT_I32_S);
+            get(bytes, step);
+            bytes.push(INSTR_F64_MUL);
+            bytes.push(INSTR_F64_ADD);
+            get(bytes, y);
+            bytes.push(INSTR_FUNCTION_CALL);
+            bytes.append(&mut encode_leb128(plot.functions[0]));
+            bytes.push(INSTR_LOCAL_TEE);
+            bytes.push(t);
+            bytes.push(MEMORY_F64_STORE);
+            bytes.push(0x03); // alignment
+            bytes.push(0x00); // offset
+
+            // Only finite values count for the minimum and the maximum (t - t is NaN otherwise)
+            get(bytes, t);
+            get(bytes, t);
+            bytes.push(INSTR_F64_SUB);
+            constant(bytes, 0.0);
+            bytes.push(INSTR_F64_EQ);
+            bytes.push(INSTR_BLOCK_IF);
+            bytes.push(INSTR_VOID);
+            get(bytes, lower);
+            get(bytes, t);
+            bytes.push(INSTR_F64_MIN);
+            set(bytes, lower);
+            get(bytes, upper);
+            get(bytes, t);
+            bytes.push(INSTR_F64_MAX);
+            set(bytes, upper);
+            bytes.push(EXPRESSION_END);
+
+            increment(bytes, address, 8);
+        });
+    });
+
+    // the header is the minimum and the maximum
+    for (local, offset) in [(lower, 0), (upper, 8)] {
+        get(bytes, header);
+        get(bytes, local);
+        bytes.push(MEMORY_F64_STORE);
+        bytes.push(0x03);
+        bytes.push(offset);
+    }
+
+    // lower and upper become the range of the color map, see ColorMap::range
+    if color_map == ColorMap::Diverging {
+        // upper = max(-lower, upper); lower = -upper
+        constant(bytes, 0.0);
+        get(bytes, lower);
+        bytes.push(INSTR_F64_SUB);
+        get(bytes, upper);
+        bytes.push(INSTR_F64_MAX);
+        set(bytes, upper);
+        constant(bytes, 0.0);
+        get(bytes, upper);
+        bytes.push(INSTR_F64_SUB);
+        set(bytes, lower);
+    }
+    get(bytes, lower);
+    get(bytes, upper);
+    bytes.push(INSTR_F64_GE);
+    bytes.push(INSTR_BLOCK_IF);
+    bytes.push(INSTR_VOID);
+    for (local, change) in [(lower, -1.0), (upper, 1.0)] {
+        get(bytes, local);
+        constant(bytes, change);
+        bytes.push(INSTR_F64_ADD);
+        set(bytes, local);
+    }
+    bytes.push(EXPRESSION_END);
+
+    // cell = header + 16, the address of the value of the pixel
+    get(bytes, header);
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(16));
+    bytes.push(INSTR_I32_ADD);
+    set(bytes, cell);
+    emit_counted_loop(bytes, j, Count::Local(rows), |bytes| {
+        emit_counted_loop(bytes, i, Count::Local(n), |bytes| {
+            // t = memory[cell]
+            get(bytes, cell);
+            bytes.push(MEMORY_F64_LOAD);
+            bytes.push(0x03);
+            bytes.push(0x00);
+            set(bytes, t);
+
+            // x = min(1, max(0, (t/2 - lower/2)/(upper/2 - lower/2))), the position in the color
+            // map, see colormap::position
+            constant(bytes, 1.0);
+            constant(bytes, 0.0);
+            for (minuend, subtrahend) in [(t, lower), (upper, lower)] {
+                for local in [minuend, subtrahend] {
+                    get(bytes, local);
+                    constant(bytes, 0.5);
+                    bytes.push(INSTR_F64_MUL);
+                }
+                bytes.push(INSTR_F64_SUB);
             }
-            {
-                // last function, the main function
-                let mut function_bytes = Vec::new();
-                let x0 = if let ExpressionNode::Number(x0) = *x_range.lower {
-                    x0
-                } else {
-                    return Err("Expected number at this point".to_string());
-                };
-                let x1 = if let ExpressionNode::Number(x1) = *x_range.upper {
-                    x1
-                } else {
-                    return Err("Expected number at this point".to_string());
-                };
-                // local variable step
-                /*
-                We want to set the results in the memory
-                * n values for each function
-                * then the settings (color and thickness)
-
-                let step = (x1 - x0) / width;
-                let n = (width / step).ceil() as i32;
-                let mut max_y = function[0](x0);
-                let mut min_y = max_y;
-                for function_index in 0..plot_function_count {
-                    for i in 0..n {
-                        let value = function[function_index](x0+step*i);
-                        store(value, plot_function_count*n*8+i*8);
-                        max_y = max(max_y, value);
-                        min_y = min(min_y, value);
-                    }
+            bytes.push(INSTR_F64_DIV);
+            bytes.push(INSTR_F64_MAX);
+            bytes.push(INSTR_F64_MIN);
+            set(bytes, x);
+
+            // Only a finite value with a position is colored, (t - t == 0)*(x == x) is 0
+            // otherwise. A NaN position would trap when the channels are truncated.
+            get(bytes, t);
+            get(bytes, t);
+            bytes.push(INSTR_F64_SUB);
+            constant(bytes, 0.0);
+            bytes.push(INSTR_F64_EQ);
+            get(bytes, x);
+            get(bytes, x);
+            bytes.push(INSTR_F64_EQ);
+            bytes.push(INSTR_I32_MUL);
+            bytes.push(INSTR_BLOCK_IF);
+            bytes.push(INSTR_VOID);
+
+            // memory[address] = red + 256*green + 65536*blue + 255 << 24, the channels rounded
+            // from the sums of the ramps of the color map
+            get(bytes, address);
+            for (channel, factor) in [(0, 1), (1, 256), (2, 65536)] {
+                let (start_value, ramps) = color_map.ramps(channel);
+                constant(bytes, start_value);
+                for (start, change) in ramps {
+                    constant(bytes, change);
+                    constant(bytes, 0.0);
+                    get(bytes, x);
+                    constant(bytes, start);
+                    bytes.push(INSTR_F64_SUB);
+                    bytes.push(INSTR_F64_MAX);
+                    bytes.push(INSTR_F64_MUL);
+                    bytes.push(INSTR_F64_ADD);
+                }
+                constant(bytes, 0.5);
+                bytes.push(INSTR_F64_ADD);
+                bytes.push(INSTR_I32_TRUNC_F64_S);
+                if factor != 1 {
+                    bytes.push(INSTR_I32_CONST);
+                    bytes.append(&mut encode_sleb128(factor));
+                    bytes.push(INSTR_I32_MUL);
+                    bytes.push(INSTR_I32_ADD);
                 }
-                */
-
-                // first locals: step, n, max_y, min_y and i
-                // That is five variables, three f64 and two i32
-                function_bytes.push(0x05);
-                function_bytes.push(0x03);
-                function_bytes.push(F64_TYPE);
-                function_bytes.push(0x02);
-                function_bytes.push(I32_TYPE);
-
-                // 1. initialize variables
-                // step = (x1 - x0) / width
-                function_bytes.push(INSTR_F64_CONST);
-                function_bytes.append(&mut encode_f64(x1));
-                function_bytes.append(&mut encode_f64(x0));
-                function_bytes.push(INSTR_F64_SUB);
-                function_bytes.push(INSTR_LOCAL_GET);
-                function_bytes.push(0);
-                function_bytes.push(INSTR_F64_DIV);
-                function_bytes.push(INSTR_LOCAL_SET);
-                let local_step = 2;
-                function_bytes.push(local_step);
-
-                // max_y = function[0](x0);
-                function_bytes.push(INSTR_LOCAL_GET);
-                function_bytes.push(0);
-                function_bytes.push(INSTR_FUNCTION_CALL);
-                // FIXME: What is the right function call index?
-                function_bytes.push(0x00);
-                function_bytes.push(INSTR_LOCAL_SET);
-                let local_max_y = 3;
-                function_bytes.push(local_max_y); // max_y
-
-                // min_y = max_y
-                function_bytes.push(INSTR_LOCAL_GET);
-                function_bytes.push(0);
-                function_bytes.push(INSTR_LOCAL_SET);
-                let local_min_y = 4;
-                function_bytes.push(local_min_y);
-
-                // n = (width / step).ceil() as i32
-                function_bytes.push(INSTR_LOCAL_GET);
-                function_bytes.push(0);
-                function_bytes.push(INSTR_LOCAL_GET);
-                function_bytes.push(2);
-                function_bytes.push(INSTR_F64_DIV);
-                function_bytes.push(INSTR_F64_CEIL);
-                function_bytes.push(INSTR_I32_TRUNC_F64_S);
-                function_bytes.push(INSTR_LOCAL_SET);
-                let local_n = 5;
-                function_bytes.push(local_n);
-
-                // i = 0
-                function_bytes.push(INSTR_F64_CONST);
-                function_bytes.push(0x00);
-                function_bytes.push(INSTR_LOCAL_SET);
-                let local_i = 6;
-                function_bytes.push(local_i);
-
-                // function loop (i=0..plot_function_count)
-                function_bytes.push(INSTR_BLOCK_LOOP);
-                function_bytes.push(INSTR_VOID);
-
-                function_bytes.push(INSTR_F64_CONST);
-                function_bytes.push(0x00);
-                function_bytes.push(INSTR_LOCAL_SET);
-                let local_j = 7;
-                function_bytes.push(local_j);
-
-                // inner loop (j=0..n)
-                function_bytes.push(INSTR_BLOCK_LOOP);
-                function_bytes.push(INSTR_VOID);
-
-                // x = x0 + j*step;
-                function_bytes.append(&mut encode_f64(x0));
-                function_bytes.push(INSTR_LOCAL_GET);
-                function_bytes.push(local_j);
-                function_bytes.push(INSTR_LOCAL_GET);
-                function_bytes.push(local_step);
-                function_bytes.push(INSTR_F64_MUL);
-                function_bytes.push(INSTR_F64_ADD);
-
-                function_bytes.push(EXPRESSION_END);
-                // j++
-                function_bytes.push(EXPRESSION_END);
-                bytes.append(&mut encode_leb128(function_bytes.len() as u32));
-                bytes.append(&mut function_bytes);
-                plot_function_count += 1;
             }
+            bytes.push(INSTR_I32_CONST);
+            bytes.append(&mut encode_sleb128(255 << 24));
+            bytes.push(INSTR_I32_ADD);
+            bytes.push(MEMORY_I32_STORE);
+            bytes.push(0x02); // alignment
+            bytes.push(0x00); // offset
+
+            // a transparent pixel when the value is not finite or has no position
+            bytes.push(INSTR_BLOCK_ELSE);
+            get(bytes, address);
+            bytes.push(INSTR_I32_CONST);
+            bytes.append(&mut encode_sleb128(0));
+            bytes.push(MEMORY_I32_STORE);
+            bytes.push(0x02);
+            bytes.push(0x00);
+            bytes.push(EXPRESSION_END);
+
+            increment(bytes, address, 4);
+            increment(bytes, cell, 8);
+        });
+    });
+
+    // address = ((address + 7) >> 3) * 8, the next plot starts at a multiple of 8
+    increment(bytes, address, 7);
+    get(bytes, address);
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(3));
+    bytes.push(INSTR_I32_SHR_U);
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(8));
+    bytes.push(INSTR_I32_MUL);
+    set(bytes, address);
+}
+
+// Emits the body of the exported function redraw(width, height).
+// It first prints the value of the Print statements that depend on the sliders.
+// Then it samples every plot function at `n = max(2, width)` points and writes the values in memory,
+// following the layout described in PLOT_DATA_ADDRESS. It returns `n`.
+fn emit_redraw_function(program: &Program) -> Result<Vec<u8>> {
+    let local_width = redraw_local("width");
+    let [local_n, local_i, local_address, local_header, local_rows] =
+        ["n", "i", "address", "header", "rows"].map(redraw_local);
+    let [local_step, local_y, local_y_min, local_y_max] =
+        ["step", "y", "y_min", "y_max"].map(redraw_local);
+
+    let header_size = |plot: &Plot| match plot.kind {
+        PlotKind::Graph | PlotKind::Density { .. } => 16,
+        PlotKind::Parametric | PlotKind::Polar => 32,
+        PlotKind::Contour { .. } => 8,
+    };
+    // the bytes that do not depend on n, the headers, the grids and segments of the contours and
+    // the padding after the pixels of the densities
+    let fixed_size: i32 = program
+        .plots
+        .iter()
+        .map(|plot| match &plot.kind {
+            PlotKind::Contour { levels, .. } => header_size(plot) + contour_size(levels.len()),
+            PlotKind::Density { .. } => header_size(plot) + 8,
+            _ => header_size(plot),
+        })
+        .sum();
+    // the number of f64 written for each sample, a polar curve writes x and y
+    let value_count: i32 = program
+        .plots
+        .iter()
+        .map(|plot| match plot.kind {
+            PlotKind::Graph | PlotKind::Parametric => plot.functions.len() as i32,
+            PlotKind::Polar => 2 * plot.functions.len() as i32,
+            PlotKind::Contour { .. } | PlotKind::Density { .. } => 0,
+        })
+        .sum();
+    // a density writes a value (8 bytes) and a color (4 bytes) for each of the n*rows pixels
+    let density_count = program
+        .plots
+        .iter()
+        .filter(|plot| matches!(plot.kind, PlotKind::Density { .. }))
+        .count() as i32;
+
+    // Seven i32 (n, i, address, header, j, cell, rows) and thirteen f64 (step, y, y_min, y_max, t,
+    // x, x_min, x_max, f00, f10, f01, f11, y_step), followed by the f64 locals of the print
+    // expressions
+    let mut bytes = vec![0x02, 0x07, I32_TYPE];
+    bytes.append(&mut encode_leb128(13 + program.print_locals.len() as u32));
+    bytes.push(F64_TYPE);
+
+    // print(index, value)
+    let print_local_base = REDRAW_LOCALS.len() as u32;
+    for (index, value) in program.prints.iter().enumerate() {
+        bytes.push(INSTR_F64_CONST);
+        bytes.append(&mut encode_f64(index as f64));
+        bytes.append(&mut emit_code_for_expression(value, print_local_base)?);
+        bytes.push(INSTR_FUNCTION_CALL);
+        let print_index = program
+            .imports
+            .iter()
+            .position(|import| *import == Import::Print)
+            .expect("");
+        bytes.append(&mut encode_leb128(print_index as u32));
+        bytes.push(INSTR_DROP);
+    }
+
+    // n = max(2, width) as i32
+    bytes.push(INSTR_LOCAL_GET);
+    bytes.push(local_width);
+    bytes.push(INSTR_F64_CONST);
+    bytes.append(&mut encode_f64(2.0));
+    bytes.push(INSTR_F64_MAX);
+    bytes.push(INSTR_I32_TRUNC_F64_S);
+    bytes.push(INSTR_LOCAL_SET);
+    bytes.push(local_n);
+
+    if density_count > 0 {
+        // rows = max(2, height) as i32
+        get(&mut bytes, redraw_local("height"));
+        constant(&mut bytes, 2.0);
+        bytes.push(INSTR_F64_MAX);
+        bytes.push(INSTR_I32_TRUNC_F64_S);
+        set(&mut bytes, local_rows);
+    }
+
+    // Make sure the memory is big enough:
+    // i = (8*value_count*n + 12*density_count*n*rows + PLOT_DATA_ADDRESS + fixed_size + 0xffff)
+    //     >> 16
+    // if i > memory.size { memory.grow(i - memory.size) }
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(8 * value_count));
+    bytes.push(INSTR_LOCAL_GET);
+    bytes.push(local_n);
+    bytes.push(INSTR_I32_MUL);
+    if density_count > 0 {
+        bytes.push(INSTR_I32_CONST);
+        bytes.append(&mut encode_sleb128(12 * density_count));
+        get(&mut bytes, local_n);
+        bytes.push(INSTR_I32_MUL);
+        get(&mut bytes, local_rows);
+        bytes.push(INSTR_I32_MUL);
+        bytes.push(INSTR_I32_ADD);
+    }
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(
+        PLOT_DATA_ADDRESS as i32 + fixed_size + 0xffff,
+    ));
+    bytes.push(INSTR_I32_ADD);
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(16));
+    bytes.push(INSTR_I32_SHR_U);
+    bytes.push(INSTR_LOCAL_TEE);
+    bytes.push(local_i);
+    bytes.push(INSTR_MEMORY_SIZE);
+    bytes.push(0x00); // memory index
+    bytes.push(INSTR_I32_GT_U);
+    bytes.push(INSTR_BLOCK_IF);
+    bytes.push(INSTR_VOID);
+    bytes.push(INSTR_LOCAL_GET);
+    bytes.push(local_i);
+    bytes.push(INSTR_MEMORY_SIZE);
+    bytes.push(0x00);
+    bytes.push(INSTR_I32_SUB);
+    bytes.push(INSTR_MEMORY_GROW);
+    bytes.push(0x00);
+    bytes.push(INSTR_DROP);
+    bytes.push(EXPRESSION_END);
+
+    // address = PLOT_DATA_ADDRESS
+    bytes.push(INSTR_I32_CONST);
+    bytes.append(&mut encode_sleb128(PLOT_DATA_ADDRESS as i32));
+    bytes.push(INSTR_LOCAL_SET);
+    bytes.push(local_address);
+
+    for plot in &program.plots {
+        let (x0, x1) = (plot.x_min, plot.x_max);
+
+        // header = address; address += header_size
+        bytes.push(INSTR_LOCAL_GET);
+        bytes.push(local_address);
+        bytes.push(INSTR_LOCAL_TEE);
+        bytes.push(local_header);
+        bytes.push(INSTR_I32_CONST);
+        bytes.append(&mut encode_sleb128(header_size(plot)));
+        bytes.push(INSTR_I32_ADD);
+        bytes.push(INSTR_LOCAL_SET);
+        bytes.push(local_address);
+
+        if let PlotKind::Contour {
+            y_min,
+            y_max,
+            levels,
+        } = &plot.kind
+        {
+            emit_contour_samples(&mut bytes, plot, *y_min, *y_max, levels);
+            continue;
+        }
+
+        // y_min = +inf; y_max = -inf
+        bytes.push(INSTR_F64_CONST);
+        bytes.append(&mut encode_f64(f64::INFINITY));
+        bytes.push(INSTR_LOCAL_SET);
+        bytes.push(local_y_min);
+        bytes.push(INSTR_F64_CONST);
+        bytes.append(&mut encode_f64(f64::NEG_INFINITY));
+        bytes.push(INSTR_LOCAL_SET);
+        bytes.push(local_y_max);
+
+        // step = (x1 - x0) / (n - 1)
+        bytes.push(INSTR_F64_CONST);
+        bytes.append(&mut encode_f64(x1 - x0));
+        bytes.push(INSTR_LOCAL_GET);
+        bytes.push(local_n);
+        bytes.push(INSTR_I32_CONST);
+        bytes.append(&mut encode_sleb128(1));
+        bytes.push(INSTR_I32_SUB);
+        bytes.push(INSTR_F64_CONVERT_I32_S);
+        bytes.push(INSTR_F64_DIV);
+        bytes.push(INSTR_LOCAL_SET);
+        bytes.push(local_step);
+
+        if let PlotKind::Density {
+            y_min,
+            y_max,
+            color_map,
+        } = plot.kind
+        {
+            emit_density_samples(&mut bytes, plot, y_min, y_max, color_map);
+            continue;
         }
+        if plot.kind != PlotKind::Graph {
+            emit_parametric_samples(&mut bytes, plot, &program.imports)?;
+            continue;
+        }
+
+        for function_index in &plot.functions {
+            // i = 0
+            bytes.push(INSTR_I32_CONST);
+            bytes.append(&mut encode_sleb128(0));
+            bytes.push(INSTR_LOCAL_SET);
+            bytes.push(local_i);
+
+            bytes.push(INSTR_BLOCK_LOOP);
+            bytes.push(INSTR_VOID);
+
+            // memory[address] = y = f(x0 + i*step)
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_address);
+            bytes.push(INSTR_F64_CONST);
+            bytes.append(&mut encode_f64(x0));
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_i);
+            bytes.push(INSTR_F64_CONVERT_I32_S);
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_step);
+            bytes.push(INSTR_F64_MUL);
+            bytes.push(INSTR_F64_ADD);
+            bytes.push(INSTR_FUNCTION_CALL);
+            bytes.append(&mut encode_leb128(*function_index));
+            bytes.push(INSTR_LOCAL_TEE);
+            bytes.push(local_y);
+            bytes.push(MEMORY_F64_STORE);
+            bytes.push(0x03); // alignment
+            bytes.push(0x00); // offset
+
+            // Only finite values count for the minimum and the maximum (y - y is NaN otherwise)
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_y);
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_y);
+            bytes.push(INSTR_F64_SUB);
+            bytes.push(INSTR_F64_CONST);
+            bytes.append(&mut encode_f64(0.0));
+            bytes.push(INSTR_F64_EQ);
+            bytes.push(INSTR_BLOCK_IF);
+            bytes.push(INSTR_VOID);
+            // y_min = min(y_min, y)
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_y_min);
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_y);
+            bytes.push(INSTR_F64_MIN);
+            bytes.push(INSTR_LOCAL_SET);
+            bytes.push(local_y_min);
+            // y_max = max(y_max, y)
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_y_max);
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_y);
+            bytes.push(INSTR_F64_MAX);
+            bytes.push(INSTR_LOCAL_SET);
+            bytes.push(local_y_max);
+            bytes.push(EXPRESSION_END);
+
+            // address += 8
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_address);
+            bytes.push(INSTR_I32_CONST);
+            bytes.append(&mut encode_sleb128(8));
+            bytes.push(INSTR_I32_ADD);
+            bytes.push(INSTR_LOCAL_SET);
+            bytes.push(local_address);
+
+            // i++
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_i);
+            bytes.push(INSTR_I32_CONST);
+            bytes.append(&mut encode_sleb128(1));
+            bytes.push(INSTR_I32_ADD);
+            bytes.push(INSTR_LOCAL_TEE);
+            bytes.push(local_i);
+
+            // continue while i < n
+            bytes.push(INSTR_LOCAL_GET);
+            bytes.push(local_n);
+            bytes.push(INSTR_I32_LT_S);
+            bytes.push(INSTR_BR_IF);
+            bytes.push(0x00); // break depth
+            bytes.push(EXPRESSION_END);
+        }
+
+        // memory[header] = y_min; memory[header + 8] = y_max
+        bytes.push(INSTR_LOCAL_GET);
+        bytes.push(local_header);
+        bytes.push(INSTR_LOCAL_GET);
+        bytes.push(local_y_min);
+        bytes.push(MEMORY_F64_STORE);
+        bytes.push(0x03);
+        bytes.push(0x00);
+        bytes.push(INSTR_LOCAL_GET);
+        bytes.push(local_header);
+        bytes.push(INSTR_LOCAL_GET);
+        bytes.push(local_y_max);
+        bytes.push(MEMORY_F64_STORE);
+        bytes.push(0x03);
+        bytes.push(0x08);
+    }
+
+    // return n
+    bytes.push(INSTR_LOCAL_GET);
+    bytes.push(local_n);
+    bytes.push(INSTR_F64_CONVERT_I32_S);
+    bytes.push(EXPRESSION_END);
+    Ok(bytes)
+}
+
+fn emit_code_section(program: &Program) -> Result<Vec<u8>> {
+    // Has the code for all the functions
+    let mut bytes = Vec::new();
+    let mut function_count = 0;
+    // The user defined functions and then the plot functions
+    for function in &program.functions {
+        // the locals of the Sums come after the arguments, they are all f64
+        let local_count = function.locals.len() as u32 - function.argument_count;
+        let mut function_bytes = if local_count == 0 {
+            vec![0x00]
+        } else {
+            let mut locals = vec![0x01];
+            locals.append(&mut encode_leb128(local_count));
+            locals.push(F64_TYPE);
+            locals
+        };
+        function_bytes.append(&mut emit_code_for_expression(&function.body, 0)?);
+        // end
+        function_bytes.push(EXPRESSION_END);
+        // function size
+        bytes.append(&mut encode_leb128(function_bytes.len() as u32));
+        bytes.append(&mut function_bytes);
+        function_count += 1;
     }
+    // Finally the function redraw(width, height)
+    let mut function_bytes = emit_redraw_function(program)?;
+    bytes.append(&mut encode_leb128(function_bytes.len() as u32));
+    bytes.append(&mut function_bytes);
+    function_count += 1;
+
+    let mut code = encode_leb128(function_count);
+    code.append(&mut bytes);
     let mut result = vec![SECTION_CODE];
-    let bytes_count = bytes.len() as u32;
-    result.append(&mut encode_leb128(bytes_count));
-    result.append(&mut encode_leb128(function_count + plot_function_count));
-    result.append(&mut bytes);
+    result.append(&mut encode_leb128(code.len() as u32));
+    result.append(&mut code);
     Ok(result)
 }
 
-pub(crate) fn emit_code(node: &ProgramNode, symbol_table: &SymbolTable) -> Result<Vec<u8>, String> {
+/// The arguments of the Print statements that could not be evaluated at compile time
+/// because they depend on the sliders. The module prints them calling the imported
+/// function `console.print(index, value)`, where `index` is the position in this list.
+pub(crate) fn runtime_prints(root: &ProgramNode) -> Vec<&ExpressionNode> {
+    let mut prints = Vec::new();
+    for statement in &root.statements {
+        if let StatementKind::PrintStatement { argument } = &statement.kind {
+            if !matches!(argument.kind, ExpressionKind::Number(_)) {
+                prints.push(argument);
+            }
+        }
+    }
+    prints
+}
+
+/// The module of the program as written, without inlining, so the tests can call every function
+#[cfg(test)]
+pub(crate) fn emit_code(
+    node: &ProgramNode,
+    symbol_table: &crate::analyzer::SymbolTable,
+) -> Result<Vec<u8>> {
+    emit_program(&crate::ir::lower_program(node, symbol_table)?)
+}
+
+/// The wasm module of a lowered program
+pub(crate) fn emit_program(program: &Program) -> Result<Vec<u8>> {
     let mut result = vec![
         0x00, 0x61, 0x73, 0x6d, // module header
         0x01, 0x00, 0x00, 0x00, // module version
     ];
     let mut signatures = Vec::new();
-    let mut constants = Vec::new();
-    let mut functions = Vec::new();
-    for function in &symbol_table.builtins {
-        functions.push(function.name().to_string());
-    }
-    for function in &symbol_table.functions {
-        functions.push(function.name.clone());
-    }
-    result.append(&mut emit_type_section(symbol_table, &mut signatures)?);
-    result.append(&mut emit_imports_section(
-        node,
-        symbol_table,
-        &signatures,
-        &mut constants,
-    )?);
-    result.append(&mut emit_function_section(node, &signatures)?);
-    result.append(&mut emit_global_section(node)?);
-    result.append(&mut emit_memory_section(node)?);
-    result.append(&mut emit_export_section(node)?);
-    result.append(&mut emit_code_section(node, symbol_table, &functions)?);
+    // The function index space: first the imported builtins and print, then the user defined
+    // functions, the plot functions and the last one is redraw(width, height)
+    result.append(&mut emit_type_section(program, &mut signatures)?);
+    result.append(&mut emit_imports_section(program, &signatures)?);
+    result.append(&mut emit_function_section(program, &signatures)?);
+    result.append(&mut emit_memory_section(program)?);
+    result.append(&mut emit_export_section(program)?);
+    result.append(&mut emit_code_section(program)?);
 
+    // A module the browser would reject is a bug here, not in the program
+    if let Err(message) = validate(&result) {
+        return Err(InternalCompilerError { message }.into());
+    }
     Ok(result)
 }
@@ -1,10 +1,12 @@
 use std::{error, fmt::Display};
 
+use crate::tokens::Span;
+
 pub(crate) type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
 
 #[derive(Debug)]
 pub struct ParserError {
-    pub position: usize,
+    pub span: Span,
     pub message: String,
 }
 
@@ -18,7 +20,7 @@ impl error::Error for ParserError {}
 
 #[derive(Debug)]
 pub struct LexerError {
-    pub position: usize,
+    pub span: Span,
     pub message: String,
 }
 
@@ -32,6 +34,7 @@ impl error::Error for LexerError {}
 
 #[derive(Debug)]
 pub(crate) struct SemanticError {
+    pub span: Span,
     pub message: String,
 }
 
@@ -45,6 +48,7 @@ impl error::Error for SemanticError {}
 
 #[derive(Debug)]
 pub(crate) struct EvaluationError {
+    pub span: Span,
     pub message: String,
 }
 
@@ -55,3 +59,120 @@ impl Display for EvaluationError {
 }
 
 impl error::Error for EvaluationError {}
+
+#[derive(Debug)]
+pub(crate) struct EmitterError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Display for EmitterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Emitter error: '{}'", self.message)
+    }
+}
+
+impl error::Error for EmitterError {}
+
+/// A bug in the compiler, the emitter wrote an invalid module
+#[derive(Debug)]
+pub(crate) struct InternalCompilerError {
+    pub message: String,
+}
+
+impl Display for InternalCompilerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Internal compiler error: '{}'", self.message)
+    }
+}
+
+impl error::Error for InternalCompilerError {}
+
+/// All the errors found in a single pass of the compiler
+#[derive(Debug)]
+pub(crate) struct ErrorList {
+    pub errors: Vec<Box<dyn error::Error>>,
+}
+
+impl Display for ErrorList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<String> = self.errors.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", messages.join("\n"))
+    }
+}
+
+impl error::Error for ErrorList {}
+
+/// Ok if there are no errors, the error itself if there is only one and an ErrorList otherwise
+pub(crate) fn collect_errors(mut errors: Vec<Box<dyn error::Error>>) -> Result<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(ErrorList { errors }.into()),
+    }
+}
+
+fn error_span(error: &(dyn error::Error + 'static)) -> Option<Span> {
+    if let Some(e) = error.downcast_ref::<ParserError>() {
+        Some(e.span)
+    } else if let Some(e) = error.downcast_ref::<LexerError>() {
+        Some(e.span)
+    } else if let Some(e) = error.downcast_ref::<SemanticError>() {
+        Some(e.span)
+    } else if let Some(e) = error.downcast_ref::<EvaluationError>() {
+        Some(e.span)
+    } else {
+        error.downcast_ref::<EmitterError>().map(|e| e.span)
+    }
+}
+
+/// Renders the error like rustc does, pointing to the offending code:
+///
+/// ```text
+/// Semantic error: 'Undefined variable: 'y''
+///  --> program.keith:2:8
+///   |
+/// 2 | f(x) = y*x
+///   |        ^
+/// ```
+pub(crate) fn render_error(
+    error: &(dyn error::Error + 'static),
+    file_name: &str,
+    source: &str,
+) -> String {
+    if let Some(list) = error.downcast_ref::<ErrorList>() {
+        let rendered: Vec<String> = list
+            .errors
+            .iter()
+            .map(|e| render_error(e.as_ref(), file_name, source))
+            .collect();
+        return format!(
+            "{}\n{} errors found\n",
+            rendered.join("\n"),
+            list.errors.len()
+        );
+    }
+    let span = match error_span(error) {
+        Some(span) => span,
+        None => return format!("{error}\n"),
+    };
+    let start = span.start.min(source.len());
+    let end = span.end.clamp(start, source.len());
+
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let line_number = source[..start].matches('\n').count() + 1;
+    let line = &source[line_start..line_end];
+    let column = source[line_start..start].chars().count() + 1;
+    // the underline does not go beyond the end of the line and has at least one caret
+    let underline_length = source[start..end.min(line_end)].chars().count().max(1);
+
+    let gutter = " ".repeat(line_number.to_string().len());
+    format!(
+        "{error}\n{gutter}--> {file_name}:{line_number}:{column}\n{gutter} |\n{line_number} | {line}\n{gutter} | {}{}\n",
+        " ".repeat(column - 1),
+        "^".repeat(underline_length)
+    )
+}
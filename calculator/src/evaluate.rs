@@ -1,52 +1,180 @@
+use crate::builtins::get_builtin_by_name;
 use crate::errors::{EvaluationError, Result};
-use crate::parser::{Operator, UnaryOperator};
+use crate::numeric::run;
+use crate::parser::{Comparator, ExpressionKind, Operator, UnaryOperator};
 use crate::{analyzer::Context, parser::ExpressionNode};
 
+/// Evaluates a constant expression, it can only depend on the globals and functions of the context
 pub(crate) fn evaluate_in_context(expr: &ExpressionNode, context: &Context) -> Result<f64> {
-    match expr {
-        ExpressionNode::Number(f) => Ok(*f),
-        ExpressionNode::Variable(name) => {
+    evaluate_with_bindings(expr, context, &[])
+}
+
+/// Evaluates an expression where the local variables (function arguments, Sum variables and sliders)
+/// have the values given in `bindings`. A binding shadows all the bindings with the same name before it.
+pub(crate) fn evaluate_with_bindings(
+    expr: &ExpressionNode,
+    context: &Context,
+    bindings: &[(String, f64)],
+) -> Result<f64> {
+    let span = expr.span;
+    match &expr.kind {
+        ExpressionKind::Number(f) => Ok(*f),
+        ExpressionKind::Variable(name) => {
+            if let Some((_, value)) = bindings.iter().rev().find(|(local, _)| local == name) {
+                return Ok(*value);
+            }
             for global in context.globals {
                 if &global.name == name {
                     return Ok(global.value);
                 }
             }
-            Err(EvaluationError{message: format!("Undefined variable: '{}'", name)}.into())
-        },
-        ExpressionNode::BinaryOp { op, left, right } => {
-            let l = evaluate_in_context(left, context)?;
-            let r = evaluate_in_context(right, context)?;
+            if context.sliders.iter().any(|slider| &slider.name == name) {
+                return Err(EvaluationError {
+                    span,
+                    message: format!("Cannot use slider '{}' in a constant expression", name),
+                }
+                .into());
+            }
+            Err(EvaluationError {
+                span,
+                message: format!("Undefined variable: '{}'", name),
+            }
+            .into())
+        }
+        ExpressionKind::BinaryOp { op, left, right } => {
+            let l = evaluate_with_bindings(left, context, bindings)?;
+            let r = evaluate_with_bindings(right, context, bindings)?;
             match op {
-                Operator::Plus => Ok(l+r),
-                Operator::Minus => Ok(l-r),
-                Operator::Times => Ok(l*r),
+                Operator::Plus => Ok(l + r),
+                Operator::Minus => Ok(l - r),
+                Operator::Times => Ok(l * r),
                 Operator::Divide => {
                     if r == 0.0 {
-                        return Err(EvaluationError{message: "Division by 0".to_string()}.into())
+                        return Err(EvaluationError {
+                            span,
+                            message: "Division by 0".to_string(),
+                        }
+                        .into());
                     }
-                    Ok(l/r)
-                },
+                    Ok(l / r)
+                }
                 Operator::Power => {
                     let result = l.powf(r);
                     Ok(result)
-                },
+                }
             }
-        },
-        ExpressionNode::UnaryOp { op, right } => {
-            let r = evaluate_in_context(right, context)?;
+        }
+        ExpressionKind::UnaryOp { op, right } => {
+            let r = evaluate_with_bindings(right, context, bindings)?;
             match op {
                 UnaryOperator::Plus => Ok(r),
                 UnaryOperator::Minus => Ok(-r),
             }
-        },
-        ExpressionNode::FunctionCall { .. } => {
-            Err(EvaluationError{message: "Cannot use functions in this context".to_string()}.into())
-        },
-        ExpressionNode::IfExpression { .. } => {
-            Err(EvaluationError{message: "Cannot use If expressions in this context".to_string()}.into())
-        },
-        ExpressionNode::SumExpression { .. } => {
-            Err(EvaluationError{message: "Cannot use Sum expressions in this context".to_string()}.into())
-        },
+        }
+        ExpressionKind::FunctionCall { name, args } => {
+            let mut values = Vec::new();
+            for arg in args {
+                values.push(evaluate_with_bindings(arg, context, bindings)?);
+            }
+            if let Some(builtin) = get_builtin_by_name(name) {
+                if values.len() != builtin.arg_count() as usize {
+                    return Err(EvaluationError {
+                        span,
+                        message: format!(
+                            "Expected {} arguments but got {}",
+                            builtin.arg_count(),
+                            values.len()
+                        ),
+                    }
+                    .into());
+                }
+                return Ok(builtin.evaluate(&values));
+            }
+            if let Some(function) = context.functions.iter().find(|f| &f.name == name) {
+                if values.len() != function.arguments.len() {
+                    return Err(EvaluationError {
+                        span,
+                        message: format!(
+                            "Expected {} arguments but got {}",
+                            function.arguments.len(),
+                            values.len()
+                        ),
+                    }
+                    .into());
+                }
+                // The body of the function sees the sliders and its arguments but not the locals of the caller
+                let mut function_bindings: Vec<(String, f64)> = bindings
+                    .iter()
+                    .filter(|(local, _)| context.sliders.iter().any(|s| &s.name == local))
+                    .cloned()
+                    .collect();
+                function_bindings.extend(function.arguments.iter().cloned().zip(values));
+                return evaluate_with_bindings(&function.value, context, &function_bindings);
+            }
+            Err(EvaluationError {
+                span,
+                message: format!("Undefined function: '{}'", name),
+            }
+            .into())
+        }
+        ExpressionKind::IfExpression {
+            condition,
+            if_true,
+            if_false,
+        } => {
+            let l = evaluate_with_bindings(&condition.left, context, bindings)?;
+            let r = evaluate_with_bindings(&condition.right, context, bindings)?;
+            let holds = match condition.op {
+                Comparator::Equal => l == r,
+                Comparator::NotEqual => l != r,
+                Comparator::LessThan => l < r,
+                Comparator::GreaterThan => l > r,
+                Comparator::LessThanOrEqual => l <= r,
+                Comparator::GreaterThanOrEqual => l >= r,
+            };
+            // Only the branch taken is evaluated
+            if holds {
+                evaluate_with_bindings(if_true, context, bindings)
+            } else {
+                evaluate_with_bindings(if_false, context, bindings)
+            }
+        }
+        ExpressionKind::SumExpression { value, range } => {
+            // Sum(value, {n, lower, upper}) adds value for n = lower, lower + 1, ... while n <= upper
+            let lower = evaluate_with_bindings(&range.lower, context, bindings)?;
+            let upper = evaluate_with_bindings(&range.upper, context, bindings)?;
+            let mut sum_bindings = bindings.to_vec();
+            sum_bindings.push((range.variable_name.clone(), lower));
+            let index = sum_bindings.len() - 1;
+            let mut sum = 0.0;
+            let mut n = lower;
+            while n <= upper {
+                sum_bindings[index].1 = n;
+                sum += evaluate_with_bindings(value, context, &sum_bindings)?;
+                n += 1.0;
+            }
+            Ok(sum)
+        }
+        ExpressionKind::Numeric {
+            method,
+            value,
+            range,
+        } => {
+            let lower = evaluate_with_bindings(&range.lower, context, bindings)?;
+            let upper = evaluate_with_bindings(&range.upper, context, bindings)?;
+            let mut numeric_bindings = bindings.to_vec();
+            numeric_bindings.push((range.variable_name.clone(), lower));
+            let index = numeric_bindings.len() - 1;
+            let f = |t| {
+                numeric_bindings[index].1 = t;
+                evaluate_with_bindings(value, context, &numeric_bindings)
+            };
+            run(&method.routine(), lower, upper, f)
+        }
+        ExpressionKind::Derivative { .. } => Err(EvaluationError {
+            span,
+            message: "Derivatives are not computed by the interpreter".to_string(),
+        }
+        .into()),
     }
 }
@@ -0,0 +1,318 @@
+// Inlining of user defined functions and dead function elimination, both on the IR.
+// Keith programs are made of many small helpers like `f(x) = x*x*a`. A call to a small function,
+// or to a function that is called only once, is replaced by the body of the function with the
+// arguments in place of the parameters, so plot functions become straight line code.
+// Then the user defined functions that no plot or print calls are removed.
+//
+// Functions can only call the functions defined before them, so inlining them in order means
+// that the callee has already been inlined into. Recursive functions are never inlined.
+
+use crate::ir::{Expression, Program};
+
+// Functions with at most this many nodes are inlined everywhere, larger ones only if called once
+const MAX_INLINED_SIZE: usize = 16;
+
+// Calls `visit` on every node, the children before their parent
+fn visit_mut(expression: &mut Expression, visit: &mut impl FnMut(&mut Expression)) {
+    for child in expression.children_mut() {
+        visit_mut(child, visit);
+    }
+    visit(expression);
+}
+
+// The functions called by the expression, with repetitions
+fn calls(expression: &Expression, functions: &mut Vec<u32>) {
+    if let Expression::Call { function, .. } = expression {
+        functions.push(*function);
+    }
+    for child in expression.children() {
+        calls(child, functions);
+    }
+}
+
+// How many times the value of the local is read. A read in the body of a Sum or a Numeric
+// counts as many
+fn local_reads(expression: &Expression, local: u32) -> usize {
+    match expression {
+        Expression::Local(index) if *index == local => 1,
+        Expression::Sum {
+            lower,
+            upper,
+            value,
+            ..
+        }
+        | Expression::Numeric {
+            lower,
+            upper,
+            value,
+            ..
+        } => {
+            let in_loop = match local_reads(value, local) {
+                0 => 0,
+                _ => 2,
+            };
+            local_reads(lower, local) + local_reads(upper, local) + in_loop
+        }
+        _ => expression
+            .children()
+            .into_iter()
+            .map(|child| local_reads(child, local))
+            .sum(),
+    }
+}
+
+// An argument that is as cheap to compute as to read from a local can be copied anywhere
+fn is_trivial(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::Number(_) | Expression::Local(_) | Expression::Global(_)
+    )
+}
+
+// The body of a function where the parameters are replaced by the arguments and its other
+// locals, the ones of its Sums and Numerics, are moved to `first_local` and up in the caller
+fn substitute(expression: &Expression, arguments: &[Expression], first_local: u32) -> Expression {
+    let argument_count = arguments.len() as u32;
+    let local = |index: u32| index - argument_count + first_local;
+    let substitute =
+        |expression: &Expression| Box::new(substitute(expression, arguments, first_local));
+    match expression {
+        Expression::Number(_) | Expression::Global(_) => expression.clone(),
+        Expression::Local(index) if *index < argument_count => arguments[*index as usize].clone(),
+        Expression::Local(index) => Expression::Local(local(*index)),
+        Expression::Negate(right) => Expression::Negate(substitute(right)),
+        Expression::Binary { op, left, right } => Expression::Binary {
+            op: *op,
+            left: substitute(left),
+            right: substitute(right),
+        },
+        Expression::Call {
+            function,
+            arguments: call_arguments,
+        } => Expression::Call {
+            function: *function,
+            arguments: call_arguments
+                .iter()
+                .map(|argument| *substitute(argument))
+                .collect(),
+        },
+        Expression::If {
+            comparator,
+            left,
+            right,
+            if_true,
+            if_false,
+        } => Expression::If {
+            comparator: comparator.clone(),
+            left: substitute(left),
+            right: substitute(right),
+            if_true: substitute(if_true),
+            if_false: substitute(if_false),
+        },
+        Expression::Sum {
+            variable,
+            total,
+            limit,
+            lower,
+            upper,
+            value,
+        } => Expression::Sum {
+            variable: local(*variable),
+            total: local(*total),
+            limit: local(*limit),
+            lower: substitute(lower),
+            upper: substitute(upper),
+            value: substitute(value),
+        },
+        Expression::Numeric {
+            method,
+            variable,
+            lower,
+            upper,
+            value,
+        } => Expression::Numeric {
+            method: *method,
+            variable: local(*variable),
+            lower: substitute(lower),
+            upper: substitute(upper),
+            value: substitute(value),
+        },
+        Expression::Tee {
+            local: index,
+            value,
+        } => Expression::Tee {
+            local: local(*index),
+            value: substitute(value),
+        },
+        Expression::Let {
+            local: index,
+            value,
+            body,
+        } => Expression::Let {
+            local: local(*index),
+            value: substitute(value),
+            body: substitute(body),
+        },
+    }
+}
+
+// True for each function that can call itself
+fn recursive_functions(program: &Program) -> Vec<bool> {
+    let first = program.imports.len() as u32;
+    let callees: Vec<Vec<u32>> = program
+        .functions
+        .iter()
+        .map(|function| {
+            let mut functions = Vec::new();
+            calls(&function.body, &mut functions);
+            functions
+                .into_iter()
+                .filter(|index| *index >= first)
+                .map(|index| index - first)
+                .collect()
+        })
+        .collect();
+    (0..callees.len())
+        .map(|start| {
+            let mut seen = vec![false; callees.len()];
+            let mut pending = callees[start].clone();
+            while let Some(function) = pending.pop() {
+                let function = function as usize;
+                if function == start {
+                    return true;
+                }
+                if !seen[function] {
+                    seen[function] = true;
+                    pending.extend(&callees[function]);
+                }
+            }
+            false
+        })
+        .collect()
+}
+
+/// Replaces the calls to small functions and to the functions called only once by their bodies
+pub(crate) fn inline_functions(program: &mut Program) {
+    let first = program.imports.len() as u32;
+    let recursive = recursive_functions(program);
+    let mut call_counts = vec![0; program.functions.len()];
+    let mut called = Vec::new();
+    for function in &program.functions {
+        calls(&function.body, &mut called);
+    }
+    for print in &program.prints {
+        calls(print, &mut called);
+    }
+    for function in called {
+        if function >= first {
+            call_counts[(function - first) as usize] += 1;
+        }
+    }
+
+    // Inlines into a body whose locals are `locals`
+    let inline = |body: &mut Expression, locals: &mut Vec<String>, program: &Program| {
+        visit_mut(body, &mut |expression| {
+            let Expression::Call {
+                function,
+                arguments,
+            } = expression
+            else {
+                return;
+            };
+            let Some(index) = function.checked_sub(first) else {
+                return;
+            };
+            let callee = &program.functions[index as usize];
+            if recursive[index as usize]
+                || (callee.body.size() > MAX_INLINED_SIZE && call_counts[index as usize] > 1)
+            {
+                return;
+            }
+            // Copying an argument that is read several times would compute it again each time
+            let copies_work = arguments.iter().enumerate().any(|(parameter, argument)| {
+                !is_trivial(argument) && local_reads(&callee.body, parameter as u32) > 1
+            });
+            if copies_work {
+                return;
+            }
+            let first_local = locals.len() as u32;
+            locals.extend(
+                callee.locals[callee.argument_count as usize..]
+                    .iter()
+                    .cloned(),
+            );
+            *expression = substitute(&callee.body, arguments, first_local);
+        });
+    };
+
+    for index in 0..program.functions.len() {
+        let mut body =
+            std::mem::replace(&mut program.functions[index].body, Expression::Number(0.0));
+        let mut locals = std::mem::take(&mut program.functions[index].locals);
+        inline(&mut body, &mut locals, program);
+        program.functions[index].body = body;
+        program.functions[index].locals = locals;
+    }
+    let mut prints = std::mem::take(&mut program.prints);
+    let mut print_locals = std::mem::take(&mut program.print_locals);
+    for print in &mut prints {
+        inline(print, &mut print_locals, program);
+    }
+    program.prints = prints;
+    program.print_locals = print_locals;
+}
+
+/// Removes the user defined functions that are not called by a plot or a print
+pub(crate) fn remove_dead_functions(program: &mut Program) {
+    let first = program.imports.len() as u32;
+    let mut live = vec![false; program.functions.len()];
+    let mut pending: Vec<u32> = program
+        .plots
+        .iter()
+        .flat_map(|plot| plot.functions.iter().copied())
+        .collect();
+    for print in &program.prints {
+        calls(print, &mut pending);
+    }
+    while let Some(function) = pending.pop() {
+        let Some(index) = function.checked_sub(first) else {
+            continue;
+        };
+        if !live[index as usize] {
+            live[index as usize] = true;
+            calls(&program.functions[index as usize].body, &mut pending);
+        }
+    }
+
+    // The new index of each function that is kept
+    let mut new_indices = Vec::new();
+    let mut next = first;
+    for is_live in &live {
+        new_indices.push(next);
+        if *is_live {
+            next += 1;
+        }
+    }
+    let mut renumber = |expression: &mut Expression| {
+        if let Expression::Call { function, .. } = expression {
+            if *function >= first {
+                *function = new_indices[(*function - first) as usize];
+            }
+        }
+    };
+    let mut functions = std::mem::take(&mut program.functions);
+    let mut is_live = live.iter();
+    functions.retain(|_| *is_live.next().unwrap());
+    for function in &mut functions {
+        visit_mut(&mut function.body, &mut renumber);
+    }
+    for print in &mut program.prints {
+        visit_mut(print, &mut renumber);
+    }
+    for plot in &mut program.plots {
+        for function in &mut plot.functions {
+            *function = new_indices[(*function - first) as usize];
+        }
+    }
+    program.functions = functions;
+}
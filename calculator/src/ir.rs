@@ -0,0 +1,579 @@
+// The intermediate representation between the analyzed AST and the backends.
+// Lowering resolves every name: arguments and Sum variables become locals of the function,
+// sliders become globals, constants are replaced by their value and calls (including `^`,
+// a call to Pow) refer to functions by their index. Every value is an f64.
+//
+// The function index space is the same as in the wasm module: first the imports, then the user
+// defined functions and then one function for each plotted expression.
+
+use std::collections::HashMap;
+
+use crate::{
+    analyzer::SymbolTable,
+    builtins::Builtin,
+    colormap::ColorMap,
+    errors::{EmitterError, Result},
+    numeric::Method,
+    parser::{
+        Comparator, ExpressionKind, ExpressionNode, Operator, ProgramNode, StatementKind,
+        UnaryOperator,
+    },
+    tokens::Span,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Import {
+    Builtin(Builtin),
+    // print(index, value), prints the value of the Print statement `index`
+    Print,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expression {
+    Number(f64),
+    // an argument or the variable of a Sum
+    Local(u32),
+    // a slider
+    Global(u32),
+    Negate(Box<Expression>),
+    Binary {
+        op: BinaryOperator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    Call {
+        function: u32,
+        arguments: Vec<Expression>,
+    },
+    If {
+        comparator: Comparator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+        if_true: Box<Expression>,
+        if_false: Box<Expression>,
+    },
+    // A loop adding `value` for `variable` = lower, lower + 1, ... while `variable` <= upper.
+    // The bounds are evaluated once, before the loop, `limit` keeps the upper bound and
+    // `total` the sum so far, all three are locals of the function
+    Sum {
+        variable: u32,
+        total: u32,
+        limit: u32,
+        lower: Box<Expression>,
+        upper: Box<Expression>,
+        value: Box<Expression>,
+    },
+    // The routine of the method for `value` of `variable` from lower to upper, see `numeric`. The
+    // registers of the routine are `variable` and the locals that follow it
+    Numeric {
+        method: Method,
+        variable: u32,
+        lower: Box<Expression>,
+        upper: Box<Expression>,
+        value: Box<Expression>,
+    },
+    // `value` is stored in `local` and is also the result, later reads of it are `Local(local)`
+    Tee {
+        local: u32,
+        value: Box<Expression>,
+    },
+    // Stores `value` in `local` and then evaluates `body`, which reads it
+    Let {
+        local: u32,
+        value: Box<Expression>,
+        body: Box<Expression>,
+    },
+}
+
+impl Expression {
+    /// The operands, in the order they are evaluated
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Number(_) | Expression::Local(_) | Expression::Global(_) => vec![],
+            Expression::Negate(right) => vec![right],
+            Expression::Binary { left, right, .. } => vec![left, right],
+            Expression::Call { arguments, .. } => arguments.iter().collect(),
+            Expression::If {
+                left,
+                right,
+                if_true,
+                if_false,
+                ..
+            } => vec![left, right, if_true, if_false],
+            Expression::Sum {
+                lower,
+                upper,
+                value,
+                ..
+            }
+            | Expression::Numeric {
+                lower,
+                upper,
+                value,
+                ..
+            } => vec![lower, upper, value],
+            Expression::Tee { value, .. } => vec![value],
+            Expression::Let { value, body, .. } => vec![value, body],
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            Expression::Number(_) | Expression::Local(_) | Expression::Global(_) => vec![],
+            Expression::Negate(right) => vec![right],
+            Expression::Binary { left, right, .. } => vec![left, right],
+            Expression::Call { arguments, .. } => arguments.iter_mut().collect(),
+            Expression::If {
+                left,
+                right,
+                if_true,
+                if_false,
+                ..
+            } => vec![left, right, if_true, if_false],
+            Expression::Sum {
+                lower,
+                upper,
+                value,
+                ..
+            }
+            | Expression::Numeric {
+                lower,
+                upper,
+                value,
+                ..
+            } => vec![lower, upper, value],
+            Expression::Tee { value, .. } => vec![value],
+            Expression::Let { value, body, .. } => vec![value, body],
+        }
+    }
+
+    /// The number of nodes
+    pub fn size(&self) -> usize {
+        1 + self
+            .children()
+            .into_iter()
+            .map(Expression::size)
+            .sum::<usize>()
+    }
+}
+
+pub(crate) struct Function {
+    pub name: String,
+    pub argument_count: u32,
+    // the names of the locals, first the arguments and then the locals of the Sums and the
+    // common subexpressions
+    pub locals: Vec<String>,
+    pub body: Expression,
+    // where the function is defined in the source code
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum PlotKind {
+    // the curves y = f(x)
+    Graph,
+    // the curves (x(t), y(t)), each one has two functions, the one of x and then the one of y
+    Parametric,
+    // the curves r(θ), redraw writes the points (r(θ)*Cos(θ), r(θ)*Sin(θ)) like a Parametric plot
+    Polar,
+    // the curves F(x, y) = level, the only function is F and x is in the range of the plot
+    Contour {
+        y_min: f64,
+        y_max: f64,
+        levels: Vec<f64>,
+    },
+    // the values F(x, y) as colors, the only function is F and x is in the range of the plot
+    Density {
+        y_min: f64,
+        y_max: f64,
+        color_map: ColorMap,
+    },
+}
+
+pub(crate) struct Plot {
+    pub kind: PlotKind,
+    // the range of the variable
+    pub x_min: f64,
+    pub x_max: f64,
+    // the function that computes each plotted expression
+    pub functions: Vec<u32>,
+}
+
+pub(crate) struct Program {
+    pub imports: Vec<Import>,
+    pub sliders: Vec<String>,
+    pub functions: Vec<Function>,
+    pub plots: Vec<Plot>,
+    // the Print statements that depend on the sliders, printed by redraw
+    pub prints: Vec<Expression>,
+    // the locals of the print expressions, the locals of their Sums
+    pub print_locals: Vec<String>,
+}
+
+impl Program {
+    /// The index of redraw(width, height), which comes after every other function
+    pub fn redraw_index(&self) -> u32 {
+        (self.imports.len() + self.functions.len()) as u32
+    }
+}
+
+// What a name refers to while lowering the body of a function
+struct Scope<'a> {
+    symbol_table: &'a SymbolTable,
+    // the index of each function by name
+    functions: &'a HashMap<String, u32>,
+    // the locals of the function, the arguments come first
+    locals: Vec<String>,
+    argument_count: usize,
+    // the Sums around the expression being lowered, (name, local), innermost last
+    sum_variables: Vec<(String, u32)>,
+}
+
+impl Scope<'_> {
+    fn add_local(&mut self, name: String) -> u32 {
+        self.locals.push(name);
+        self.locals.len() as u32 - 1
+    }
+}
+
+fn expect_number(node: &ExpressionNode) -> Result<f64> {
+    match node.kind {
+        ExpressionKind::Number(f) => Ok(f),
+        _ => Err(EmitterError {
+            span: node.span,
+            message: "Expecting number at this point".to_string(),
+        }
+        .into()),
+    }
+}
+
+fn lower_expression(node: &ExpressionNode, scope: &mut Scope) -> Result<Expression> {
+    let expression = match &node.kind {
+        ExpressionKind::Number(f) => Expression::Number(*f),
+        ExpressionKind::Variable(name) => {
+            let symbol_table = scope.symbol_table;
+            // The variable of a Sum shadows the arguments
+            if let Some((_, local)) = scope.sum_variables.iter().rev().find(|(n, _)| n == name) {
+                Expression::Local(*local)
+            } else if let Some(index) = scope.locals[..scope.argument_count]
+                .iter()
+                .position(|s| s == name)
+            {
+                Expression::Local(index as u32)
+            } else if let Some(global) = symbol_table.globals.iter().find(|g| &g.name == name) {
+                Expression::Number(global.value)
+            } else if let Some(index) = symbol_table.sliders.iter().position(|s| &s.name == name) {
+                Expression::Global(index as u32)
+            } else {
+                return Err(EmitterError {
+                    span: node.span,
+                    message: format!("Unrecognized variable name '{name}'"),
+                }
+                .into());
+            }
+        }
+        ExpressionKind::BinaryOp { op, left, right } => {
+            let left = Box::new(lower_expression(left, scope)?);
+            let right = Box::new(lower_expression(right, scope)?);
+            let op = match op {
+                Operator::Plus => BinaryOperator::Add,
+                Operator::Minus => BinaryOperator::Subtract,
+                Operator::Times => BinaryOperator::Multiply,
+                Operator::Divide => BinaryOperator::Divide,
+                Operator::Power => {
+                    let function = match scope.functions.get("Pow") {
+                        Some(function) => *function,
+                        None => {
+                            return Err(EmitterError {
+                                span: node.span,
+                                message: "Pow has not been imported".to_string(),
+                            }
+                            .into())
+                        }
+                    };
+                    return Ok(Expression::Call {
+                        function,
+                        arguments: vec![*left, *right],
+                    });
+                }
+            };
+            Expression::Binary { op, left, right }
+        }
+        ExpressionKind::UnaryOp { op, right } => {
+            let right = lower_expression(right, scope)?;
+            match op {
+                UnaryOperator::Plus => right,
+                UnaryOperator::Minus => Expression::Negate(Box::new(right)),
+            }
+        }
+        ExpressionKind::FunctionCall { name, args } => {
+            let function = match scope.functions.get(name) {
+                Some(function) => *function,
+                None => {
+                    return Err(EmitterError {
+                        span: node.span,
+                        message: format!("Unrecognized function name '{name}'"),
+                    }
+                    .into())
+                }
+            };
+            let mut arguments = Vec::new();
+            for arg in args {
+                arguments.push(lower_expression(arg, scope)?);
+            }
+            Expression::Call {
+                function,
+                arguments,
+            }
+        }
+        ExpressionKind::IfExpression {
+            condition,
+            if_true,
+            if_false,
+        } => Expression::If {
+            comparator: condition.op.clone(),
+            left: Box::new(lower_expression(&condition.left, scope)?),
+            right: Box::new(lower_expression(&condition.right, scope)?),
+            if_true: Box::new(lower_expression(if_true, scope)?),
+            if_false: Box::new(lower_expression(if_false, scope)?),
+        },
+        ExpressionKind::SumExpression { value, range } => {
+            // The bounds are outside of the scope of the variable
+            let lower = lower_expression(&range.lower, scope)?;
+            let upper = lower_expression(&range.upper, scope)?;
+            let name = &range.variable_name;
+            let variable = scope.add_local(name.clone());
+            let total = scope.add_local(format!("{name}_total"));
+            let limit = scope.add_local(format!("{name}_limit"));
+            scope.sum_variables.push((name.clone(), variable));
+            let value = lower_expression(value, scope)?;
+            scope.sum_variables.pop();
+            Expression::Sum {
+                variable,
+                total,
+                limit,
+                lower: Box::new(lower),
+                upper: Box::new(upper),
+                value: Box::new(value),
+            }
+        }
+        ExpressionKind::Numeric {
+            method,
+            value,
+            range,
+        } => {
+            let lower = lower_expression(&range.lower, scope)?;
+            let upper = lower_expression(&range.upper, scope)?;
+            let name = &range.variable_name;
+            let variable = scope.add_local(name.clone());
+            for register in &method.routine().registers[1..] {
+                scope.add_local(format!("{name}_{register}"));
+            }
+            scope.sum_variables.push((name.clone(), variable));
+            let value = lower_expression(value, scope)?;
+            scope.sum_variables.pop();
+            Expression::Numeric {
+                method: *method,
+                variable,
+                lower: Box::new(lower),
+                upper: Box::new(upper),
+                value: Box::new(value),
+            }
+        }
+        ExpressionKind::Derivative { .. } => {
+            return Err(EmitterError {
+                span: node.span,
+                message: "The derivative has not been computed".to_string(),
+            }
+            .into())
+        }
+    };
+    Ok(expression)
+}
+
+// Lowers the body of a function with the given arguments, returns the body and the locals
+fn lower_function_body(
+    node: &ExpressionNode,
+    arguments: &[String],
+    symbol_table: &SymbolTable,
+    functions: &HashMap<String, u32>,
+) -> Result<(Expression, Vec<String>)> {
+    let mut scope = Scope {
+        symbol_table,
+        functions,
+        locals: arguments.to_vec(),
+        argument_count: arguments.len(),
+        sum_variables: Vec::new(),
+    };
+    let body = lower_expression(node, &mut scope)?;
+    Ok((body, scope.locals))
+}
+
+/// Lowers an analyzed program, the input of every optimization and of the wasm backend
+pub(crate) fn lower_program(root: &ProgramNode, symbol_table: &SymbolTable) -> Result<Program> {
+    let mut imports: Vec<Import> = symbol_table
+        .builtins
+        .iter()
+        .map(|builtin| Import::Builtin(*builtin))
+        .collect();
+    let has_runtime_prints = root.statements.iter().any(|statement| {
+        matches!(&statement.kind, StatementKind::PrintStatement { argument }
+            if !matches!(argument.kind, ExpressionKind::Number(_)))
+    });
+    if has_runtime_prints {
+        imports.push(Import::Print);
+    }
+
+    // user defined functions start with a lowercase letter, so "Print" is not taken
+    let mut function_indices = HashMap::new();
+    for (index, import) in imports.iter().enumerate() {
+        let name = match import {
+            Import::Builtin(builtin) => builtin.name(),
+            Import::Print => "Print",
+        };
+        function_indices.insert(name.to_string(), index as u32);
+    }
+    for (index, function) in symbol_table.functions.iter().enumerate() {
+        function_indices.insert(function.name.clone(), (imports.len() + index) as u32);
+    }
+
+    let mut functions = Vec::new();
+    for statement in &root.statements {
+        if let StatementKind::FunctionDeclaration {
+            name,
+            arguments,
+            value,
+        } = &statement.kind
+        {
+            let (body, locals) =
+                lower_function_body(value, arguments, symbol_table, &function_indices)?;
+            functions.push(Function {
+                name: name.clone(),
+                argument_count: arguments.len() as u32,
+                locals,
+                body,
+                span: statement.span,
+            });
+        }
+    }
+
+    // Each plotted expression is a function of the plot variable, or of both variables of a
+    // ContourPlot or a DensityPlot
+    let user_function_count = functions.len();
+    let mut plots = Vec::new();
+    for statement in &root.statements {
+        let mut arguments = Vec::new();
+        let (kind, range, values): (_, _, Vec<&ExpressionNode>) = match &statement.kind {
+            StatementKind::PlotStatement {
+                functions, x_range, ..
+            } => (
+                PlotKind::Graph,
+                x_range,
+                functions.iter().map(|function| &function.value).collect(),
+            ),
+            StatementKind::ParametricPlotStatement { functions, range } => (
+                PlotKind::Parametric,
+                range,
+                functions
+                    .iter()
+                    .flat_map(|function| [&function.x, &function.y])
+                    .collect(),
+            ),
+            StatementKind::PolarPlotStatement { functions, range } => (
+                PlotKind::Polar,
+                range,
+                functions.iter().map(|function| &function.value).collect(),
+            ),
+            StatementKind::ContourPlotStatement {
+                value,
+                x_range,
+                y_range,
+                levels,
+                ..
+            } => {
+                arguments.push(y_range.variable_name.clone());
+                let kind = PlotKind::Contour {
+                    y_min: expect_number(&y_range.lower)?,
+                    y_max: expect_number(&y_range.upper)?,
+                    levels: levels.iter().map(expect_number).collect::<Result<_>>()?,
+                };
+                (kind, x_range, vec![value])
+            }
+            StatementKind::DensityPlotStatement {
+                value,
+                x_range,
+                y_range,
+                color_map,
+            } => {
+                arguments.push(y_range.variable_name.clone());
+                let kind = PlotKind::Density {
+                    y_min: expect_number(&y_range.lower)?,
+                    y_max: expect_number(&y_range.upper)?,
+                    color_map: *color_map,
+                };
+                (kind, x_range, vec![value])
+            }
+            _ => continue,
+        };
+        arguments.insert(0, range.variable_name.clone());
+        let mut plot = Plot {
+            kind,
+            x_min: expect_number(&range.lower)?,
+            x_max: expect_number(&range.upper)?,
+            functions: Vec::new(),
+        };
+        for value in values {
+            let plot_function = functions.len() - user_function_count;
+            let (body, locals) =
+                lower_function_body(value, &arguments, symbol_table, &function_indices)?;
+            plot.functions
+                .push((imports.len() + functions.len()) as u32);
+            functions.push(Function {
+                name: format!("plot{plot_function}"),
+                argument_count: arguments.len() as u32,
+                locals,
+                body,
+                span: value.span,
+            });
+        }
+        plots.push(plot);
+    }
+
+    // The print expressions are evaluated in redraw, they only have the locals of their Sums
+    let mut scope = Scope {
+        symbol_table,
+        functions: &function_indices,
+        locals: Vec::new(),
+        argument_count: 0,
+        sum_variables: Vec::new(),
+    };
+    let mut prints = Vec::new();
+    for statement in &root.statements {
+        if let StatementKind::PrintStatement { argument } = &statement.kind {
+            if !matches!(argument.kind, ExpressionKind::Number(_)) {
+                prints.push(lower_expression(argument, &mut scope)?);
+            }
+        }
+    }
+
+    Ok(Program {
+        imports,
+        sliders: symbol_table
+            .sliders
+            .iter()
+            .map(|slider| slider.name.clone())
+            .collect(),
+        functions,
+        plots,
+        prints,
+        print_locals: scope.locals,
+    })
+}
@@ -1,4 +1,4 @@
-use crate::tokens::Token;
+use crate::tokens::{Comment, Span, SpannedToken, Token};
 
 enum NumberParseState {
     State1,
@@ -13,23 +13,42 @@ enum NumberParseState {
 
 pub struct Lexer {
     input_chars: Vec<char>,
-    previous_position: usize,
+    // byte offset in the input text of each char (and of the end of the text)
+    byte_offsets: Vec<usize>,
     position: usize,
+    // comments found so far
+    pub comments: Vec<Comment>,
 }
 
 impl Lexer {
     pub fn new(input_text: &str) -> Lexer {
         let input_chars: Vec<char> = input_text.chars().collect();
+        let mut byte_offsets: Vec<usize> = input_text.char_indices().map(|(i, _)| i).collect();
+        byte_offsets.push(input_text.len());
         Lexer {
             input_chars,
-            previous_position: 0,
+            byte_offsets,
             position: 0,
+            comments: Vec::new(),
         }
     }
 
-    pub fn next_token(&mut self) -> Token {
-        self.previous_position = self.position;
-        self.consume_whitespace();
+    pub fn next_token(&mut self) -> SpannedToken {
+        if let Err(start) = self.consume_whitespace() {
+            return SpannedToken {
+                token: Token::Illegal("Unterminated comment".to_string()),
+                span: Span::new(self.byte_offsets[start], self.byte_offsets[self.position]),
+            };
+        }
+        let start = self.position;
+        let token = self.read_token();
+        SpannedToken {
+            token,
+            span: Span::new(self.byte_offsets[start], self.byte_offsets[self.position]),
+        }
+    }
+
+    fn read_token(&mut self) -> Token {
         match self.read_next_char() {
             Some(ch) => match ch {
                 '+' => Token::Plus,
@@ -93,10 +112,6 @@ impl Lexer {
         }
     }
 
-    pub fn get_position(&self) -> usize {
-        self.position
-    }
-
     fn read_name(&mut self) -> Token {
         // A valid function name starts with an upper letter and it is followed
         // by [a-z][A-Z]_[0-9]
@@ -127,13 +142,54 @@ impl Lexer {
         self.input_chars.get(self.position)
     }
 
-    fn consume_whitespace(&mut self) {
+    // Skips whitespace (but not new lines) and comments.
+    // On an unterminated block comment returns the position where it starts.
+    fn consume_whitespace(&mut self) -> Result<(), usize> {
         while let Some(&char) = self.input_chars.get(self.position) {
-            if !char.is_whitespace() || char == '\n' {
+            if char == '/' && self.input_chars.get(self.position + 1) == Some(&'/') {
+                // line comment, the new line is not part of the comment
+                let start = self.position;
+                while let Some(&c) = self.input_chars.get(self.position) {
+                    if c == '\n' {
+                        break;
+                    }
+                    self.position += 1;
+                }
+                self.add_comment(start);
+            } else if char == '/' && self.input_chars.get(self.position + 1) == Some(&'*') {
+                let start = self.position;
+                self.position += 2;
+                loop {
+                    match self.input_chars.get(self.position) {
+                        Some('*') if self.input_chars.get(self.position + 1) == Some(&'/') => {
+                            self.position += 2;
+                            break;
+                        }
+                        Some(_) => self.position += 1,
+                        None => return Err(start),
+                    }
+                }
+                self.add_comment(start);
+            } else if char.is_whitespace() && char != '\n' {
+                self.position += 1;
+            } else {
                 break;
             }
-            self.position += 1;
         }
+        Ok(())
+    }
+
+    fn add_comment(&mut self, start: usize) {
+        let own_line = self.input_chars[..start]
+            .iter()
+            .rev()
+            .take_while(|&&c| c != '\n')
+            .all(|c| c.is_whitespace());
+        self.comments.push(Comment {
+            text: self.input_chars[start..self.position].iter().collect(),
+            span: Span::new(self.byte_offsets[start], self.byte_offsets[self.position]),
+            own_line,
+        });
     }
 
     // We use a standard parser.
@@ -2,43 +2,270 @@
 // mod evaluate;
 mod analyzer;
 mod builtins;
+mod colormap;
+mod cse;
+mod derivative;
+mod disasm;
+mod driver;
 mod emitter;
 mod errors;
 mod evaluate;
+mod inliner;
+mod ir;
 mod lexer;
+mod numeric;
 mod opcodes;
 mod parser;
 mod pretty_print;
-mod tokens;
+mod svg;
 #[cfg(test)]
 mod test;
+mod tokens;
+mod validator;
+mod wasm_decoder;
+#[cfg(test)]
+mod wasm_interpreter;
+mod wat;
 
-use std::{env, fs};
+use std::{env, error::Error, fs, path::Path, process};
 
 use crate::{
-    analyzer::analyze_program, emitter::emit_code, parser::Parser, pretty_print::pretty_print,
+    analyzer::{analyze_program, simplify_program, SymbolTable},
+    cse::eliminate_common_subexpressions,
+    disasm::disassemble,
+    driver::emit_driver,
+    emitter::emit_program,
+    errors::{collect_errors, render_error},
+    inliner::{inline_functions, remove_dead_functions},
+    ir::{lower_program, Program},
+    parser::{ExpressionKind, Parser, ProgramNode, StatementKind},
+    pretty_print::pretty_print,
+    svg::render_svg,
+    wat::emit_wat,
 };
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let arg_count = args.len();
-    if arg_count < 2 {
-        panic!("Usage keithc program.keith");
-    }
-    let file_path = &args[1];
-    println!("In file {}", file_path);
+const USAGE: &str = "\
+Usage keithc [--emit=wasm|wat] [--no-simplify] [--no-inline] [--no-cse] program.keith
+      keithc render [--no-simplify] program.keith -o plot.svg
+      keithc disasm module.wasm";
+
+fn fail(error: Box<dyn Error>, file_name: &str, source: &str) -> ! {
+    eprint!("{}", render_error(error.as_ref(), file_name, source));
+    process::exit(1);
+}
+
+// Parses, analyzes and simplifies the program, exits on error. Returns the source code too.
+// The simplification can be turned off with --no-simplify to debug the backends
+fn front_end(file_path: &Path, simplify: bool) -> (String, ProgramNode, SymbolTable) {
+    println!("In file {}", file_path.display());
 
     let contents = fs::read_to_string(file_path).expect("Failed reading file");
+    let file_name = file_path.display().to_string();
 
-    match Parser::parse(&contents) {
-        Ok(mut ast) => match analyze_program(&mut ast) {
-            Ok(symbol_table) => {
-                let code = emit_code(&ast, &symbol_table).expect("Error emitting code");
-                println!("{}", pretty_print(&ast));
-                println!("{:?}", code);
+    // every syntax error is reported, not only the first one
+    let (mut ast, errors) = Parser::parse_with_recovery(&contents);
+    if let Err(error) = collect_errors(errors) {
+        fail(error, &file_name, &contents);
+    }
+    let mut symbol_table = match analyze_program(&mut ast) {
+        Ok(symbol_table) => symbol_table,
+        Err(error) => fail(error, &file_name, &contents),
+    };
+    if simplify {
+        simplify_program(&mut ast, &mut symbol_table);
+    }
+    // Print statements that do not depend on the sliders have been evaluated by the analyzer
+    for statement in &ast.statements {
+        if let StatementKind::PrintStatement { argument } = &statement.kind {
+            if let ExpressionKind::Number(f) = argument.kind {
+                let span = argument.span;
+                println!("{} = {}", &contents[span.start..span.end], f);
             }
-            Err(error) => println!("Failed! {}", error),
-        },
-        Err(error) => println!("Failed: {}", error),
+        }
+    }
+    (contents, ast, symbol_table)
+}
+
+// The optimizations can be turned off to debug the compiler
+struct Optimizations {
+    simplify: bool,
+    inline: bool,
+    cse: bool,
+}
+
+// Lowers the program to the IR, inlines the small functions and computes the repeated
+// subexpressions once, exits on error
+fn middle_end(
+    ast: &ProgramNode,
+    symbol_table: &SymbolTable,
+    optimizations: &Optimizations,
+    file_path: &Path,
+    contents: &str,
+) -> Program {
+    let mut program = match lower_program(ast, symbol_table) {
+        Ok(program) => program,
+        Err(error) => fail(error, &file_path.display().to_string(), contents),
+    };
+    if optimizations.inline {
+        inline_functions(&mut program);
+        remove_dead_functions(&mut program);
+    }
+    if optimizations.cse {
+        eliminate_common_subexpressions(&mut program);
+    }
+    program
+}
+
+// keithc [--emit=wasm|wat] [--no-simplify] [--no-inline] [--no-cse] program.keith
+fn compile(args: &[String]) {
+    let mut file_path = None;
+    let mut emit_text = false;
+    let mut optimizations = Optimizations {
+        simplify: true,
+        inline: true,
+        cse: true,
+    };
+    for arg in args {
+        if arg == "--no-simplify" {
+            optimizations.simplify = false;
+        } else if arg == "--no-inline" {
+            optimizations.inline = false;
+        } else if arg == "--no-cse" {
+            optimizations.cse = false;
+        } else if let Some(format) = arg.strip_prefix("--emit=") {
+            emit_text = match format {
+                "wasm" => false,
+                "wat" => true,
+                _ => {
+                    eprintln!("Unknown output format '{format}', expected wasm or wat");
+                    process::exit(2);
+                }
+            };
+        } else {
+            file_path = Some(Path::new(arg));
+        }
+    }
+    let file_path = match file_path {
+        Some(file_path) => file_path,
+        None => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
+    };
+    if emit_text {
+        compile_to_text(file_path, &optimizations);
+        return;
+    }
+
+    // The wasm file and the html driver are written next to the source file
+    let wasm_path = file_path.with_extension("wasm");
+    let wasm_file_name = wasm_path.file_name().unwrap().to_string_lossy().to_string();
+    let html_path = file_path.with_file_name("index.html");
+
+    let (contents, ast, symbol_table) = front_end(file_path, optimizations.simplify);
+    println!("{}", pretty_print(&ast));
+    let program = middle_end(&ast, &symbol_table, &optimizations, file_path, &contents);
+    let code = match emit_program(&program) {
+        Ok(code) => code,
+        Err(error) => fail(error, &file_path.display().to_string(), &contents),
+    };
+    let html = match emit_driver(&ast, &symbol_table, &wasm_file_name) {
+        Ok(html) => html,
+        Err(error) => {
+            println!("Failed emitting driver: {}", error);
+            process::exit(1);
+        }
+    };
+
+    fs::write(&wasm_path, code).expect("Failed writing wasm file");
+    fs::write(&html_path, html).expect("Failed writing html file");
+    println!("Wrote {} and {}", wasm_path.display(), html_path.display());
+}
+
+// keithc --emit=wat program.keith writes the WebAssembly text format next to the source file
+fn compile_to_text(file_path: &Path, optimizations: &Optimizations) {
+    let wat_path = file_path.with_extension("wat");
+    let (contents, ast, symbol_table) = front_end(file_path, optimizations.simplify);
+    let program = middle_end(&ast, &symbol_table, optimizations, file_path, &contents);
+    let wat = match emit_wat(&program, &contents) {
+        Ok(wat) => wat,
+        Err(error) => fail(error, &file_path.display().to_string(), &contents),
+    };
+    fs::write(&wat_path, wat).expect("Failed writing wat file");
+    println!("Wrote {}", wat_path.display());
+}
+
+// keithc render [--no-simplify] program.keith [-o plot.svg]
+fn render(args: &[String]) {
+    let mut file_path = None;
+    let mut svg_path = None;
+    let mut simplify = true;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--no-simplify" {
+            simplify = false;
+        } else if arg == "-o" {
+            svg_path = args.next().map(Path::new);
+        } else {
+            file_path = Some(Path::new(arg));
+        }
+    }
+    let file_path = match file_path {
+        Some(file_path) => file_path,
+        None => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
+    };
+    // By default the image is written next to the source file
+    let svg_path = match svg_path {
+        Some(svg_path) => svg_path.to_path_buf(),
+        None => file_path.with_extension("svg"),
+    };
+
+    let (_, ast, symbol_table) = front_end(file_path, simplify);
+    let svg = match render_svg(&ast, &symbol_table) {
+        Ok(svg) => svg,
+        Err(error) => {
+            println!("Failed rendering: {}", error);
+            process::exit(1);
+        }
+    };
+    fs::write(&svg_path, svg).expect("Failed writing svg file");
+    println!("Wrote {}", svg_path.display());
+}
+
+// keithc disasm module.wasm prints the sections and the code of any wasm binary
+fn disasm(args: &[String]) {
+    let file_path = match args {
+        [file_path] => Path::new(file_path),
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
+    };
+    let bytes = fs::read(file_path).expect("Failed reading file");
+    let file_name = file_path.file_name().unwrap().to_string_lossy();
+    match disassemble(&bytes, &file_name) {
+        Ok(text) => print!("{text}"),
+        Err(error) => {
+            eprintln!("Failed decoding {}: {}", file_path.display(), error);
+            process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("{USAGE}");
+        process::exit(2);
+    }
+    if args[1] == "render" {
+        render(&args[2..]);
+    } else if args[1] == "disasm" {
+        disasm(&args[2..]);
+    } else {
+        compile(&args[1..]);
     }
 }
@@ -0,0 +1,739 @@
+// Numerical methods on an expression of one variable over a range: `Integrate(value, {t, a, b})`,
+// `Solve(left = right, {x, a, b})`, `FindMinimum(value, {x, a, b})` and `FindMaximum`.
+//
+// Each method is written once, as a `Routine`: a small program on f64 registers. The interpreter
+// runs it with `run` and the emitter compiles it to wasm where the registers are locals of the
+// function, so both compute the same values with the operations in the same order. Register 0 is
+// the variable, registers 1 and 2 start with the lower and the upper bound and `Evaluate` stores
+// the value of the expression at the variable. A routine evaluates the expression in a single
+// place, because the emitter writes the code of the expression there and a copy in each place
+// would grow exponentially with nested methods. A routine that needs the values at several points
+// evaluates in a loop and keeps in a register which value the turn computes.
+//
+// Integrate is adaptive Simpson. It compares Simpson's rule on a piece with the sum of the rule on
+// its two halves and cuts the piece in two when they differ by more than 15 times its part of the
+// tolerance, which halves with the width, so only the pieces where the function is hard are cut.
+// The pieces are visited from left to right as the leaves of a binary tree and the stack of the
+// right halves still to visit is the index i of the piece at its depth: the piece is a right half
+// when i is odd and going up divides i by 2. Each piece is cut at least MIN_DEPTH times so that a
+// function sampled where it is 0 by chance does not stop early, and at most MAX_DEPTH times.
+// Each turn of its loop computes one of the five values of a piece.
+//
+// Solve and FindMinimum are Brent's methods, as in Numerical Recipes (zbrent and brent). Solve
+// keeps the root between b and c, where the value changes sign, and moves b by inverse
+// quadratic interpolation, or by bisection when that would be slow. It is NaN when the sign does
+// not change between the bounds. FindMinimum is a golden section search that steps to the
+// minimum of the parabola through the last three points when it is inside the interval, it
+// finds a local minimum. FindMaximum is FindMinimum of the opposite value. Both give the x.
+
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::{errors::Result, ir::BinaryOperator, parser::Comparator};
+
+/// The tolerance when `Integrate` does not have a `tolerance` option
+pub(crate) const DEFAULT_TOLERANCE: f64 = 1e-8;
+// Integrate cuts the range in at least 2^MIN_DEPTH pieces and the smallest pieces are 2^MAX_DEPTH
+// times narrower than the range
+const MIN_DEPTH: f64 = 4.0;
+const MAX_DEPTH: f64 = 14.0;
+// Solve stops when the root is known within this distance
+const ROOT_TOLERANCE: f64 = 1e-12;
+// FindMinimum stops when the minimum is known within this distance relative to x, about the
+// square root of the precision of an f64 because the values near a minimum are almost equal,
+// plus an absolute distance for a minimum at 0
+const MINIMUM_TOLERANCE: f64 = 3e-8;
+const MINIMUM_ABSOLUTE_TOLERANCE: f64 = 1e-10;
+// (3 - √5)/2, the golden section of an interval
+const GOLDEN_SECTION: f64 = 0.381_966_011_250_105_1;
+// The most values Solve and FindMinimum compute, they are NaN if they have not converged
+const MAX_ITERATIONS: f64 = 200.0;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Method {
+    Integrate { tolerance: f64 },
+    // the root of the value, `Solve(left = right, range)` is the root of left - right
+    Solve,
+    FindMinimum,
+    FindMaximum,
+}
+
+impl Method {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Integrate { .. } => "Integrate",
+            Method::Solve => "Solve",
+            Method::FindMinimum => "FindMinimum",
+            Method::FindMaximum => "FindMaximum",
+        }
+    }
+
+    /// The method called `name`, with the default options
+    pub(crate) fn from_name(name: &str) -> Option<Method> {
+        match name {
+            "Integrate" => Some(Method::Integrate {
+                tolerance: DEFAULT_TOLERANCE,
+            }),
+            "Solve" => Some(Method::Solve),
+            "FindMinimum" => Some(Method::FindMinimum),
+            "FindMaximum" => Some(Method::FindMaximum),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn routine(&self) -> Routine {
+        match self {
+            Method::Integrate { tolerance } => integrate(*tolerance),
+            Method::Solve => solve(),
+            Method::FindMinimum => find_minimum(false),
+            Method::FindMaximum => find_minimum(true),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Value {
+    Number(f64),
+    Register(u32),
+    Abs(Box<Value>),
+    // the smallest integer that is not less than the value
+    Ceil(Box<Value>),
+    Binary(BinaryOperator, Box<Value>, Box<Value>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Condition {
+    Compare(Comparator, Value, Value),
+    // both sides are computed
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Statement {
+    Set(u32, Value),
+    // Stores the value of the expression at the variable (register 0) in the register
+    Evaluate(u32),
+    If(Condition, Vec<Statement>, Vec<Statement>),
+    While(Condition, Vec<Statement>),
+    // Leaves the innermost While
+    Break,
+}
+
+pub(crate) struct Routine {
+    // the names of the registers, the first one is the variable
+    pub registers: Vec<&'static str>,
+    pub code: Vec<Statement>,
+    // the register that has the result at the end
+    pub result: u32,
+}
+
+impl Add for Value {
+    type Output = Value;
+    fn add(self, right: Value) -> Value {
+        Value::Binary(BinaryOperator::Add, Box::new(self), Box::new(right))
+    }
+}
+
+impl Sub for Value {
+    type Output = Value;
+    fn sub(self, right: Value) -> Value {
+        Value::Binary(BinaryOperator::Subtract, Box::new(self), Box::new(right))
+    }
+}
+
+impl Mul for Value {
+    type Output = Value;
+    fn mul(self, right: Value) -> Value {
+        Value::Binary(BinaryOperator::Multiply, Box::new(self), Box::new(right))
+    }
+}
+
+impl Div for Value {
+    type Output = Value;
+    fn div(self, right: Value) -> Value {
+        Value::Binary(BinaryOperator::Divide, Box::new(self), Box::new(right))
+    }
+}
+
+fn v(register: u32) -> Value {
+    Value::Register(register)
+}
+
+fn number(f: f64) -> Value {
+    Value::Number(f)
+}
+
+fn abs(value: Value) -> Value {
+    Value::Abs(Box::new(value))
+}
+
+fn ceil(value: Value) -> Value {
+    Value::Ceil(Box::new(value))
+}
+
+fn compare(left: Value, comparator: Comparator, right: Value) -> Condition {
+    Condition::Compare(comparator, left, right)
+}
+
+fn and(left: Condition, right: Condition) -> Condition {
+    Condition::And(Box::new(left), Box::new(right))
+}
+
+fn or(left: Condition, right: Condition) -> Condition {
+    Condition::Or(Box::new(left), Box::new(right))
+}
+
+fn set(register: u32, value: Value) -> Statement {
+    Statement::Set(register, value)
+}
+
+fn when(condition: Condition, if_true: Vec<Statement>, if_false: Vec<Statement>) -> Statement {
+    Statement::If(condition, if_true, if_false)
+}
+
+// Runs the k-th statements when the register is k, and the last ones when it is larger. There are
+// at least two cases.
+fn cases(register: u32, cases: Vec<Vec<Statement>>) -> Statement {
+    let mut cases = cases.into_iter().enumerate().rev();
+    let (_, mut statements) = cases.next().unwrap();
+    for (k, case) in cases {
+        statements = vec![when(
+            compare(v(register), Comparator::Equal, number(k as f64)),
+            case,
+            statements,
+        )];
+    }
+    statements.pop().unwrap()
+}
+
+// Numbers the registers in order from 0 and keeps their names in `$names`
+macro_rules! registers {
+    ($names:ident: $($register:ident),*) => {
+        let $names = vec![$(stringify!($register)),*];
+        let mut next = 0..;
+        $(let $register: u32 = next.next().unwrap();)*
+    };
+}
+
+fn integrate(tolerance: f64) -> Routine {
+    use Comparator::*;
+    // The current piece is the i-th of the 2^depth pieces of width h, fa, fm and fb are the values
+    // at its left end, middle and right end, fl and fr the ones at a quarter and at three quarters,
+    // whole is its Simpson estimate and limit the largest change accepted for it. Each turn of the
+    // loop evaluates the expression at t and `next` tells which value it is: 0 for fa, 1 for fm,
+    // 2 for fb, 3 for fl and 4 for fr. h starts as the upper bound.
+    registers!(
+        names: t, a, h, i, depth, left, next, value, fa, fl, fm, fr, fb, whole, halves, change,
+        limit, sum
+    );
+    let estimate = || {
+        set(
+            whole,
+            v(h) / number(6.0) * (v(fa) + number(4.0) * v(fm) + v(fb)),
+        )
+    };
+    // the value the next turn computes and the point where it is
+    let then = |value: f64, point: Value| vec![set(next, number(value)), set(t, point)];
+    let middle = || v(left) + v(h) / number(2.0);
+    let quarter = || v(left) + v(h) / number(4.0);
+    // goes on with the left half, whose fa, fm and fb are known
+    let cut = [
+        vec![
+            set(depth, v(depth) + number(1.0)),
+            set(i, v(i) * number(2.0)),
+            set(h, v(h) / number(2.0)),
+            set(limit, v(limit) / number(2.0)),
+            set(fb, v(fm)),
+            set(fm, v(fl)),
+            estimate(),
+        ],
+        then(3.0, quarter()),
+    ]
+    .concat();
+    // adds the halves, which are more precise than the whole by the change divided by 15, and goes
+    // up while the piece is a right half, to the next piece whose fa is known
+    let accept = [
+        vec![
+            set(sum, v(sum) + v(halves) + v(change) / number(15.0)),
+            set(fa, v(fb)),
+            Statement::While(
+                compare(ceil(v(i) / number(2.0)), NotEqual, v(i) / number(2.0)),
+                vec![
+                    set(depth, v(depth) - number(1.0)),
+                    set(i, (v(i) - number(1.0)) / number(2.0)),
+                    set(h, v(h) * number(2.0)),
+                    set(limit, v(limit) * number(2.0)),
+                ],
+            ),
+            when(
+                compare(v(depth), Equal, number(0.0)),
+                vec![Statement::Break],
+                vec![],
+            ),
+            set(i, v(i) + number(1.0)),
+            set(left, v(a) + v(h) * v(i)),
+        ],
+        then(1.0, middle()),
+    ]
+    .concat();
+    let values = vec![
+        [vec![set(fa, v(value))], then(1.0, middle())].concat(),
+        [
+            vec![set(fm, v(value))],
+            then(2.0, v(a) + v(h) * (v(i) + number(1.0))),
+        ]
+        .concat(),
+        [vec![set(fb, v(value)), estimate()], then(3.0, quarter())].concat(),
+        [
+            vec![set(fl, v(value))],
+            then(4.0, v(left) + v(h) * number(3.0) / number(4.0)),
+        ]
+        .concat(),
+        vec![
+            set(fr, v(value)),
+            set(
+                halves,
+                v(h) / number(12.0)
+                    * (v(fa)
+                        + number(4.0) * v(fl)
+                        + number(2.0) * v(fm)
+                        + number(4.0) * v(fr)
+                        + v(fb)),
+            ),
+            set(change, v(halves) - v(whole)),
+            // cuts the piece when the change is large, it is accepted when it is NaN
+            when(
+                or(
+                    compare(v(depth), LessThan, number(MIN_DEPTH)),
+                    and(
+                        compare(abs(v(change)), GreaterThan, v(limit)),
+                        compare(v(depth), LessThan, number(MAX_DEPTH)),
+                    ),
+                ),
+                cut,
+                accept,
+            ),
+        ],
+    ];
+    let code = vec![
+        set(h, v(h) - v(a)),
+        set(i, number(0.0)),
+        set(depth, number(0.0)),
+        set(left, v(a)),
+        set(limit, number(15.0 * tolerance)),
+        set(sum, number(0.0)),
+        set(t, v(a)),
+        set(next, number(0.0)),
+        Statement::While(
+            compare(v(depth), GreaterThanOrEqual, number(0.0)),
+            vec![Statement::Evaluate(value), cases(next, values)],
+        ),
+    ];
+    Routine {
+        registers: names,
+        code,
+        result: sum,
+    }
+}
+
+fn solve() -> Routine {
+    use Comparator::*;
+    // fb is the value at b, fa the one at the previous b and fc the one at c, d is the last step
+    // and e the one before, the root is NaN until it is found
+    registers!(names: x, a, b, c, fa, fb, fc, d, e, p, q, r, s, tolerance, middle, count, root);
+    // the value at a, then the one at b and then the ones at the next b
+    let next = vec![
+        when(
+            compare(v(count), Equal, number(2.0)),
+            vec![when(
+                compare(v(fa) * v(fb), LessThanOrEqual, number(0.0)),
+                vec![set(c, v(b)), set(fc, v(fb))],
+                vec![Statement::Break],
+            )],
+            vec![],
+        ),
+        when(
+            compare(v(fb) * v(fc), GreaterThan, number(0.0)),
+            vec![
+                set(c, v(a)),
+                set(fc, v(fa)),
+                set(d, v(b) - v(a)),
+                set(e, v(d)),
+            ],
+            vec![],
+        ),
+        when(
+            compare(abs(v(fc)), LessThan, abs(v(fb))),
+            vec![
+                set(a, v(b)),
+                set(b, v(c)),
+                set(c, v(a)),
+                set(fa, v(fb)),
+                set(fb, v(fc)),
+                set(fc, v(fa)),
+            ],
+            vec![],
+        ),
+        set(
+            tolerance,
+            number(2.0 * f64::EPSILON) * abs(v(b)) + number(0.5 * ROOT_TOLERANCE),
+        ),
+        set(middle, number(0.5) * (v(c) - v(b))),
+        when(
+            or(
+                compare(abs(v(middle)), LessThanOrEqual, v(tolerance)),
+                compare(v(fb), Equal, number(0.0)),
+            ),
+            vec![set(root, v(b)), Statement::Break],
+            vec![],
+        ),
+        when(
+            and(
+                compare(abs(v(e)), GreaterThanOrEqual, v(tolerance)),
+                compare(abs(v(fa)), GreaterThan, abs(v(fb))),
+            ),
+            vec![
+                set(s, v(fb) / v(fa)),
+                when(
+                    compare(v(a), Equal, v(c)),
+                    // linear interpolation
+                    vec![
+                        set(p, number(2.0) * v(middle) * v(s)),
+                        set(q, number(1.0) - v(s)),
+                    ],
+                    // inverse quadratic interpolation
+                    vec![
+                        set(q, v(fa) / v(fc)),
+                        set(r, v(fb) / v(fc)),
+                        set(
+                            p,
+                            v(s) * (number(2.0) * v(middle) * v(q) * (v(q) - v(r))
+                                - (v(b) - v(a)) * (v(r) - number(1.0))),
+                        ),
+                        set(
+                            q,
+                            (v(q) - number(1.0)) * (v(r) - number(1.0)) * (v(s) - number(1.0)),
+                        ),
+                    ],
+                ),
+                when(
+                    compare(v(p), GreaterThan, number(0.0)),
+                    vec![set(q, number(0.0) - v(q))],
+                    vec![],
+                ),
+                set(p, abs(v(p))),
+                // the interpolation stays between b and c and converges fast enough
+                when(
+                    and(
+                        compare(
+                            number(2.0) * v(p),
+                            LessThan,
+                            number(3.0) * v(middle) * v(q) - abs(v(tolerance) * v(q)),
+                        ),
+                        compare(number(2.0) * v(p), LessThan, abs(v(e) * v(q))),
+                    ),
+                    vec![set(e, v(d)), set(d, v(p) / v(q))],
+                    vec![set(d, v(middle)), set(e, v(d))],
+                ),
+            ],
+            // bisection
+            vec![set(d, v(middle)), set(e, v(d))],
+        ),
+        set(a, v(b)),
+        set(fa, v(fb)),
+        // a step of at least the tolerance
+        when(
+            compare(abs(v(d)), GreaterThan, v(tolerance)),
+            vec![set(b, v(b) + v(d))],
+            vec![when(
+                compare(v(middle), GreaterThan, number(0.0)),
+                vec![set(b, v(b) + v(tolerance))],
+                vec![set(b, v(b) - v(tolerance))],
+            )],
+        ),
+        set(x, v(b)),
+    ];
+    let code = vec![
+        set(root, number(f64::NAN)),
+        set(d, number(0.0)),
+        set(e, number(0.0)),
+        set(count, number(0.0)),
+        set(x, v(a)),
+        Statement::While(
+            compare(v(count), LessThan, number(MAX_ITERATIONS)),
+            vec![
+                Statement::Evaluate(fb),
+                set(count, v(count) + number(1.0)),
+                when(
+                    compare(v(count), Equal, number(1.0)),
+                    vec![set(fa, v(fb)), set(x, v(b))],
+                    next,
+                ),
+            ],
+        ),
+    ];
+    Routine {
+        registers: names,
+        code,
+        result: root,
+    }
+}
+
+fn find_minimum(maximum: bool) -> Routine {
+    use Comparator::*;
+    // the minimum is between a and b, x is the lowest point so far, w the second lowest and z
+    // the previous w, fx, fw, fz and fu are their values and the one at u, d is the last step
+    // and e the one before, the minimum is NaN until it is found
+    registers!(names: u, a, b, x, w, z, fx, fw, fz, fu, d, e, p, q, r, middle, tolerance, count, minimum);
+    let golden_section = vec![
+        when(
+            compare(v(x), GreaterThanOrEqual, v(middle)),
+            vec![set(e, v(a) - v(x))],
+            vec![set(e, v(b) - v(x))],
+        ),
+        set(d, number(GOLDEN_SECTION) * v(e)),
+    ];
+    let mut body = vec![Statement::Evaluate(fu)];
+    if maximum {
+        body.push(set(fu, number(0.0) - v(fu)));
+    }
+    body.extend([
+        set(count, v(count) + number(1.0)),
+        when(
+            compare(v(count), Equal, number(1.0)),
+            vec![
+                set(x, v(u)),
+                set(w, v(u)),
+                set(z, v(u)),
+                set(fx, v(fu)),
+                set(fw, v(fu)),
+                set(fz, v(fu)),
+            ],
+            vec![when(
+                compare(v(fu), LessThanOrEqual, v(fx)),
+                vec![
+                    when(
+                        compare(v(u), GreaterThanOrEqual, v(x)),
+                        vec![set(a, v(x))],
+                        vec![set(b, v(x))],
+                    ),
+                    set(z, v(w)),
+                    set(w, v(x)),
+                    set(x, v(u)),
+                    set(fz, v(fw)),
+                    set(fw, v(fx)),
+                    set(fx, v(fu)),
+                ],
+                vec![
+                    when(
+                        compare(v(u), LessThan, v(x)),
+                        vec![set(a, v(u))],
+                        vec![set(b, v(u))],
+                    ),
+                    when(
+                        or(
+                            compare(v(fu), LessThanOrEqual, v(fw)),
+                            compare(v(w), Equal, v(x)),
+                        ),
+                        vec![set(z, v(w)), set(w, v(u)), set(fz, v(fw)), set(fw, v(fu))],
+                        vec![when(
+                            or(
+                                compare(v(fu), LessThanOrEqual, v(fz)),
+                                or(compare(v(z), Equal, v(x)), compare(v(z), Equal, v(w))),
+                            ),
+                            vec![set(z, v(u)), set(fz, v(fu))],
+                            vec![],
+                        )],
+                    ),
+                ],
+            )],
+        ),
+        set(middle, number(0.5) * (v(a) + v(b))),
+        set(
+            tolerance,
+            number(MINIMUM_TOLERANCE) * abs(v(x)) + number(MINIMUM_ABSOLUTE_TOLERANCE),
+        ),
+        when(
+            compare(
+                abs(v(x) - v(middle)),
+                LessThanOrEqual,
+                number(2.0) * v(tolerance) - number(0.5) * (v(b) - v(a)),
+            ),
+            vec![set(minimum, v(x)), Statement::Break],
+            vec![],
+        ),
+        when(
+            compare(abs(v(e)), GreaterThan, v(tolerance)),
+            vec![
+                // the minimum of the parabola through x, w and z is x + p/q
+                set(r, (v(x) - v(w)) * (v(fx) - v(fz))),
+                set(q, (v(x) - v(z)) * (v(fx) - v(fw))),
+                set(p, (v(x) - v(z)) * v(q) - (v(x) - v(w)) * v(r)),
+                set(q, number(2.0) * (v(q) - v(r))),
+                when(
+                    compare(v(q), GreaterThan, number(0.0)),
+                    vec![set(p, number(0.0) - v(p))],
+                    vec![],
+                ),
+                set(q, abs(v(q))),
+                set(r, v(e)),
+                set(e, v(d)),
+                // it is used when it is inside the interval and the step is less than half the
+                // one before the last
+                when(
+                    or(
+                        compare(
+                            abs(v(p)),
+                            GreaterThanOrEqual,
+                            abs(number(0.5) * v(q) * v(r)),
+                        ),
+                        or(
+                            compare(v(p), LessThanOrEqual, v(q) * (v(a) - v(x))),
+                            compare(v(p), GreaterThanOrEqual, v(q) * (v(b) - v(x))),
+                        ),
+                    ),
+                    golden_section.clone(),
+                    vec![
+                        set(d, v(p) / v(q)),
+                        set(u, v(x) + v(d)),
+                        // not too close to the ends
+                        when(
+                            or(
+                                compare(v(u) - v(a), LessThan, number(2.0) * v(tolerance)),
+                                compare(v(b) - v(u), LessThan, number(2.0) * v(tolerance)),
+                            ),
+                            vec![when(
+                                compare(v(middle), GreaterThanOrEqual, v(x)),
+                                vec![set(d, v(tolerance))],
+                                vec![set(d, number(0.0) - v(tolerance))],
+                            )],
+                            vec![],
+                        ),
+                    ],
+                ),
+            ],
+            golden_section,
+        ),
+        // a step of at least the tolerance
+        when(
+            compare(abs(v(d)), GreaterThanOrEqual, v(tolerance)),
+            vec![set(u, v(x) + v(d))],
+            vec![when(
+                compare(v(d), GreaterThanOrEqual, number(0.0)),
+                vec![set(u, v(x) + v(tolerance))],
+                vec![set(u, v(x) - v(tolerance))],
+            )],
+        ),
+    ]);
+    let code = vec![
+        when(
+            compare(v(b), LessThan, v(a)),
+            vec![set(u, v(a)), set(a, v(b)), set(b, v(u))],
+            vec![],
+        ),
+        set(minimum, number(f64::NAN)),
+        set(d, number(0.0)),
+        set(e, number(0.0)),
+        set(count, number(0.0)),
+        // the first point is at the golden section
+        set(u, v(a) + number(GOLDEN_SECTION) * (v(b) - v(a))),
+        Statement::While(compare(v(count), LessThan, number(MAX_ITERATIONS)), body),
+    ];
+    Routine {
+        registers: names,
+        code,
+        result: minimum,
+    }
+}
+
+// Whether the statements ended with a Break
+enum Flow {
+    Next,
+    Break,
+}
+
+fn value(value: &Value, registers: &[f64]) -> f64 {
+    match value {
+        Value::Number(f) => *f,
+        Value::Register(register) => registers[*register as usize],
+        Value::Abs(value) => self::value(value, registers).abs(),
+        Value::Ceil(value) => self::value(value, registers).ceil(),
+        Value::Binary(op, left, right) => {
+            let left = self::value(left, registers);
+            let right = self::value(right, registers);
+            match op {
+                BinaryOperator::Add => left + right,
+                BinaryOperator::Subtract => left - right,
+                BinaryOperator::Multiply => left * right,
+                BinaryOperator::Divide => left / right,
+            }
+        }
+    }
+}
+
+fn holds(condition: &Condition, registers: &[f64]) -> bool {
+    match condition {
+        Condition::Compare(comparator, left, right) => {
+            let left = value(left, registers);
+            let right = value(right, registers);
+            match comparator {
+                Comparator::Equal => left == right,
+                Comparator::NotEqual => left != right,
+                Comparator::LessThan => left < right,
+                Comparator::GreaterThan => left > right,
+                Comparator::LessThanOrEqual => left <= right,
+                Comparator::GreaterThanOrEqual => left >= right,
+            }
+        }
+        Condition::And(left, right) => holds(left, registers) & holds(right, registers),
+        Condition::Or(left, right) => holds(left, registers) | holds(right, registers),
+    }
+}
+
+fn execute(
+    statements: &[Statement],
+    registers: &mut [f64],
+    f: &mut impl FnMut(f64) -> Result<f64>,
+) -> Result<Flow> {
+    for statement in statements {
+        match statement {
+            Statement::Set(register, value) => {
+                registers[*register as usize] = self::value(value, registers)
+            }
+            Statement::Evaluate(register) => registers[*register as usize] = f(registers[0])?,
+            Statement::If(condition, if_true, if_false) => {
+                let branch = if holds(condition, registers) {
+                    if_true
+                } else {
+                    if_false
+                };
+                if let Flow::Break = execute(branch, registers, f)? {
+                    return Ok(Flow::Break);
+                }
+            }
+            Statement::While(condition, body) => {
+                while holds(condition, registers) {
+                    if let Flow::Break = execute(body, registers, f)? {
+                        break;
+                    }
+                }
+            }
+            Statement::Break => return Ok(Flow::Break),
+        }
+    }
+    Ok(Flow::Next)
+}
+
+/// Runs the routine for the expression `f` of the variable, from `lower` to `upper`
+pub(crate) fn run(
+    routine: &Routine,
+    lower: f64,
+    upper: f64,
+    mut f: impl FnMut(f64) -> Result<f64>,
+) -> Result<f64> {
+    let mut registers = vec![0.0; routine.registers.len()];
+    registers[1] = lower;
+    registers[2] = upper;
+    execute(&routine.code, &mut registers, &mut f)?;
+    Ok(registers[routine.result as usize])
+}
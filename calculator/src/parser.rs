@@ -1,9 +1,14 @@
 use std::fmt::Display;
 
 use crate::errors::{LexerError, ParserError, Result};
-use crate::{lexer::Lexer, tokens::Token};
-
-#[derive(Debug, PartialEq)]
+use crate::{
+    colormap::ColorMap,
+    lexer::Lexer,
+    numeric::Method,
+    tokens::{Comment, Span, SpannedToken, Token},
+};
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Operator {
     Plus,
     Minus,
@@ -24,7 +29,7 @@ impl Display for Operator {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Comparator {
     Equal,
     NotEqual,
@@ -60,9 +65,9 @@ impl Default for Options {
         }
     }
 }
-
 pub struct ProgramNode {
     pub statements: Vec<StatementNode>,
+    pub comments: Vec<Comment>,
 }
 
 pub struct YRange {
@@ -75,7 +80,19 @@ pub struct PlotFunctionNode {
     pub options: Options,
 }
 
-pub enum StatementNode {
+// A curve of a ParametricPlot, the point (x, y) for each value of the parameter
+pub struct ParametricFunctionNode {
+    pub x: ExpressionNode,
+    pub y: ExpressionNode,
+    pub options: Options,
+}
+
+pub struct StatementNode {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+pub enum StatementKind {
     ConstantAssignment {
         name: String,
         value: ExpressionNode,
@@ -96,24 +113,64 @@ pub enum StatementNode {
         x_range: SumRange,
         y_range: Option<YRange>,
     },
+    ParametricPlotStatement {
+        functions: Vec<ParametricFunctionNode>,
+        range: SumRange,
+    },
+    // the curves r = r(θ) in polar coordinates, θ is the variable of the range
+    PolarPlotStatement {
+        functions: Vec<PlotFunctionNode>,
+        range: SumRange,
+    },
+    // ContourPlot(left = right, x_range, y_range, options), the curves where the value,
+    // left - right, is equal to one of the levels, 0 unless there is a `levels` option
+    ContourPlotStatement {
+        value: ExpressionNode,
+        x_range: SumRange,
+        y_range: SumRange,
+        levels: Vec<ExpressionNode>,
+        options: Options,
+    },
+    // DensityPlot(value, x_range, y_range, colormap="name"), the value at each pixel as a color
+    DensityPlotStatement {
+        value: ExpressionNode,
+        x_range: SumRange,
+        y_range: SumRange,
+        color_map: ColorMap,
+    },
     PrintStatement {
         argument: ExpressionNode,
     },
 }
 
+#[derive(Clone)]
 pub struct CompareNode {
     pub op: Comparator,
     pub left: Box<ExpressionNode>,
     pub right: Box<ExpressionNode>,
 }
 
+#[derive(Clone)]
 pub struct SumRange {
     pub variable_name: String,
     pub lower: Box<ExpressionNode>,
     pub upper: Box<ExpressionNode>,
 }
 
-pub enum ExpressionNode {
+#[derive(Clone)]
+pub struct ExpressionNode {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl ExpressionNode {
+    pub fn new(kind: ExpressionKind, span: Span) -> ExpressionNode {
+        ExpressionNode { kind, span }
+    }
+}
+
+#[derive(Clone)]
+pub enum ExpressionKind {
     Number(f64),
     Variable(String),
     BinaryOp {
@@ -138,9 +195,21 @@ pub enum ExpressionNode {
         value: Box<ExpressionNode>,
         range: SumRange,
     },
+    // Integrate(value, {variable, lower, upper}, tolerance=1e-8), Solve(left = right, range) whose
+    // value is left - right, FindMinimum(value, range) and FindMaximum(value, range), see `numeric`
+    Numeric {
+        method: Method,
+        value: Box<ExpressionNode>,
+        range: SumRange,
+    },
+    // D(value, variable), the analyzer replaces it by the derivative
+    Derivative {
+        value: Box<ExpressionNode>,
+        variable: String,
+    },
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum UnaryOperator {
     Plus,
     Minus,
@@ -157,11 +226,13 @@ impl Display for UnaryOperator {
 
 pub struct Parser {
     lexer: Lexer,
-    next_token: Token,
-    peek_token: Token,
+    next_token: SpannedToken,
+    peek_token: SpannedToken,
+    // span of the last token consumed
+    last_span: Span,
 }
 
-fn binding_power(op: &Operator) -> u8 {
+pub(crate) fn binding_power(op: &Operator) -> u8 {
     match op {
         Operator::Plus => 1,
         Operator::Minus => 1,
@@ -172,7 +243,18 @@ fn binding_power(op: &Operator) -> u8 {
 }
 
 impl Parser {
+    /// The program, or all its syntax errors
+    #[cfg(test)]
     pub fn parse(input_text: &str) -> Result<ProgramNode> {
+        let (program, errors) = Parser::parse_with_recovery(input_text);
+        crate::errors::collect_errors(errors)?;
+        Ok(program)
+    }
+
+    /// Parses the whole input even in the presence of syntax errors.
+    /// A statement with an error is skipped up to the next new line and parsing resumes there,
+    /// so the program returned contains only the statements that were parsed correctly.
+    pub fn parse_with_recovery(input_text: &str) -> (ProgramNode, Vec<Box<dyn std::error::Error>>) {
         let mut lexer = Lexer::new(input_text);
         let next_token = lexer.next_token();
         let peek_token = lexer.next_token();
@@ -180,15 +262,16 @@ impl Parser {
             lexer,
             next_token,
             peek_token,
+            last_span: Span::default(),
         };
         parser.parse_root()
     }
 
     fn expect_token(&mut self, token: Token) -> Result<()> {
-        if self.next_token != token {
+        if self.next_token.token != token {
             return Err(ParserError {
-                position: self.lexer.get_position(),
-                message: format!("Expected {} but got {}", token, self.next_token),
+                span: self.next_token.span,
+                message: format!("Expected {} but got {}", token, self.next_token.token),
             }
             .into());
         }
@@ -196,53 +279,117 @@ impl Parser {
         Ok(())
     }
 
+    fn unexpected_token(&self) -> Box<dyn std::error::Error> {
+        match &self.next_token.token {
+            Token::Illegal(message) => LexerError {
+                span: self.next_token.span,
+                message: message.to_string(),
+            }
+            .into(),
+            token => ParserError {
+                span: self.next_token.span,
+                message: format!("Unexpected token '{}'", token),
+            }
+            .into(),
+        }
+    }
+
+    // The span from the start of `start` to the end of the last token consumed
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.last_span)
+    }
+
     fn advance_new_lines(&mut self) {
-        while self.next_token == Token::NewLine {
+        while self.next_token.token == Token::NewLine {
+            self.advance_tokens();
+        }
+    }
+
+    // Skips all tokens up to the next new line (panic mode)
+    fn synchronize(&mut self) {
+        while self.next_token.token != Token::NewLine && self.next_token.token != Token::EoI {
             self.advance_tokens();
         }
     }
 
-    fn parse_root(&mut self) -> Result<ProgramNode> {
+    fn parse_root(&mut self) -> (ProgramNode, Vec<Box<dyn std::error::Error>>) {
         let mut statements = Vec::new();
-        while self.next_token != Token::EoI {
-            statements.push(self.parse_statement()?);
+        let mut errors = Vec::new();
+        self.advance_new_lines();
+        while self.next_token.token != Token::EoI {
+            match self.parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    errors.push(error);
+                    self.synchronize();
+                }
+            }
             self.advance_new_lines();
         }
-        Ok(ProgramNode { statements })
+        // By now the lexer has seen the whole input
+        let comments = std::mem::take(&mut self.lexer.comments);
+        (
+            ProgramNode {
+                statements,
+                comments,
+            },
+            errors,
+        )
     }
 
     fn parse_statement(&mut self) -> Result<StatementNode> {
-        let next_token = self.next_token.clone();
+        let start = self.next_token.span;
+        let kind = self.parse_statement_kind()?;
+        Ok(StatementNode {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    fn parse_statement_kind(&mut self) -> Result<StatementKind> {
+        let next_token = self.next_token.token.clone();
         if let Token::Name(name) = next_token {
             self.advance_tokens();
             if name == "Plot" {
                 self.expect_token(Token::OpenParenthesis)?;
                 return self.parse_plot_statement();
+            } else if name == "ParametricPlot" {
+                self.expect_token(Token::OpenParenthesis)?;
+                return self.parse_parametric_plot_statement();
+            } else if name == "PolarPlot" {
+                self.expect_token(Token::OpenParenthesis)?;
+                return self.parse_polar_plot_statement();
+            } else if name == "ContourPlot" {
+                self.expect_token(Token::OpenParenthesis)?;
+                return self.parse_contour_plot_statement();
+            } else if name == "DensityPlot" {
+                self.expect_token(Token::OpenParenthesis)?;
+                return self.parse_density_plot_statement();
             } else if name == "Print" {
                 self.expect_token(Token::OpenParenthesis)?;
                 let argument = self.parse_expression()?;
                 self.expect_token(Token::CloseParenthesis)?;
-                return Ok(StatementNode::PrintStatement { argument });
+                return Ok(StatementKind::PrintStatement { argument });
             }
-            if self.next_token == Token::OpenParenthesis {
+            if self.next_token.token == Token::OpenParenthesis {
                 // function definition
                 self.advance_tokens();
                 let mut arguments = Vec::new();
-                if let Token::Name(variable) = &self.next_token {
+                if let Token::Name(variable) = &self.next_token.token {
                     arguments.push(variable.to_string());
                     self.advance_tokens();
                 } else {
                     self.expect_token(Token::CloseParenthesis)?;
                     let value = self.parse_expression()?;
-                    return Ok(StatementNode::FunctionDeclaration {
+                    return Ok(StatementKind::FunctionDeclaration {
                         name,
                         arguments: Vec::new(),
                         value,
                     });
                 }
-                while self.next_token == Token::Comma {
+                while self.next_token.token == Token::Comma {
                     self.advance_tokens();
-                    if let Token::Name(variable) = &self.next_token {
+                    if let Token::Name(variable) = &self.next_token.token {
                         arguments.push(variable.to_string());
                         self.advance_tokens();
                     }
@@ -251,15 +398,15 @@ impl Parser {
                 self.expect_token(Token::Equal)?;
                 let value = self.parse_expression()?;
 
-                Ok(StatementNode::FunctionDeclaration {
+                Ok(StatementKind::FunctionDeclaration {
                     name,
                     arguments,
                     value,
                 })
-            } else if self.next_token == Token::Equal {
+            } else if self.next_token.token == Token::Equal {
                 // variable or slider
                 self.advance_tokens();
-                if self.next_token == Token::OpenBrace {
+                if self.next_token.token == Token::OpenBrace {
                     self.advance_tokens();
                     let default_value = self.parse_expression()?;
                     self.expect_token(Token::Comma)?;
@@ -268,110 +415,83 @@ impl Parser {
                     let maximum_value = self.parse_expression()?;
                     self.expect_token(Token::CloseBrace)?;
 
-                    return Ok(StatementNode::Slider {
+                    Ok(StatementKind::Slider {
                         name,
                         default_value,
                         minimum_value,
                         maximum_value,
-                    });
+                    })
                 } else {
                     let value = self.parse_expression()?;
-                    return Ok(StatementNode::ConstantAssignment { name, value });
+                    Ok(StatementKind::ConstantAssignment { name, value })
                 }
             } else {
-                Err(ParserError {
-                    position: self.lexer.get_position(),
-                    message: format!("Unexpected token '{}'", self.next_token),
-                }
-                .into())
+                Err(self.unexpected_token())
             }
         } else {
-            Err(ParserError {
-                position: self.lexer.get_position(),
-                message: format!("Unexpected token '{}'", self.next_token),
-            }
-            .into())
+            Err(self.unexpected_token())
         }
     }
 
     fn parse_name(&mut self) -> Result<String> {
-        if let Token::Name(s) = &self.next_token {
+        if let Token::Name(s) = &self.next_token.token {
             let name = s.to_string();
             self.advance_tokens();
             Ok(name)
         } else {
-            Err(ParserError {
-                position: self.lexer.get_position(),
-                message: format!("Unexpected token '{}'", self.next_token),
-            }
-            .into())
+            Err(self.unexpected_token())
         }
     }
 
     fn parse_string_literal(&mut self) -> Result<String> {
-        if let Token::StringLiteral(s) = &self.next_token {
+        if let Token::StringLiteral(s) = &self.next_token.token {
             let name = s.to_string();
             self.advance_tokens();
             Ok(name)
         } else {
-            Err(ParserError {
-                position: self.lexer.get_position(),
-                message: format!("Unexpected token '{}'", self.next_token),
-            }
-            .into())
+            Err(self.unexpected_token())
         }
     }
 
     fn parse_number(&mut self) -> Result<f64> {
-        if let Token::Number(f) = self.next_token {
+        if let Token::Number(f) = self.next_token.token {
             self.advance_tokens();
             Ok(f)
         } else {
-            Err(ParserError {
-                position: self.lexer.get_position(),
-                message: format!("Unexpected token '{}'", self.next_token),
-            }
-            .into())
+            Err(self.unexpected_token())
         }
     }
 
-    fn parse_plot_statement(&mut self) -> Result<StatementNode> {
+    // One function or a list of functions in brackets
+    fn parse_function_list<T>(
+        &mut self,
+        parse_function: fn(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
         let mut functions = Vec::new();
-
-        // function or a list of functions
-        if self.next_token == Token::OpenBracket {
+        if self.next_token.token == Token::OpenBracket {
             // list of functions
             self.advance_tokens();
-            functions.push(self.parse_plot_function()?);
-            while self.next_token == Token::Comma {
+            functions.push(parse_function(self)?);
+            while self.next_token.token == Token::Comma {
                 self.advance_tokens();
-                functions.push(self.parse_plot_function()?);
+                functions.push(parse_function(self)?);
             }
             self.expect_token(Token::CloseBracket)?;
         } else {
             // just one function
-            functions.push(self.parse_plot_function()?);
+            functions.push(parse_function(self)?);
         }
+        Ok(functions)
+    }
 
+    fn parse_plot_statement(&mut self) -> Result<StatementKind> {
+        let functions = self.parse_function_list(Self::parse_plot_function)?;
         self.expect_token(Token::Comma)?;
 
-        let x_range = {
-            self.expect_token(Token::OpenBrace)?;
-            let variable_name = self.parse_name()?;
-            self.expect_token(Token::Comma)?;
-            let lower = Box::new(self.parse_expression()?);
-            self.expect_token(Token::Comma)?;
-            let upper = Box::new(self.parse_expression()?);
-            self.expect_token(Token::CloseBrace)?;
-            SumRange {
-                variable_name,
-                lower,
-                upper,
-            }
-        };
+        let x_range = self.parse_range()?;
 
         // y-range
-        let y_range = if self.next_token == Token::Comma {
+        let y_range = if self.next_token.token == Token::Comma {
             self.expect_token(Token::Comma)?;
             self.expect_token(Token::OpenBrace)?;
             let minimum = self.parse_expression()?;
@@ -387,14 +507,152 @@ impl Parser {
 
         self.expect_token(Token::CloseParenthesis)?;
 
-        Ok(StatementNode::PlotStatement {
+        Ok(StatementKind::PlotStatement {
             functions,
             x_range,
             y_range,
         })
     }
 
+    fn parse_parametric_plot_statement(&mut self) -> Result<StatementKind> {
+        let functions = self.parse_function_list(Self::parse_parametric_function)?;
+        self.expect_token(Token::Comma)?;
+        let range = self.parse_range()?;
+        self.expect_token(Token::CloseParenthesis)?;
+        Ok(StatementKind::ParametricPlotStatement { functions, range })
+    }
+
+    fn parse_polar_plot_statement(&mut self) -> Result<StatementKind> {
+        let functions = self.parse_function_list(Self::parse_plot_function)?;
+        self.expect_token(Token::Comma)?;
+        let range = self.parse_range()?;
+        self.expect_token(Token::CloseParenthesis)?;
+        Ok(StatementKind::PolarPlotStatement { functions, range })
+    }
+
+    fn parse_contour_plot_statement(&mut self) -> Result<StatementKind> {
+        let left = self.parse_expression()?;
+        self.expect_token(Token::Equal)?;
+        let right = self.parse_expression()?;
+        let span = left.span.to(right.span);
+        let value = ExpressionNode::new(
+            ExpressionKind::BinaryOp {
+                op: Operator::Minus,
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+            span,
+        );
+        self.expect_token(Token::Comma)?;
+        let x_range = self.parse_range()?;
+        self.expect_token(Token::Comma)?;
+        let y_range = self.parse_range()?;
+
+        let mut levels = vec![ExpressionNode::new(ExpressionKind::Number(0.0), span)];
+        let mut options = Options::default();
+        while self.next_token.token == Token::Comma {
+            self.advance_tokens();
+            if matches!(&self.next_token.token, Token::Name(name) if name == "levels") {
+                // levels={level, ...}
+                self.advance_tokens();
+                self.expect_token(Token::Equal)?;
+                self.expect_token(Token::OpenBrace)?;
+                levels = vec![self.parse_expression()?];
+                while self.next_token.token == Token::Comma {
+                    self.advance_tokens();
+                    levels.push(self.parse_expression()?);
+                }
+                self.expect_token(Token::CloseBrace)?;
+            } else {
+                self.add_option(&mut options)?;
+            }
+        }
+        self.expect_token(Token::CloseParenthesis)?;
+        Ok(StatementKind::ContourPlotStatement {
+            value,
+            x_range,
+            y_range,
+            levels,
+            options,
+        })
+    }
+
+    fn parse_density_plot_statement(&mut self) -> Result<StatementKind> {
+        let value = self.parse_expression()?;
+        self.expect_token(Token::Comma)?;
+        let x_range = self.parse_range()?;
+        self.expect_token(Token::Comma)?;
+        let y_range = self.parse_range()?;
+        let mut color_map = ColorMap::Viridis;
+        if self.next_token.token == Token::Comma {
+            // colormap="name"
+            self.advance_tokens();
+            let name_span = self.next_token.span;
+            let name = self.parse_name()?;
+            if name != "colormap" {
+                return Err(ParserError {
+                    span: name_span,
+                    message: format!("Unexpected option name: '{name}'"),
+                }
+                .into());
+            }
+            self.expect_token(Token::Equal)?;
+            let map_span = self.next_token.span;
+            let map_name = self.parse_string_literal()?;
+            color_map = ColorMap::from_name(&map_name).ok_or_else(|| ParserError {
+                span: map_span,
+                message: format!(
+                    "Unknown color map '{map_name}', expected one of: {}",
+                    ColorMap::ALL.map(|map| map.name()).join(", ")
+                ),
+            })?;
+        }
+        self.expect_token(Token::CloseParenthesis)?;
+        Ok(StatementKind::DensityPlotStatement {
+            value,
+            x_range,
+            y_range,
+            color_map,
+        })
+    }
+
+    // {x, y} or {{x, y}, options}
+    fn parse_parametric_function(&mut self) -> Result<ParametricFunctionNode> {
+        self.expect_token(Token::OpenBrace)?;
+        let with_options = self.next_token.token == Token::OpenBrace;
+        if with_options {
+            self.advance_tokens();
+        }
+        let x = self.parse_expression()?;
+        self.expect_token(Token::Comma)?;
+        let y = self.parse_expression()?;
+        self.expect_token(Token::CloseBrace)?;
+        let mut options = Options::default();
+        if with_options {
+            self.parse_options(&mut options)?;
+            self.expect_token(Token::CloseBrace)?;
+        }
+        Ok(ParametricFunctionNode { x, y, options })
+    }
+
+    // {variable, lower, upper}
+    fn parse_range(&mut self) -> Result<SumRange> {
+        self.expect_token(Token::OpenBrace)?;
+        let variable_name = self.parse_name()?;
+        self.expect_token(Token::Comma)?;
+        let lower = Box::new(self.parse_expression()?);
+        self.expect_token(Token::Comma)?;
+        let upper = Box::new(self.parse_expression()?);
+        self.expect_token(Token::CloseBrace)?;
+        Ok(SumRange {
+            variable_name,
+            lower,
+            upper,
+        })
+    }
+
     fn add_option(&mut self, options: &mut Options) -> Result<()> {
+        let name_span = self.next_token.span;
         match self.parse_name()?.as_str() {
             "color" => {
                 self.expect_token(Token::Equal)?;
@@ -409,26 +667,28 @@ impl Parser {
                 Ok(())
             }
             name => Err(ParserError {
-                position: self.lexer.get_position(),
+                span: name_span,
                 message: format!("Unexpected option name: '{name}'"),
             }
             .into()),
         }
     }
 
+    // (',' option)*
+    fn parse_options(&mut self, options: &mut Options) -> Result<()> {
+        while self.next_token.token == Token::Comma {
+            self.advance_tokens();
+            self.add_option(options)?;
+        }
+        Ok(())
+    }
+
     fn parse_plot_function(&mut self) -> Result<PlotFunctionNode> {
-        if self.next_token == Token::OpenBrace {
+        if self.next_token.token == Token::OpenBrace {
             self.advance_tokens();
             let value = self.parse_expression()?;
             let mut options = Options::default();
-            if self.next_token == Token::Comma {
-                self.advance_tokens();
-                self.add_option(&mut options)?;
-                while self.next_token == Token::Comma {
-                    self.advance_tokens();
-                    self.add_option(&mut options)?;
-                }
-            }
+            self.parse_options(&mut options)?;
             self.expect_token(Token::CloseBrace)?;
             Ok(PlotFunctionNode { value, options })
         } else {
@@ -447,7 +707,7 @@ impl Parser {
     fn parse_expression_bp(&mut self, min_bp: u8) -> Result<ExpressionNode> {
         let mut lhs = self.parse_primary()?;
         loop {
-            let op = match &self.next_token {
+            let op = match &self.next_token.token {
                 Token::EoI
                 | Token::Comma
                 | Token::CloseBrace
@@ -465,9 +725,10 @@ impl Parser {
                 Token::Times => Operator::Times,
                 Token::Divide => Operator::Divide,
                 Token::Power => Operator::Power,
+                Token::Illegal(_) => return Err(self.unexpected_token()),
                 unexpected => {
                     return Err(ParserError {
-                        position: self.lexer.get_position(),
+                        span: self.next_token.span,
                         message: format!(
                             "Expected operator: +, -,*, /, ^ or EoI, ) but found '{}'",
                             unexpected
@@ -485,43 +746,52 @@ impl Parser {
             self.advance_tokens();
             let rhs = self.parse_expression_bp(l_bp)?;
 
-            lhs = ExpressionNode::BinaryOp {
-                op,
-                left: Box::new(lhs),
-                right: Box::new(rhs),
-            };
+            let span = lhs.span.to(rhs.span);
+            lhs = ExpressionNode::new(
+                ExpressionKind::BinaryOp {
+                    op,
+                    left: Box::new(lhs),
+                    right: Box::new(rhs),
+                },
+                span,
+            );
         }
         Ok(lhs)
     }
 
     fn advance_tokens(&mut self) {
+        self.last_span = self.next_token.span;
         self.next_token = self.peek_token.clone();
         self.peek_token = self.lexer.next_token();
     }
 
     fn parse_primary(&mut self) -> Result<ExpressionNode> {
-        let next_token = self.next_token.clone();
+        let start = self.next_token.span;
+        let kind = self.parse_primary_kind()?;
+        Ok(ExpressionNode::new(kind, self.span_from(start)))
+    }
+
+    fn parse_primary_kind(&mut self) -> Result<ExpressionKind> {
+        let next_token = self.next_token.token.clone();
+        let span = self.next_token.span;
         match next_token {
-            Token::Illegal(s) => Err(Box::new(LexerError {
-                position: self.lexer.get_position(),
-                message: s,
-            })),
+            Token::Illegal(s) => Err(Box::new(LexerError { span, message: s })),
             Token::EoI => Err(Box::new(ParserError {
-                position: self.lexer.get_position(),
+                span,
                 message: "Unexpected end of Input".to_string(),
             })),
             Token::NewLine => Err(Box::new(ParserError {
-                position: self.lexer.get_position(),
+                span,
                 message: "Unexpected new line".to_string(),
             })),
             Token::Number(value) => {
                 self.advance_tokens();
-                Ok(ExpressionNode::Number(value))
+                Ok(ExpressionKind::Number(value))
             }
             Token::Plus => {
                 self.advance_tokens();
                 let primary = self.parse_primary()?;
-                Ok(ExpressionNode::UnaryOp {
+                Ok(ExpressionKind::UnaryOp {
                     op: UnaryOperator::Plus,
                     right: Box::new(primary),
                 })
@@ -529,30 +799,30 @@ impl Parser {
             Token::Minus => {
                 self.advance_tokens();
                 let primary = self.parse_primary()?;
-                Ok(ExpressionNode::UnaryOp {
+                Ok(ExpressionKind::UnaryOp {
                     op: UnaryOperator::Minus,
                     right: Box::new(primary),
                 })
             }
             Token::Times => Err(ParserError {
-                position: self.lexer.get_position(),
+                span,
                 message: "Unexpected token: '*'".to_string(),
             }
             .into()),
             Token::Power => Err(ParserError {
-                position: self.lexer.get_position(),
+                span,
                 message: "Unexpected token: '^'".to_string(),
             }
             .into()),
             Token::Divide => Err(ParserError {
-                position: self.lexer.get_position(),
+                span,
                 message: "Unexpected token: '/'".to_string(),
             }
             .into()),
             Token::Name(name) => {
                 self.advance_tokens();
-                if self.next_token != Token::OpenParenthesis {
-                    return Ok(ExpressionNode::Variable(name));
+                if self.next_token.token != Token::OpenParenthesis {
+                    return Ok(ExpressionKind::Variable(name));
                 }
                 self.advance_tokens();
                 if name == "If" {
@@ -568,7 +838,7 @@ impl Parser {
 
                     self.expect_token(Token::CloseParenthesis)?;
 
-                    Ok(ExpressionNode::IfExpression {
+                    Ok(ExpressionKind::IfExpression {
                         condition: CompareNode { op, left, right },
                         if_true,
                         if_false,
@@ -576,32 +846,78 @@ impl Parser {
                 } else if name == "Sum" {
                     let value = Box::new(self.parse_expression()?);
                     self.expect_token(Token::Comma)?;
-                    self.expect_token(Token::OpenBrace)?;
-                    let variable_name = self.parse_name()?;
-                    self.expect_token(Token::Comma)?;
-                    let lower = Box::new(self.parse_expression()?);
+                    let range = self.parse_range()?;
+                    self.expect_token(Token::CloseParenthesis)?;
+                    Ok(ExpressionKind::SumExpression { value, range })
+                } else if let Some(mut method) = Method::from_name(&name) {
+                    let mut value = self.parse_expression()?;
+                    if method == Method::Solve {
+                        // the root of left - right
+                        self.expect_token(Token::Equal)?;
+                        let right = self.parse_expression()?;
+                        let span = value.span.to(right.span);
+                        value = ExpressionNode::new(
+                            ExpressionKind::BinaryOp {
+                                op: Operator::Minus,
+                                left: Box::new(value),
+                                right: Box::new(right),
+                            },
+                            span,
+                        );
+                    }
                     self.expect_token(Token::Comma)?;
-                    let upper = Box::new(self.parse_expression()?);
-                    self.expect_token(Token::CloseBrace)?;
+                    let range = self.parse_range()?;
+                    if self.next_token.token == Token::Comma {
+                        self.advance_tokens();
+                        let name_span = self.next_token.span;
+                        let name = self.parse_name()?;
+                        let Method::Integrate { tolerance } = &mut method else {
+                            return Err(ParserError {
+                                span: name_span,
+                                message: format!("Unexpected option name: '{name}'"),
+                            }
+                            .into());
+                        };
+                        if name != "tolerance" {
+                            return Err(ParserError {
+                                span: name_span,
+                                message: format!("Unexpected option name: '{name}'"),
+                            }
+                            .into());
+                        }
+                        self.expect_token(Token::Equal)?;
+                        let value_span = self.next_token.span;
+                        *tolerance = self.parse_number()?;
+                        if *tolerance <= 0.0 {
+                            return Err(ParserError {
+                                span: value_span,
+                                message: "The tolerance must be positive".to_string(),
+                            }
+                            .into());
+                        }
+                    }
                     self.expect_token(Token::CloseParenthesis)?;
-                    Ok(ExpressionNode::SumExpression {
-                        value,
-                        range: SumRange {
-                            variable_name,
-                            lower,
-                            upper,
-                        },
+                    Ok(ExpressionKind::Numeric {
+                        method,
+                        value: Box::new(value),
+                        range,
                     })
+                } else if name == "D" {
+                    let value = Box::new(self.parse_expression()?);
+                    self.expect_token(Token::Comma)?;
+                    let variable = self.parse_name()?;
+                    self.expect_token(Token::CloseParenthesis)?;
+                    Ok(ExpressionKind::Derivative { value, variable })
                 } else {
                     let mut arguments = Vec::new();
                     arguments.push(self.parse_expression()?);
-                    while self.next_token == Token::Comma {
+                    while self.next_token.token == Token::Comma {
                         self.advance_tokens();
                         arguments.push(self.parse_expression()?);
                     }
                     self.expect_token(Token::CloseParenthesis)?;
 
-                    Ok(ExpressionNode::FunctionCall {
+                    Ok(ExpressionKind::FunctionCall {
                         name,
                         args: arguments,
                     })
@@ -610,15 +926,15 @@ impl Parser {
             Token::OpenParenthesis => {
                 self.advance_tokens();
                 let primary = self.parse_expression()?;
-                if self.next_token != Token::CloseParenthesis {
+                if self.next_token.token != Token::CloseParenthesis {
                     return Err(ParserError {
-                        position: self.lexer.get_position(),
+                        span: self.next_token.span,
                         message: "Expecting: ')'".to_string(),
                     }
                     .into());
                 }
                 self.advance_tokens();
-                Ok(primary)
+                Ok(primary.kind)
             }
             Token::CloseParenthesis
             | Token::StringLiteral(_)
@@ -633,7 +949,7 @@ impl Parser {
             | Token::GreaterThan
             | Token::LessThanOrEqual
             | Token::GreaterThanOrEqual => Err(ParserError {
-                position: self.lexer.get_position(),
+                span,
                 message: format!("Unexpected token: '{}'", next_token),
             }
             .into()),
@@ -641,7 +957,7 @@ impl Parser {
     }
 
     fn parse_comparator(&mut self) -> Result<Comparator> {
-        let result = match self.next_token {
+        let result = match self.next_token.token {
             Token::Equal => Ok(Comparator::Equal),
             Token::NotEqual => Ok(Comparator::NotEqual),
             Token::LessThan => Ok(Comparator::LessThan),
@@ -650,8 +966,11 @@ impl Parser {
             Token::GreaterThanOrEqual => Ok(Comparator::GreaterThanOrEqual),
             _ => {
                 return Err(ParserError {
-                    position: self.lexer.get_position(),
-                    message: "Unexpected token: ')'".to_string(),
+                    span: self.next_token.span,
+                    message: format!(
+                        "Expected a comparison operator but got {}",
+                        self.next_token.token
+                    ),
                 }
                 .into())
             }
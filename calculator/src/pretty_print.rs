@@ -1,18 +1,30 @@
-use crate::parser::{
-    CompareNode, ExpressionNode, PlotFunctionNode, ProgramNode, StatementNode, SumRange, YRange,
+use crate::{
+    colormap::ColorMap,
+    numeric::{Method, DEFAULT_TOLERANCE},
+    parser::{
+        binding_power, CompareNode, ExpressionKind, ExpressionNode, Operator, Options,
+        ParametricFunctionNode, PlotFunctionNode, ProgramNode, StatementKind, SumRange, YRange,
+    },
 };
 
 pub(crate) fn pretty_print(node: &ProgramNode) -> String {
     let mut str = "".to_string();
+    let mut comments = node.comments.iter().peekable();
+    let mut next_starts = node.statements.iter().skip(1).map(|s| s.span.start);
     node.statements.iter().for_each(|statement| {
-        match statement {
-            StatementNode::ConstantAssignment { name, value } => {
+        // comments before the statement go in their own lines
+        while let Some(comment) = comments.next_if(|c| c.span.start < statement.span.start) {
+            str.push_str(&comment.text);
+            str.push('\n');
+        }
+        match &statement.kind {
+            StatementKind::ConstantAssignment { name, value } => {
                 str.push_str(&format!("{name} = {}", pretty_print_expression(value)));
             }
-            StatementNode::PrintStatement { argument } => {
+            StatementKind::PrintStatement { argument } => {
                 str.push_str(&format!("Print({})", pretty_print_expression(argument)));
             }
-            StatementNode::Slider {
+            StatementKind::Slider {
                 name,
                 default_value,
                 minimum_value,
@@ -25,7 +37,7 @@ pub(crate) fn pretty_print(node: &ProgramNode) -> String {
                     pretty_print_expression(maximum_value)
                 ));
             }
-            StatementNode::FunctionDeclaration {
+            StatementKind::FunctionDeclaration {
                 name,
                 arguments,
                 value,
@@ -36,17 +48,12 @@ pub(crate) fn pretty_print(node: &ProgramNode) -> String {
                     pretty_print_expression(value)
                 ));
             }
-            StatementNode::PlotStatement {
+            StatementKind::PlotStatement {
                 functions,
                 x_range,
                 y_range,
             } => {
-                let fun_str: Vec<String> = functions.iter().map(pretty_print_function).collect();
-                let fun_str = if functions.len() > 1 {
-                    format!("[{}]", fun_str.join(", "))
-                } else {
-                    fun_str.join(", ")
-                };
+                let fun_str = pretty_print_function_list(functions, pretty_print_function);
                 match y_range {
                     Some(y) => str.push_str(&format!(
                         "Plot({fun_str}, {}, {})",
@@ -59,14 +66,106 @@ pub(crate) fn pretty_print(node: &ProgramNode) -> String {
                     )),
                 }
             }
+            StatementKind::ParametricPlotStatement { functions, range } => {
+                let fun_str =
+                    pretty_print_function_list(functions, pretty_print_parametric_function);
+                str.push_str(&format!(
+                    "ParametricPlot({fun_str}, {})",
+                    pretty_print_sum_range(range)
+                ));
+            }
+            StatementKind::ContourPlotStatement {
+                value,
+                x_range,
+                y_range,
+                levels,
+                options,
+            } => {
+                let value = match &value.kind {
+                    ExpressionKind::BinaryOp { op, left, right } if *op == Operator::Minus => {
+                        format!(
+                            "{} = {}",
+                            pretty_print_expression(left),
+                            pretty_print_expression(right)
+                        )
+                    }
+                    _ => format!("{} = 0", pretty_print_expression(value)),
+                };
+                let mut option_list = pretty_print_options(options);
+                // the only level is 0 by default
+                let default_levels = match &levels[..] {
+                    [level] => matches!(level.kind, ExpressionKind::Number(f) if f == 0.0),
+                    _ => false,
+                };
+                if !default_levels {
+                    let levels: Vec<String> = levels.iter().map(pretty_print_expression).collect();
+                    option_list.push(format!("levels={{{}}}", levels.join(", ")));
+                }
+                let options: String = option_list
+                    .iter()
+                    .map(|option| format!(", {option}"))
+                    .collect();
+                str.push_str(&format!(
+                    "ContourPlot({value}, {}, {}{options})",
+                    pretty_print_sum_range(x_range),
+                    pretty_print_sum_range(y_range)
+                ));
+            }
+            StatementKind::DensityPlotStatement {
+                value,
+                x_range,
+                y_range,
+                color_map,
+            } => {
+                let option = if *color_map == ColorMap::Viridis {
+                    String::new()
+                } else {
+                    format!(", colormap=\"{}\"", color_map.name())
+                };
+                str.push_str(&format!(
+                    "DensityPlot({}, {}, {}{option})",
+                    pretty_print_expression(value),
+                    pretty_print_sum_range(x_range),
+                    pretty_print_sum_range(y_range)
+                ));
+            }
+            StatementKind::PolarPlotStatement { functions, range } => {
+                let fun_str = pretty_print_function_list(functions, pretty_print_function);
+                str.push_str(&format!(
+                    "PolarPlot({fun_str}, {})",
+                    pretty_print_sum_range(range)
+                ));
+            }
+        }
+        // comments in the same line after the statement
+        let next_start = next_starts.next().unwrap_or(usize::MAX);
+        while let Some(comment) = comments.next_if(|c| {
+            !c.own_line && c.span.start >= statement.span.end && c.span.start < next_start
+        }) {
+            str.push(' ');
+            str.push_str(&comment.text);
         }
         str.push('\n');
     });
+    for comment in comments {
+        str.push_str(&comment.text);
+        str.push('\n');
+    }
     str
 }
 
-fn pretty_print_function(node: &PlotFunctionNode) -> String {
-    let options = &node.options;
+// One function or a list of functions in brackets
+fn pretty_print_function_list<T>(functions: &[T], pretty_print: fn(&T) -> String) -> String {
+    let fun_str: Vec<String> = functions.iter().map(pretty_print).collect();
+    if functions.len() > 1 {
+        format!("[{}]", fun_str.join(", "))
+    } else {
+        fun_str.join(", ")
+    }
+}
+
+// The options that are not the default ones
+fn pretty_print_options(options: &Options) -> Vec<String> {
     let mut option_list = Vec::new();
     if options.color != "black" {
         option_list.push(format!("color=\"{}\"", options.color));
@@ -74,6 +173,11 @@ fn pretty_print_function(node: &PlotFunctionNode) -> String {
     if options.width != 1 {
         option_list.push(format!("width={}", options.width));
     }
+    option_list
+}
+
+fn pretty_print_function(node: &PlotFunctionNode) -> String {
+    let option_list = pretty_print_options(&node.options);
     if option_list.is_empty() {
         pretty_print_expression(&node.value)
     } else {
@@ -85,27 +189,55 @@ fn pretty_print_function(node: &PlotFunctionNode) -> String {
     }
 }
 
-fn pretty_print_expression(node: &ExpressionNode) -> String {
-    match node {
-        ExpressionNode::Number(f) => format!("{f}"),
-        ExpressionNode::Variable(s) => s.to_string(),
-        ExpressionNode::BinaryOp { op, left, right } => {
-            format!(
-                "{}{}{}",
-                pretty_print_expression(left),
-                op,
-                pretty_print_expression(right)
-            )
+fn pretty_print_parametric_function(node: &ParametricFunctionNode) -> String {
+    let point = format!(
+        "{{{}, {}}}",
+        pretty_print_expression(&node.x),
+        pretty_print_expression(&node.y)
+    );
+    let option_list = pretty_print_options(&node.options);
+    if option_list.is_empty() {
+        point
+    } else {
+        format!("{{ {point}, {} }}", option_list.join(", "))
+    }
+}
+
+pub(crate) fn pretty_print_expression(node: &ExpressionNode) -> String {
+    match &node.kind {
+        ExpressionKind::Number(f) => format!("{f}"),
+        ExpressionKind::Variable(s) => s.to_string(),
+        ExpressionKind::BinaryOp { op, left, right } => {
+            // The parser groups operators with the same binding power to the right: a-b-c is a-(b-c)
+            let bp = binding_power(op);
+            let left = match &left.kind {
+                ExpressionKind::BinaryOp { op, .. } if binding_power(op) <= bp => {
+                    format!("({})", pretty_print_expression(left))
+                }
+                _ => pretty_print_expression(left),
+            };
+            let right = match &right.kind {
+                ExpressionKind::BinaryOp { op, .. } if binding_power(op) < bp => {
+                    format!("({})", pretty_print_expression(right))
+                }
+                _ => pretty_print_expression(right),
+            };
+            format!("{left}{op}{right}")
         }
-        ExpressionNode::UnaryOp { op, right } => {
-            format!("{}{}", op, pretty_print_expression(right))
+        ExpressionKind::UnaryOp { op, right } => {
+            // The operand of an unary operator is a primary expression
+            if let ExpressionKind::BinaryOp { .. } = right.kind {
+                format!("{}({})", op, pretty_print_expression(right))
+            } else {
+                format!("{}{}", op, pretty_print_expression(right))
+            }
         }
-        ExpressionNode::FunctionCall { name, args } => {
+        ExpressionKind::FunctionCall { name, args } => {
             let arguments: Vec<String> = args.iter().map(pretty_print_expression).collect();
 
             format!("{name}({})", arguments.join(", "))
         }
-        ExpressionNode::IfExpression {
+        ExpressionKind::IfExpression {
             condition,
             if_true,
             if_false,
@@ -117,13 +249,46 @@ fn pretty_print_expression(node: &ExpressionNode) -> String {
                 pretty_print_expression(if_false)
             )
         }
-        ExpressionNode::SumExpression { value, range } => {
+        ExpressionKind::SumExpression { value, range } => {
             format!(
                 "Sum({}, {})",
                 pretty_print_expression(value),
                 pretty_print_sum_range(range)
             )
         }
+        ExpressionKind::Numeric {
+            method,
+            value,
+            range,
+        } => {
+            let value = match (method, &value.kind) {
+                (Method::Solve, ExpressionKind::BinaryOp { op, left, right })
+                    if *op == Operator::Minus =>
+                {
+                    format!(
+                        "{} = {}",
+                        pretty_print_expression(left),
+                        pretty_print_expression(right)
+                    )
+                }
+                (Method::Solve, _) => format!("{} = 0", pretty_print_expression(value)),
+                _ => pretty_print_expression(value),
+            };
+            let option = match method {
+                Method::Integrate { tolerance } if *tolerance != DEFAULT_TOLERANCE => {
+                    format!(", tolerance={tolerance}")
+                }
+                _ => String::new(),
+            };
+            format!(
+                "{}({value}, {}{option})",
+                method.name(),
+                pretty_print_sum_range(range)
+            )
+        }
+        ExpressionKind::Derivative { value, variable } => {
+            format!("D({}, {variable})", pretty_print_expression(value))
+        }
     }
 }
 
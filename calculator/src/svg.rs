@@ -0,0 +1,581 @@
+// A native backend that draws the plots of a program as an SVG image, without a browser.
+// The functions are sampled with the interpreter, with every slider at its default value.
+// Each plot statement is drawn in a panel, one below the other.
+
+use crate::{
+    analyzer::{Context, SymbolTable},
+    colormap::{position, ColorMap},
+    emitter::CONTOUR_CELLS,
+    evaluate::evaluate_with_bindings,
+    parser::{ExpressionKind, ExpressionNode, Options, ProgramNode, StatementKind},
+};
+
+const PANEL_WIDTH: f64 = 600.0;
+const PANEL_HEIGHT: f64 = 400.0;
+// Space around the plot area for the tick labels
+const MARGIN_LEFT: f64 = 60.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 20.0;
+const MARGIN_BOTTOM: f64 = 40.0;
+// Number of samples per function
+const SAMPLE_COUNT: usize = 500;
+// The side of the squares of a DensityPlot, which divide the plot area evenly
+const DENSITY_CELL_SIZE: f64 = 10.0;
+const TICK_LENGTH: f64 = 5.0;
+const FONT_SIZE: u32 = 12;
+
+fn as_number(node: &ExpressionNode) -> Result<f64, String> {
+    if let ExpressionKind::Number(f) = node.kind {
+        Ok(f)
+    } else {
+        Err("Expected number at this point".to_string())
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+// A round step (1, 2 or 5 times a power of 10) that gives about 8 ticks in [minimum, maximum]
+fn tick_step(minimum: f64, maximum: f64) -> f64 {
+    let raw_step = (maximum - minimum) / 8.0;
+    let magnitude = 10.0_f64.powf(raw_step.log10().floor());
+    let normalized = raw_step / magnitude;
+    let factor = if normalized < 1.5 {
+        1.0
+    } else if normalized < 3.0 {
+        2.0
+    } else if normalized < 7.0 {
+        5.0
+    } else {
+        10.0
+    };
+    factor * magnitude
+}
+
+fn ticks(minimum: f64, maximum: f64) -> Vec<(f64, String)> {
+    let step = tick_step(minimum, maximum);
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let mut ticks = Vec::new();
+    let mut k = (minimum / step).ceil();
+    // The number of ticks is bounded in case k + 1 == k
+    while k * step <= maximum + step * 1e-9 && ticks.len() < 100 {
+        let value = k * step;
+        // avoid printing "-0"
+        let label = if value.abs() < step * 1e-9 {
+            format!("{:.*}", decimals, 0.0)
+        } else {
+            format!("{:.*}", decimals, value)
+        };
+        ticks.push((value, label));
+        k += 1.0;
+    }
+    ticks
+}
+
+// Converts between the coordinates of the plot and the coordinates of the panel
+struct Frame {
+    x_min: f64,
+    x_max: f64,
+    y_min: f64,
+    y_max: f64,
+}
+
+impl Frame {
+    // The frame of the points where x and y are finite, with the same scale in both axes if
+    // `equal_aspect`
+    fn fit(curves: &[(Vec<f64>, Vec<f64>)], equal_aspect: bool) -> Frame {
+        let points: Vec<(f64, f64)> = curves
+            .iter()
+            .flat_map(|(xs, ys)| xs.iter().copied().zip(ys.iter().copied()))
+            .filter(|(x, y)| x.is_finite() && y.is_finite())
+            .collect();
+        let (x_min, x_max) = finite_range(points.iter().map(|(x, _)| x));
+        let (y_min, y_max) = finite_range(points.iter().map(|(_, y)| y));
+        let frame = Frame {
+            x_min,
+            x_max,
+            y_min,
+            y_max,
+        };
+        if equal_aspect {
+            frame.with_equal_aspect()
+        } else {
+            frame
+        }
+    }
+
+    // Widens one of the ranges around its middle so that a unit is as long in both axes
+    fn with_equal_aspect(self) -> Frame {
+        let scale = f64::max(
+            (self.x_max - self.x_min) / plot_width(),
+            (self.y_max - self.y_min) / plot_height(),
+        );
+        let x_half = scale * plot_width() / 2.0;
+        let y_half = scale * plot_height() / 2.0;
+        let x_middle = (self.x_min + self.x_max) / 2.0;
+        let y_middle = (self.y_min + self.y_max) / 2.0;
+        Frame {
+            x_min: x_middle - x_half,
+            x_max: x_middle + x_half,
+            y_min: y_middle - y_half,
+            y_max: y_middle + y_half,
+        }
+    }
+
+    fn to_x(&self, x: f64) -> f64 {
+        MARGIN_LEFT + (x - self.x_min) / (self.x_max - self.x_min) * plot_width()
+    }
+
+    fn to_y(&self, y: f64) -> f64 {
+        let y = MARGIN_TOP + (self.y_max - y) / (self.y_max - self.y_min) * plot_height();
+        // very big values are clipped anyway, we keep the numbers in the file reasonable
+        y.clamp(-PANEL_HEIGHT, 2.0 * PANEL_HEIGHT)
+    }
+}
+
+fn plot_width() -> f64 {
+    PANEL_WIDTH - MARGIN_LEFT - MARGIN_RIGHT
+}
+
+fn plot_height() -> f64 {
+    PANEL_HEIGHT - MARGIN_TOP - MARGIN_BOTTOM
+}
+
+fn render_axes(frame: &Frame) -> String {
+    let mut svg = String::new();
+    let left = MARGIN_LEFT;
+    let right = MARGIN_LEFT + plot_width();
+    let top = MARGIN_TOP;
+    let bottom = MARGIN_TOP + plot_height();
+    svg.push_str(&format!(
+        "<rect x=\"{left}\" y=\"{top}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>\n",
+        plot_width(),
+        plot_height()
+    ));
+    // The axes x = 0 and y = 0 if they are visible
+    if frame.x_min <= 0.0 && 0.0 <= frame.x_max {
+        let x = frame.to_x(0.0);
+        svg.push_str(&format!(
+            "<line x1=\"{x:.2}\" y1=\"{top}\" x2=\"{x:.2}\" y2=\"{bottom}\" stroke=\"#aaaaaa\"/>\n"
+        ));
+    }
+    if frame.y_min <= 0.0 && 0.0 <= frame.y_max {
+        let y = frame.to_y(0.0);
+        svg.push_str(&format!(
+            "<line x1=\"{left}\" y1=\"{y:.2}\" x2=\"{right}\" y2=\"{y:.2}\" stroke=\"#aaaaaa\"/>\n"
+        ));
+    }
+    for (value, label) in ticks(frame.x_min, frame.x_max) {
+        let x = frame.to_x(value);
+        svg.push_str(&format!(
+            "<line x1=\"{x:.2}\" y1=\"{bottom}\" x2=\"{x:.2}\" y2=\"{}\" stroke=\"black\"/>\n",
+            bottom + TICK_LENGTH
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{x:.2}\" y=\"{}\" text-anchor=\"middle\">{label}</text>\n",
+            bottom + TICK_LENGTH + FONT_SIZE as f64 + 2.0
+        ));
+    }
+    for (value, label) in ticks(frame.y_min, frame.y_max) {
+        let y = frame.to_y(value);
+        svg.push_str(&format!(
+            "<line x1=\"{}\" y1=\"{y:.2}\" x2=\"{left}\" y2=\"{y:.2}\" stroke=\"black\"/>\n",
+            left - TICK_LENGTH
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{:.2}\" text-anchor=\"end\">{label}</text>\n",
+            left - TICK_LENGTH - 2.0,
+            y + FONT_SIZE as f64 / 3.0
+        ));
+    }
+    svg
+}
+
+// The path of a sampled curve, with a gap wherever x or y is not finite
+fn render_function(frame: &Frame, xs: &[f64], ys: &[f64]) -> String {
+    let mut path = String::new();
+    let mut pen_down = false;
+    for (x, y) in xs.iter().zip(ys) {
+        if !x.is_finite() || !y.is_finite() {
+            pen_down = false;
+            continue;
+        }
+        let command = if pen_down { 'L' } else { 'M' };
+        path.push_str(&format!(
+            "{command}{:.2} {:.2} ",
+            frame.to_x(*x),
+            frame.to_y(*y)
+        ));
+        pen_down = true;
+    }
+    path.trim_end().to_string()
+}
+
+// The smallest range containing the finite values, widened when it is empty or a single point
+fn finite_range<'a>(values: impl Iterator<Item = &'a f64> + Clone) -> (f64, f64) {
+    let finite = values.filter(|value| value.is_finite());
+    let minimum = finite.clone().fold(f64::INFINITY, |a, &b| a.min(b));
+    let maximum = finite.fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+    if minimum < maximum {
+        (minimum, maximum)
+    } else if minimum == maximum {
+        (minimum - 1.0, maximum + 1.0)
+    } else {
+        // none of the values was finite
+        (-1.0, 1.0)
+    }
+}
+
+// The values of `value` for each of the values `ts` of the variable
+fn sample(
+    value: &ExpressionNode,
+    variable: &str,
+    ts: &[f64],
+    context: &Context,
+    slider_bindings: &[(String, f64)],
+) -> Vec<f64> {
+    let mut bindings = slider_bindings.to_vec();
+    bindings.push((variable.to_string(), 0.0));
+    let index = bindings.len() - 1;
+    ts.iter()
+        .map(|&t| {
+            bindings[index].1 = t;
+            // errors like a division by zero leave a gap in the curve
+            evaluate_with_bindings(value, context, &bindings).unwrap_or(f64::NAN)
+        })
+        .collect()
+}
+
+// The cells + 1 points of a grid of `cells` cells from `lower` to `upper`
+fn sample_grid(lower: f64, upper: f64, cells: usize) -> Vec<f64> {
+    let step = (upper - lower) / cells as f64;
+    (0..=cells).map(|i| lower + i as f64 * step).collect()
+}
+
+// SAMPLE_COUNT evenly spaced values, the first one is `lower` and the last one `upper`
+fn sample_points(lower: f64, upper: f64) -> Vec<f64> {
+    (0..SAMPLE_COUNT)
+        .map(|i| lower + (upper - lower) * i as f64 / (SAMPLE_COUNT - 1) as f64)
+        .collect()
+}
+
+// The segments x1, y1, x2, y2 where the values sampled on a grid of `cells` by `cells` cells are
+// equal to the level, with the marching squares of the ContourPlot of the wasm backend: the
+// crossings of the edges of a cell are paired in order and when the four edges are crossed, the
+// segments cut off the corners with a sign different from the center of the cell.
+fn contour_segments(values: &[f64], cells: usize, frame: &Frame, level: f64) -> Vec<[f64; 4]> {
+    let dx = (frame.x_max - frame.x_min) / cells as f64;
+    let dy = (frame.y_max - frame.y_min) / cells as f64;
+    let mut segments = Vec::new();
+    for j in 0..cells {
+        for i in 0..cells {
+            let value = |di: usize, dj: usize| values[(j + dj) * (cells + 1) + i + di] - level;
+            let [f00, f10, f01, f11] = [value(0, 0), value(1, 0), value(0, 1), value(1, 1)];
+            if ![f00, f10, f01, f11].iter().all(|f| f.is_finite()) {
+                continue;
+            }
+            let x = frame.x_min + i as f64 * dx;
+            let y = frame.y_min + j as f64 * dy;
+            // the values at the ends of the edge, its first end and whether it is along x
+            let bottom = (f00, f10, x, y, true);
+            let right = (f10, f11, x + dx, y, false);
+            let top = (f01, f11, x, y + dy, true);
+            let left = (f00, f01, x, y, false);
+            let edges = if (f00 + f10 + f01 + f11 < 0.0) != (f00 < 0.0) {
+                [left, bottom, right, top]
+            } else {
+                [bottom, right, top, left]
+            };
+            let crossings: Vec<(f64, f64)> = edges
+                .iter()
+                .filter(|(v0, v1, ..)| (*v0 < 0.0) != (*v1 < 0.0))
+                .map(|&(v0, v1, x, y, along_x)| {
+                    let t = v0 / (v0 - v1);
+                    if along_x {
+                        (x + t * dx, y)
+                    } else {
+                        (x, y + t * dy)
+                    }
+                })
+                .collect();
+            for pair in crossings.chunks(2) {
+                segments.push([pair[0].0, pair[0].1, pair[1].0, pair[1].1]);
+            }
+        }
+    }
+    segments
+}
+
+// The panel at position `index`, with the curves given by their points and options
+fn render_panel(index: usize, frame: &Frame, curves: &[(&[f64], &[f64], &Options)]) -> String {
+    let mut panel = format!(
+        "<g transform=\"translate(0, {})\">\n",
+        index as f64 * PANEL_HEIGHT
+    );
+    panel.push_str(&render_axes(frame));
+    panel.push_str("<g clip-path=\"url(#plot-area)\">\n");
+    for (xs, ys, options) in curves {
+        panel.push_str(&format!(
+            "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>\n",
+            render_function(frame, xs, ys),
+            escape_xml(&options.color),
+            options.width
+        ));
+    }
+    panel.push_str("</g>\n</g>\n");
+    panel
+}
+
+// The panel at position `index` of a DensityPlot, a square of the color of the value at its
+// center for each cell of `columns` by `rows` cells, the row at the top first. The axes are drawn
+// over the squares.
+fn render_density_panel(
+    index: usize,
+    frame: &Frame,
+    values: &[f64],
+    columns: usize,
+    color_map: ColorMap,
+) -> String {
+    let mut panel = format!(
+        "<g transform=\"translate(0, {})\" shape-rendering=\"crispEdges\">\n",
+        index as f64 * PANEL_HEIGHT
+    );
+    let finite = values.iter().filter(|value| value.is_finite());
+    let minimum = finite.clone().fold(f64::INFINITY, |a, &b| a.min(b));
+    let maximum = finite.fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+    let range = color_map.range(minimum, maximum);
+    for (k, value) in values.iter().enumerate() {
+        let position = position(*value, range);
+        if !value.is_finite() || position.is_nan() {
+            continue;
+        }
+        let [red, green, blue] = color_map.color(position);
+        panel.push_str(&format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{DENSITY_CELL_SIZE}\" height=\"{DENSITY_CELL_SIZE}\" fill=\"#{red:02x}{green:02x}{blue:02x}\"/>\n",
+            MARGIN_LEFT + (k % columns) as f64 * DENSITY_CELL_SIZE,
+            MARGIN_TOP + (k / columns) as f64 * DENSITY_CELL_SIZE
+        ));
+    }
+    panel.push_str(&render_axes(frame));
+    panel.push_str("</g>\n");
+    panel
+}
+
+/// Returns an SVG image with all the plots of the program
+pub(crate) fn render_svg(node: &ProgramNode, symbol_table: &SymbolTable) -> Result<String, String> {
+    let context = Context {
+        globals: &symbol_table.globals,
+        sliders: &symbol_table.sliders,
+        functions: &symbol_table.functions,
+        locals: &vec![],
+    };
+    let slider_bindings: Vec<(String, f64)> = symbol_table
+        .sliders
+        .iter()
+        .map(|slider| (slider.name.clone(), slider.default))
+        .collect();
+
+    let mut panels = Vec::new();
+    for statement in &node.statements {
+        match &statement.kind {
+            StatementKind::PlotStatement {
+                functions,
+                x_range,
+                y_range,
+            } => {
+                let x_min = as_number(&x_range.lower)?;
+                let x_max = as_number(&x_range.upper)?;
+                let xs = sample_points(x_min, x_max);
+                let samples: Vec<Vec<f64>> = functions
+                    .iter()
+                    .map(|function| {
+                        let variable = &x_range.variable_name;
+                        sample(&function.value, variable, &xs, &context, &slider_bindings)
+                    })
+                    .collect();
+
+                let (y_min, y_max) = match y_range {
+                    Some(range) => {
+                        let (y_min, y_max) =
+                            (as_number(&range.minimum)?, as_number(&range.maximum)?);
+                        if y_min == y_max {
+                            (y_min - 1.0, y_max + 1.0)
+                        } else {
+                            (y_min, y_max)
+                        }
+                    }
+                    None => finite_range(samples.iter().flatten()),
+                };
+                let frame = Frame {
+                    x_min,
+                    x_max,
+                    y_min,
+                    y_max,
+                };
+                let curves: Vec<(&[f64], &[f64], &Options)> = functions
+                    .iter()
+                    .zip(&samples)
+                    .map(|(function, ys)| (&xs[..], &ys[..], &function.options))
+                    .collect();
+                panels.push(render_panel(panels.len(), &frame, &curves));
+            }
+            StatementKind::ParametricPlotStatement { functions, range } => {
+                let ts = sample_points(as_number(&range.lower)?, as_number(&range.upper)?);
+                let samples: Vec<(Vec<f64>, Vec<f64>)> = functions
+                    .iter()
+                    .map(|function| {
+                        let variable = &range.variable_name;
+                        let xs = sample(&function.x, variable, &ts, &context, &slider_bindings);
+                        let ys = sample(&function.y, variable, &ts, &context, &slider_bindings);
+                        (xs, ys)
+                    })
+                    .collect();
+                let frame = Frame::fit(&samples, false);
+                let curves: Vec<(&[f64], &[f64], &Options)> = functions
+                    .iter()
+                    .zip(&samples)
+                    .map(|(function, (xs, ys))| (&xs[..], &ys[..], &function.options))
+                    .collect();
+                panels.push(render_panel(panels.len(), &frame, &curves));
+            }
+            StatementKind::PolarPlotStatement { functions, range } => {
+                let thetas = sample_points(as_number(&range.lower)?, as_number(&range.upper)?);
+                let samples: Vec<(Vec<f64>, Vec<f64>)> = functions
+                    .iter()
+                    .map(|function| {
+                        let variable = &range.variable_name;
+                        let rs = sample(
+                            &function.value,
+                            variable,
+                            &thetas,
+                            &context,
+                            &slider_bindings,
+                        );
+                        let points = rs.iter().zip(&thetas);
+                        let xs = points.clone().map(|(r, theta)| r * theta.cos()).collect();
+                        let ys = points.map(|(r, theta)| r * theta.sin()).collect();
+                        (xs, ys)
+                    })
+                    .collect();
+                let frame = Frame::fit(&samples, true);
+                let curves: Vec<(&[f64], &[f64], &Options)> = functions
+                    .iter()
+                    .zip(&samples)
+                    .map(|(function, (xs, ys))| (&xs[..], &ys[..], &function.options))
+                    .collect();
+                panels.push(render_panel(panels.len(), &frame, &curves));
+            }
+            StatementKind::ContourPlotStatement {
+                value,
+                x_range,
+                y_range,
+                levels,
+                options,
+            } => {
+                let frame = Frame {
+                    x_min: as_number(&x_range.lower)?,
+                    x_max: as_number(&x_range.upper)?,
+                    y_min: as_number(&y_range.lower)?,
+                    y_max: as_number(&y_range.upper)?,
+                };
+                // the values on the grid, row by row
+                let cells = CONTOUR_CELLS as usize;
+                let xs = sample_grid(frame.x_min, frame.x_max, cells);
+                let ys = sample_grid(frame.y_min, frame.y_max, cells);
+                let mut bindings = slider_bindings.clone();
+                bindings.push((x_range.variable_name.clone(), 0.0));
+                bindings.push((y_range.variable_name.clone(), 0.0));
+                let index = bindings.len() - 2;
+                let mut values = Vec::new();
+                for &y in &ys {
+                    for &x in &xs {
+                        bindings[index].1 = x;
+                        bindings[index + 1].1 = y;
+                        let f = evaluate_with_bindings(value, &context, &bindings);
+                        values.push(f.unwrap_or(f64::NAN));
+                    }
+                }
+
+                // the segments, separated by a NaN where the path is interrupted
+                let (mut xs, mut ys) = (Vec::new(), Vec::new());
+                for level in levels {
+                    for [x1, y1, x2, y2] in
+                        contour_segments(&values, cells, &frame, as_number(level)?)
+                    {
+                        xs.extend([x1, x2, f64::NAN]);
+                        ys.extend([y1, y2, f64::NAN]);
+                    }
+                }
+                let frame = frame.with_equal_aspect();
+                panels.push(render_panel(panels.len(), &frame, &[(&xs, &ys, options)]));
+            }
+            StatementKind::DensityPlotStatement {
+                value,
+                x_range,
+                y_range,
+                color_map,
+            } => {
+                let frame = Frame {
+                    x_min: as_number(&x_range.lower)?,
+                    x_max: as_number(&x_range.upper)?,
+                    y_min: as_number(&y_range.lower)?,
+                    y_max: as_number(&y_range.upper)?,
+                };
+                let columns = (plot_width() / DENSITY_CELL_SIZE) as usize;
+                let rows = (plot_height() / DENSITY_CELL_SIZE) as usize;
+                let dx = (frame.x_max - frame.x_min) / columns as f64;
+                let dy = (frame.y_max - frame.y_min) / rows as f64;
+                // the values at the centers of the cells, the row at the top first
+                let mut bindings = slider_bindings.clone();
+                bindings.push((x_range.variable_name.clone(), 0.0));
+                bindings.push((y_range.variable_name.clone(), 0.0));
+                let index = bindings.len() - 2;
+                let mut values = Vec::new();
+                for j in 0..rows {
+                    for i in 0..columns {
+                        bindings[index].1 = frame.x_min + (i as f64 + 0.5) * dx;
+                        bindings[index + 1].1 = frame.y_max - (j as f64 + 0.5) * dy;
+                        let f = evaluate_with_bindings(value, &context, &bindings);
+                        values.push(f.unwrap_or(f64::NAN));
+                    }
+                }
+                panels.push(render_density_panel(
+                    panels.len(),
+                    &frame,
+                    &values,
+                    columns,
+                    *color_map,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let height = panels.len() as f64 * PANEL_HEIGHT;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{PANEL_WIDTH}\" height=\"{height}\" \
+viewBox=\"0 0 {PANEL_WIDTH} {height}\" font-family=\"sans-serif\" font-size=\"{FONT_SIZE}\">\n"
+    );
+    svg.push_str(&format!(
+        "<defs><clipPath id=\"plot-area\"><rect x=\"{MARGIN_LEFT}\" y=\"{MARGIN_TOP}\" width=\"{}\" height=\"{}\"/></clipPath></defs>\n",
+        plot_width(),
+        plot_height()
+    ));
+    svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+    for panel in panels {
+        svg.push_str(&panel);
+    }
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
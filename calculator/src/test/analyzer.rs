@@ -1,4 +1,10 @@
-use crate::{parser::Parser, analyzer::analyze_program};
+use crate::{
+    analyzer::{analyze_program, simplify_program},
+    builtins::Builtin,
+    errors::ErrorList,
+    parser::{ExpressionKind, Parser, StatementKind},
+    pretty_print::{pretty_print, pretty_print_expression},
+};
 
 #[test]
 fn one_function() {
@@ -84,4 +90,111 @@ f(x) = x*x*a
     let symbol_table = analyze_program(&mut ast).unwrap();
 
     assert_eq!(symbol_table.functions.len(), 1);
-}
\ No newline at end of file
+}
+#[test]
+fn reports_all_errors() {
+    let script = "\
+a = b + 1
+f(x) = x*y
+g(x) = Sin(x, 2) + h(x)
+c = a + 2
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let error = analyze_program(&mut ast).err().unwrap();
+    let list = error.downcast_ref::<ErrorList>().unwrap();
+    let messages: Vec<String> = list.errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Evaluation error: 'Undefined variable: 'b''",
+            "Semantic error: 'Undefined variable: 'y''",
+            "Semantic error: 'Expected 1 arguments but got 2'",
+            "Semantic error: 'Undefined function: 'h''",
+        ]
+    );
+}
+
+#[test]
+fn print_statements() {
+    let script = "\
+a = {5, 1, 10}
+f(x) = x*a
+g(x) = 2*x
+Print(g(3) + 1)
+Print(f(3) + Cos(1))
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let arguments: Vec<&ExpressionKind> = ast
+        .statements
+        .iter()
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::PrintStatement { argument } => Some(&argument.kind),
+            _ => None,
+        })
+        .collect();
+    // The first one is folded, the second one depends on a slider
+    assert!(matches!(arguments[0], ExpressionKind::Number(f) if *f == 7.0));
+    assert!(matches!(arguments[1], ExpressionKind::BinaryOp { .. }));
+    // Cos will be called at runtime
+    assert_eq!(symbol_table.builtins.len(), 1);
+}
+
+#[test]
+fn sum_bounds() {
+    let script = "\
+a = {4, 1, 9}
+f(x) = Sum(n*x, {n, 1, Sqrt(a)})
+g(x) = Sum(n, {n, 1, n})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let error = analyze_program(&mut ast).err().unwrap();
+    // the variable of the Sum is not defined in its bounds
+    assert_eq!(error.to_string(), "Semantic error: 'Undefined variable: 'n''");
+
+    let mut ast = Parser::parse(&script[..script.find("g(x)").unwrap()]).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    // Sqrt is imported for the upper bound
+    assert_eq!(symbol_table.builtins, vec![Builtin::Sqrt]);
+}
+
+#[test]
+fn simplification() {
+    let script = "\
+a = 2
+b = {1, 0, 5}
+f(x) = (x*1 + 0)*(a + Sqrt(4)) - -(-x^1)
+g(a) = a + Cos(0)*b/(2 - a*0)
+h(x) = If(a > 1, x, Sin(x)) + 1/0
+Plot(-(-f(x)) + Exp(0), {x, 0, 1})
+Print(g(b + 0))
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let mut symbol_table = analyze_program(&mut ast).unwrap();
+    assert_eq!(
+        symbol_table.builtins,
+        vec![Builtin::Sqrt, Builtin::Pow, Builtin::Cos, Builtin::Sin, Builtin::Exp]
+    );
+    simplify_program(&mut ast, &mut symbol_table);
+    // the argument `a` of g shadows the global, 0*a is not 0 when a is infinite and
+    // divisions by 0 are left for the runtime
+    assert_eq!(
+        pretty_print(&ast),
+        "\
+a = 2
+b = { 1, 0, 5 };
+f(x) = x*4-x
+g(a) = a+b/(2-a*0)
+h(x) = x+1/0
+Plot(f(x)+1, {x, 0, 1})
+Print(g(b))
+"
+    );
+    // the function bodies used by the interpreter are simplified too
+    assert_eq!(
+        pretty_print_expression(&symbol_table.functions[0].value),
+        "x*4-x"
+    );
+    // the builtins that are only called with constants are not imported
+    assert!(symbol_table.builtins.is_empty());
+}
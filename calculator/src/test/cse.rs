@@ -0,0 +1,97 @@
+use super::lower;
+use crate::{
+    cse::eliminate_common_subexpressions,
+    emitter::emit_program,
+    ir::{BinaryOperator, Expression, Program},
+    wasm_interpreter::Instance,
+};
+
+// Sin(x), Sin is the only import
+fn sin_x() -> Expression {
+    Expression::Call {
+        function: 0,
+        arguments: vec![Expression::Local(0)],
+    }
+}
+
+#[test]
+fn computes_repeated_calls_once() {
+    let script = "\
+f(x) = Sin(x)*Sin(x)
+g(x) = If(x > 0, Sin(x), -Sin(x))
+h(x) = If(x > 0, 1, Sin(x) + Sin(x))
+";
+    let mut program = lower(script);
+    eliminate_common_subexpressions(&mut program);
+    let [f, g, h] = &program.functions[..] else {
+        panic!()
+    };
+
+    // the first call is always evaluated, its value is kept with local.tee
+    assert_eq!(f.locals, vec!["x", "cse"]);
+    assert_eq!(
+        f.body,
+        Expression::Binary {
+            op: BinaryOperator::Multiply,
+            left: Box::new(Expression::Tee {
+                local: 1,
+                value: Box::new(sin_x()),
+            }),
+            right: Box::new(Expression::Local(1)),
+        }
+    );
+
+    // only one branch runs, the value is computed before the If
+    let Expression::Let {
+        local: 1,
+        value,
+        body,
+    } = &g.body
+    else {
+        panic!("{:?}", g.body)
+    };
+    assert_eq!(**value, sin_x());
+    assert!(matches!(
+        &**body,
+        Expression::If { if_true, .. } if **if_true == Expression::Local(1)
+    ));
+
+    // both copies are in the same branch, it is computed there
+    let Expression::If { if_false, .. } = &h.body else {
+        panic!()
+    };
+    assert!(matches!(&**if_false, Expression::Binary { left, right, .. }
+        if matches!(**left, Expression::Tee { local: 1, .. }) && **right == Expression::Local(1)));
+}
+
+#[test]
+fn sums_and_prints() {
+    let script = "\
+a = {2, 0, 4}
+f(x) = Sum(Cos(n*x)*Cos(n*x), {n, 1, 3}) + Cos(x*x) + Cos(x*x)
+Print(Sin(a) + Sin(a) + Sum(Sin(a)*k, {k, 1, a}))
+Plot(f(x), {x, 0, 1})
+";
+    let original = lower(script);
+    let mut program = lower(script);
+    eliminate_common_subexpressions(&mut program);
+    // one local for the function body, one for the body of the Sum and one for the print
+    assert_eq!(
+        program.functions[0].locals,
+        vec!["x", "n", "n_total", "n_limit", "cse", "cse"]
+    );
+    assert_eq!(program.print_locals, vec!["k", "k_total", "k_limit", "cse"]);
+
+    let run = |program: &Program| {
+        let code = emit_program(program).unwrap();
+        let mut instance = Instance::new(&code, &[("a", 2.0)]).unwrap();
+        let f = instance.imported_function_count();
+        let values: Vec<f64> = [0.0, 0.5, 2.0]
+            .iter()
+            .map(|x| instance.call_index(f, &[*x]).unwrap())
+            .collect();
+        instance.call("redraw", &[10.0, 10.0]).unwrap();
+        (values, instance.printed)
+    };
+    assert_eq!(run(&program), run(&original));
+}
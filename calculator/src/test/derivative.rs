@@ -0,0 +1,89 @@
+use crate::{
+    analyzer::analyze_program, emitter::emit_program, ir::lower_program, parser::Parser,
+    pretty_print::pretty_print, wasm_interpreter::Instance,
+};
+
+#[test]
+fn declares_partial_derivatives() {
+    let script = "\
+a = {2, 1, 3}
+f(x) = x^3 + a*Sin(x)
+g(x, y) = x/y
+h(x) = D(f(x), x) + D(g(x, x*x), x)
+Print(D(f(1), a))
+";
+    let mut ast = Parser::parse(script).unwrap();
+    analyze_program(&mut ast).unwrap();
+    // the derivative with respect to the slider is fDa(1), that does not depend on a and is
+    // computed at compile time
+    assert_eq!(
+        pretty_print(&ast),
+        "\
+a = { 2, 1, 3 };
+f(x) = x^3+a*Sin(x)
+fDx(x) = 3*x^2+a*Cos(x)
+fDa(x) = Sin(x)
+g(x,y) = x/y
+gDx(x,y) = 1/y
+gDy(x,y) = -x/y*y
+h(x) = fDx(x)+gDx(x, x*x)+gDy(x, x*x)*(x+x)
+Print(0.8414709848078965)
+"
+    );
+}
+
+#[test]
+fn matches_finite_differences() {
+    let functions = [
+        "x*Exp(x) - Log(x)/x",
+        "Tan(x) + Asin(x/2) + Acos(x/3) + Atan(x)",
+        "Sinh(x)*Cosh(x) + Tanh(x) + Asinh(x) + Acosh(x + 1) + Atanh(x/2)",
+        "Log10(x) + Sqrt(x) + Atan2(x, 2) + Pow(x, x) + 2^x",
+        "If(x > 1, x*x, -x) + Sum(x^n/n, {n, 1, 4})",
+        "g(x*x, a) + g(a, Cos(x))",
+    ];
+    let mut script = "a = {2, 1, 3}\ng(x, y) = x*y*a + Sin(x/y)\n".to_string();
+    for (i, function) in functions.iter().enumerate() {
+        script += &format!("f{i}(x) = {function}\nd{i}(x) = D(f{i}(x), x)\n");
+    }
+    let mut ast = Parser::parse(&script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let program = lower_program(&ast, &symbol_table).unwrap();
+    let code = emit_program(&program).unwrap();
+    let mut instance = Instance::new(&code, &[("a", 2.0)]).unwrap();
+    let imports = instance.imported_function_count();
+    let index = |name: &str| {
+        let position = program.functions.iter().position(|f| f.name == name);
+        imports + position.unwrap() as u32
+    };
+    let h = 1e-6;
+    for (i, function) in functions.iter().enumerate() {
+        let (f, d) = (index(&format!("f{i}")), index(&format!("d{i}")));
+        for x in [0.3, 0.7, 1.4] {
+            let numeric = (instance.call_index(f, &[x + h]).unwrap()
+                - instance.call_index(f, &[x - h]).unwrap())
+                / (2.0 * h);
+            let symbolic = instance.call_index(d, &[x]).unwrap();
+            assert!(
+                (numeric - symbolic).abs() < 1e-5 * (1.0 + symbolic.abs()),
+                "{function}: {numeric} != {symbolic} at {x}"
+            );
+        }
+    }
+}
+
+#[test]
+fn errors() {
+    let script = "\
+f(x) = D(Sum(n, {n, 1, x}), x)
+g(x) = D(h(x), x)
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let error = analyze_program(&mut ast).err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "\
+Semantic error: 'Cannot differentiate a Sum whose bounds depend on 'x''
+Semantic error: 'Undefined function: 'h''"
+    );
+}
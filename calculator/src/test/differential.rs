@@ -0,0 +1,542 @@
+// Differential testing: random programs are run both by the interpreter (evaluate.rs) and as
+// WebAssembly (emitter.rs + wasm_interpreter.rs) and both must agree. The wasm is compiled as
+// written, after simplifying the program and after inlining the functions and eliminating the
+// common subexpressions, the interpreter runs the program as written.
+// A failing program is shrunk before it is reported.
+
+use crate::{
+    analyzer::{analyze_program, simplify_program, Context, SymbolTable},
+    cse::eliminate_common_subexpressions,
+    emitter::{emit_code, emit_program},
+    evaluate::evaluate_with_bindings,
+    inliner::inline_functions,
+    ir::lower_program,
+    numeric::{Method, DEFAULT_TOLERANCE},
+    parser::{
+        Comparator, CompareNode, ExpressionKind, ExpressionNode, Operator, ProgramNode,
+        StatementKind, StatementNode, SumRange, UnaryOperator,
+    },
+    pretty_print::pretty_print,
+    tokens::Span,
+    wasm_interpreter::Instance,
+};
+
+const PROGRAM_COUNT: u64 = 300;
+const MAX_DEPTH: u32 = 4;
+const SLIDER_VALUE: f64 = 0.5;
+// The values the arguments of every function are tested with
+const ARGUMENTS: [f64; 5] = [-2.0, -0.5, 0.0, 1.0, 2.5];
+const BUILTINS: [(&str, usize); 8] = [
+    ("Sin", 1),
+    ("Cos", 1),
+    ("Atan", 1),
+    ("Exp", 1),
+    ("Sqrt", 1),
+    ("Log", 1),
+    ("Atan2", 2),
+    ("Pow", 2),
+];
+
+// xorshift64*, good enough to generate programs and it makes the tests reproducible
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random {
+            state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // A number in 0..n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[derive(Clone)]
+struct Function {
+    name: String,
+    arguments: Vec<String>,
+    value: ExpressionNode,
+}
+
+// A program with a slider `s`, a constant `c` and some functions, each one can call the ones before it
+#[derive(Clone)]
+struct Program {
+    functions: Vec<Function>,
+}
+
+fn node(kind: ExpressionKind) -> ExpressionNode {
+    ExpressionNode::new(kind, Span::default())
+}
+
+fn number(f: f64) -> ExpressionNode {
+    node(ExpressionKind::Number(f))
+}
+
+fn variable(name: &str) -> ExpressionNode {
+    node(ExpressionKind::Variable(name.to_string()))
+}
+
+fn statement(kind: StatementKind) -> StatementNode {
+    StatementNode {
+        kind,
+        span: Span::default(),
+    }
+}
+
+impl Program {
+    fn to_ast(&self) -> ProgramNode {
+        let mut statements = vec![
+            statement(StatementKind::Slider {
+                name: "s".to_string(),
+                default_value: number(SLIDER_VALUE),
+                minimum_value: number(0.0),
+                maximum_value: number(1.0),
+            }),
+            statement(StatementKind::ConstantAssignment {
+                name: "c".to_string(),
+                value: number(1.5),
+            }),
+        ];
+        for function in &self.functions {
+            statements.push(statement(StatementKind::FunctionDeclaration {
+                name: function.name.clone(),
+                arguments: function.arguments.clone(),
+                value: function.value.clone(),
+            }));
+        }
+        ProgramNode {
+            statements,
+            comments: vec![],
+        }
+    }
+}
+
+fn generate_expression(
+    random: &mut Random,
+    depth: u32,
+    variables: &[String],
+    functions: &[Function],
+) -> ExpressionNode {
+    if depth == 0 || random.below(4) == 0 {
+        return if random.below(2) == 0 {
+            number((random.below(13) as f64 - 6.0) / 2.0)
+        } else {
+            node(ExpressionKind::Variable(
+                variables[random.below(variables.len())].clone(),
+            ))
+        };
+    }
+    let generate = |random: &mut Random, variables: &[String]| {
+        Box::new(generate_expression(random, depth - 1, variables, functions))
+    };
+    match random.below(10) {
+        0 | 1 => {
+            let op = match random.below(9) {
+                0 | 1 => Operator::Plus,
+                2 | 3 => Operator::Minus,
+                4 | 5 => Operator::Times,
+                6 | 7 => Operator::Divide,
+                _ => Operator::Power,
+            };
+            node(ExpressionKind::BinaryOp {
+                op,
+                left: generate(random, variables),
+                right: generate(random, variables),
+            })
+        }
+        2 => node(ExpressionKind::UnaryOp {
+            op: UnaryOperator::Minus,
+            right: generate(random, variables),
+        }),
+        3 => {
+            let (name, arg_count) = BUILTINS[random.below(BUILTINS.len())];
+            let args = (0..arg_count)
+                .map(|_| *generate(random, variables))
+                .collect();
+            node(ExpressionKind::FunctionCall {
+                name: name.to_string(),
+                args,
+            })
+        }
+        4 if !functions.is_empty() => {
+            let function = &functions[random.below(functions.len())];
+            let args = (0..function.arguments.len())
+                .map(|_| *generate(random, variables))
+                .collect();
+            node(ExpressionKind::FunctionCall {
+                name: function.name.clone(),
+                args,
+            })
+        }
+        5 => {
+            let op = match random.below(6) {
+                0 => Comparator::Equal,
+                1 => Comparator::NotEqual,
+                2 => Comparator::LessThan,
+                3 => Comparator::GreaterThan,
+                4 => Comparator::LessThanOrEqual,
+                _ => Comparator::GreaterThanOrEqual,
+            };
+            node(ExpressionKind::IfExpression {
+                condition: CompareNode {
+                    op,
+                    left: generate(random, variables),
+                    right: generate(random, variables),
+                },
+                if_true: generate(random, variables),
+                if_false: generate(random, variables),
+            })
+        }
+        6 => {
+            // with respect to an argument or the slider, not to the variable of a range
+            let candidates: Vec<&String> = variables
+                .iter()
+                .filter(|name| ["x", "y", "s"].contains(&name.as_str()))
+                .collect();
+            node(ExpressionKind::Derivative {
+                value: generate(random, variables),
+                variable: candidates[random.below(candidates.len())].clone(),
+            })
+        }
+        7 if depth >= 2 => {
+            let method = Method::Integrate {
+                tolerance: DEFAULT_TOLERANCE,
+            };
+            generate_numeric(random, depth, method, variables, functions)
+        }
+        8 if depth >= 2 => {
+            let method = [Method::Solve, Method::FindMinimum, Method::FindMaximum][random.below(3)];
+            generate_numeric(random, depth, method, variables, functions)
+        }
+        _ => {
+            // a new name for every level of nesting
+            let variable_name = format!("n{depth}");
+            let lower = random.below(3) as f64;
+            // the upper bound can be the variable of an enclosing Sum
+            let outer_sum = variables.iter().rev().find(|name| name.starts_with('n'));
+            let upper = match outer_sum {
+                Some(name) if random.below(2) == 0 => variable(name),
+                _ => number(lower + random.below(4) as f64),
+            };
+            let mut variables = variables.to_vec();
+            variables.push(variable_name.clone());
+            node(ExpressionKind::SumExpression {
+                value: generate(random, &variables),
+                range: SumRange {
+                    variable_name,
+                    lower: Box::new(number(lower)),
+                    upper: Box::new(upper),
+                },
+            })
+        }
+    }
+}
+
+// A numerical method on a small value, that cannot have another numerical method inside, over
+// a constant range
+fn generate_numeric(
+    random: &mut Random,
+    depth: u32,
+    method: Method,
+    variables: &[String],
+    functions: &[Function],
+) -> ExpressionNode {
+    let variable_name = format!("t{depth}");
+    let lower = random.below(5) as f64 - 2.0;
+    let upper = lower + 1.0 + random.below(3) as f64;
+    let mut variables = variables.to_vec();
+    variables.push(variable_name.clone());
+    let mut value = generate_expression(random, 1, &variables, functions);
+    if method == Method::Solve {
+        // Solve(left = right, range) is parsed as the root of left - right
+        value = node(ExpressionKind::BinaryOp {
+            op: Operator::Minus,
+            left: Box::new(value),
+            right: Box::new(generate_expression(random, 1, &variables, functions)),
+        });
+    }
+    node(ExpressionKind::Numeric {
+        method,
+        value: Box::new(value),
+        range: SumRange {
+            variable_name,
+            lower: Box::new(number(lower)),
+            upper: Box::new(number(upper)),
+        },
+    })
+}
+
+fn generate_program(seed: u64) -> Program {
+    let mut random = Random::new(seed);
+    let mut functions: Vec<Function> = Vec::new();
+    for name in ["f", "g", "h"].iter().take(1 + random.below(3)) {
+        let arguments: Vec<String> = ["x", "y"]
+            .iter()
+            .take(1 + random.below(2))
+            .map(|s| s.to_string())
+            .collect();
+        let mut variables = arguments.clone();
+        variables.push("s".to_string());
+        variables.push("c".to_string());
+        let value = generate_expression(&mut random, MAX_DEPTH, &variables, &functions);
+        functions.push(Function {
+            name: name.to_string(),
+            arguments,
+            value,
+        });
+    }
+    Program { functions }
+}
+
+fn agree(a: f64, b: f64) -> bool {
+    (a.is_nan() && b.is_nan()) || a == b || (a - b).abs() <= 1e-9 * a.abs().max(b.abs())
+}
+
+enum Outcome {
+    // the program does not compile, a shrunk program could be wrong
+    Invalid,
+    Agree,
+    Disagree(String),
+}
+
+// Calls every function of the program with the test arguments in both backends
+fn check(program: &Program) -> Outcome {
+    let mut ast = program.to_ast();
+    let Ok(symbol_table) = analyze_program(&mut ast) else {
+        return Outcome::Invalid;
+    };
+    let Ok(code) = emit_code(&ast, &symbol_table) else {
+        return Outcome::Invalid;
+    };
+    let mut simplified = program.to_ast();
+    let mut simplified_symbol_table = analyze_program(&mut simplified).unwrap();
+    simplify_program(&mut simplified, &mut simplified_symbol_table);
+    let simplified_code = match emit_code(&simplified, &simplified_symbol_table) {
+        Ok(code) => code,
+        Err(error) => return Outcome::Disagree(format!("the simplified program fails: {error}")),
+    };
+    // the functions are not removed, they are called by the test
+    let mut optimized = lower_program(&ast, &symbol_table).unwrap();
+    inline_functions(&mut optimized);
+    eliminate_common_subexpressions(&mut optimized);
+    let optimized_code = match emit_program(&optimized) {
+        Ok(code) => code,
+        Err(error) => return Outcome::Disagree(format!("the optimized program fails: {error}")),
+    };
+    for (code, label) in [
+        (code, "wasm"),
+        (simplified_code, "simplified wasm"),
+        (optimized_code, "optimized wasm"),
+    ] {
+        if let Outcome::Disagree(message) = check_module(program, &symbol_table, &code, label) {
+            return Outcome::Disagree(message);
+        }
+    }
+    Outcome::Agree
+}
+
+fn check_module(
+    program: &Program,
+    symbol_table: &SymbolTable,
+    code: &[u8],
+    label: &str,
+) -> Outcome {
+    let mut instance = match Instance::new(code, &[("s", SLIDER_VALUE)]) {
+        Ok(instance) => instance,
+        Err(error) => {
+            return Outcome::Disagree(format!("the {label} module does not load: {error}"))
+        }
+    };
+    let context = Context {
+        globals: &symbol_table.globals,
+        sliders: &symbol_table.sliders,
+        functions: &symbol_table.functions,
+        locals: &vec![],
+    };
+    let bindings = [("s".to_string(), SLIDER_VALUE)];
+    for function in &program.functions {
+        // the partial derivatives declared for D are between the functions of the program
+        let index = symbol_table
+            .functions
+            .iter()
+            .position(|f| f.name == function.name)
+            .unwrap();
+        for &x in &ARGUMENTS {
+            let args: Vec<f64> = function.arguments.iter().map(|_| x).collect();
+            let call = node(ExpressionKind::FunctionCall {
+                name: function.name.clone(),
+                args: args.iter().map(|&a| number(a)).collect(),
+            });
+            // the interpreter reports the divisions by 0 that give infinity or NaN in wasm
+            let Ok(expected) = evaluate_with_bindings(&call, &context, &bindings) else {
+                continue;
+            };
+            let function_index = instance.imported_function_count() + index as u32;
+            match instance.call_index(function_index, &args) {
+                Ok(actual) if agree(expected, actual) => {}
+                Ok(actual) => {
+                    return Outcome::Disagree(format!(
+                        "{}{args:?}: the interpreter gives {expected} but {label} gives {actual}",
+                        function.name
+                    ))
+                }
+                Err(error) => {
+                    return Outcome::Disagree(format!(
+                        "{}{args:?}: the interpreter gives {expected} but {label} fails: {error}",
+                        function.name
+                    ))
+                }
+            }
+        }
+    }
+    Outcome::Agree
+}
+
+fn children(node: &ExpressionNode) -> Vec<&ExpressionNode> {
+    match &node.kind {
+        ExpressionKind::Number(_) | ExpressionKind::Variable(_) => vec![],
+        ExpressionKind::BinaryOp { left, right, .. } => vec![left, right],
+        ExpressionKind::UnaryOp { right, .. } => vec![right],
+        ExpressionKind::FunctionCall { args, .. } => args.iter().collect(),
+        ExpressionKind::IfExpression {
+            condition,
+            if_true,
+            if_false,
+        } => vec![&condition.left, &condition.right, if_true, if_false],
+        // the bounds are always numbers
+        ExpressionKind::SumExpression { value, .. }
+        | ExpressionKind::Numeric { value, .. }
+        | ExpressionKind::Derivative { value, .. } => {
+            vec![value]
+        }
+    }
+}
+
+// A copy of `node` where the child number `index` (in the order of `children`) is `child`
+fn with_child(node: &ExpressionNode, index: usize, child: ExpressionNode) -> ExpressionNode {
+    let mut node = node.clone();
+    match &mut node.kind {
+        ExpressionKind::Number(_) | ExpressionKind::Variable(_) => unreachable!(),
+        ExpressionKind::BinaryOp { left, right, .. } => {
+            *[left, right][index].as_mut() = child;
+        }
+        ExpressionKind::UnaryOp { right, .. } => **right = child,
+        ExpressionKind::FunctionCall { args, .. } => args[index] = child,
+        ExpressionKind::IfExpression {
+            condition,
+            if_true,
+            if_false,
+        } => match index {
+            0 => *condition.left = child,
+            1 => *condition.right = child,
+            2 => **if_true = child,
+            _ => **if_false = child,
+        },
+        ExpressionKind::SumExpression { value, .. }
+        | ExpressionKind::Numeric { value, .. }
+        | ExpressionKind::Derivative { value, .. } => **value = child,
+    }
+    node
+}
+
+// Smaller versions of the expression: a node is replaced by one of its children or by a number
+fn shrink_expression(node: &ExpressionNode) -> Vec<ExpressionNode> {
+    let mut candidates = Vec::new();
+    match node.kind {
+        ExpressionKind::Number(0.0) => {}
+        ExpressionKind::Number(_) => candidates.push(number(0.0)),
+        _ => {
+            candidates.push(number(0.0));
+            candidates.push(number(1.0));
+        }
+    }
+    for child in children(node) {
+        candidates.push(child.clone());
+    }
+    for (index, child) in children(node).into_iter().enumerate() {
+        for candidate in shrink_expression(child) {
+            candidates.push(with_child(node, index, candidate));
+        }
+    }
+    candidates
+}
+
+fn shrink_program(program: &Program) -> Vec<Program> {
+    let mut candidates = Vec::new();
+    for index in 0..program.functions.len() {
+        let mut candidate = program.clone();
+        candidate.functions.remove(index);
+        candidates.push(candidate);
+    }
+    for (index, function) in program.functions.iter().enumerate() {
+        for value in shrink_expression(&function.value) {
+            let mut candidate = program.clone();
+            candidate.functions[index].value = value;
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
+
+// Replaces the program by a smaller one while it still fails
+fn shrink(mut program: Program, fails: impl Fn(&Program) -> bool) -> Program {
+    while let Some(smaller) = shrink_program(&program).into_iter().find(|p| fails(p)) {
+        program = smaller;
+    }
+    program
+}
+
+#[test]
+fn wasm_agrees_with_interpreter() {
+    let mut valid = 0;
+    for seed in 0..PROGRAM_COUNT {
+        let program = generate_program(seed);
+        match check(&program) {
+            Outcome::Invalid => {}
+            Outcome::Agree => valid += 1,
+            Outcome::Disagree(_) => {
+                let program = shrink(program, |p| matches!(check(p), Outcome::Disagree(_)));
+                let Outcome::Disagree(message) = check(&program) else {
+                    unreachable!()
+                };
+                panic!(
+                    "Seed {seed}: {message}\n{}",
+                    pretty_print(&program.to_ast())
+                );
+            }
+        }
+    }
+    // the generator should not waste most of its programs
+    assert!(valid > PROGRAM_COUNT / 2, "only {valid} valid programs");
+}
+
+#[test]
+fn shrinks_to_minimal_program() {
+    // Pretend that any program calling Cos fails
+    fn calls_cos(node: &ExpressionNode) -> bool {
+        matches!(&node.kind, ExpressionKind::FunctionCall { name, .. } if name == "Cos")
+            || children(node).into_iter().any(calls_cos)
+    }
+    let fails = |program: &Program| {
+        matches!(check(program), Outcome::Agree)
+            && program.functions.iter().any(|f| calls_cos(&f.value))
+    };
+    let seed = (0..PROGRAM_COUNT)
+        .find(|&seed| fails(&generate_program(seed)))
+        .unwrap();
+    let program = shrink(generate_program(seed), fails);
+    let printed = pretty_print(&program.to_ast());
+    assert!(printed.ends_with(") = Cos(0)\n"), "{printed}");
+    assert_eq!(program.functions.len(), 1);
+}
@@ -0,0 +1,39 @@
+use std::fs;
+
+use crate::{analyzer::analyze_program, disasm::disassemble, emitter::emit_code, parser::Parser};
+
+#[test]
+fn disassembles_emitted_module() {
+    let script = "\
+a = {2, 0, 10}
+sq(x) = x*x*a
+Plot(sq(x), {x, -1, 1})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let code = emit_code(&ast, &symbol_table).unwrap();
+    let text = disassemble(&code, "sq.wasm").unwrap();
+    assert!(text.starts_with("sq.wasm:\tfile format wasm 0x1\n"));
+    assert!(text.contains("     Type start=0x0000000a "));
+    assert!(text.contains(" - global[0] f64 mutable=1 <- globals.a\n"));
+    assert!(text.contains(" - memory[0] pages: initial=1\n"));
+    // the body of sq, the offset of each instruction and its bytes
+    assert!(text.contains(
+        ": 20 00                          | local.get 0
+ 00004e: 20 00                          | local.get 0
+ 000050: 23 00                          | global.get $globals.a
+ 000052: a2                             | f64.mul
+"
+    ));
+}
+
+#[test]
+fn disassembles_clang_module() {
+    let bytes = fs::read("../WebAssemblyExamples/redraw/redraw.wasm").unwrap();
+    let text = disassemble(&bytes, "redraw.wasm").unwrap();
+    assert!(text
+        .contains("   Custom start=0x00000235 end=0x0000025b (size=0x00000026) \"producers\"\n"));
+    assert!(text.contains(" - func[5] sig=4 <redraw>\n"));
+    assert!(text.contains(" 00011d: 28 02 00                       | i32.load\n"));
+    assert!(text.contains(" 000167: 03 40                          | loop\n"));
+}
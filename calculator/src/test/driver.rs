@@ -0,0 +1,88 @@
+use crate::{analyzer::analyze_program, driver::emit_driver, parser::Parser};
+
+#[test]
+fn one_slider_one_plot() {
+    let script = "\
+a = {5, 1, 10}
+f(x) = Sin(x)*a
+Plot({f(x), color=\"red\", width=2}, {x, -1, 1})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let html = emit_driver(&ast, &symbol_table, "main.wasm").unwrap();
+    assert!(html.contains("const wasmFile = \"main.wasm\";"));
+    assert!(html.contains("{ name: \"a\", minimum: 1, maximum: 10, default: 5 }"));
+    assert!(html.contains("Sin: Math.sin"));
+    assert!(html
+        .contains("{ xRange: [-1, 1], yRange: null, functions: [{ color: \"red\", width: 2 }] }"));
+}
+
+#[test]
+fn parametric_plot() {
+    let script = "\
+f(t) = Cos(t)
+ParametricPlot([{f(t), Sin(t)}, {{t, t}, color=\"blue\"}], {t, 0, 1})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let html = emit_driver(&ast, &symbol_table, "main.wasm").unwrap();
+    assert!(html.contains(
+        "{ parametric: true, equalAspect: false, functions: [{ color: \"black\", width: 1 }, { color: \"blue\", width: 1 }] }"
+    ));
+    assert!(html.contains("draw = drawParametricPlot;"));
+}
+
+#[test]
+fn polar_plot_has_equal_axes() {
+    let script = "PolarPlot({1 + Cos(theta), width=3}, {theta, 0, 6})";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let html = emit_driver(&ast, &symbol_table, "main.wasm").unwrap();
+    assert!(html.contains(
+        "{ parametric: true, equalAspect: true, functions: [{ color: \"black\", width: 3 }] }"
+    ));
+    // the module imports Cos and Sin even if r does not call them
+    assert!(html.contains("Cos: Math.cos, Sin: Math.sin"));
+}
+
+#[test]
+fn contour_plot() {
+    let script = "\
+a = 3
+ContourPlot(x*y = 1, {x, -a, a}, {y, -2, 2}, color=\"green\", levels={1, 2})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let html = emit_driver(&ast, &symbol_table, "main.wasm").unwrap();
+    assert!(html.contains(
+        "{ contour: true, xRange: [-3, 3], yRange: [-2, 2], options: { color: \"green\", width: 1 } }"
+    ));
+    assert!(html.contains("const contourCells = 100;"));
+}
+
+#[test]
+fn prints_slider_values() {
+    let script = "\
+a = {5, 1, 10}
+b = 2
+Print(b*3)
+Print(Sin(a)*b)
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let html = emit_driver(&ast, &symbol_table, "main.wasm").unwrap();
+    // only the Print that depends on a slider is printed by the module
+    assert!(html.contains("const prints = [\"Sin(a)*b\"];"));
+    assert!(html.contains("console: { print }"));
+}
+
+#[test]
+fn density_plot() {
+    let script = "DensityPlot(x*y, {x, -3, 3}, {y, -2, 2}, colormap=\"grayscale\")";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let html = emit_driver(&ast, &symbol_table, "main.wasm").unwrap();
+    assert!(html.contains("{ density: true, xRange: [-3, 3], yRange: [-2, 2] }"));
+    // the colors are computed by redraw, the driver copies them to the canvas
+    assert!(html.contains("putImageData(image, 0, 0)"));
+}
@@ -0,0 +1,52 @@
+use crate::{analyzer::analyze_program, errors::render_error, parser::Parser};
+
+#[test]
+fn renders_parser_error() {
+    let script = "a = 3\nf(x) = Sin(x))\n";
+    let error = Parser::parse(script).err().unwrap();
+    let expected = "\
+Parser error: 'Unexpected token ')''
+ --> test.keith:2:14
+  |
+2 | f(x) = Sin(x))
+  |              ^
+";
+    assert_eq!(render_error(error.as_ref(), "test.keith", script), expected);
+}
+
+#[test]
+fn renders_semantic_error() {
+    let script = "a = 3\nf(x) = Sin(x)\ng(x) = Cos(f(x, a))\n";
+    let mut ast = Parser::parse(script).unwrap();
+    let error = analyze_program(&mut ast).err().unwrap();
+    let expected = "\
+Semantic error: 'Expected 1 arguments but got 2'
+ --> test.keith:3:12
+  |
+3 | g(x) = Cos(f(x, a))
+  |            ^^^^^^^
+";
+    assert_eq!(render_error(error.as_ref(), "test.keith", script), expected);
+}
+
+#[test]
+fn renders_all_parser_errors() {
+    let script = "a = 3 +\nb = 2\nc = (b\n";
+    let error = Parser::parse(script).err().unwrap();
+    let expected = "\
+Parser error: 'Unexpected new line'
+ --> test.keith:1:8
+  |
+1 | a = 3 +
+  |        ^
+
+Parser error: 'Expecting: ')''
+ --> test.keith:3:7
+  |
+3 | c = (b
+  |       ^
+
+2 errors found
+";
+    assert_eq!(render_error(error.as_ref(), "test.keith", script), expected);
+}
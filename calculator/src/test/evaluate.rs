@@ -1,14 +1,20 @@
 use crate::{
-    analyzer::Context,
-    evaluate::evaluate_in_context,
-    parser::{ExpressionNode, Operator},
+    analyzer::{analyze_program, Context},
+    evaluate::{evaluate_in_context, evaluate_with_bindings},
+    parser::{ExpressionKind, ExpressionNode, Operator, Parser, StatementKind},
+    tokens::Span,
 };
 
+fn number(f: f64) -> ExpressionNode {
+    ExpressionNode::new(ExpressionKind::Number(f), Span::default())
+}
+
 #[test]
-fn number() {
-    let expr = ExpressionNode::Number(2.0);
+fn evaluate_number() {
+    let expr = number(2.0);
     let context = Context {
         globals: &Vec::new(),
+        sliders: &Vec::new(),
         functions: &Vec::new(),
         locals: &Vec::new(),
     };
@@ -16,20 +22,112 @@ fn number() {
     assert_eq!(result, 2.0);
 }
 
-
 #[test]
 fn multiply_numbers() {
     // 2*3
-    let expr = ExpressionNode::BinaryOp {
-        op: Operator::Times,
-        left: Box::new(ExpressionNode::Number(2.0)),
-        right: Box::new(ExpressionNode::Number(3.0)),
-    };
+    let expr = ExpressionNode::new(
+        ExpressionKind::BinaryOp {
+            op: Operator::Times,
+            left: Box::new(number(2.0)),
+            right: Box::new(number(3.0)),
+        },
+        Span::default(),
+    );
     let context = Context {
         globals: &Vec::new(),
+        sliders: &Vec::new(),
         functions: &Vec::new(),
         locals: &Vec::new(),
     };
     let result = evaluate_in_context(&expr, &context).unwrap();
     assert_eq!(result, 6.0);
 }
+
+// Evaluates the last function of the script at x with the sliders at their default value
+fn evaluate_last_function(script: &str, x: f64) -> f64 {
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let context = Context {
+        globals: &symbol_table.globals,
+        sliders: &symbol_table.sliders,
+        functions: &symbol_table.functions,
+        locals: &Vec::new(),
+    };
+    let function = symbol_table.functions.last().unwrap();
+    let mut bindings: Vec<(String, f64)> = symbol_table
+        .sliders
+        .iter()
+        .map(|slider| (slider.name.clone(), slider.default))
+        .collect();
+    bindings.push((function.arguments[0].clone(), x));
+    evaluate_with_bindings(&function.value, &context, &bindings).unwrap()
+}
+
+#[test]
+fn builtins_in_constants() {
+    let script = "b = Sqrt(2)*Atan2(1, 1)";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let b = symbol_table.globals.first().unwrap();
+    assert_eq!(b.value, 2.0_f64.sqrt() * std::f64::consts::FRAC_PI_4);
+    // The constant has been folded
+    if let StatementKind::ConstantAssignment { value, .. } = &ast.statements[0].kind {
+        assert!(matches!(value.kind, ExpressionKind::Number(f) if f == b.value));
+    } else {
+        panic!("Expected a constant assignment");
+    }
+}
+
+#[test]
+fn user_functions_in_constants() {
+    let script = "\
+f(x, y) = x*x + y
+b = f(3, 1) - f(1, 0)
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    assert_eq!(symbol_table.globals[0].value, 9.0);
+}
+
+#[test]
+fn sum_in_slider_bounds() {
+    let script = "a = {1, 0, Sum(n, {n, 1, 4})}";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    assert_eq!(symbol_table.sliders[0].maximum, 10.0);
+}
+
+#[test]
+fn slider_in_constant() {
+    let script = "\
+a = {1, 0, 2}
+f(x) = a*x
+b = f(2)
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let error = analyze_program(&mut ast).err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Evaluation error: 'Cannot use slider 'a' in a constant expression'"
+    );
+}
+
+#[test]
+fn evaluates_functions() {
+    let script = "\
+b = 10
+a = {5, 1, b}
+f(x) = x*x*a
+g(x, y) = f(x)*f(y*x)
+h(x) = Sum(g(n/b, x), {n, 1, b})
+k(x) = If(x>0, h(x), -h(x))
+";
+    let f = |x: f64| x * x * 5.0;
+    let h = |x: f64| {
+        (1..=10)
+            .map(|n| f(n as f64 / 10.0) * f(x * n as f64 / 10.0))
+            .sum::<f64>()
+    };
+    assert!((evaluate_last_function(script, 0.5) - h(0.5)).abs() <= 1e-12);
+    assert!((evaluate_last_function(script, -2.0) + h(-2.0)).abs() <= 1e-12);
+}
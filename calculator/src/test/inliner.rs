@@ -0,0 +1,94 @@
+use super::lower;
+use crate::{
+    emitter::{emit_program, PLOT_DATA_ADDRESS},
+    inliner::{inline_functions, remove_dead_functions},
+    ir::{BinaryOperator, Expression, Program},
+    wasm_interpreter::Instance,
+};
+
+// The values written by redraw and the printed values
+fn redraw(program: &Program, width: f64) -> (Vec<f64>, Vec<(f64, f64)>) {
+    let code = emit_program(program).unwrap();
+    let mut instance = Instance::new(&code, &[("a", 1.5)]).unwrap();
+    instance.call("redraw", &[width, 100.0]).unwrap();
+    let count: usize = program
+        .plots
+        .iter()
+        .map(|plot| 2 + plot.functions.len() * width as usize)
+        .sum();
+    let values = (0..count)
+        .map(|i| {
+            instance
+                .read_f64(PLOT_DATA_ADDRESS as usize + 8 * i)
+                .unwrap()
+        })
+        .collect();
+    (values, instance.printed)
+}
+
+#[test]
+fn inlines_small_functions() {
+    let script = "\
+a = {1, 0, 2}
+sq(x) = x*x*a
+unused(x) = x + 1
+g(x) = sq(x) + sq(2)
+Plot(g(x), {x, -1, 1})
+";
+    let mut program = lower(script);
+    inline_functions(&mut program);
+    remove_dead_functions(&mut program);
+    let names: Vec<&str> = program.functions.iter().map(|f| f.name.as_str()).collect();
+    // g is called once, by the plot, so it is inlined too
+    assert_eq!(names, vec!["plot0"]);
+    assert_eq!(program.plots[0].functions, vec![0]);
+    let square = |x: Expression| Expression::Binary {
+        op: BinaryOperator::Multiply,
+        left: Box::new(x.clone()),
+        right: Box::new(Expression::Binary {
+            op: BinaryOperator::Multiply,
+            left: Box::new(x),
+            right: Box::new(Expression::Global(0)),
+        }),
+    };
+    let g = Expression::Binary {
+        op: BinaryOperator::Add,
+        left: Box::new(square(Expression::Local(0))),
+        right: Box::new(square(Expression::Number(2.0))),
+    };
+    assert_eq!(program.functions[0].body, g);
+}
+
+#[test]
+fn keeps_the_meaning_of_the_program() {
+    // Sums in the callee and in the caller with the same variable, arguments that are not
+    // trivial and a large function called twice
+    let script = "\
+a = {1, 0, 2}
+f(x, n) = Sum(x*n, {n, 1, n})
+big(x) = Sin(x)*Cos(x)*Sin(x)*Cos(x)*Sin(x)*Cos(x) + Exp(x)*Sqrt(x)*x*x*x*x
+once(x) = big(x)*2
+g(x) = Sum(f(n, n + x), {n, 1, 3}) + once(x) - big(x - 1)
+h(x) = f(x, a)
+Plot([g(x), h(x)], {x, 0, 2})
+Print(f(a, 3) + g(a*a))
+";
+    let original = lower(script);
+    let mut program = lower(script);
+    inline_functions(&mut program);
+    remove_dead_functions(&mut program);
+    // NaN is not equal to itself
+    assert_eq!(
+        format!("{:?}", redraw(&program, 5.0)),
+        format!("{:?}", redraw(&original, 5.0))
+    );
+
+    let names: Vec<&str> = program.functions.iter().map(|f| f.name.as_str()).collect();
+    // big and g are large and called twice, f, once and h are inlined
+    assert_eq!(names, vec!["big", "g", "plot0", "plot1"]);
+    // the locals of the Sum of f are added to the plot function
+    assert_eq!(
+        program.functions[3].locals,
+        vec!["x", "n", "n_total", "n_limit"]
+    );
+}
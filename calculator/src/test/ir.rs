@@ -0,0 +1,73 @@
+use crate::{
+    analyzer::analyze_program,
+    ir::{lower_program, BinaryOperator, Expression, Import},
+    parser::Parser,
+};
+
+#[test]
+fn lowers_names_to_indices() {
+    let script = "\
+a = {2, 0, 10}
+b = 3
+f(x, k) = Sum(k * x, {k, 1, 4}) + k ^ b
+Print(f(a, 1))
+Plot(f(x, 2), {x, -1, 1})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let program = lower_program(&ast, &symbol_table).unwrap();
+    assert_eq!(
+        program.imports,
+        vec![
+            Import::Builtin(crate::builtins::Builtin::Pow),
+            Import::Print
+        ]
+    );
+    assert_eq!(program.sliders, vec!["a"]);
+    assert_eq!(program.redraw_index(), 4);
+
+    // the variable of the Sum shadows the argument `k`, the constant `b` is replaced by its value
+    let f = &program.functions[0];
+    assert_eq!(f.locals, vec!["x", "k", "k", "k_total", "k_limit"]);
+    assert_eq!(
+        f.body,
+        Expression::Binary {
+            op: BinaryOperator::Add,
+            left: Box::new(Expression::Sum {
+                variable: 2,
+                total: 3,
+                limit: 4,
+                lower: Box::new(Expression::Number(1.0)),
+                upper: Box::new(Expression::Number(4.0)),
+                value: Box::new(Expression::Binary {
+                    op: BinaryOperator::Multiply,
+                    left: Box::new(Expression::Local(2)),
+                    right: Box::new(Expression::Local(0)),
+                }),
+            }),
+            right: Box::new(Expression::Call {
+                function: 0,
+                arguments: vec![Expression::Local(1), Expression::Number(3.0)],
+            }),
+        }
+    );
+
+    // the plot function is a function of `x` and calls f
+    let plot = &program.functions[1];
+    assert_eq!(plot.name, "plot0");
+    assert_eq!(program.plots[0].functions, vec![3]);
+    assert_eq!(
+        plot.body,
+        Expression::Call {
+            function: 2,
+            arguments: vec![Expression::Local(0), Expression::Number(2.0)],
+        }
+    );
+    assert_eq!(
+        program.prints,
+        vec![Expression::Call {
+            function: 2,
+            arguments: vec![Expression::Global(0), Expression::Number(1.0)],
+        }]
+    );
+}
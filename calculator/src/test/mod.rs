@@ -1,5 +1,31 @@
+use crate::{
+    analyzer::analyze_program,
+    ir::{lower_program, Program},
+    parser::Parser,
+};
+
 mod analyzer;
+mod cse;
+mod derivative;
+mod differential;
+mod disasm;
+mod driver;
+mod errors;
 mod evaluate;
+mod inliner;
+mod ir;
+mod numeric;
 mod parser;
 mod pretty_print;
+mod svg;
 mod tokenizer;
+mod validator;
+mod wasm;
+mod wat;
+
+// The IR of a valid script
+fn lower(script: &str) -> Program {
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    lower_program(&ast, &symbol_table).unwrap()
+}
@@ -0,0 +1,265 @@
+use std::f64::consts::PI;
+
+use crate::{
+    analyzer::{analyze_program, Context},
+    cse::eliminate_common_subexpressions,
+    emitter::emit_program,
+    evaluate::evaluate_with_bindings,
+    inliner::inline_functions,
+    ir::{lower_program, Program},
+    numeric::{run, Method, Statement},
+    parser::{ExpressionKind, ExpressionNode, Parser},
+    pretty_print::pretty_print,
+    tokens::Span,
+    wasm_decoder::{decode, Instruction},
+    wasm_interpreter::Instance,
+};
+
+fn call(method: Method, f: fn(f64) -> f64, a: f64, b: f64) -> f64 {
+    run(&method.routine(), a, b, |t| Ok(f(t))).unwrap()
+}
+
+#[test]
+fn integrates_to_the_tolerance() {
+    let integral = |f, a, b, tolerance| call(Method::Integrate { tolerance }, f, a, b);
+    assert!((integral(f64::sin, 0.0, PI, 1e-8) - 2.0).abs() < 1e-8);
+    assert!((integral(f64::exp, 1.0, 0.0, 1e-8) + 1.0_f64.exp_m1()).abs() < 1e-8);
+    // Simpson's rule is exact for cubics
+    assert_eq!(integral(|t| t * t * t, 0.0, 2.0, 1e-8), 4.0);
+    // the derivative is infinite at 0, the error is larger but it stops
+    assert!((integral(f64::sqrt, 0.0, 1.0, 1e-8) - 2.0 / 3.0).abs() < 1e-5);
+    assert!((integral(f64::sqrt, 0.0, 1.0, 1e-3) - 2.0 / 3.0).abs() < 1e-2);
+    assert_eq!(integral(f64::sin, 1.0, 1.0, 1e-8), 0.0);
+}
+
+#[test]
+fn cuts_only_the_pieces_that_need_it() {
+    // a peak of width 0.001, the smallest pieces are 1/8192 wide and cutting the whole range in
+    // them would take more than 30000 values
+    let mut count = 0;
+    let integral = run(
+        &Method::Integrate { tolerance: 1e-8 }.routine(),
+        -1.0,
+        1.0,
+        |x| {
+            count += 1;
+            Ok(1.0 / (1e-6 + x * x))
+        },
+    )
+    .unwrap();
+    assert!((integral - 2000.0 * 1000.0_f64.atan()).abs() < 1e-8);
+    assert!(count < 8000, "{count} values");
+
+    let mut count = 0;
+    run(
+        &Method::Integrate { tolerance: 1e-8 }.routine(),
+        0.0,
+        1.0,
+        |x| {
+            count += 1;
+            Ok(x.sin())
+        },
+    )
+    .unwrap();
+    assert!(count < 100, "{count} values");
+}
+
+#[test]
+fn evaluates_in_one_place() {
+    fn evaluations(statements: &[Statement]) -> usize {
+        statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::Evaluate(_) => 1,
+                Statement::If(_, if_true, if_false) => evaluations(if_true) + evaluations(if_false),
+                Statement::While(_, body) => evaluations(body),
+                Statement::Set(..) | Statement::Break => 0,
+            })
+            .sum()
+    }
+    for name in ["Integrate", "Solve", "FindMinimum", "FindMaximum"] {
+        let routine = Method::from_name(name).unwrap().routine();
+        assert_eq!(evaluations(&routine.code), 1, "{name}");
+    }
+    // the code of the expression is written once, not once per level
+    let script = "\
+f(x) = Integrate(Integrate(Integrate(Sin(r*s*t), {r, 0, s}), {s, 0, t}), {t, 0, x})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let program = lower_program(&ast, &symbol_table).unwrap();
+    let module = decode(&emit_program(&program).unwrap()).unwrap();
+    let sin = module
+        .imports
+        .iter()
+        .position(|import| import.name == "Sin")
+        .unwrap() as u32;
+    let calls = module.code[0]
+        .code
+        .iter()
+        .filter(|instruction| **instruction == Instruction::Call(sin))
+        .count();
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn finds_roots_and_extrema() {
+    let solve = |f, a, b| call(Method::Solve, f, a, b);
+    assert!((solve(|x| x * x - 2.0, 0.0, 2.0) - 2.0_f64.sqrt()).abs() < 1e-12);
+    assert!((solve(|x| x.cos() - x, 1.0, 0.0) - 0.7390851332151607).abs() < 1e-12);
+    assert!((solve(|x| x * x * x, -1.0, 3.0)).abs() < 1e-4);
+    // a root at a bound
+    assert_eq!(solve(|x| x, 0.0, 1.0), 0.0);
+    // the sign does not change
+    assert!(solve(|x| x * x + 1.0, -1.0, 1.0).is_nan());
+    assert!(solve(|x| x.sqrt() - 2.0, -1.0, 1.0).is_nan());
+
+    let minimum = |f, a, b| call(Method::FindMinimum, f, a, b);
+    assert!((minimum(|x| (x - 1.0) * (x - 1.0), -3.0, 4.0) - 1.0).abs() < 1e-7);
+    assert!((minimum(|x| x.cosh() + x, 5.0, -5.0) + 1.0_f64.asinh()).abs() < 1e-7);
+    // the lowest point of the range when it decreases
+    assert!((minimum(|x| -x, 0.0, 1.0) - 1.0).abs() < 1e-7);
+    let maximum = |f, a, b| call(Method::FindMaximum, f, a, b);
+    assert!((maximum(f64::sin, 0.0, 3.0) - PI / 2.0).abs() < 1e-7);
+}
+
+#[test]
+fn parses_the_options() {
+    let script = "\
+f(x) = Integrate(Sin(t*x), {t, 0, x}, tolerance=0.001)
+g(x) = Integrate(Integrate(s*t, {s, 0, t}), {t, 0, x})
+h(a) = Solve(x*x = a, {x, 0, a})+FindMinimum(Cos(x), {x, 0, a})*FindMaximum(Sin(x), {x, 0, a})
+";
+    let ast = Parser::parse(script).unwrap();
+    // the default tolerance is not written
+    assert_eq!(pretty_print(&ast), script);
+
+    let error = Parser::parse("f(x) = Integrate(t, {t, 0, x}, tolerance=0)")
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Parser error: 'The tolerance must be positive'"
+    );
+    let error = Parser::parse("f(x) = Integrate(t, {t, 0, x}, color=\"red\")")
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Parser error: 'Unexpected option name: 'color''"
+    );
+    let error = Parser::parse("f(a) = Solve(x = a, {x, 0, a}, tolerance=0.1)")
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Parser error: 'Unexpected option name: 'tolerance''"
+    );
+    assert!(Parser::parse("f(a) = Solve(x*x - a, {x, 0, a})").is_err());
+}
+
+#[test]
+fn differentiates_integrals() {
+    let script = "\
+a = {1, 0, 2}
+f(t) = Exp(-t*t)
+area(x) = Integrate(f(t), {t, 0, x})
+g(x) = D(area(x), x)
+h(x) = D(Integrate(t*x, {t, x, a}), x)
+";
+    let mut ast = Parser::parse(script).unwrap();
+    analyze_program(&mut ast).unwrap();
+    // value(upper)*upper' - value(lower)*lower' + Integrate(value', range)
+    assert_eq!(
+        pretty_print(&ast),
+        "\
+a = { 1, 0, 2 };
+f(t) = Exp(-t*t)
+area(x) = Integrate(f(t), {t, 0, x})
+areaDx(x) = f(x)
+g(x) = areaDx(x)
+h(x) = Integrate(t, {t, x, a})-x*x
+"
+    );
+}
+
+#[test]
+fn differentiates_roots_and_extrema() {
+    let script = "\
+a = {2, 1, 3}
+root(y) = Solve(x*x = y*a, {x, 0, 10})
+droot(y) = D(root(y), y)
+minimum(y) = FindMinimum(Cosh(x - y) + a*x, {x, -10, 10})
+dminimum(y) = D(minimum(y), y)
+dslider(y) = D(minimum(y), a)
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let program = lower_program(&ast, &symbol_table).unwrap();
+    let code = emit_program(&program).unwrap();
+    let mut instance = Instance::new(&code, &[("a", 2.0)]).unwrap();
+    let imports = instance.imported_function_count();
+    let index = |name: &str| {
+        let position = program.functions.iter().position(|f| f.name == name);
+        imports + position.unwrap() as u32
+    };
+    for y in [0.5, 1.0, 4.0] {
+        // the root is √(2y) and the minimum is at y - Asinh(2)
+        let droot = instance.call_index(index("droot"), &[y]).unwrap();
+        assert!((droot - 1.0 / (2.0 * y).sqrt()).abs() < 1e-9);
+        let dminimum = instance.call_index(index("dminimum"), &[y]).unwrap();
+        assert!((dminimum - 1.0).abs() < 1e-6);
+        let dslider = instance.call_index(index("dslider"), &[y]).unwrap();
+        assert!((dslider + 1.0 / 5.0_f64.sqrt()).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn wasm_agrees_with_interpreter() {
+    let script = "\
+s = {0.5, 0, 1}
+f(t) = Exp(-t*t*s)
+area(x) = Integrate(f(t) + f(t), {t, -1, x})
+g(x) = Integrate(Integrate(Sin(u*v), {u, 0, v}), {v, 0, x}, tolerance=0.000001)
+root(x) = Solve(area(y) = s*Sin(x)*Sin(x), {y, -1, 10})
+top(x) = FindMaximum(Sin(y*s) + Cos(y*x), {y, 0, 3}) + FindMinimum(f(y - x)*y, {y, 0, x + 1})
+h(x) = area(x) + g(x)*Integrate(If(t > 0, t, -t), {t, -x, 1}) + root(x) + top(x)
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let context = Context {
+        globals: &symbol_table.globals,
+        sliders: &symbol_table.sliders,
+        functions: &symbol_table.functions,
+        locals: &vec![],
+    };
+    let program = lower_program(&ast, &symbol_table).unwrap();
+    let mut optimized = lower_program(&ast, &symbol_table).unwrap();
+    inline_functions(&mut optimized);
+    eliminate_common_subexpressions(&mut optimized);
+
+    let run = |program: &Program, s: f64, x: f64| {
+        let code = emit_program(program).unwrap();
+        let mut instance = Instance::new(&code, &[("s", s)]).unwrap();
+        let h = instance.imported_function_count() + 5;
+        instance.call_index(h, &[x]).unwrap()
+    };
+    for (s, x) in [(0.5, -2.0), (0.5, 0.0), (0.5, 0.5), (0.2, 0.5), (1.0, 3.0)] {
+        let call = ExpressionNode::new(
+            ExpressionKind::FunctionCall {
+                name: "h".to_string(),
+                args: vec![ExpressionNode::new(
+                    ExpressionKind::Number(x),
+                    Span::default(),
+                )],
+            },
+            Span::default(),
+        );
+        let bindings = [("s".to_string(), s)];
+        let expected = evaluate_with_bindings(&call, &context, &bindings).unwrap();
+        assert!(expected.is_finite());
+        // the same operations in the same order
+        assert_eq!(run(&program, s, x), expected);
+        assert!((run(&optimized, s, x) - expected).abs() < 1e-9);
+    }
+}
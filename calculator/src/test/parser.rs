@@ -1,16 +1,23 @@
-use crate::parser::{ExpressionNode, Parser, StatementNode, UnaryOperator, CompareNode};
+use crate::{
+    errors::ParserError,
+    parser::{
+        CompareNode, ExpressionKind, ExpressionNode, Parser, StatementKind, StatementNode,
+        UnaryOperator,
+    },
+    tokens::Span,
+};
 
 fn compare_condition(_left: &CompareNode, _right: &CompareNode) -> bool {
     panic!("Not implemented")
 }
 
 fn compare_expressions(left: &ExpressionNode, right: &ExpressionNode) -> bool {
-    match (left, right) {
-        (ExpressionNode::Number(x), ExpressionNode::Number(y)) => (x-y).abs() < f64::EPSILON,
-        (ExpressionNode::Variable(a), ExpressionNode::Variable(b)) => a == b,
+    match (&left.kind, &right.kind) {
+        (ExpressionKind::Number(x), ExpressionKind::Number(y)) => (x-y).abs() < f64::EPSILON,
+        (ExpressionKind::Variable(a), ExpressionKind::Variable(b)) => a == b,
         (
-            ExpressionNode::BinaryOp { op, left, right },
-            ExpressionNode::BinaryOp { op: op2, left: left2, right:right2 },
+            ExpressionKind::BinaryOp { op, left, right },
+            ExpressionKind::BinaryOp { op: op2, left: left2, right:right2 },
         ) => {
             if op != op2 {
                 return false;
@@ -20,15 +27,15 @@ fn compare_expressions(left: &ExpressionNode, right: &ExpressionNode) -> bool {
             }
             compare_expressions(right, right2)
         },
-        (ExpressionNode::UnaryOp { op, right }, ExpressionNode::UnaryOp { op: op2, right: right2 }) => {
+        (ExpressionKind::UnaryOp { op, right }, ExpressionKind::UnaryOp { op: op2, right: right2 }) => {
             if op != op2 {
                 return false;
             };
             compare_expressions(right, right2)
         },
         (
-            ExpressionNode::FunctionCall { name, args },
-            ExpressionNode::FunctionCall { name: name2, args: args2 },
+            ExpressionKind::FunctionCall { name, args },
+            ExpressionKind::FunctionCall { name: name2, args: args2 },
         ) => {
             if name != name2 {
                 return false;
@@ -44,12 +51,12 @@ fn compare_expressions(left: &ExpressionNode, right: &ExpressionNode) -> bool {
             true
         },
         (
-            ExpressionNode::IfExpression {
+            ExpressionKind::IfExpression {
                 condition,
                 if_true,
                 if_false,
             },
-            ExpressionNode::IfExpression {
+            ExpressionKind::IfExpression {
                 condition: condition2,
                 if_true: if_true2,
                 if_false: if_false2,
@@ -64,18 +71,18 @@ fn compare_expressions(left: &ExpressionNode, right: &ExpressionNode) -> bool {
             compare_expressions(if_false, if_false2)
         },
         (
-            ExpressionNode::SumExpression { value: _, range: _ },
-            ExpressionNode::SumExpression { value: _value2, range: _range2 },
+            ExpressionKind::SumExpression { value: _, range: _ },
+            ExpressionKind::SumExpression { value: _value2, range: _range2 },
         ) => panic!("Not implemented"),
         _ => false,
     }
 }
 
 fn compare_statements(left: &StatementNode, right: &StatementNode) -> bool {
-    match (left, right) {
+    match (&left.kind, &right.kind) {
         (
-            StatementNode::ConstantAssignment { name, value },
-            StatementNode::ConstantAssignment {
+            StatementKind::ConstantAssignment { name, value },
+            StatementKind::ConstantAssignment {
                 name: name_right,
                 value: value_right,
             },
@@ -86,13 +93,13 @@ fn compare_statements(left: &StatementNode, right: &StatementNode) -> bool {
             compare_expressions(value, value_right)
         }
         (
-            StatementNode::Slider {
+            StatementKind::Slider {
                 name,
                 default_value,
                 minimum_value,
                 maximum_value,
             },
-            StatementNode::Slider {
+            StatementKind::Slider {
                 name: name_right,
                 default_value: default_value_right,
                 minimum_value: minimum_value_right,
@@ -111,12 +118,12 @@ fn compare_statements(left: &StatementNode, right: &StatementNode) -> bool {
             compare_expressions(maximum_value, maximum_value_right)
         }
         (
-            StatementNode::FunctionDeclaration {
+            StatementKind::FunctionDeclaration {
                 name,
                 arguments,
                 value,
             },
-            StatementNode::FunctionDeclaration {
+            StatementKind::FunctionDeclaration {
                 name: name_right,
                 arguments: arguments_right,
                 value: value_right,
@@ -131,20 +138,20 @@ fn compare_statements(left: &StatementNode, right: &StatementNode) -> bool {
             compare_expressions(value, value_right)
         }
         (
-            StatementNode::PlotStatement {
+            StatementKind::PlotStatement {
                 functions: _,
                 x_range: _,
                 y_range: _,
             },
-            StatementNode::PlotStatement {
+            StatementKind::PlotStatement {
                 functions: _functions_right,
                 x_range: _x_range_right,
                 y_range: _y_range_right,
             },
         ) => panic!("Not implemented"),
         (
-            StatementNode::PrintStatement { argument },
-            StatementNode::PrintStatement {
+            StatementKind::PrintStatement { argument },
+            StatementKind::PrintStatement {
                 argument: argument_right,
             },
         ) => compare_expressions(argument, argument_right),
@@ -158,12 +165,85 @@ fn parses_assign() {
     let program = Parser::parse(script).unwrap();
     let statements = program.statements;
     assert_eq!(statements.len(), 1);
-    let stm = StatementNode::ConstantAssignment {
-        value: ExpressionNode::UnaryOp {
-            op: UnaryOperator::Minus,
-            right: Box::new(ExpressionNode::Number(3.0)),
+    let stm = StatementNode {
+        kind: StatementKind::ConstantAssignment {
+            value: ExpressionNode::new(
+                ExpressionKind::UnaryOp {
+                    op: UnaryOperator::Minus,
+                    right: Box::new(ExpressionNode::new(
+                        ExpressionKind::Number(3.0),
+                        Span::new(5, 6),
+                    )),
+                },
+                Span::new(4, 6),
+            ),
+            name: "a".to_string(),
         },
-        name: "a".to_string(),
+        span: Span::new(0, 6),
     };
     assert!(compare_statements(&statements[0], &stm));
 }
+
+#[test]
+fn spans() {
+    let script = "a = 3\nf(x) = Sin(x)*(x + a)";
+    let program = Parser::parse(script).unwrap();
+    let statements = program.statements;
+    assert_eq!(statements.len(), 2);
+    assert_eq!(statements[0].span, Span::new(0, 5));
+    assert_eq!(statements[1].span, Span::new(6, 27));
+    if let StatementKind::FunctionDeclaration { value, .. } = &statements[1].kind {
+        assert_eq!(value.span, Span::new(13, 27));
+        if let ExpressionKind::BinaryOp { left, right, .. } = &value.kind {
+            // Sin(x)
+            assert_eq!(left.span, Span::new(13, 19));
+            // (x + a)
+            assert_eq!(right.span, Span::new(20, 27));
+        } else {
+            panic!("Expected a binary operation");
+        }
+    } else {
+        panic!("Expected a function declaration");
+    }
+}
+
+#[test]
+fn error_span() {
+    let script = "a = 3\nf(x) = Sin(x))";
+    let error = Parser::parse(script).err().unwrap();
+    let error = error.downcast_ref::<ParserError>().unwrap();
+    // the extra ')'
+    assert_eq!(error.span, Span::new(19, 20));
+}
+
+#[test]
+fn recovers_from_errors() {
+    let script = "\
+a = 3 +
+b = 4
+f(x = x
+g(x) = x*b
+c = )
+";
+    let (program, errors) = Parser::parse_with_recovery(script);
+    // one error per broken line
+    assert_eq!(errors.len(), 3);
+    let lines: Vec<usize> = errors
+        .iter()
+        .map(|e| {
+            let span = e.downcast_ref::<ParserError>().unwrap().span;
+            script[..span.start].matches('\n').count() + 1
+        })
+        .collect();
+    assert_eq!(lines, vec![1, 3, 5]);
+    // the valid statements are kept
+    assert_eq!(program.statements.len(), 2);
+    assert!(matches!(
+        &program.statements[0].kind,
+        StatementKind::ConstantAssignment { name, .. } if name == "b"
+    ));
+    assert!(matches!(
+        &program.statements[1].kind,
+        StatementKind::FunctionDeclaration { name, .. } if name == "g"
+    ));
+}
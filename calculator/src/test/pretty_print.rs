@@ -20,3 +20,130 @@ Plot([f(x), g(x)], {x, -1, 1})\n";
     let script_formatted = pretty_print(&ast);
     assert_eq!(script, script_formatted);
 }
+
+#[test]
+fn preserves_comments() {
+    let script = "\
+// The parabola
+a = 2 // the slope
+f(x) = a*x^2 /* not a line */
+/* and
+now we plot */
+Plot(f(x), {x, -1, 1})
+// the end
+";
+    let mut ast = Parser::parse(script).unwrap();
+    assert_eq!(script, pretty_print(&ast));
+    _ = analyze_program(&mut ast).unwrap();
+    assert_eq!(script, pretty_print(&ast));
+}
+
+#[test]
+fn keeps_parentheses() {
+    let script = "\
+f(x) = (x+1)*(x-1)
+g(x) = (x-1)-x^(2*x)
+h(x) = -(x+1)/2-(x-1)+x
+";
+    let ast = Parser::parse(script).unwrap();
+    let printed = pretty_print(&ast);
+    assert_eq!(script, printed);
+    // printing the program again gives the same result
+    assert_eq!(printed, pretty_print(&Parser::parse(&printed).unwrap()));
+}
+
+#[test]
+fn parametric_plots() {
+    let script = "\
+r = 2
+ParametricPlot({r*Cos(t), r*Sin(t)}, {t, 0, 3*r})
+ParametricPlot([{t, t^2}, { {t^2, t}, color=\"red\", width=2 }], {t, -r, r})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    assert_eq!(script, pretty_print(&ast));
+    _ = analyze_program(&mut ast).unwrap();
+    // the bounds of the range are evaluated
+    assert!(pretty_print(&ast).contains("{t, 0, 6})\n"));
+    assert!(pretty_print(&ast).ends_with("], {t, -2, 2})\n"));
+    assert!(Parser::parse("ParametricPlot({t}, {t, 0, 1})").is_err());
+    assert!(Parser::parse("ParametricPlot({{t, t}, tolerance=1}, {t, 0, 1})").is_err());
+}
+
+#[test]
+fn polar_plots() {
+    let script = "\
+PolarPlot(1+Cos(theta), {theta, 0, 7})
+PolarPlot([theta, { 2, color=\"red\" }], {theta, 0, 10})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    assert_eq!(script, pretty_print(&ast));
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    assert_eq!(script, pretty_print(&ast));
+    // redraw calls Cos and Sin to compute the points
+    let builtins: Vec<&str> = symbol_table.builtins.iter().map(|b| b.name()).collect();
+    assert_eq!(builtins, ["Cos", "Sin"]);
+}
+
+#[test]
+fn contour_plots() {
+    let script = "\
+r = 2
+ContourPlot(x^2+y^2 = r, {x, -r, r}, {y, -1, 1})
+ContourPlot(Sin(x*y) = 0, {x, 0, 1}, {y, 0, 1}, color=\"red\", width=2, levels={-r, 0, r/4})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    assert_eq!(script, pretty_print(&ast));
+    _ = analyze_program(&mut ast).unwrap();
+    // the ranges and the levels are evaluated
+    assert!(pretty_print(&ast).contains("{x, -2, 2}, {y, -1, 1})\n"));
+    assert!(pretty_print(&ast).ends_with("levels={-2, 0, 0.5})\n"));
+
+    let mut ast = Parser::parse("ContourPlot(x = y, {x, 0, 1}, {x, 0, 1})").unwrap();
+    let error = analyze_program(&mut ast).err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Semantic error: 'Both ranges have the same variable 'x''"
+    );
+    assert!(Parser::parse("ContourPlot(x*y, {x, 0, 1}, {y, 0, 1})").is_err());
+    assert!(Parser::parse("ContourPlot(x = y, {x, 0, 1}, {y, 0, 1}, levels=1)").is_err());
+}
+
+#[test]
+fn density_plots() {
+    let script = "\
+a = 2
+DensityPlot(Sin(x*y), {x, -a, a}, {y, 0, 1})
+DensityPlot(x-y, {x, 0, 1}, {y, 0, 1}, colormap=\"diverging\")
+";
+    let mut ast = Parser::parse(script).unwrap();
+    assert_eq!(script, pretty_print(&ast));
+    _ = analyze_program(&mut ast).unwrap();
+    assert!(pretty_print(&ast).contains("DensityPlot(Sin(x*y), {x, -2, 2}, {y, 0, 1})\n"));
+
+    let error = Parser::parse("DensityPlot(x, {x, 0, 1}, {y, 0, 1}, colormap=\"jet\")")
+        .err()
+        .unwrap();
+    assert!(error
+        .to_string()
+        .contains("Unknown color map 'jet', expected one of: viridis, grayscale, diverging"));
+    assert!(Parser::parse("DensityPlot(x, {x, 0, 1}, {y, 0, 1}, color=\"red\")").is_err());
+}
+
+#[test]
+fn derivatives_round_trip() {
+    let script = "\
+top(x, y) = x*y^2
+fDx(x) = 1
+f(x) = Sin(x)*x
+g(x) = D(f(x), x)+D(top(x, x), x)+fDx(x)
+";
+    let mut ast = Parser::parse(script).unwrap();
+    _ = analyze_program(&mut ast).unwrap();
+    let printed = pretty_print(&ast);
+    // the partial derivatives are new functions, fDx is already declared
+    assert!(printed.contains("topDy(x,y) = "));
+    assert!(printed.contains("fDxD(x) = "));
+    let mut ast = Parser::parse(&printed).unwrap();
+    _ = analyze_program(&mut ast).unwrap();
+    assert_eq!(printed, pretty_print(&ast));
+}
@@ -0,0 +1,127 @@
+use crate::{analyzer::analyze_program, parser::Parser, svg::render_svg};
+
+#[test]
+fn renders_plot_with_options() {
+    let script = "\
+a = {2, 1, 10}
+f(x) = a*x
+Plot([{f(x), color=\"red\", width=3}, x^2], {x, -1, 1}, {-2, 2})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let svg = render_svg(&ast, &symbol_table).unwrap();
+    assert!(
+        svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"600\" height=\"400\"")
+    );
+    assert!(svg.ends_with("</svg>\n"));
+    assert!(svg.contains("stroke=\"red\" stroke-width=\"3\""));
+    assert!(svg.contains("stroke=\"black\" stroke-width=\"1\""));
+    // f(x) = 2x with the slider at its default goes from y = -2 (the bottom) to y = 2 (the top)
+    assert!(svg.contains("<path d=\"M60.00 360.00 L"));
+    assert!(svg.contains("L580.00 20.00\""));
+    // tick labels of the y axis
+    assert!(svg.contains(">-2.0</text>"));
+    assert!(svg.contains(">2.0</text>"));
+}
+
+#[test]
+fn gaps_and_auto_scale() {
+    let script = "\
+Plot(Sqrt(x), {x, -4, 4})
+Plot(x, {x, 0, 10})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let svg = render_svg(&ast, &symbol_table).unwrap();
+    // two panels, one below the other
+    assert!(svg.contains("height=\"800\""));
+    assert!(svg.contains("<g transform=\"translate(0, 400)\">"));
+    // Sqrt(x) is only drawn for x >= 0 and scaled to the values it takes
+    let path = svg.split("<path d=\"").nth(1).unwrap();
+    assert_eq!(path.matches('M').count(), 1);
+    assert!(path.starts_with("M320.52 "));
+    assert!(svg.contains(">2.0</text>"));
+    assert!(svg.contains(">10</text>"));
+}
+
+#[test]
+fn parametric_plot() {
+    let script = "\
+pi = 3.141592653589793
+ParametricPlot({{Cos(t), Sin(t)}, color=\"green\"}, {t, 0, 2*pi})
+ParametricPlot({Sqrt(t), t}, {t, -1, 1})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let svg = render_svg(&ast, &symbol_table).unwrap();
+    assert!(svg.contains("height=\"800\""));
+    // the unit circle starts at (1, 0), on the right edge and halfway up
+    let circle = svg.split("<path d=\"").nth(1).unwrap();
+    assert!(circle.starts_with("M580.00 190.00 L"));
+    assert!(circle.contains("stroke=\"green\""));
+    assert!(svg.contains(">1.0</text>"));
+    // the points where x is not finite are not drawn
+    let parabola = svg.split("<path d=\"").nth(2).unwrap();
+    assert_eq!(parabola.matches('M').count(), 1);
+    assert!(parabola.starts_with("M60.00 "));
+}
+
+#[test]
+fn polar_plot_has_equal_axes() {
+    let script = "PolarPlot({1, color=\"blue\"}, {theta, 0, 7})";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let svg = render_svg(&ast, &symbol_table).unwrap();
+    // the unit circle starts at (1, 0), the plot area is 520 wide and 340 high and a unit has
+    // the same length, 170, in both axes
+    let circle = svg.split("<path d=\"").nth(1).unwrap();
+    assert!(circle.starts_with("M490.00 190.00 L"));
+    assert!(circle.contains("stroke=\"blue\""));
+    assert!(svg.contains(">-1.5</text>"));
+}
+
+#[test]
+fn contour_plot() {
+    let script = "ContourPlot(x*x + y*y = 1.1, {x, -2, 2}, {y, -2, 2}, color=\"red\")";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let svg = render_svg(&ast, &symbol_table).unwrap();
+    let path = svg.split("<path d=\"").nth(1).unwrap();
+    assert!(path.contains("stroke=\"red\""));
+    // every segment is a move and a line, all of them close to the circle
+    let path = path.split('"').next().unwrap();
+    let segments: Vec<&str> = path.split('M').skip(1).collect();
+    assert!(segments.len() > 100);
+    for segment in segments {
+        for point in segment.split('L') {
+            let [x, y]: [f64; 2] = point
+                .split_whitespace()
+                .map(|s| s.parse().unwrap())
+                .collect::<Vec<f64>>()
+                .try_into()
+                .unwrap();
+            // the center of the plot area is (320, 190) and a unit is 85 long
+            let radius = ((x - 320.0).powi(2) + (y - 190.0).powi(2)).sqrt() / 85.0;
+            assert!((radius - 1.1_f64.sqrt()).abs() < 1e-2);
+        }
+    }
+}
+
+#[test]
+fn density_plot() {
+    let script = "DensityPlot(If(x < 0, 0, 1/0), {x, -1, 1}, {y, 0, 1}, colormap=\"grayscale\")";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let svg = render_svg(&ast, &symbol_table).unwrap();
+    // 52 by 34 squares, the ones where the value is not finite are left out
+    let squares: Vec<&str> = svg
+        .split("<rect x=")
+        .filter(|rect| rect.contains("width=\"10\" height=\"10\""))
+        .collect();
+    assert_eq!(squares.len(), 26 * 34);
+    // a single value is in the middle of the color map
+    assert!(squares
+        .iter()
+        .all(|square| square.contains("fill=\"#808080\"")));
+    assert!(squares[0].starts_with("\"60\" y=\"20\""));
+}
@@ -1,4 +1,7 @@
-use crate::{lexer::Lexer, tokens::Token};
+use crate::{
+    lexer::Lexer,
+    tokens::{Span, Token},
+};
 
 #[test]
 fn tokenize_formula() {
@@ -12,6 +15,75 @@ fn tokenize_formula() {
         Token::EoI,
     ];
     for token in tokens {
-        assert_eq!(token, lexer.next_token());
+        assert_eq!(token, lexer.next_token().token);
     }
 }
+
+#[test]
+fn token_spans() {
+    let input_text = "f(x) = Sqrt(x)\nPlot(f(x), {x, -1.5, 1})";
+    let mut lexer = Lexer::new(input_text);
+    let mut spans = Vec::new();
+    loop {
+        let spanned_token = lexer.next_token();
+        if spanned_token.token == Token::EoI {
+            assert_eq!(spanned_token.span, Span::new(39, 39));
+            break;
+        }
+        spans.push((spanned_token.token, spanned_token.span));
+    }
+    assert_eq!(spans[0], (Token::Name("f".to_string()), Span::new(0, 1)));
+    assert_eq!(
+        spans[5],
+        (Token::Name("Sqrt".to_string()), Span::new(7, 11))
+    );
+    assert_eq!(spans[9], (Token::NewLine, Span::new(14, 15)));
+    assert_eq!(spans[21], (Token::Number(1.5), Span::new(31, 34)));
+}
+
+#[test]
+fn skips_comments() {
+    let input_text = "// a line comment\na = 2 /* a block\ncomment */ / 3 // the end";
+    let mut lexer = Lexer::new(input_text);
+    let tokens = [
+        Token::NewLine,
+        Token::Name("a".to_string()),
+        Token::Equal,
+        Token::Number(2.0),
+        Token::Divide,
+        Token::Number(3.0),
+        Token::EoI,
+    ];
+    for token in tokens {
+        assert_eq!(token, lexer.next_token().token);
+    }
+    let comments: Vec<(&str, Span, bool)> = lexer
+        .comments
+        .iter()
+        .map(|c| (c.text.as_str(), c.span, c.own_line))
+        .collect();
+    assert_eq!(
+        comments,
+        vec![
+            ("// a line comment", Span::new(0, 17), true),
+            ("/* a block\ncomment */", Span::new(24, 45), false),
+            ("// the end", Span::new(50, 60), false),
+        ]
+    );
+}
+
+#[test]
+fn unterminated_comment() {
+    let input_text = "a = 1 /* never closed\nb = 2";
+    let mut lexer = Lexer::new(input_text);
+    for _ in 0..3 {
+        lexer.next_token();
+    }
+    let spanned_token = lexer.next_token();
+    assert_eq!(
+        spanned_token.token,
+        Token::Illegal("Unterminated comment".to_string())
+    );
+    assert_eq!(spanned_token.span, Span::new(6, 27));
+    assert_eq!(lexer.next_token().token, Token::EoI);
+}
@@ -0,0 +1,95 @@
+use crate::{analyzer::analyze_program, emitter::emit_code, parser::Parser, validator::validate};
+
+const HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+// one type: () -> f64
+const TYPE_SECTION: [u8; 7] = [0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7c];
+
+// A module with one function of type 0 and the given body (without the final end)
+fn module(type_section: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut bytes = HEADER.to_vec();
+    bytes.extend_from_slice(type_section);
+    // function section: one function of type 0
+    bytes.extend_from_slice(&[0x03, 0x02, 0x01, 0x00]);
+    // code section: one body without locals
+    let body_size = body.len() as u8 + 2;
+    bytes.extend_from_slice(&[0x0a, body_size + 2, 0x01, body_size, 0x00]);
+    bytes.extend_from_slice(body);
+    bytes.push(0x0b);
+    bytes
+}
+
+#[test]
+fn emitted_modules_are_valid() {
+    let script = "\
+a = {2, 0, 10}
+b = 3
+f(x, y) = If(x > y, x - y, Pow(x, y) * a)
+g(x) = Sum(f(x, k), {k, 1, 4})
+Print(f(1, 2))
+Print(g(a))
+Plot(g(x) + b, {x, -1, 1})
+Plot(Sin(x), {x, 0, 3})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let code = emit_code(&ast, &symbol_table).unwrap();
+    assert_eq!(validate(&code), Ok(()));
+}
+
+#[test]
+fn handwritten_module_is_valid() {
+    // f64.const 1
+    let body = [0x44, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f];
+    assert_eq!(validate(&module(&TYPE_SECTION, &body)), Ok(()));
+}
+
+#[test]
+fn empty_type_section() {
+    // the emitter used to write the signatures to a vector that was then dropped
+    let error = validate(&module(&[0x01, 0x01, 0x00], &[])).unwrap_err();
+    assert_eq!(error, "func[0]: type 0 out of bounds");
+}
+
+#[test]
+fn wrong_result_type() {
+    // i32.const 1
+    let error = validate(&module(&TYPE_SECTION, &[0x41, 0x01])).unwrap_err();
+    assert_eq!(
+        error,
+        "func[0] at offset 0x1a (end): Expected f64 but found i32"
+    );
+}
+
+#[test]
+fn f32_instruction_on_f64() {
+    // f64.const 2, f32.ceil: 0x8d is not f64.ceil, which is 0x9b
+    let body = [0x44, 0, 0, 0, 0, 0, 0, 0, 0x40, 0x8d];
+    let error = validate(&module(&TYPE_SECTION, &body)).unwrap_err();
+    assert_eq!(
+        error,
+        "func[0] at offset 0x21 (f32.ceil): Expected f32 but found f64"
+    );
+    // with f64.ceil it is valid
+    let body = [0x44, 0, 0, 0, 0, 0, 0, 0, 0x40, 0x9b];
+    assert_eq!(validate(&module(&TYPE_SECTION, &body)), Ok(()));
+}
+
+#[test]
+fn call_out_of_bounds() {
+    // call 5
+    let error = validate(&module(&TYPE_SECTION, &[0x10, 0x05])).unwrap_err();
+    assert_eq!(
+        error,
+        "func[0] at offset 0x18 (call 5): Call to function 5 out of bounds"
+    );
+}
+
+#[test]
+fn sections_out_of_order() {
+    let mut bytes = HEADER.to_vec();
+    // a function section and then a type section
+    bytes.extend_from_slice(&[0x03, 0x01, 0x00]);
+    bytes.extend_from_slice(&[0x01, 0x01, 0x00]);
+    let error = validate(&bytes).unwrap_err();
+    assert_eq!(error, "Section 1 at offset 0xd is out of order or repeated");
+}
@@ -0,0 +1,427 @@
+use crate::{
+    analyzer::analyze_program,
+    colormap::ColorMap,
+    emitter::{emit_code, encode_sleb128, PLOT_DATA_ADDRESS},
+    opcodes::*,
+    parser::Parser,
+    wasm_decoder::{decode, Instruction},
+    wasm_interpreter::Instance,
+    wat::NUMERIC_INSTRUCTIONS,
+};
+
+// Compiles the script and instantiates the module with the sliders at the given values
+fn instantiate(script: &str, sliders: &[(&str, f64)]) -> Instance {
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let code = emit_code(&ast, &symbol_table).unwrap();
+    Instance::new(&code, sliders).unwrap()
+}
+
+// Calls the n-th user defined function
+fn call(instance: &mut Instance, function: u32, args: &[f64]) -> f64 {
+    let index = instance.imported_function_count() + function;
+    instance.call_index(index, args).unwrap()
+}
+
+#[test]
+fn signed_leb128() {
+    assert_eq!(encode_sleb128(0), [0x00]);
+    assert_eq!(encode_sleb128(-1), [0x7f]);
+    assert_eq!(encode_sleb128(63), [0x3f]);
+    // 64 and -65 need a second byte for the sign bit
+    assert_eq!(encode_sleb128(64), [0xc0, 0x00]);
+    assert_eq!(encode_sleb128(-64), [0x40]);
+    assert_eq!(encode_sleb128(-65), [0xbf, 0x7f]);
+    assert_eq!(encode_sleb128(i32::MIN), [0x80, 0x80, 0x80, 0x80, 0x78]);
+    assert_eq!(encode_sleb128(i32::MAX), [0xff, 0xff, 0xff, 0xff, 0x07]);
+}
+
+#[test]
+fn exports_redraw() {
+    let mut ast = Parser::parse("a = 2\nPlot(Sin(x)*a, {x, 0, 1})").unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let code = emit_code(&ast, &symbol_table).unwrap();
+    let module = decode(&code).unwrap();
+    let redraw = module
+        .exports
+        .iter()
+        .find(|export| export.name == "redraw")
+        .unwrap();
+    assert_eq!(redraw.kind, FUNCTION_EXPORT_KIND);
+    // after the import of Sin and the plot function, the last function of the module
+    assert_eq!(redraw.index, 2);
+    assert_eq!(
+        redraw.index,
+        module.imported_function_count() + module.functions.len() as u32 - 1
+    );
+}
+
+#[test]
+fn integrate_writes_f64_ceil() {
+    // the opcode was the one of f32.ceil, which engines reject on an f64
+    let mut ast = Parser::parse("q(x) = Integrate(t^2, {t, 0, x})").unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let code = emit_code(&ast, &symbol_table).unwrap();
+    let module = decode(&code).unwrap();
+    let body = &module.code[0];
+    let position = body
+        .code
+        .iter()
+        .position(|instruction| *instruction == Instruction::F64Ceil)
+        .unwrap();
+    assert_eq!(code[body.offsets[position]], 0x9b);
+}
+
+#[test]
+fn decodes_the_opcodes_of_the_spec() {
+    // the text format prints the numeric instructions from the table of the spec
+    for (opcode, name) in [
+        (INSTR_I32_LT_S, "i32.lt_s"),
+        (INSTR_I32_GT_U, "i32.gt_u"),
+        (INSTR_F64_EQ, "f64.eq"),
+        (INSTR_F64_GE, "f64.ge"),
+        (INSTR_I32_ADD, "i32.add"),
+        (INSTR_I32_MUL, "i32.mul"),
+        (INSTR_I32_SHR_U, "i32.shr_u"),
+        (INSTR_F64_CEIL, "f64.ceil"),
+        (INSTR_F64_ADD, "f64.add"),
+        (INSTR_F64_DIV, "f64.div"),
+        (INSTR_F64_MAX, "f64.max"),
+        (INSTR_I32_TRUNC_F64_S, "i32.trunc_f64_s"),
+        (INSTR_F64_CONVERT_I32_S, "f64.convert_i32_s"),
+    ] {
+        assert_eq!(
+            NUMERIC_INSTRUCTIONS[(opcode - INSTR_FIRST_NUMERIC) as usize],
+            name
+        );
+    }
+    // f32.ceil is not run, the emitter never writes it
+    let mut ast = Parser::parse("q(x) = Integrate(t^2, {t, 0, x})").unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let mut code = emit_code(&ast, &symbol_table).unwrap();
+    let body = &decode(&code).unwrap().code[0];
+    let position = body
+        .code
+        .iter()
+        .position(|instruction| *instruction == Instruction::F64Ceil)
+        .unwrap();
+    code[body.offsets[position]] = 0x8d;
+    let body = &decode(&code).unwrap().code[0];
+    assert_eq!(body.code[position], Instruction::Numeric(0x8d));
+    let mut instance = Instance::new(&code, &[]).unwrap();
+    let q = instance.imported_function_count();
+    assert_eq!(
+        instance.call_index(q, &[1.0]).unwrap_err(),
+        "Unsupported instruction Numeric(141)"
+    );
+}
+
+#[test]
+fn decodes_sections() {
+    let mut ast = Parser::parse("a = {1, 0, 2}\nf(x) = Sin(x)*a\nPlot(f(x), {x, 0, 1})").unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let code = emit_code(&ast, &symbol_table).unwrap();
+    let module = decode(&code).unwrap();
+    let ids: Vec<u8> = module.sections.iter().map(|s| s.id).collect();
+    assert_eq!(
+        ids,
+        vec![
+            SECTION_TYPE,
+            SECTION_IMPORTS,
+            SECTION_FUNCTION,
+            SECTION_MEMORY,
+            SECTION_EXPORT,
+            SECTION_CODE
+        ]
+    );
+    assert_eq!(module.imports.len(), 2);
+    assert_eq!(module.imported_function_count(), 1);
+    assert_eq!(module.imported_global_count(), 1);
+    // f, the plot function and redraw
+    assert_eq!(module.code.len(), 3);
+
+    assert!(decode(b"\0wasm").is_err());
+    assert!(decode(&code[..code.len() - 1]).is_err());
+}
+
+#[test]
+fn arithmetic_and_builtins() {
+    let script = "\
+f(x) = 2*x + 1
+g(x, y) = x^y - Sin(y)/4
+h(x) = -Sqrt(x)*Atan2(x, 2)
+";
+    let mut instance = instantiate(script, &[]);
+    assert_eq!(call(&mut instance, 0, &[3.0]), 7.0);
+    assert_eq!(
+        call(&mut instance, 1, &[2.0, 3.0]),
+        8.0 - 3.0_f64.sin() / 4.0
+    );
+    assert_eq!(
+        call(&mut instance, 2, &[2.0]),
+        -(2.0_f64.sqrt()) * 2.0_f64.atan2(2.0)
+    );
+    assert!(call(&mut instance, 2, &[-1.0]).is_nan());
+}
+
+#[test]
+fn if_expressions() {
+    let script = "\
+f(x) = If(x < 0, -x, x)
+g(x) = If(x = 1, 10, If(x >= 2, 20, 30))
+";
+    let mut instance = instantiate(script, &[]);
+    assert_eq!(call(&mut instance, 0, &[-2.5]), 2.5);
+    assert_eq!(call(&mut instance, 0, &[1.5]), 1.5);
+    assert_eq!(call(&mut instance, 1, &[1.0]), 10.0);
+    assert_eq!(call(&mut instance, 1, &[2.0]), 20.0);
+    assert_eq!(call(&mut instance, 1, &[0.0]), 30.0);
+}
+
+#[test]
+fn sliders_and_prints() {
+    let script = "\
+a = {2, 0, 10}
+f(x) = a*x + 3
+Print(f(2))
+Plot(f(x), {x, 0, 1})
+";
+    let mut instance = instantiate(script, &[("a", 2.0)]);
+    assert_eq!(call(&mut instance, 0, &[1.0]), 5.0);
+    instance.call("redraw", &[10.0, 10.0]).unwrap();
+    assert_eq!(instance.printed, vec![(0.0, 7.0)]);
+
+    instance.set_global("a", 5.0).unwrap();
+    assert_eq!(call(&mut instance, 0, &[1.0]), 8.0);
+    instance.call("redraw", &[10.0, 10.0]).unwrap();
+    assert_eq!(instance.printed, vec![(0.0, 7.0), (0.0, 13.0)]);
+}
+
+#[test]
+fn sums() {
+    let script = "\
+f(x) = Sum(n*x, {n, 1, 4})
+g(x) = Sum(Sum(n*m, {m, 1, 2}), {n, 1, 3}) + x
+h(x) = Sum(g(n) + f(1), {n, 0, 1})
+k(n) = Sum(n, {n, 1, 3}) + n
+e(x) = Sum(x, {n, 2, 1})
+";
+    let mut instance = instantiate(script, &[]);
+    assert_eq!(call(&mut instance, 0, &[2.0]), 20.0);
+    // (1 + 2) + (2 + 4) + (3 + 6)
+    assert_eq!(call(&mut instance, 1, &[0.0]), 18.0);
+    // the Sums in g do not overwrite the variable of the Sum in h
+    assert_eq!(call(&mut instance, 2, &[0.0]), 18.0 + 19.0 + 2.0 * 10.0);
+    // the variable of the Sum shadows the argument
+    assert_eq!(call(&mut instance, 3, &[10.0]), 16.0);
+    assert_eq!(call(&mut instance, 4, &[1.0]), 0.0);
+}
+
+#[test]
+fn sums_with_variable_bounds() {
+    let script = "\
+a = {3, 0, 10}
+f(x) = Sum(n*x, {n, 1, a})
+g(x) = Sum(Sum(m, {m, 1, n}), {n, 1, x})
+h(x) = Sum(n, {n, x, x + 2.5})
+Print(Sum(f(n), {n, 1, a}))
+";
+    let mut instance = instantiate(script, &[("a", 3.0)]);
+    assert_eq!(call(&mut instance, 0, &[2.0]), 12.0);
+    // 1 + (1 + 2) + (1 + 2 + 3)
+    assert_eq!(call(&mut instance, 1, &[3.0]), 10.0);
+    // the lower bound does not have to be an integer
+    assert_eq!(call(&mut instance, 2, &[0.5]), 0.5 + 1.5 + 2.5);
+    instance.call("redraw", &[10.0, 10.0]).unwrap();
+    assert_eq!(instance.printed, vec![(0.0, 6.0 * 6.0)]);
+
+    // the bounds are read when the Sum starts
+    instance.set_global("a", 1.0).unwrap();
+    assert_eq!(call(&mut instance, 0, &[2.0]), 2.0);
+}
+
+#[test]
+fn redraw_memory_layout() {
+    let script = "\
+Plot([x, 1/x], {x, -1, 1})
+Plot(x*x, {x, 0, 2})
+";
+    let mut instance = instantiate(script, &[]);
+    // width 3 samples at x = -1, 0, 1 and x = 0, 1, 2
+    let n = instance.call("redraw", &[3.0, 100.0]).unwrap();
+    assert_eq!(n, 3.0);
+    let values: Vec<f64> = (0..14)
+        .map(|i| {
+            instance
+                .read_f64(PLOT_DATA_ADDRESS as usize + 8 * i)
+                .unwrap()
+        })
+        .collect();
+    // 1/0 is infinite and does not count for the maximum
+    assert_eq!(&values[0..2], &[-1.0, 1.0]);
+    assert_eq!(&values[2..5], &[-1.0, 0.0, 1.0]);
+    assert_eq!(&values[5..8], &[-1.0, f64::INFINITY, 1.0]);
+    assert_eq!(&values[8..10], &[0.0, 4.0]);
+    assert_eq!(&values[10..13], &[0.0, 1.0, 4.0]);
+
+    // a width below 2 still takes 2 samples
+    assert_eq!(instance.call("redraw", &[0.0, 100.0]).unwrap(), 2.0);
+}
+
+#[test]
+fn parametric_memory_layout() {
+    let script = "\
+Plot(x, {x, 0, 1})
+ParametricPlot([{t, t*t}, {{1/t, 2 - t*t}, color=\"red\"}], {t, -1, 1})
+";
+    let mut instance = instantiate(script, &[]);
+    assert_eq!(instance.call("redraw", &[3.0, 100.0]).unwrap(), 3.0);
+    let values: Vec<f64> = (0..21)
+        .map(|i| {
+            instance
+                .read_f64(PLOT_DATA_ADDRESS as usize + 8 * i)
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(&values[0..5], &[0.0, 1.0, 0.0, 0.5, 1.0]);
+    // the point (1/0, 2) does not count for the ranges
+    assert_eq!(&values[5..9], &[-1.0, 1.0, 0.0, 1.0]);
+    assert_eq!(&values[9..15], &[-1.0, 1.0, 0.0, 0.0, 1.0, 1.0]);
+    assert_eq!(&values[15..21], &[-1.0, 1.0, f64::INFINITY, 2.0, 1.0, 1.0]);
+}
+
+#[test]
+fn polar_memory_layout() {
+    let script = "\
+PolarPlot([2, {1/theta, color=\"red\"}], {theta, 0, 3})
+Plot(x, {x, 0, 1})
+";
+    let mut instance = instantiate(script, &[]);
+    assert_eq!(instance.call("redraw", &[2.0, 100.0]).unwrap(), 2.0);
+    let values: Vec<f64> = (0..16)
+        .map(|i| {
+            instance
+                .read_f64(PLOT_DATA_ADDRESS as usize + 8 * i)
+                .unwrap()
+        })
+        .collect();
+    let (cos, sin) = (3.0_f64.cos(), 3.0_f64.sin());
+    // the point (1/0*cos 0, 1/0*sin 0) is (inf, NaN) and does not count for the ranges
+    assert_eq!(&values[0..4], &[2.0 * cos, 2.0, 0.0, 2.0 * sin]);
+    assert_eq!(&values[4..8], &[2.0, 0.0, 2.0 * cos, 2.0 * sin]);
+    assert_eq!(values[8], f64::INFINITY);
+    assert!(values[9].is_nan());
+    assert_eq!(&values[10..12], &[cos / 3.0, sin / 3.0]);
+    // the graph follows
+    assert_eq!(&values[12..16], &[0.0, 1.0, 0.0, 1.0]);
+}
+
+#[test]
+fn contour_memory_layout() {
+    let script = "\
+ContourPlot(x*x + y*y = 1.1, {x, -2, 2}, {y, -2, 2}, levels={0, 2})
+Plot(x, {x, 0, 1})
+";
+    let mut instance = instantiate(script, &[]);
+    assert_eq!(instance.call("redraw", &[2.0, 100.0]).unwrap(), 2.0);
+    let read = |index: usize| {
+        instance
+            .read_f64(PLOT_DATA_ADDRESS as usize + 8 * index)
+            .unwrap()
+    };
+    // the grid of x*x + y*y - 1.1 has 101 points in each row and starts at (-2, -2)
+    assert_eq!(read(1), 8.0 - 1.1);
+    assert_eq!(read(1 + 50 * 101 + 50), -1.1);
+    assert_eq!(read(1 + 50 * 101 + 100), 4.0 - 1.1);
+
+    let count = read(0) as usize;
+    let start = 1 + 101 * 101;
+    let points: Vec<(f64, f64)> = (0..2 * count)
+        .map(|k| (read(start + 2 * k), read(start + 2 * k + 1)))
+        .collect();
+    // the circles x*x + y*y = 1.1 and x*x + y*y = 3.1
+    let square = |(x, y): (f64, f64)| x * x + y * y;
+    assert!(points.chunks(2).all(|segment| {
+        let (s1, s2) = (square(segment[0]), square(segment[1]));
+        (s1 - 1.1).abs() < 1e-3 && (s2 - 1.1).abs() < 1e-3
+            || (s1 - 3.1).abs() < 1e-3 && (s2 - 3.1).abs() < 1e-3
+    }));
+    assert!(count > 3 * 100);
+    // the curves are closed, every end of a segment is the end of another one
+    for (k, (x, y)) in points.iter().enumerate() {
+        let same = points
+            .iter()
+            .filter(|(x2, y2)| (x - x2).abs() < 1e-9 && (y - y2).abs() < 1e-9)
+            .count();
+        assert_eq!(same, 2, "point {k} ({x}, {y})");
+    }
+
+    // the graph follows the segments
+    let header = start + 4 * count;
+    assert_eq!([read(header), read(header + 1)], [0.0, 1.0]);
+}
+
+#[test]
+fn density_memory_layout() {
+    let script = "\
+DensityPlot(x - y, {x, 0, 1}, {y, 0, 2}, colormap=\"grayscale\")
+DensityPlot(Sqrt(y) - 1, {x, 0, 1}, {y, -1, 3}, colormap=\"diverging\")
+Plot(x, {x, 0, 1})
+";
+    let mut instance = instantiate(script, &[]);
+    // three columns and three rows of pixels
+    assert_eq!(instance.call("redraw", &[3.0, 3.0]).unwrap(), 3.0);
+    let read = |index: usize| {
+        instance
+            .read_f64(PLOT_DATA_ADDRESS as usize + 8 * index)
+            .unwrap()
+    };
+    // the pixel k of the plot starting at `header`, red, green, blue and alpha
+    let pixel = |header: usize, k: usize| -> [u8; 4] {
+        let bytes = read(header + 11 + k / 2).to_bits().to_le_bytes();
+        bytes[4 * (k % 2)..4 * (k % 2) + 4].try_into().unwrap()
+    };
+
+    // the minimum and the maximum, then the values from the top row, at y = 2, to the bottom one
+    let values: Vec<f64> = (0..11).map(read).collect();
+    assert_eq!(
+        values,
+        [-2.0, 1.0, -2.0, -1.5, -1.0, -1.0, -0.5, 0.0, 0.0, 0.5, 1.0]
+    );
+    assert_eq!(pixel(0, 0), [0, 0, 0, 255]);
+    assert_eq!(pixel(0, 4), [128, 128, 128, 255]);
+    assert_eq!(pixel(0, 8), [255, 255, 255, 255]);
+
+    // the 36 bytes of the pixels are padded to 40, 0 is white in the middle of a diverging map
+    let [red, green, blue] = ColorMap::Diverging.color(0.5);
+    assert_eq!(pixel(16, 3), [red, green, blue, 255]);
+    assert_eq!(pixel(16, 0), [180, 4, 38, 255]);
+    // Sqrt(-1) is transparent
+    assert!(read(16).is_finite() && read(16 + 2 + 6).is_nan());
+    assert_eq!(pixel(16, 6), [0, 0, 0, 0]);
+
+    // the graph follows the pixels
+    assert_eq!([read(32), read(33)], [0.0, 1.0]);
+}
+
+#[test]
+fn density_of_an_overflowing_range() {
+    // upper - lower overflows to infinity, redraw must not trap truncating the channels
+    let script = "DensityPlot(x*1e308, {x, -1, 1}, {y, -1, 1})";
+    let mut instance = instantiate(script, &[]);
+    assert_eq!(instance.call("redraw", &[3.0, 2.0]).unwrap(), 3.0);
+    let read = |index: usize| {
+        instance
+            .read_f64(PLOT_DATA_ADDRESS as usize + 8 * index)
+            .unwrap()
+    };
+    assert_eq!([read(0), read(1)], [-1e308, 1e308]);
+    // the pixels of the first row are at the ends and in the middle of the color map
+    let bytes: Vec<u8> = (8..10)
+        .flat_map(|index| read(index).to_bits().to_le_bytes())
+        .collect();
+    for (k, position) in [0.0, 0.5, 1.0].into_iter().enumerate() {
+        let [red, green, blue] = ColorMap::Viridis.color(position);
+        assert_eq!(&bytes[4 * k..4 * k + 4], &[red, green, blue, 255]);
+    }
+}
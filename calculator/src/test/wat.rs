@@ -0,0 +1,54 @@
+use crate::{analyzer::analyze_program, ir::lower_program, parser::Parser, wat::emit_wat};
+
+#[test]
+fn names_and_source_lines() {
+    let script = "\
+a = {2, 0, 10}
+// the square
+sq(x) = x*x*a
+Plot(If(x > 0, sq(x), Sin(x)), {x, -1, 1})
+";
+    let mut ast = Parser::parse(script).unwrap();
+    let symbol_table = analyze_program(&mut ast).unwrap();
+    let program = lower_program(&ast, &symbol_table).unwrap();
+    let wat = emit_wat(&program, script).unwrap();
+    assert!(wat.starts_with("(module\n"));
+    assert!(wat.ends_with(")\n"));
+    assert!(
+        wat.contains("  (import \"Math\" \"Sin\" (func $Sin (type 0) (param f64) (result f64)))\n")
+    );
+    assert!(wat.contains("  (import \"globals\" \"a\" (global $a (mut f64)))\n"));
+    assert!(wat.contains(
+        "  ;; line 3: sq(x) = x*x*a
+  (func $sq (type 0) (param $x f64) (result f64)
+    local.get $x
+    local.get $x
+    global.get $a
+    f64.mul
+    f64.mul
+  )
+"
+    ));
+    assert!(wat.contains(
+        "  ;; line 4: If(x > 0, sq(x), Sin(x))
+  (func $plot0 (type 0) (param $x f64) (result f64)
+    local.get $x
+    f64.const 0
+    f64.gt
+    if (result f64)
+      local.get $x
+      call $sq
+    else
+      local.get $x
+      call $Sin
+    end
+  )
+"
+    ));
+    // the locals of redraw are declared with the names used in the code
+    assert!(wat.contains("    (local $y_min f64)\n"));
+    assert!(wat.contains("      call $plot0\n"));
+    // the only globals are the sliders
+    assert!(!wat.contains("  (global "));
+    assert!(wat.contains("  (export \"redraw\" (func $redraw))\n"));
+}
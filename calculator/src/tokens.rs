@@ -1,5 +1,26 @@
 use std::fmt;
 
+/// A region of the source text, as byte offsets
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// The span that goes from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub enum Token {
     Number(f64),
@@ -31,6 +52,22 @@ pub enum Token {
     EoI,
 }
 
+/// A `// line` or `/* block */` comment, including the delimiters.
+/// Comments are ignored by the compiler but kept around for the pretty printer.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+    // true if there is nothing but whitespace before the comment in its line
+    pub own_line: bool,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
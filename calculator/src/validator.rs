@@ -0,0 +1,538 @@
+// Checks that a module is well formed before it is written out, so a bug in the emitter is
+// reported by the compiler instead of by the browser's `WebAssembly.compile`.
+// It checks the section order, that every index is in bounds and that the operand stack is used
+// with the right types, following the validation algorithm of the spec:
+// https://webassembly.github.io/spec/core/appendix/algorithm.html
+// Like the decoder it only knows the instructions the emitter writes, and the types of the other
+// numeric instructions from the opcode table of the spec.
+
+use std::collections::HashSet;
+
+use crate::{
+    opcodes::{FUNCTION_EXPORT_KIND, GLOBAL_EXPORT_KIND, MEMORY_EXPORT_KIND, SECTION_CUSTOM},
+    wasm_decoder::{
+        decode, BlockType, FunctionBody, ImportKind, Instruction, MemoryArgument, Module, ValueType,
+    },
+    wat::{instruction_text, value_type, Names},
+};
+
+use ValueType::{F64, I32};
+
+// More locals than this is surely a bug, and we do not want to allocate 4 billion of them
+const MAX_LOCALS: u64 = 50_000;
+
+// The position of a section in the order the spec requires, the data count section (12) goes
+// before the code section (10)
+fn section_rank(id: u8) -> Option<u8> {
+    match id {
+        1..=9 => Some(id),
+        12 => Some(10),
+        10 => Some(11),
+        11 => Some(12),
+        _ => None,
+    }
+}
+
+#[derive(PartialEq)]
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+struct Frame {
+    kind: FrameKind,
+    results: Vec<ValueType>,
+    // the height of the operand stack when the frame was entered
+    height: usize,
+    // after br, return and unreachable anything can be popped
+    unreachable: bool,
+}
+
+impl Frame {
+    // the values a branch to this frame takes, a loop branches back to its start
+    fn label_types(&self) -> Vec<ValueType> {
+        if self.kind == FrameKind::Loop {
+            Vec::new()
+        } else {
+            self.results.clone()
+        }
+    }
+}
+
+fn block_results(block_type: BlockType) -> Vec<ValueType> {
+    match block_type {
+        BlockType::Empty => Vec::new(),
+        BlockType::Value(t) => vec![t],
+    }
+}
+
+struct FunctionValidator<'a> {
+    module: &'a Module,
+    locals: Vec<ValueType>,
+    results: Vec<ValueType>,
+    has_memory: bool,
+    // None is a value of unknown type, found in unreachable code
+    operands: Vec<Option<ValueType>>,
+    frames: Vec<Frame>,
+}
+
+impl<'a> FunctionValidator<'a> {
+    fn push(&mut self, value_type: ValueType) {
+        self.operands.push(Some(value_type));
+    }
+
+    fn pop(&mut self, expected: Option<ValueType>) -> Result<Option<ValueType>, String> {
+        let frame = self
+            .frames
+            .last()
+            .ok_or("Instruction after the end of the function")?;
+        if self.operands.len() == frame.height {
+            if frame.unreachable {
+                return Ok(expected);
+            }
+            return Err("Operand stack underflow".to_string());
+        }
+        let actual = self.operands.pop().unwrap();
+        match (actual, expected) {
+            (Some(actual), Some(expected)) if actual != expected => Err(format!(
+                "Expected {} but found {}",
+                value_type(expected),
+                value_type(actual)
+            )),
+            (None, _) => Ok(expected),
+            _ => Ok(actual),
+        }
+    }
+
+    fn pop_types(&mut self, types: &[ValueType]) -> Result<(), String> {
+        for t in types.iter().rev() {
+            self.pop(Some(*t))?;
+        }
+        Ok(())
+    }
+
+    // pops the operands of a numeric instruction and pushes its result
+    fn operation(&mut self, operands: &[ValueType], result: ValueType) -> Result<(), String> {
+        self.pop_types(operands)?;
+        self.push(result);
+        Ok(())
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.operands.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn enter(&mut self, kind: FrameKind, results: Vec<ValueType>) {
+        self.frames.push(Frame {
+            kind,
+            results,
+            height: self.operands.len(),
+            unreachable: false,
+        });
+    }
+
+    // checks that the frame leaves exactly its results on the stack
+    fn leave(&mut self) -> Result<Frame, String> {
+        let results = self.frames.last().ok_or("Unmatched end")?.results.clone();
+        self.pop_types(&results)?;
+        let frame = self.frames.pop().unwrap();
+        if self.operands.len() != frame.height {
+            return Err(format!(
+                "{} values left on the stack at the end of the block",
+                self.operands.len() - frame.height
+            ));
+        }
+        Ok(frame)
+    }
+
+    fn label(&self, depth: u32) -> Result<Vec<ValueType>, String> {
+        match self.frames.len().checked_sub(depth as usize + 1) {
+            Some(index) => Ok(self.frames[index].label_types()),
+            None => Err(format!("Branch depth {depth} out of bounds")),
+        }
+    }
+
+    fn local(&self, index: u32) -> Result<ValueType, String> {
+        match self.locals.get(index as usize) {
+            Some(t) => Ok(*t),
+            None => Err(format!("Local {index} out of bounds")),
+        }
+    }
+
+    fn memory(&self, argument: &MemoryArgument, natural_align: u32) -> Result<(), String> {
+        if !self.has_memory {
+            return Err("Memory instruction without a memory".to_string());
+        }
+        if argument.align > natural_align {
+            return Err(format!(
+                "Alignment 2^{} larger than natural alignment 2^{natural_align}",
+                argument.align
+            ));
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) -> Result<(), String> {
+        match instruction {
+            Instruction::Unreachable => self.set_unreachable(),
+            Instruction::Nop => {}
+            Instruction::Block(t) => self.enter(FrameKind::Block, block_results(*t)),
+            Instruction::Loop(t) => self.enter(FrameKind::Loop, block_results(*t)),
+            Instruction::If(t) => {
+                self.pop(Some(I32))?;
+                self.enter(FrameKind::If, block_results(*t));
+            }
+            Instruction::Else => {
+                if self.frames.last().map(|frame| &frame.kind) != Some(&FrameKind::If) {
+                    return Err("Else outside of an if".to_string());
+                }
+                let frame = self.leave()?;
+                self.enter(FrameKind::Else, frame.results);
+            }
+            Instruction::End => {
+                let frame = self.leave()?;
+                // without an else the condition being false leaves nothing on the stack
+                if frame.kind == FrameKind::If && !frame.results.is_empty() {
+                    return Err("If with a result but no else".to_string());
+                }
+                for t in frame.results {
+                    self.push(t);
+                }
+            }
+            Instruction::Br(depth) => {
+                let types = self.label(*depth)?;
+                self.pop_types(&types)?;
+                self.set_unreachable();
+            }
+            Instruction::BrIf(depth) => {
+                self.pop(Some(I32))?;
+                let types = self.label(*depth)?;
+                self.pop_types(&types)?;
+                for t in types {
+                    self.push(t);
+                }
+            }
+            Instruction::Return => {
+                let results = self.results.clone();
+                self.pop_types(&results)?;
+                self.set_unreachable();
+            }
+            Instruction::Call(index) => {
+                let function_type = self
+                    .module
+                    .function_type(*index)
+                    .and_then(|type_index| self.module.types.get(type_index as usize))
+                    .ok_or(format!("Call to function {index} out of bounds"))?;
+                self.pop_types(&function_type.params)?;
+                for t in &function_type.results {
+                    self.push(*t);
+                }
+            }
+            Instruction::Drop => {
+                self.pop(None)?;
+            }
+            Instruction::Select => {
+                self.pop(Some(I32))?;
+                let t = self.pop(None)?;
+                let t = self.pop(t)?;
+                self.operands.push(t);
+            }
+            Instruction::LocalGet(index) => {
+                let t = self.local(*index)?;
+                self.push(t);
+            }
+            Instruction::LocalSet(index) => {
+                let t = self.local(*index)?;
+                self.pop(Some(t))?;
+            }
+            Instruction::LocalTee(index) => {
+                let t = self.local(*index)?;
+                self.pop(Some(t))?;
+                self.push(t);
+            }
+            Instruction::GlobalGet(index) => {
+                let (t, _) = global_type(self.module, *index)?;
+                self.push(t);
+            }
+            Instruction::GlobalSet(index) => {
+                let (t, mutable) = global_type(self.module, *index)?;
+                if !mutable {
+                    return Err(format!("Global {index} is immutable"));
+                }
+                self.pop(Some(t))?;
+            }
+            Instruction::I32Load(argument) => {
+                self.memory(argument, 2)?;
+                self.operation(&[I32], I32)?;
+            }
+            Instruction::F64Load(argument) => {
+                self.memory(argument, 3)?;
+                self.operation(&[I32], F64)?;
+            }
+            Instruction::I32Store(argument) => {
+                self.memory(argument, 2)?;
+                self.pop_types(&[I32, I32])?;
+            }
+            Instruction::F64Store(argument) => {
+                self.memory(argument, 3)?;
+                self.pop_types(&[I32, F64])?;
+            }
+            Instruction::MemorySize => {
+                self.memory(
+                    &MemoryArgument {
+                        align: 0,
+                        offset: 0,
+                    },
+                    0,
+                )?;
+                self.push(I32);
+            }
+            Instruction::MemoryGrow => {
+                self.memory(
+                    &MemoryArgument {
+                        align: 0,
+                        offset: 0,
+                    },
+                    0,
+                )?;
+                self.operation(&[I32], I32)?;
+            }
+            Instruction::I32Const(_) => self.push(I32),
+            Instruction::I64Const(_) => self.push(ValueType::I64),
+            Instruction::F32Const(_) => self.push(ValueType::F32),
+            Instruction::F64Const(_) => self.push(F64),
+            Instruction::I32LtS | Instruction::I32GtU => self.operation(&[I32, I32], I32)?,
+            Instruction::F64Eq
+            | Instruction::F64Ne
+            | Instruction::F64Lt
+            | Instruction::F64Gt
+            | Instruction::F64Le
+            | Instruction::F64Ge => self.operation(&[F64, F64], I32)?,
+            Instruction::I32Add
+            | Instruction::I32Sub
+            | Instruction::I32Mul
+            | Instruction::I32ShrU => self.operation(&[I32, I32], I32)?,
+            Instruction::F64Ceil => self.operation(&[F64], F64)?,
+            Instruction::F64Add
+            | Instruction::F64Sub
+            | Instruction::F64Mul
+            | Instruction::F64Div
+            | Instruction::F64Min
+            | Instruction::F64Max => self.operation(&[F64, F64], F64)?,
+            Instruction::I32TruncF64S => self.operation(&[F64], I32)?,
+            Instruction::F64ConvertI32S => self.operation(&[I32], F64)?,
+            Instruction::Numeric(opcode) => {
+                let (operands, result) = numeric_type(*opcode);
+                self.operation(operands, result)?
+            }
+            Instruction::BrTable(..) | Instruction::CallIndirect(..) | Instruction::Memory(..) => {
+                return Err("Instruction not written by the emitter".to_string())
+            }
+        }
+        Ok(())
+    }
+}
+
+// The operands and the result of a numeric instruction, from i32.eqz (0x45) to
+// f64.reinterpret_i64 (0xbf)
+fn numeric_type(opcode: u8) -> (&'static [ValueType], ValueType) {
+    use ValueType::{F32, I64};
+    match opcode {
+        0x45 => (&[I32], I32),
+        0x46..=0x4f => (&[I32, I32], I32),
+        0x50 => (&[I64], I32),
+        0x51..=0x5a => (&[I64, I64], I32),
+        0x5b..=0x60 => (&[F32, F32], I32),
+        0x61..=0x66 => (&[F64, F64], I32),
+        0x67..=0x69 => (&[I32], I32),
+        0x6a..=0x78 => (&[I32, I32], I32),
+        0x79..=0x7b => (&[I64], I64),
+        0x7c..=0x8a => (&[I64, I64], I64),
+        0x8b..=0x91 => (&[F32], F32),
+        0x92..=0x98 => (&[F32, F32], F32),
+        0x99..=0x9f => (&[F64], F64),
+        0xa0..=0xa6 => (&[F64, F64], F64),
+        0xa7 => (&[I64], I32),
+        0xa8 | 0xa9 | 0xbc => (&[F32], I32),
+        0xaa | 0xab => (&[F64], I32),
+        0xac | 0xad => (&[I32], I64),
+        0xae | 0xaf => (&[F32], I64),
+        0xb0 | 0xb1 | 0xbd => (&[F64], I64),
+        0xb2 | 0xb3 | 0xbe => (&[I32], F32),
+        0xb4 | 0xb5 => (&[I64], F32),
+        0xb6 => (&[F64], F32),
+        0xb7 | 0xb8 => (&[I32], F64),
+        0xb9 | 0xba | 0xbf => (&[I64], F64),
+        // f64.promote_f32
+        _ => (&[F32], F64),
+    }
+}
+
+// The type of a global in the global index space (imports first) and whether it is mutable
+fn global_type(module: &Module, index: u32) -> Result<(ValueType, bool), String> {
+    let imported = module
+        .imports
+        .iter()
+        .filter_map(|import| match import.kind {
+            ImportKind::Global {
+                value_type,
+                mutable,
+            } => Some((value_type, mutable)),
+            _ => None,
+        });
+    let defined = module
+        .globals
+        .iter()
+        .map(|global| (global.value_type, global.mutable));
+    imported
+        .chain(defined)
+        .nth(index as usize)
+        .ok_or(format!("Global {index} out of bounds"))
+}
+
+fn validate_function(
+    module: &Module,
+    function_index: u32,
+    type_index: u32,
+    body: &FunctionBody,
+    has_memory: bool,
+) -> Result<(), String> {
+    let function_type = module.types.get(type_index as usize).ok_or(format!(
+        "func[{function_index}]: type {type_index} out of bounds"
+    ))?;
+    let local_count: u64 = body.locals.iter().map(|(count, _)| *count as u64).sum();
+    if local_count > MAX_LOCALS {
+        return Err(format!("func[{function_index}]: {local_count} locals"));
+    }
+    let mut locals = function_type.params.clone();
+    for (count, t) in &body.locals {
+        locals.extend(vec![*t; *count as usize]);
+    }
+    let mut validator = FunctionValidator {
+        module,
+        locals,
+        results: function_type.results.clone(),
+        has_memory,
+        operands: Vec::new(),
+        frames: Vec::new(),
+    };
+    validator.enter(FrameKind::Function, function_type.results.clone());
+    for (position, instruction) in body.code.iter().enumerate() {
+        validator.instruction(instruction).map_err(|message| {
+            format!(
+                "func[{function_index}] at offset {:#x} ({}): {message}",
+                body.offsets[position],
+                instruction_text(instruction, &Names::default(), function_index)
+            )
+        })?;
+    }
+    if !validator.frames.is_empty() {
+        return Err(format!("func[{function_index}]: unterminated block"));
+    }
+    Ok(())
+}
+
+/// Ok if `bytes` is a valid module, a description of the first problem otherwise
+pub(crate) fn validate(bytes: &[u8]) -> Result<(), String> {
+    let module = decode(bytes).map_err(|message| format!("Malformed module: {message}"))?;
+
+    let mut last_rank = 0;
+    for section in &module.sections {
+        if section.id == SECTION_CUSTOM {
+            continue;
+        }
+        let rank = section_rank(section.id).ok_or(format!("Unknown section {}", section.id))?;
+        if rank <= last_rank {
+            return Err(format!(
+                "Section {} at offset {:#x} is out of order or repeated",
+                section.id, section.offset
+            ));
+        }
+        last_rank = rank;
+    }
+
+    if module.functions.len() != module.code.len() {
+        return Err(format!(
+            "The function section declares {} functions but the code section has {} bodies",
+            module.functions.len(),
+            module.code.len()
+        ));
+    }
+    for import in &module.imports {
+        if let ImportKind::Function(type_index) = import.kind {
+            if type_index as usize >= module.types.len() {
+                return Err(format!(
+                    "Import {}.{}: type {type_index} out of bounds",
+                    import.module, import.name
+                ));
+            }
+        }
+    }
+
+    let has_memory = module.memory.is_some()
+        || module
+            .imports
+            .iter()
+            .any(|import| matches!(import.kind, ImportKind::Memory(_)));
+    if let Some(limits) = &module.memory {
+        if limits
+            .maximum
+            .is_some_and(|maximum| maximum < limits.minimum)
+        {
+            return Err("Memory maximum smaller than the minimum".to_string());
+        }
+    }
+
+    let imported_globals = module.imported_global_count();
+    for (offset, global) in module.globals.iter().enumerate() {
+        let index = imported_globals + offset as u32;
+        let init_type = match global.init.as_slice() {
+            [Instruction::I32Const(_), Instruction::End] => I32,
+            [Instruction::F64Const(_), Instruction::End] => F64,
+            // only imported globals can be read in an initializer
+            [Instruction::GlobalGet(imported), Instruction::End]
+                if *imported < imported_globals =>
+            {
+                global_type(&module, *imported)?.0
+            }
+            _ => return Err(format!("global[{index}]: invalid initializer")),
+        };
+        if init_type != global.value_type {
+            return Err(format!(
+                "global[{index}]: initializer of type {} for a global of type {}",
+                value_type(init_type),
+                value_type(global.value_type)
+            ));
+        }
+    }
+
+    let function_count = module.imported_function_count() + module.functions.len() as u32;
+    let global_count = imported_globals + module.globals.len() as u32;
+    let mut export_names = HashSet::new();
+    for export in &module.exports {
+        if !export_names.insert(&export.name) {
+            return Err(format!("Duplicate export \"{}\"", export.name));
+        }
+        let in_bounds = match export.kind {
+            FUNCTION_EXPORT_KIND => export.index < function_count,
+            MEMORY_EXPORT_KIND => export.index == 0 && has_memory,
+            GLOBAL_EXPORT_KIND => export.index < global_count,
+            _ => false,
+        };
+        if !in_bounds {
+            return Err(format!("Export \"{}\": index out of bounds", export.name));
+        }
+    }
+
+    let imported_functions = module.imported_function_count();
+    for (offset, (type_index, body)) in module.functions.iter().zip(&module.code).enumerate() {
+        let function_index = imported_functions + offset as u32;
+        validate_function(&module, function_index, *type_index, body, has_memory)?;
+    }
+    Ok(())
+}
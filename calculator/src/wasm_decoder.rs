@@ -0,0 +1,619 @@
+// Decodes a binary WebAssembly module, the inverse of the emitter.
+// It understands the sections and instructions the emitter writes (see opcodes.rs)
+// https://webassembly.github.io/spec/core/binary/index.html
+
+use crate::opcodes::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ValueType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct FunctionType {
+    pub params: Vec<ValueType>,
+    pub results: Vec<ValueType>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ImportKind {
+    Function(u32),
+    Table(Limits),
+    Memory(Limits),
+    Global {
+        value_type: ValueType,
+        mutable: bool,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Import {
+    pub module: String,
+    pub name: String,
+    pub kind: ImportKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Limits {
+    pub minimum: u32,
+    pub maximum: Option<u32>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Global {
+    pub value_type: ValueType,
+    pub mutable: bool,
+    pub init: Vec<Instruction>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Export {
+    pub name: String,
+    // FUNCTION_EXPORT_KIND, MEMORY_EXPORT_KIND, ...
+    pub kind: u8,
+    pub index: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct FunctionBody {
+    // where the body is in the binary, `offset` is the first byte after the size
+    pub offset: usize,
+    pub size: u32,
+    // run length encoded local declarations: (count, type)
+    pub locals: Vec<(u32, ValueType)>,
+    pub code: Vec<Instruction>,
+    // the offset in the binary of each instruction of `code`
+    pub offsets: Vec<usize>,
+}
+
+// Where a section is in the binary, `offset` is the first byte after the size
+#[derive(Debug, PartialEq)]
+pub(crate) struct Section {
+    pub id: u8,
+    pub offset: usize,
+    pub size: u32,
+    // only custom sections have a name
+    pub name: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct MemoryArgument {
+    pub align: u32,
+    pub offset: u32,
+}
+
+// The type of the values a block leaves on the stack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BlockType {
+    Empty,
+    Value(ValueType),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Instruction {
+    // control
+    Unreachable,
+    Nop,
+    Block(BlockType),
+    Loop(BlockType),
+    If(BlockType),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    // (type index, table index)
+    CallIndirect(u32, u32),
+    Drop,
+    Select,
+    // variables
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    // memory
+    I32Load(MemoryArgument),
+    F64Load(MemoryArgument),
+    I32Store(MemoryArgument),
+    F64Store(MemoryArgument),
+    // the other loads and stores, by opcode
+    Memory(u8, MemoryArgument),
+    MemorySize,
+    MemoryGrow,
+    // numeric
+    I32Const(i32),
+    I64Const(i64),
+    F32Const(f32),
+    F64Const(f64),
+    I32LtS,
+    I32GtU,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Gt,
+    F64Le,
+    F64Ge,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32ShrU,
+    F64Ceil,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    F64Min,
+    F64Max,
+    I32TruncF64S,
+    F64ConvertI32S,
+    // the other numeric instructions, they have no immediates
+    Numeric(u8),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Module {
+    pub sections: Vec<Section>,
+    pub types: Vec<FunctionType>,
+    pub imports: Vec<Import>,
+    // the type index of each function defined in the module
+    pub functions: Vec<u32>,
+    pub memory: Option<Limits>,
+    pub globals: Vec<Global>,
+    pub exports: Vec<Export>,
+    pub code: Vec<FunctionBody>,
+}
+
+impl Module {
+    pub fn imported_function_count(&self) -> u32 {
+        self.imports
+            .iter()
+            .filter(|import| matches!(import.kind, ImportKind::Function(_)))
+            .count() as u32
+    }
+
+    pub fn imported_global_count(&self) -> u32 {
+        self.imports
+            .iter()
+            .filter(|import| matches!(import.kind, ImportKind::Global { .. }))
+            .count() as u32
+    }
+
+    /// The type index of a function in the function index space (imports first)
+    pub fn function_type(&self, function_index: u32) -> Option<u32> {
+        let imported: Vec<u32> = self
+            .imports
+            .iter()
+            .filter_map(|import| match import.kind {
+                ImportKind::Function(type_index) => Some(type_index),
+                _ => None,
+            })
+            .collect();
+        match imported.get(function_index as usize) {
+            Some(type_index) => Some(*type_index),
+            None => self
+                .functions
+                .get(function_index as usize - imported.len())
+                .copied(),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("{message} at offset {:#x}", self.position)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, String> {
+        match self.bytes.get(self.position) {
+            Some(&byte) => {
+                self.position += 1;
+                Ok(byte)
+            }
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.position + count > self.bytes.len() {
+            return Err(self.error("Unexpected end of input"));
+        }
+        let bytes = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, String> {
+        let mut result: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_byte()?;
+            result |= ((byte & 0b0111_1111) as u64) << shift;
+            if byte & 0b1000_0000 == 0 {
+                break;
+            }
+            shift += 7;
+            if shift > 28 {
+                return Err(self.error("Integer too long"));
+            }
+        }
+        u32::try_from(result).map_err(|_| self.error("Integer too large"))
+    }
+
+    fn read_i32(&mut self) -> Result<i32, String> {
+        let result = self.read_signed(32)?;
+        i32::try_from(result).map_err(|_| self.error("Integer too large"))
+    }
+
+    fn read_i64(&mut self) -> Result<i64, String> {
+        let result = self.read_signed(64)?;
+        i64::try_from(result).map_err(|_| self.error("Integer too large"))
+    }
+
+    // signed leb128 of at most `bits` bits
+    fn read_signed(&mut self, bits: u32) -> Result<i128, String> {
+        let mut result: i128 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_byte()?;
+            result |= ((byte & 0b0111_1111) as i128) << shift;
+            shift += 7;
+            if byte & 0b1000_0000 == 0 {
+                // sign extend
+                if byte & 0b0100_0000 != 0 {
+                    result |= -1 << shift;
+                }
+                return Ok(result);
+            }
+            if shift >= bits {
+                return Err(self.error("Integer too long"));
+            }
+        }
+    }
+
+    fn read_f32(&mut self) -> Result<f32, String> {
+        let bytes = self.read_bytes(4)?;
+        Ok(f32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_f64(&mut self) -> Result<f64, String> {
+        let bytes = self.read_bytes(8)?;
+        Ok(f64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_name(&mut self) -> Result<String, String> {
+        let length = self.read_u32()? as usize;
+        let bytes = self.read_bytes(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error("Invalid UTF-8 name"))
+    }
+
+    fn read_value_type(&mut self) -> Result<ValueType, String> {
+        match self.read_byte()? {
+            I32_TYPE => Ok(ValueType::I32),
+            I64_TYPE => Ok(ValueType::I64),
+            F32_TYPE => Ok(ValueType::F32),
+            F64_TYPE => Ok(ValueType::F64),
+            byte => Err(self.error(&format!("Unknown value type {byte:#04x}"))),
+        }
+    }
+
+    fn read_block_type(&mut self) -> Result<BlockType, String> {
+        if self.bytes.get(self.position) == Some(&INSTR_VOID) {
+            self.position += 1;
+            Ok(BlockType::Empty)
+        } else {
+            Ok(BlockType::Value(self.read_value_type()?))
+        }
+    }
+
+    fn read_memory_argument(&mut self) -> Result<MemoryArgument, String> {
+        let align = self.read_u32()?;
+        let offset = self.read_u32()?;
+        Ok(MemoryArgument { align, offset })
+    }
+
+    fn read_limits(&mut self) -> Result<Limits, String> {
+        match self.read_byte()? {
+            LIMITS_FLAG_NO_MAX => Ok(Limits {
+                minimum: self.read_u32()?,
+                maximum: None,
+            }),
+            LIMITS_FLAG_MAX => Ok(Limits {
+                minimum: self.read_u32()?,
+                maximum: Some(self.read_u32()?),
+            }),
+            byte => Err(self.error(&format!("Unknown limits flag {byte:#04x}"))),
+        }
+    }
+
+    fn read_instruction(&mut self) -> Result<Instruction, String> {
+        let opcode = self.read_byte()?;
+        let instruction = match opcode {
+            INSTR_UNREACHABLE => Instruction::Unreachable,
+            INSTR_NOP => Instruction::Nop,
+            INSTR_BLOCK => Instruction::Block(self.read_block_type()?),
+            INSTR_BLOCK_LOOP => Instruction::Loop(self.read_block_type()?),
+            INSTR_BLOCK_IF => Instruction::If(self.read_block_type()?),
+            INSTR_BLOCK_ELSE => Instruction::Else,
+            EXPRESSION_END => Instruction::End,
+            INSTR_BR => Instruction::Br(self.read_u32()?),
+            INSTR_BR_IF => Instruction::BrIf(self.read_u32()?),
+            INSTR_BR_TABLE => {
+                let count = self.read_u32()?;
+                let mut labels = Vec::new();
+                for _ in 0..count {
+                    labels.push(self.read_u32()?);
+                }
+                Instruction::BrTable(labels, self.read_u32()?)
+            }
+            INSTR_RETURN => Instruction::Return,
+            INSTR_FUNCTION_CALL => Instruction::Call(self.read_u32()?),
+            INSTR_CALL_INDIRECT => {
+                let type_index = self.read_u32()?;
+                Instruction::CallIndirect(type_index, self.read_u32()?)
+            }
+            INSTR_DROP => Instruction::Drop,
+            INSTR_SELECT => Instruction::Select,
+            INSTR_LOCAL_GET => Instruction::LocalGet(self.read_u32()?),
+            INSTR_LOCAL_SET => Instruction::LocalSet(self.read_u32()?),
+            INSTR_LOCAL_TEE => Instruction::LocalTee(self.read_u32()?),
+            INSTR_GLOBAL_GET => Instruction::GlobalGet(self.read_u32()?),
+            INSTR_GLOBAL_SET => Instruction::GlobalSet(self.read_u32()?),
+            MEMORY_I32_LOAD => Instruction::I32Load(self.read_memory_argument()?),
+            MEMORY_F64_LOAD => Instruction::F64Load(self.read_memory_argument()?),
+            MEMORY_I32_STORE => Instruction::I32Store(self.read_memory_argument()?),
+            MEMORY_F64_STORE => Instruction::F64Store(self.read_memory_argument()?),
+            _ if (MEMORY_FIRST_LOAD..=MEMORY_LAST_STORE).contains(&opcode) => {
+                Instruction::Memory(opcode, self.read_memory_argument()?)
+            }
+            INSTR_MEMORY_SIZE | INSTR_MEMORY_GROW => {
+                // memory index, always 0
+                if self.read_byte()? != 0x00 {
+                    return Err(self.error("Expected memory index 0"));
+                }
+                if opcode == INSTR_MEMORY_SIZE {
+                    Instruction::MemorySize
+                } else {
+                    Instruction::MemoryGrow
+                }
+            }
+            INSTR_I32_CONST => Instruction::I32Const(self.read_i32()?),
+            INSTR_I64_CONST => Instruction::I64Const(self.read_i64()?),
+            INSTR_F32_CONST => Instruction::F32Const(self.read_f32()?),
+            INSTR_F64_CONST => Instruction::F64Const(self.read_f64()?),
+            INSTR_I32_LT_S => Instruction::I32LtS,
+            INSTR_I32_GT_U => Instruction::I32GtU,
+            INSTR_F64_EQ => Instruction::F64Eq,
+            INSTR_F64_NE => Instruction::F64Ne,
+            INSTR_F64_LT => Instruction::F64Lt,
+            INSTR_F64_GT => Instruction::F64Gt,
+            INSTR_F64_LE => Instruction::F64Le,
+            INSTR_F64_GE => Instruction::F64Ge,
+            INSTR_I32_ADD => Instruction::I32Add,
+            INSTR_I32_SUB => Instruction::I32Sub,
+            INSTR_I32_MUL => Instruction::I32Mul,
+            INSTR_I32_SHR_U => Instruction::I32ShrU,
+            INSTR_F64_CEIL => Instruction::F64Ceil,
+            INSTR_F64_ADD => Instruction::F64Add,
+            INSTR_F64_SUB => Instruction::F64Sub,
+            INSTR_F64_MUL => Instruction::F64Mul,
+            INSTR_F64_DIV => Instruction::F64Div,
+            INSTR_F64_MIN => Instruction::F64Min,
+            INSTR_F64_MAX => Instruction::F64Max,
+            INSTR_I32_TRUNC_F64_S => Instruction::I32TruncF64S,
+            INSTR_F64_CONVERT_I32_S => Instruction::F64ConvertI32S,
+            _ if (INSTR_FIRST_NUMERIC..=INSTR_LAST_NUMERIC).contains(&opcode) => {
+                Instruction::Numeric(opcode)
+            }
+            _ => {
+                self.position -= 1;
+                return Err(self.error(&format!("Unknown opcode {opcode:#04x}")));
+            }
+        };
+        Ok(instruction)
+    }
+
+    // Reads instructions up to the `end` that closes the expression (included),
+    // the offset of each instruction is added to `offsets`
+    fn read_expression(&mut self, offsets: &mut Vec<usize>) -> Result<Vec<Instruction>, String> {
+        let mut code = Vec::new();
+        let mut depth = 0;
+        loop {
+            offsets.push(self.position);
+            let instruction = self.read_instruction()?;
+            match instruction {
+                Instruction::Block(_) | Instruction::Loop(_) | Instruction::If(_) => depth += 1,
+                Instruction::End => {
+                    if depth == 0 {
+                        code.push(instruction);
+                        return Ok(code);
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+            code.push(instruction);
+        }
+    }
+
+    fn read_type_section(&mut self, module: &mut Module) -> Result<(), String> {
+        let count = self.read_u32()?;
+        for _ in 0..count {
+            if self.read_byte()? != FUNCTION_TYPE_MARKER {
+                return Err(self.error("Expected a function type"));
+            }
+            let param_count = self.read_u32()?;
+            let mut params = Vec::new();
+            for _ in 0..param_count {
+                params.push(self.read_value_type()?);
+            }
+            let result_count = self.read_u32()?;
+            let mut results = Vec::new();
+            for _ in 0..result_count {
+                results.push(self.read_value_type()?);
+            }
+            module.types.push(FunctionType { params, results });
+        }
+        Ok(())
+    }
+
+    fn read_import_section(&mut self, module: &mut Module) -> Result<(), String> {
+        let count = self.read_u32()?;
+        for _ in 0..count {
+            let module_name = self.read_name()?;
+            let name = self.read_name()?;
+            let kind = match self.read_byte()? {
+                FUNCTION_DESCRIPTOR => ImportKind::Function(self.read_u32()?),
+                TABLE_DESCRIPTOR => {
+                    // the type of the elements, a function or an external reference
+                    self.read_byte()?;
+                    ImportKind::Table(self.read_limits()?)
+                }
+                MEMORY_DESCRIPTOR => ImportKind::Memory(self.read_limits()?),
+                CONSTANT_DESCRIPTOR => {
+                    let value_type = self.read_value_type()?;
+                    let mutable = self.read_byte()? == CONSTANT_MUTABLE;
+                    ImportKind::Global {
+                        value_type,
+                        mutable,
+                    }
+                }
+                byte => return Err(self.error(&format!("Unsupported import kind {byte:#04x}"))),
+            };
+            module.imports.push(Import {
+                module: module_name,
+                name,
+                kind,
+            });
+        }
+        Ok(())
+    }
+
+    fn read_function_section(&mut self, module: &mut Module) -> Result<(), String> {
+        let count = self.read_u32()?;
+        for _ in 0..count {
+            module.functions.push(self.read_u32()?);
+        }
+        Ok(())
+    }
+
+    fn read_memory_section(&mut self, module: &mut Module) -> Result<(), String> {
+        let count = self.read_u32()?;
+        if count != 1 {
+            return Err(self.error("Expected exactly one memory"));
+        }
+        module.memory = Some(self.read_limits()?);
+        Ok(())
+    }
+
+    fn read_global_section(&mut self, module: &mut Module) -> Result<(), String> {
+        let count = self.read_u32()?;
+        for _ in 0..count {
+            let value_type = self.read_value_type()?;
+            let mutable = self.read_byte()? == CONSTANT_MUTABLE;
+            let init = self.read_expression(&mut Vec::new())?;
+            module.globals.push(Global {
+                value_type,
+                mutable,
+                init,
+            });
+        }
+        Ok(())
+    }
+
+    fn read_export_section(&mut self, module: &mut Module) -> Result<(), String> {
+        let count = self.read_u32()?;
+        for _ in 0..count {
+            let name = self.read_name()?;
+            let kind = self.read_byte()?;
+            let index = self.read_u32()?;
+            module.exports.push(Export { name, kind, index });
+        }
+        Ok(())
+    }
+
+    fn read_code_section(&mut self, module: &mut Module) -> Result<(), String> {
+        let count = self.read_u32()?;
+        for _ in 0..count {
+            let size = self.read_u32()?;
+            let offset = self.position;
+            let end = offset + size as usize;
+            let local_declarations = self.read_u32()?;
+            let mut locals = Vec::new();
+            for _ in 0..local_declarations {
+                let count = self.read_u32()?;
+                locals.push((count, self.read_value_type()?));
+            }
+            let mut offsets = Vec::new();
+            let code = self.read_expression(&mut offsets)?;
+            if self.position != end {
+                return Err(self.error("Function body size mismatch"));
+            }
+            module.code.push(FunctionBody {
+                offset,
+                size,
+                locals,
+                code,
+                offsets,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a binary module
+pub(crate) fn decode(bytes: &[u8]) -> Result<Module, String> {
+    let mut decoder = Decoder { bytes, position: 0 };
+    if decoder.read_bytes(4)? != b"\0asm" {
+        return Err("Not a WebAssembly module: bad magic number".to_string());
+    }
+    if decoder.read_bytes(4)? != [0x01, 0x00, 0x00, 0x00] {
+        return Err("Unsupported WebAssembly version".to_string());
+    }
+    let mut module = Module::default();
+    while decoder.position < bytes.len() {
+        let id = decoder.read_byte()?;
+        let size = decoder.read_u32()?;
+        let offset = decoder.position;
+        let end = offset + size as usize;
+        if end > bytes.len() {
+            return Err(decoder.error("Section goes beyond the end of the module"));
+        }
+        let name = if id == SECTION_CUSTOM {
+            Some(decoder.read_name()?)
+        } else {
+            None
+        };
+        module.sections.push(Section {
+            id,
+            offset,
+            size,
+            name,
+        });
+        match id {
+            SECTION_TYPE => decoder.read_type_section(&mut module)?,
+            SECTION_IMPORTS => decoder.read_import_section(&mut module)?,
+            SECTION_FUNCTION => decoder.read_function_section(&mut module)?,
+            SECTION_MEMORY => decoder.read_memory_section(&mut module)?,
+            SECTION_GLOBAL => decoder.read_global_section(&mut module)?,
+            SECTION_EXPORT => decoder.read_export_section(&mut module)?,
+            SECTION_CODE => decoder.read_code_section(&mut module)?,
+            // sections the emitter never writes are skipped
+            _ => decoder.position = end,
+        }
+        if decoder.position != end {
+            return Err(decoder.error(&format!("Size mismatch in section {id}")));
+        }
+    }
+    Ok(module)
+}
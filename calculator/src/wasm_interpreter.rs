@@ -0,0 +1,598 @@
+// A small interpreter for the modules written by the emitter, so the tests can run them without a browser.
+// The imports are provided from Rust: the Math functions use `Builtin::evaluate`, `console.print`
+// records its arguments and the slider globals take the values given when instantiating.
+// https://webassembly.github.io/spec/core/exec/index.html
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    builtins::{get_builtin_by_name, Builtin},
+    wasm_decoder::{decode, BlockType, ImportKind, Instruction, MemoryArgument, Module, ValueType},
+};
+
+const PAGE_SIZE: usize = 64 * 1024;
+// memory.grow fails beyond this size (64MiB)
+const MAX_PAGES: usize = 1024;
+const MAX_CALL_DEPTH: usize = 1000;
+// Number of instructions executed before giving up, protects the tests against endless loops
+const FUEL: u64 = 100_000_000;
+
+// The position of the `else` (if any) and the `end` of every block, loop and if of a function body
+type BlockPositions = HashMap<usize, (Option<usize>, usize)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Value {
+    I32(i32),
+    F64(f64),
+}
+
+enum HostFunction {
+    Math(Builtin),
+    Print,
+}
+
+// A block, loop or if that is being executed
+struct Label {
+    // where a branch to this label continues
+    target: usize,
+    // number of values a branch to this label keeps
+    arity: usize,
+    // size of the value stack when the label was entered
+    height: usize,
+    is_loop: bool,
+}
+
+pub(crate) struct Instance {
+    module: Rc<Module>,
+    host_functions: Vec<HostFunction>,
+    // names of the imported globals, in the global index space
+    global_names: Vec<String>,
+    globals: Vec<Value>,
+    pub memory: Vec<u8>,
+    // the calls to console.print(index, value)
+    pub printed: Vec<(f64, f64)>,
+    // for each function body
+    blocks: Rc<Vec<BlockPositions>>,
+    fuel: u64,
+    depth: usize,
+}
+
+fn block_arity(block_type: BlockType) -> usize {
+    match block_type {
+        BlockType::Empty => 0,
+        BlockType::Value(_) => 1,
+    }
+}
+
+fn default_value(value_type: ValueType) -> Result<Value, String> {
+    match value_type {
+        ValueType::I32 => Ok(Value::I32(0)),
+        ValueType::F64 => Ok(Value::F64(0.0)),
+        _ => Err(format!("Unsupported value type {value_type:?}")),
+    }
+}
+
+// Finds the matching `else` and `end` of each structured instruction
+fn match_blocks(code: &[Instruction]) -> Result<BlockPositions, String> {
+    let mut blocks = HashMap::new();
+    let mut open: Vec<(usize, Option<usize>)> = Vec::new();
+    for (position, instruction) in code.iter().enumerate() {
+        match instruction {
+            Instruction::Block(_) | Instruction::Loop(_) | Instruction::If(_) => {
+                open.push((position, None))
+            }
+            Instruction::Else => match open.last_mut() {
+                Some((start, else_position)) if matches!(code[*start], Instruction::If(_)) => {
+                    *else_position = Some(position)
+                }
+                _ => return Err(format!("Unexpected else at instruction {position}")),
+            },
+            Instruction::End => {
+                // the last end closes the function body
+                if let Some((start, else_position)) = open.pop() {
+                    blocks.insert(start, (else_position, position));
+                }
+            }
+            _ => {}
+        }
+    }
+    if !open.is_empty() {
+        return Err("Unterminated block".to_string());
+    }
+    Ok(blocks)
+}
+
+// f64.min and f64.max propagate NaN and order -0 before +0, unlike f64::min and f64::max
+fn wasm_min(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        if a.is_sign_negative() {
+            a
+        } else {
+            b
+        }
+    } else {
+        a.min(b)
+    }
+}
+
+fn wasm_max(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        if a.is_sign_positive() {
+            a
+        } else {
+            b
+        }
+    } else {
+        a.max(b)
+    }
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Value, String> {
+    stack
+        .pop()
+        .ok_or_else(|| "Value stack underflow".to_string())
+}
+
+fn pop_i32(stack: &mut Vec<Value>) -> Result<i32, String> {
+    match pop(stack)? {
+        Value::I32(i) => Ok(i),
+        value => Err(format!("Expected an i32 but found {value:?}")),
+    }
+}
+
+fn pop_f64(stack: &mut Vec<Value>) -> Result<f64, String> {
+    match pop(stack)? {
+        Value::F64(f) => Ok(f),
+        value => Err(format!("Expected an f64 but found {value:?}")),
+    }
+}
+
+impl Instance {
+    /// Instantiates a module, `sliders` are the initial values of the imported globals
+    pub fn new(bytes: &[u8], sliders: &[(&str, f64)]) -> Result<Instance, String> {
+        let module = decode(bytes)?;
+        let mut host_functions = Vec::new();
+        let mut global_names = Vec::new();
+        let mut globals = Vec::new();
+        for import in &module.imports {
+            match (&import.kind, import.module.as_str()) {
+                (ImportKind::Function(_), "Math") => match get_builtin_by_name(&import.name) {
+                    Some(builtin) => host_functions.push(HostFunction::Math(builtin)),
+                    None => return Err(format!("Unknown import Math.{}", import.name)),
+                },
+                (ImportKind::Function(_), "console") if import.name == "print" => {
+                    host_functions.push(HostFunction::Print)
+                }
+                (ImportKind::Global { .. }, "globals") => {
+                    match sliders.iter().find(|(name, _)| name == &import.name) {
+                        Some((_, value)) => globals.push(Value::F64(*value)),
+                        None => return Err(format!("No value for global '{}'", import.name)),
+                    }
+                    global_names.push(import.name.clone());
+                }
+                _ => return Err(format!("Unknown import {}.{}", import.module, import.name)),
+            }
+        }
+        for global in &module.globals {
+            let value = match global.init.as_slice() {
+                [Instruction::I32Const(i), Instruction::End] => Value::I32(*i),
+                [Instruction::F64Const(f), Instruction::End] => Value::F64(*f),
+                [Instruction::GlobalGet(index), Instruction::End] => globals
+                    .get(*index as usize)
+                    .copied()
+                    .ok_or_else(|| format!("Unknown global {index}"))?,
+                _ => return Err("Unsupported global initializer".to_string()),
+            };
+            globals.push(value);
+        }
+        let pages = match &module.memory {
+            Some(limits) => limits.minimum as usize,
+            None => 0,
+        };
+        let mut blocks = Vec::new();
+        for body in &module.code {
+            blocks.push(match_blocks(&body.code)?);
+        }
+        if module.functions.len() != module.code.len() {
+            return Err("Function and code sections have different lengths".to_string());
+        }
+        Ok(Instance {
+            module: Rc::new(module),
+            host_functions,
+            global_names,
+            globals,
+            memory: vec![0; pages * PAGE_SIZE],
+            printed: Vec::new(),
+            blocks: Rc::new(blocks),
+            fuel: FUEL,
+            depth: 0,
+        })
+    }
+
+    /// The index of the first function defined in the module, after the imported ones
+    pub fn imported_function_count(&self) -> u32 {
+        self.module.imported_function_count()
+    }
+
+    /// Changes the value of an imported global, like moving a slider
+    pub fn set_global(&mut self, name: &str, value: f64) -> Result<(), String> {
+        match self.global_names.iter().position(|n| n == name) {
+            Some(index) => {
+                self.globals[index] = Value::F64(value);
+                Ok(())
+            }
+            None => Err(format!("Unknown global '{name}'")),
+        }
+    }
+
+    /// Calls an exported function that returns an f64
+    pub fn call(&mut self, name: &str, args: &[f64]) -> Result<f64, String> {
+        let index = match self
+            .module
+            .exports
+            .iter()
+            .find(|export| export.name == name)
+        {
+            Some(export) => export.index,
+            None => return Err(format!("Unknown export '{name}'")),
+        };
+        self.call_index(index, args)
+    }
+
+    /// Calls a function by its index in the function index space
+    pub fn call_index(&mut self, index: u32, args: &[f64]) -> Result<f64, String> {
+        let args: Vec<Value> = args.iter().map(|&f| Value::F64(f)).collect();
+        match self.invoke(index, args)?.as_slice() {
+            [Value::F64(f)] => Ok(*f),
+            results => Err(format!("Expected a single f64 result but got {results:?}")),
+        }
+    }
+
+    pub fn read_f64(&self, address: usize) -> Result<f64, String> {
+        let bytes = self
+            .memory
+            .get(address..address + 8)
+            .ok_or("Out of bounds memory access")?;
+        Ok(f64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn effective_address(
+        &self,
+        base: i32,
+        argument: &MemoryArgument,
+        size: usize,
+    ) -> Result<usize, String> {
+        let address = base as u32 as usize + argument.offset as usize;
+        if address + size > self.memory.len() {
+            return Err(format!("Out of bounds memory access at {address:#x}"));
+        }
+        Ok(address)
+    }
+
+    fn invoke(&mut self, index: u32, args: Vec<Value>) -> Result<Vec<Value>, String> {
+        let type_index = self
+            .module
+            .function_type(index)
+            .ok_or_else(|| format!("Unknown function {index}"))?;
+        let function_type = &self.module.types[type_index as usize];
+        if function_type.params.len() != args.len() {
+            return Err(format!(
+                "Function {index} expects {} arguments but got {}",
+                function_type.params.len(),
+                args.len()
+            ));
+        }
+        let result_count = function_type.results.len();
+
+        let imported = self.module.imported_function_count();
+        if index < imported {
+            let args: Vec<f64> = args
+                .iter()
+                .map(|value| match value {
+                    Value::F64(f) => Ok(*f),
+                    Value::I32(_) => Err("Host functions take f64 arguments".to_string()),
+                })
+                .collect::<Result<_, _>>()?;
+            let result = match &self.host_functions[index as usize] {
+                HostFunction::Math(builtin) => builtin.evaluate(&args),
+                HostFunction::Print => {
+                    self.printed.push((args[0], args[1]));
+                    0.0
+                }
+            };
+            return Ok(vec![Value::F64(result)]);
+        }
+
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err("Call stack exhausted".to_string());
+        }
+        self.depth += 1;
+        let result = self.execute((index - imported) as usize, args, result_count);
+        self.depth -= 1;
+        result
+    }
+
+    fn execute(
+        &mut self,
+        function: usize,
+        mut locals: Vec<Value>,
+        result_count: usize,
+    ) -> Result<Vec<Value>, String> {
+        // The code is borrowed from these while the memory and the globals are modified
+        let module = Rc::clone(&self.module);
+        let blocks = Rc::clone(&self.blocks);
+        let body = &module.code[function];
+        let blocks = &blocks[function];
+        for (count, value_type) in &body.locals {
+            for _ in 0..*count {
+                locals.push(default_value(*value_type)?);
+            }
+        }
+        let code = &body.code;
+        let mut stack: Vec<Value> = Vec::new();
+        let mut labels: Vec<Label> = Vec::new();
+        let mut pc = 0;
+
+        loop {
+            if self.fuel == 0 {
+                return Err("Execution limit exceeded".to_string());
+            }
+            self.fuel -= 1;
+            let instruction = code
+                .get(pc)
+                .ok_or("Execution went past the end of the function")?;
+            pc += 1;
+            match instruction {
+                Instruction::Unreachable => return Err("Unreachable executed".to_string()),
+                Instruction::Nop => {}
+                Instruction::Block(block_type) => {
+                    let (_, end) = blocks[&(pc - 1)];
+                    labels.push(Label {
+                        target: end + 1,
+                        arity: block_arity(*block_type),
+                        height: stack.len(),
+                        is_loop: false,
+                    });
+                }
+                Instruction::Loop(_) => labels.push(Label {
+                    target: pc,
+                    arity: 0,
+                    height: stack.len(),
+                    is_loop: true,
+                }),
+                Instruction::If(block_type) => {
+                    let (else_position, end) = blocks[&(pc - 1)];
+                    let condition = pop_i32(&mut stack)?;
+                    let label = Label {
+                        target: end + 1,
+                        arity: block_arity(*block_type),
+                        height: stack.len(),
+                        is_loop: false,
+                    };
+                    if condition != 0 {
+                        labels.push(label);
+                    } else if let Some(else_position) = else_position {
+                        labels.push(label);
+                        pc = else_position + 1;
+                    } else {
+                        pc = end + 1;
+                    }
+                }
+                // the end of the true branch of an if, the false branch is skipped
+                Instruction::Else => {
+                    let label = labels.pop().ok_or("Else outside of an if")?;
+                    pc = label.target;
+                }
+                Instruction::End => {
+                    if labels.pop().is_none() {
+                        break;
+                    }
+                }
+                Instruction::Br(depth) => {
+                    if branch(*depth as usize, &mut labels, &mut stack, &mut pc)? {
+                        break;
+                    }
+                }
+                Instruction::BrIf(depth) => {
+                    if pop_i32(&mut stack)? != 0
+                        && branch(*depth as usize, &mut labels, &mut stack, &mut pc)?
+                    {
+                        break;
+                    }
+                }
+                Instruction::Return => break,
+                Instruction::Call(index) => {
+                    let type_index = module
+                        .function_type(*index)
+                        .ok_or_else(|| format!("Unknown function {index}"))?;
+                    let param_count = module.types[type_index as usize].params.len();
+                    if stack.len() < param_count {
+                        return Err("Value stack underflow".to_string());
+                    }
+                    let args = stack.split_off(stack.len() - param_count);
+                    let results = self.invoke(*index, args)?;
+                    stack.extend(results);
+                }
+                Instruction::Drop => {
+                    pop(&mut stack)?;
+                }
+                Instruction::LocalGet(index) => {
+                    let value = locals.get(*index as usize).ok_or("Unknown local")?;
+                    stack.push(*value);
+                }
+                Instruction::LocalSet(index) => {
+                    let value = pop(&mut stack)?;
+                    *locals.get_mut(*index as usize).ok_or("Unknown local")? = value;
+                }
+                Instruction::LocalTee(index) => {
+                    let value = *stack.last().ok_or("Value stack underflow")?;
+                    *locals.get_mut(*index as usize).ok_or("Unknown local")? = value;
+                }
+                Instruction::GlobalGet(index) => {
+                    let value = self.globals.get(*index as usize).ok_or("Unknown global")?;
+                    stack.push(*value);
+                }
+                Instruction::GlobalSet(index) => {
+                    let value = pop(&mut stack)?;
+                    *self
+                        .globals
+                        .get_mut(*index as usize)
+                        .ok_or("Unknown global")? = value;
+                }
+                Instruction::I32Load(argument) => {
+                    let address = self.effective_address(pop_i32(&mut stack)?, argument, 4)?;
+                    let bytes = self.memory[address..address + 4].try_into().unwrap();
+                    stack.push(Value::I32(i32::from_le_bytes(bytes)));
+                }
+                Instruction::F64Load(argument) => {
+                    let address = self.effective_address(pop_i32(&mut stack)?, argument, 8)?;
+                    let bytes = self.memory[address..address + 8].try_into().unwrap();
+                    stack.push(Value::F64(f64::from_le_bytes(bytes)));
+                }
+                Instruction::I32Store(argument) => {
+                    let value = pop_i32(&mut stack)?;
+                    let address = self.effective_address(pop_i32(&mut stack)?, argument, 4)?;
+                    self.memory[address..address + 4].copy_from_slice(&value.to_le_bytes());
+                }
+                Instruction::F64Store(argument) => {
+                    let value = pop_f64(&mut stack)?;
+                    let address = self.effective_address(pop_i32(&mut stack)?, argument, 8)?;
+                    self.memory[address..address + 8].copy_from_slice(&value.to_le_bytes());
+                }
+                Instruction::MemorySize => {
+                    stack.push(Value::I32((self.memory.len() / PAGE_SIZE) as i32));
+                }
+                Instruction::MemoryGrow => {
+                    let delta = pop_i32(&mut stack)? as u32 as usize;
+                    let pages = self.memory.len() / PAGE_SIZE;
+                    let maximum = match module.memory.as_ref().and_then(|m| m.maximum) {
+                        Some(maximum) => (maximum as usize).min(MAX_PAGES),
+                        None => MAX_PAGES,
+                    };
+                    if pages + delta > maximum {
+                        stack.push(Value::I32(-1));
+                    } else {
+                        self.memory.resize((pages + delta) * PAGE_SIZE, 0);
+                        stack.push(Value::I32(pages as i32));
+                    }
+                }
+                Instruction::I32Const(i) => stack.push(Value::I32(*i)),
+                Instruction::F64Const(f) => stack.push(Value::F64(*f)),
+                Instruction::I32LtS
+                | Instruction::I32GtU
+                | Instruction::I32Add
+                | Instruction::I32Sub
+                | Instruction::I32Mul
+                | Instruction::I32ShrU => {
+                    let b = pop_i32(&mut stack)?;
+                    let a = pop_i32(&mut stack)?;
+                    let result = match instruction {
+                        Instruction::I32LtS => (a < b) as i32,
+                        Instruction::I32GtU => (a as u32 > b as u32) as i32,
+                        Instruction::I32Add => a.wrapping_add(b),
+                        Instruction::I32Sub => a.wrapping_sub(b),
+                        Instruction::I32Mul => a.wrapping_mul(b),
+                        _ => ((a as u32) >> (b as u32 % 32)) as i32,
+                    };
+                    stack.push(Value::I32(result));
+                }
+                Instruction::F64Eq
+                | Instruction::F64Ne
+                | Instruction::F64Lt
+                | Instruction::F64Gt
+                | Instruction::F64Le
+                | Instruction::F64Ge => {
+                    let b = pop_f64(&mut stack)?;
+                    let a = pop_f64(&mut stack)?;
+                    let result = match instruction {
+                        Instruction::F64Eq => a == b,
+                        Instruction::F64Ne => a != b,
+                        Instruction::F64Lt => a < b,
+                        Instruction::F64Gt => a > b,
+                        Instruction::F64Le => a <= b,
+                        _ => a >= b,
+                    };
+                    stack.push(Value::I32(result as i32));
+                }
+                Instruction::F64Add
+                | Instruction::F64Sub
+                | Instruction::F64Mul
+                | Instruction::F64Div
+                | Instruction::F64Min
+                | Instruction::F64Max => {
+                    let b = pop_f64(&mut stack)?;
+                    let a = pop_f64(&mut stack)?;
+                    let result = match instruction {
+                        Instruction::F64Add => a + b,
+                        Instruction::F64Sub => a - b,
+                        Instruction::F64Mul => a * b,
+                        Instruction::F64Div => a / b,
+                        Instruction::F64Min => wasm_min(a, b),
+                        _ => wasm_max(a, b),
+                    };
+                    stack.push(Value::F64(result));
+                }
+                Instruction::F64Ceil => {
+                    let a = pop_f64(&mut stack)?;
+                    stack.push(Value::F64(a.ceil()));
+                }
+                Instruction::I32TruncF64S => {
+                    let a = pop_f64(&mut stack)?;
+                    if a.is_nan() {
+                        return Err("Invalid conversion to integer".to_string());
+                    }
+                    let a = a.trunc();
+                    if !(-2147483648.0..=2147483647.0).contains(&a) {
+                        return Err("Integer overflow".to_string());
+                    }
+                    stack.push(Value::I32(a as i32));
+                }
+                Instruction::F64ConvertI32S => {
+                    let a = pop_i32(&mut stack)?;
+                    stack.push(Value::F64(a as f64));
+                }
+                // the emitter never writes the rest of the instruction set
+                instruction => return Err(format!("Unsupported instruction {instruction:?}")),
+            }
+        }
+        if stack.len() < result_count {
+            return Err("Value stack underflow".to_string());
+        }
+        Ok(stack.split_off(stack.len() - result_count))
+    }
+}
+
+// Leaves `depth` labels. Returns true if the branch leaves the function
+fn branch(
+    depth: usize,
+    labels: &mut Vec<Label>,
+    stack: &mut Vec<Value>,
+    pc: &mut usize,
+) -> Result<bool, String> {
+    if depth == labels.len() {
+        return Ok(true);
+    }
+    if depth > labels.len() {
+        return Err(format!("Invalid branch depth {depth}"));
+    }
+    let label = &labels[labels.len() - 1 - depth];
+    if stack.len() < label.height + label.arity {
+        return Err("Value stack underflow".to_string());
+    }
+    let kept = stack.split_off(stack.len() - label.arity);
+    stack.truncate(label.height);
+    stack.extend(kept);
+    *pc = label.target;
+    // a branch to a loop starts the next iteration, so the loop label stays
+    let remaining = if label.is_loop {
+        labels.len() - depth
+    } else {
+        labels.len() - 1 - depth
+    };
+    labels.truncate(remaining);
+    Ok(false)
+}
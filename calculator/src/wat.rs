@@ -0,0 +1,551 @@
+// Prints a decoded module in the WebAssembly text format.
+// `emit_wat` decodes what the emitter wrote, so the text is always equivalent to the binary,
+// and uses the program to give names to the functions, arguments and globals.
+// https://webassembly.github.io/spec/core/text/index.html
+
+use std::collections::HashMap;
+
+use crate::{
+    emitter::{emit_program, REDRAW_LOCALS},
+    errors::Result,
+    ir::{Import, Program},
+    opcodes::{FUNCTION_EXPORT_KIND, INSTR_FIRST_NUMERIC, MEMORY_EXPORT_KIND, MEMORY_FIRST_LOAD},
+    tokens::Span,
+    wasm_decoder::{
+        decode, BlockType, ImportKind, Instruction, Limits, MemoryArgument, Module, ValueType,
+    },
+};
+
+/// Names of the things in the index spaces of the module, the binary format only has indices
+#[derive(Default)]
+pub(crate) struct Names {
+    pub functions: HashMap<u32, String>,
+    // (function index, local index)
+    pub locals: HashMap<(u32, u32), String>,
+    pub globals: HashMap<u32, String>,
+    // a comment written before the function
+    pub comments: HashMap<u32, String>,
+}
+
+pub(crate) fn value_type(value_type: ValueType) -> &'static str {
+    match value_type {
+        ValueType::I32 => "i32",
+        ValueType::I64 => "i64",
+        ValueType::F32 => "f32",
+        ValueType::F64 => "f64",
+    }
+}
+
+fn value_types(types: &[ValueType]) -> String {
+    let types: Vec<&str> = types.iter().map(|t| value_type(*t)).collect();
+    types.join(" ")
+}
+
+// `$name` if there is a name, the index otherwise
+fn reference(name: Option<&String>, index: u32) -> String {
+    match name {
+        Some(name) => format!("${name}"),
+        None => index.to_string(),
+    }
+}
+
+// `$name` if there is a name, a comment with the index otherwise
+fn identifier(name: Option<&String>, index: u32) -> String {
+    match name {
+        Some(name) => format!("${name}"),
+        None => format!("(;{index};)"),
+    }
+}
+
+fn block_type(block_type: BlockType) -> String {
+    match block_type {
+        BlockType::Empty => "".to_string(),
+        BlockType::Value(t) => format!(" (result {})", value_type(t)),
+    }
+}
+
+fn limits_text(limits: &Limits) -> String {
+    match limits.maximum {
+        Some(maximum) => format!("{} {maximum}", limits.minimum),
+        None => limits.minimum.to_string(),
+    }
+}
+
+fn f64_literal(f: f64) -> String {
+    if f.is_nan() {
+        "nan".to_string()
+    } else {
+        // Rust prints the shortest decimal that gives back the same f64, and "inf" and "-inf"
+        format!("{f}")
+    }
+}
+
+// `natural_align` is the log2 of the size of the value, the default alignment
+fn memory_argument(argument: &MemoryArgument, natural_align: u32) -> String {
+    let mut text = String::new();
+    if argument.offset != 0 {
+        text.push_str(&format!(" offset={}", argument.offset));
+    }
+    if argument.align != natural_align {
+        text.push_str(&format!(" align={}", 1u64 << argument.align));
+    }
+    text
+}
+
+// The loads and stores from i32.load (0x28) to i64.store32 (0x3e), with their natural alignment
+const MEMORY_INSTRUCTIONS: [(&str, u32); 23] = [
+    ("i32.load", 2),
+    ("i64.load", 3),
+    ("f32.load", 2),
+    ("f64.load", 3),
+    ("i32.load8_s", 0),
+    ("i32.load8_u", 0),
+    ("i32.load16_s", 1),
+    ("i32.load16_u", 1),
+    ("i64.load8_s", 0),
+    ("i64.load8_u", 0),
+    ("i64.load16_s", 1),
+    ("i64.load16_u", 1),
+    ("i64.load32_s", 2),
+    ("i64.load32_u", 2),
+    ("i32.store", 2),
+    ("i64.store", 3),
+    ("f32.store", 2),
+    ("f64.store", 3),
+    ("i32.store8", 0),
+    ("i32.store16", 1),
+    ("i64.store8", 0),
+    ("i64.store16", 1),
+    ("i64.store32", 2),
+];
+
+// The instructions without immediates from i32.eqz (0x45) to f64.reinterpret_i64 (0xbf)
+pub(crate) const NUMERIC_INSTRUCTIONS: [&str; 123] = [
+    "i32.eqz",
+    "i32.eq",
+    "i32.ne",
+    "i32.lt_s",
+    "i32.lt_u",
+    "i32.gt_s",
+    "i32.gt_u",
+    "i32.le_s",
+    "i32.le_u",
+    "i32.ge_s",
+    "i32.ge_u",
+    "i64.eqz",
+    "i64.eq",
+    "i64.ne",
+    "i64.lt_s",
+    "i64.lt_u",
+    "i64.gt_s",
+    "i64.gt_u",
+    "i64.le_s",
+    "i64.le_u",
+    "i64.ge_s",
+    "i64.ge_u",
+    "f32.eq",
+    "f32.ne",
+    "f32.lt",
+    "f32.gt",
+    "f32.le",
+    "f32.ge",
+    "f64.eq",
+    "f64.ne",
+    "f64.lt",
+    "f64.gt",
+    "f64.le",
+    "f64.ge",
+    "i32.clz",
+    "i32.ctz",
+    "i32.popcnt",
+    "i32.add",
+    "i32.sub",
+    "i32.mul",
+    "i32.div_s",
+    "i32.div_u",
+    "i32.rem_s",
+    "i32.rem_u",
+    "i32.and",
+    "i32.or",
+    "i32.xor",
+    "i32.shl",
+    "i32.shr_s",
+    "i32.shr_u",
+    "i32.rotl",
+    "i32.rotr",
+    "i64.clz",
+    "i64.ctz",
+    "i64.popcnt",
+    "i64.add",
+    "i64.sub",
+    "i64.mul",
+    "i64.div_s",
+    "i64.div_u",
+    "i64.rem_s",
+    "i64.rem_u",
+    "i64.and",
+    "i64.or",
+    "i64.xor",
+    "i64.shl",
+    "i64.shr_s",
+    "i64.shr_u",
+    "i64.rotl",
+    "i64.rotr",
+    "f32.abs",
+    "f32.neg",
+    "f32.ceil",
+    "f32.floor",
+    "f32.trunc",
+    "f32.nearest",
+    "f32.sqrt",
+    "f32.add",
+    "f32.sub",
+    "f32.mul",
+    "f32.div",
+    "f32.min",
+    "f32.max",
+    "f32.copysign",
+    "f64.abs",
+    "f64.neg",
+    "f64.ceil",
+    "f64.floor",
+    "f64.trunc",
+    "f64.nearest",
+    "f64.sqrt",
+    "f64.add",
+    "f64.sub",
+    "f64.mul",
+    "f64.div",
+    "f64.min",
+    "f64.max",
+    "f64.copysign",
+    "i32.wrap_i64",
+    "i32.trunc_f32_s",
+    "i32.trunc_f32_u",
+    "i32.trunc_f64_s",
+    "i32.trunc_f64_u",
+    "i64.extend_i32_s",
+    "i64.extend_i32_u",
+    "i64.trunc_f32_s",
+    "i64.trunc_f32_u",
+    "i64.trunc_f64_s",
+    "i64.trunc_f64_u",
+    "f32.convert_i32_s",
+    "f32.convert_i32_u",
+    "f32.convert_i64_s",
+    "f32.convert_i64_u",
+    "f32.demote_f64",
+    "f64.convert_i32_s",
+    "f64.convert_i32_u",
+    "f64.convert_i64_s",
+    "f64.convert_i64_u",
+    "f64.promote_f32",
+    "i32.reinterpret_f32",
+    "i64.reinterpret_f64",
+    "f32.reinterpret_i32",
+    "f64.reinterpret_i64",
+];
+
+pub(crate) fn instruction_text(instruction: &Instruction, names: &Names, function: u32) -> String {
+    let local = |index: &u32| reference(names.locals.get(&(function, *index)), *index);
+    let global = |index: &u32| reference(names.globals.get(index), *index);
+    match instruction {
+        Instruction::Unreachable => "unreachable".to_string(),
+        Instruction::Nop => "nop".to_string(),
+        Instruction::Block(t) => format!("block{}", block_type(*t)),
+        Instruction::Loop(t) => format!("loop{}", block_type(*t)),
+        Instruction::If(t) => format!("if{}", block_type(*t)),
+        Instruction::Else => "else".to_string(),
+        Instruction::End => "end".to_string(),
+        Instruction::Br(depth) => format!("br {depth}"),
+        Instruction::BrIf(depth) => format!("br_if {depth}"),
+        Instruction::BrTable(labels, default) => {
+            let labels: Vec<String> = labels.iter().map(|label| label.to_string()).collect();
+            format!("br_table {} {default}", labels.join(" "))
+        }
+        Instruction::Return => "return".to_string(),
+        Instruction::Call(index) => {
+            format!("call {}", reference(names.functions.get(index), *index))
+        }
+        Instruction::CallIndirect(type_index, table) => {
+            format!("call_indirect {table} (type {type_index})")
+        }
+        Instruction::Drop => "drop".to_string(),
+        Instruction::Select => "select".to_string(),
+        Instruction::LocalGet(index) => format!("local.get {}", local(index)),
+        Instruction::LocalSet(index) => format!("local.set {}", local(index)),
+        Instruction::LocalTee(index) => format!("local.tee {}", local(index)),
+        Instruction::GlobalGet(index) => format!("global.get {}", global(index)),
+        Instruction::GlobalSet(index) => format!("global.set {}", global(index)),
+        Instruction::I32Load(argument) => format!("i32.load{}", memory_argument(argument, 2)),
+        Instruction::F64Load(argument) => format!("f64.load{}", memory_argument(argument, 3)),
+        Instruction::I32Store(argument) => format!("i32.store{}", memory_argument(argument, 2)),
+        Instruction::F64Store(argument) => format!("f64.store{}", memory_argument(argument, 3)),
+        Instruction::Memory(opcode, argument) => {
+            let (name, natural_align) = MEMORY_INSTRUCTIONS[(opcode - MEMORY_FIRST_LOAD) as usize];
+            format!("{name}{}", memory_argument(argument, natural_align))
+        }
+        Instruction::MemorySize => "memory.size".to_string(),
+        Instruction::MemoryGrow => "memory.grow".to_string(),
+        Instruction::I32Const(i) => format!("i32.const {i}"),
+        Instruction::I64Const(i) => format!("i64.const {i}"),
+        Instruction::F32Const(f) => format!("f32.const {}", f64_literal(*f as f64)),
+        Instruction::F64Const(f) => format!("f64.const {}", f64_literal(*f)),
+        Instruction::I32LtS => "i32.lt_s".to_string(),
+        Instruction::I32GtU => "i32.gt_u".to_string(),
+        Instruction::F64Eq => "f64.eq".to_string(),
+        Instruction::F64Ne => "f64.ne".to_string(),
+        Instruction::F64Lt => "f64.lt".to_string(),
+        Instruction::F64Gt => "f64.gt".to_string(),
+        Instruction::F64Le => "f64.le".to_string(),
+        Instruction::F64Ge => "f64.ge".to_string(),
+        Instruction::I32Add => "i32.add".to_string(),
+        Instruction::I32Sub => "i32.sub".to_string(),
+        Instruction::I32Mul => "i32.mul".to_string(),
+        Instruction::I32ShrU => "i32.shr_u".to_string(),
+        Instruction::F64Ceil => "f64.ceil".to_string(),
+        Instruction::F64Add => "f64.add".to_string(),
+        Instruction::F64Sub => "f64.sub".to_string(),
+        Instruction::F64Mul => "f64.mul".to_string(),
+        Instruction::F64Div => "f64.div".to_string(),
+        Instruction::F64Min => "f64.min".to_string(),
+        Instruction::F64Max => "f64.max".to_string(),
+        Instruction::I32TruncF64S => "i32.trunc_f64_s".to_string(),
+        Instruction::F64ConvertI32S => "f64.convert_i32_s".to_string(),
+        Instruction::Numeric(opcode) => {
+            NUMERIC_INSTRUCTIONS[(opcode - INSTR_FIRST_NUMERIC) as usize].to_string()
+        }
+    }
+}
+
+// The instructions of a function body or a constant expression, one per line.
+// The last `end` closes the body and is implicit in the text format.
+fn print_code(code: &[Instruction], names: &Names, function: u32, indent: usize) -> String {
+    let mut text = String::new();
+    let mut depth = 0;
+    let code = match code.split_last() {
+        Some((Instruction::End, code)) => code,
+        _ => code,
+    };
+    for instruction in code {
+        if matches!(instruction, Instruction::Else | Instruction::End) {
+            depth = usize::saturating_sub(depth, 1);
+        }
+        text.push_str(&" ".repeat(indent + 2 * depth));
+        text.push_str(&instruction_text(instruction, names, function));
+        text.push('\n');
+        if matches!(
+            instruction,
+            Instruction::Block(_) | Instruction::Loop(_) | Instruction::If(_) | Instruction::Else
+        ) {
+            depth += 1;
+        }
+    }
+    text
+}
+
+fn function_signature(module: &Module, names: &Names, index: u32, type_index: u32) -> String {
+    let mut text = format!("(type {type_index})");
+    if let Some(function_type) = module.types.get(type_index as usize) {
+        for (local, param) in function_type.params.iter().enumerate() {
+            match names.locals.get(&(index, local as u32)) {
+                Some(name) => text.push_str(&format!(" (param ${name} {})", value_type(*param))),
+                None => text.push_str(&format!(" (param {})", value_type(*param))),
+            }
+        }
+        if !function_type.results.is_empty() {
+            text.push_str(&format!(
+                " (result {})",
+                value_types(&function_type.results)
+            ));
+        }
+    }
+    text
+}
+
+/// Prints the module in the text format
+pub(crate) fn print_wat(module: &Module, names: &Names) -> String {
+    let mut text = "(module\n".to_string();
+    for (index, function_type) in module.types.iter().enumerate() {
+        text.push_str(&format!("  (type (;{index};) (func"));
+        if !function_type.params.is_empty() {
+            text.push_str(&format!(" (param {})", value_types(&function_type.params)));
+        }
+        if !function_type.results.is_empty() {
+            text.push_str(&format!(
+                " (result {})",
+                value_types(&function_type.results)
+            ));
+        }
+        text.push_str("))\n");
+    }
+
+    let mut function_index = 0;
+    let mut global_index = 0;
+    for import in &module.imports {
+        let description = match import.kind {
+            ImportKind::Function(type_index) => {
+                let description = format!(
+                    "func {} {}",
+                    identifier(names.functions.get(&function_index), function_index),
+                    function_signature(module, names, function_index, type_index)
+                );
+                function_index += 1;
+                description
+            }
+            ImportKind::Global {
+                value_type: t,
+                mutable,
+            } => {
+                let t = if mutable {
+                    format!("(mut {})", value_type(t))
+                } else {
+                    value_type(t).to_string()
+                };
+                let description = format!(
+                    "global {} {t}",
+                    identifier(names.globals.get(&global_index), global_index)
+                );
+                global_index += 1;
+                description
+            }
+            ImportKind::Table(ref limits) => format!("table {} funcref", limits_text(limits)),
+            ImportKind::Memory(ref limits) => format!("memory {}", limits_text(limits)),
+        };
+        text.push_str(&format!(
+            "  (import \"{}\" \"{}\" ({description}))\n",
+            import.module, import.name
+        ));
+    }
+
+    for (type_index, body) in module.functions.iter().zip(&module.code) {
+        if let Some(comment) = names.comments.get(&function_index) {
+            text.push_str(&format!("  ;; {comment}\n"));
+        }
+        text.push_str(&format!(
+            "  (func {} {}\n",
+            identifier(names.functions.get(&function_index), function_index),
+            function_signature(module, names, function_index, *type_index)
+        ));
+        // the locals come after the parameters in the local index space
+        let mut local_index = match module.types.get(*type_index as usize) {
+            Some(function_type) => function_type.params.len() as u32,
+            None => 0,
+        };
+        for (count, t) in &body.locals {
+            for _ in 0..*count {
+                match names.locals.get(&(function_index, local_index)) {
+                    Some(name) => {
+                        text.push_str(&format!("    (local ${name} {})\n", value_type(*t)))
+                    }
+                    None => text.push_str(&format!("    (local {})\n", value_type(*t))),
+                }
+                local_index += 1;
+            }
+        }
+        text.push_str(&print_code(&body.code, names, function_index, 4));
+        text.push_str("  )\n");
+        function_index += 1;
+    }
+
+    if let Some(limits) = &module.memory {
+        text.push_str(&format!("  (memory (;0;) {})\n", limits_text(limits)));
+    }
+
+    for global in &module.globals {
+        let t = if global.mutable {
+            format!("(mut {})", value_type(global.value_type))
+        } else {
+            value_type(global.value_type).to_string()
+        };
+        let init: Vec<String> = global
+            .init
+            .iter()
+            .filter(|instruction| !matches!(instruction, Instruction::End))
+            .map(|instruction| format!("({})", instruction_text(instruction, names, 0)))
+            .collect();
+        text.push_str(&format!(
+            "  (global {} {t} {})\n",
+            identifier(names.globals.get(&global_index), global_index),
+            init.join(" ")
+        ));
+        global_index += 1;
+    }
+
+    for export in &module.exports {
+        let description = match export.kind {
+            FUNCTION_EXPORT_KIND => format!(
+                "func {}",
+                reference(names.functions.get(&export.index), export.index)
+            ),
+            MEMORY_EXPORT_KIND => format!("memory {}", export.index),
+            _ => format!("global {}", export.index),
+        };
+        text.push_str(&format!("  (export \"{}\" ({description}))\n", export.name));
+    }
+    text.push_str(")\n");
+    text
+}
+
+// "line 3: f(x) = x*x", the first line of the source code of the span
+fn source_comment(source: &str, span: Span) -> String {
+    let line_number = source[..span.start].matches('\n').count() + 1;
+    let code = source[span.start..span.end].lines().next().unwrap_or("");
+    format!("line {line_number}: {}", code.trim())
+}
+
+// A Sum variable can have the name of an argument or of another Sum, the text format needs
+// different names so the later ones get their index: `$n`, `$n.3`
+fn name_locals(names: &mut Names, function: u32, locals: &[String]) {
+    for (index, name) in locals.iter().enumerate() {
+        let name = if locals[..index].contains(name) {
+            format!("{name}.{index}")
+        } else {
+            name.clone()
+        };
+        names.locals.insert((function, index as u32), name);
+    }
+}
+
+/// The program compiled to the text format, with the names of the program
+pub(crate) fn emit_wat(program: &Program, source: &str) -> Result<String> {
+    let code = emit_program(program)?;
+    let module = decode(&code)?;
+
+    // The index spaces are laid out like in `emit_program`
+    let mut names = Names::default();
+    let mut function_index = 0;
+    for import in &program.imports {
+        let name = match import {
+            Import::Builtin(builtin) => builtin.name(),
+            Import::Print => "Print",
+        };
+        names.functions.insert(function_index, name.to_string());
+        function_index += 1;
+    }
+    for (global_index, slider) in program.sliders.iter().enumerate() {
+        names.globals.insert(global_index as u32, slider.clone());
+    }
+
+    // the user defined functions and then the plot functions
+    for function in &program.functions {
+        names
+            .functions
+            .insert(function_index, function.name.clone());
+        name_locals(&mut names, function_index, &function.locals);
+        names
+            .comments
+            .insert(function_index, source_comment(source, function.span));
+        function_index += 1;
+    }
+    names.functions.insert(function_index, "redraw".to_string());
+    let mut redraw_locals: Vec<String> = REDRAW_LOCALS.iter().map(|s| s.to_string()).collect();
+    redraw_locals.extend(program.print_locals.iter().cloned());
+    name_locals(&mut names, function_index, &redraw_locals);
+    names.comments.insert(
+        function_index,
+        "redraw(width, height) prints and samples the plot functions".to_string(),
+    );
+
+    Ok(print_wat(&module, &names))
+}